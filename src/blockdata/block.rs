@@ -20,14 +20,18 @@
 //! these blocks and the blockchain.
 //!
 
+use std::{error, fmt};
+
 use util;
 use util::Error::{SpvBadTarget, SpvBadProofOfWork};
-use util::hash::{BitcoinHash, Sha256dHash};
+use util::hash::{BitcoinHash, Sha256dEncoder, Sha256dHash, bitcoin_merkle_root};
 use util::uint::Uint256;
-use consensus::encode::VarInt;
+use consensus::encode::{Encodable, VarInt};
+use consensus::params::Params;
 use network::constants::Network;
-use blockdata::transaction::Transaction;
-use blockdata::constants::max_target;
+use blockdata::transaction::{SanityError, Transaction};
+use util::amount::Amount;
+use blockdata::constants::{max_target, MAX_BLOCK_SIGOPS_COST, MAX_BLOCK_WEIGHT, WITNESS_SCALE_FACTOR};
 
 /// A block header, which contains all the block's information except
 /// the actual transactions
@@ -140,6 +144,182 @@ impl BlockHeader {
     }
 }
 
+/// The start of a coinbase output script committing to the block's witnesses:
+/// OP_RETURN, a push of 36 bytes, and the commitment header (BIP141)
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Computes the merkle root of the given hashes, also returning whether the
+/// tree is mutated: whether a node equals its sibling at some level, so
+/// that the same root would result from a list with duplicated entries
+/// (CVE-2012-2459)
+fn merkle_root_and_mutation(mut hashes: Vec<Sha256dHash>) -> (Sha256dHash, bool) {
+    if hashes.is_empty() {
+        return (Default::default(), false);
+    }
+    let mut mutated = false;
+    while hashes.len() > 1 {
+        let mut next = Vec::with_capacity((hashes.len() + 1) / 2);
+        for pair in hashes.chunks(2) {
+            // An odd node out is paired with itself
+            let (left, right) = (pair[0], pair[pair.len() - 1]);
+            if pair.len() == 2 && left == right {
+                mutated = true;
+            }
+            let mut encoder = Sha256dEncoder::new();
+            left.consensus_encode(&mut encoder).unwrap();
+            right.consensus_encode(&mut encoder).unwrap();
+            next.push(encoder.into_hash());
+        }
+        hashes = next;
+    }
+    (hashes[0], mutated)
+}
+
+impl Block {
+    /// Computes the merkle root of the block's txids, which the header must
+    /// commit to
+    pub fn compute_merkle_root(&self) -> Sha256dHash {
+        bitcoin_merkle_root(self.txdata.iter().map(|tx| tx.txid()).collect())
+    }
+
+    /// Gets the block's weight, as defined by BIP141
+    pub fn get_weight(&self) -> u64 {
+        let base = 80 + VarInt(self.txdata.len() as u64).encoded_length();
+        base * WITNESS_SCALE_FACTOR + self.txdata.iter().map(|tx| tx.get_weight()).sum::<u64>()
+    }
+
+    /// Gets the block's size without witness data, which is what its weight
+    /// would be without the witness discount divided by `WITNESS_SCALE_FACTOR`
+    pub fn get_stripped_size(&self) -> u64 {
+        let base = 80 + VarInt(self.txdata.len() as u64).encoded_length();
+        base + self.txdata.iter().map(|tx| tx.get_stripped_size()).sum::<u64>()
+    }
+
+    /// Checks the block against the consensus rules which need no knowledge
+    /// of the chain or of the outputs it spends, as Bitcoin Core's
+    /// `CheckBlock`: the proof of work against the header's own target, the
+    /// merkle root, a single leading coinbase, the weight of the block
+    /// without witness data and the legacy sigop limit, and the sanity of
+    /// each transaction. Whether the target is the one required at the
+    /// block's height is left to `spv_validate`.
+    ///
+    /// Witness data is not committed to by the merkle root, so the full
+    /// weight must only be checked after `check_witness_commitment`, lest a
+    /// valid block padded with extra witness data be rejected.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let target = self.header.target();
+        if target == Default::default() || self.header.bitcoin_hash().into_le() > target {
+            return Err(ValidationError::BadProofOfWork);
+        }
+
+        if self.txdata.is_empty() {
+            return Err(ValidationError::NoTransactions);
+        }
+        let (merkle_root, mutated) = merkle_root_and_mutation(self.txdata.iter().map(|tx| tx.txid()).collect());
+        if merkle_root != self.header.merkle_root {
+            return Err(ValidationError::BadMerkleRoot);
+        }
+        // A mutated block may be a malleated copy of a valid one, so must not
+        // cause that block to be marked invalid
+        if mutated {
+            return Err(ValidationError::MutatedMerkleRoot);
+        }
+
+        let weight = self.get_stripped_size() * WITNESS_SCALE_FACTOR;
+        if weight > MAX_BLOCK_WEIGHT {
+            return Err(ValidationError::Weight(weight));
+        }
+
+        if !self.txdata[0].is_coin_base() {
+            return Err(ValidationError::NoCoinbase);
+        }
+        for (idx, tx) in self.txdata.iter().enumerate().skip(1) {
+            if tx.is_coin_base() {
+                return Err(ValidationError::MultipleCoinbase(idx));
+            }
+        }
+
+        for (idx, tx) in self.txdata.iter().enumerate() {
+            if let Err(e) = tx.check_sanity() {
+                return Err(ValidationError::BadTransaction(idx, e));
+            }
+        }
+
+        let sigops = self.txdata.iter().map(|tx| tx.get_legacy_sigop_count() as u64).sum::<u64>();
+        if sigops * WITNESS_SCALE_FACTOR > MAX_BLOCK_SIGOPS_COST {
+            return Err(ValidationError::Sigops(sigops));
+        }
+        Ok(())
+    }
+
+    /// Checks that the coinbase does not claim more than the subsidy at the
+    /// block's height plus `fees`, the total fees of the block's other
+    /// transactions. Computing those needs the outputs they spend, so is
+    /// left to the caller.
+    pub fn check_coinbase_value(&self, params: &Params, height: u32, fees: Amount) -> Result<(), ValidationError> {
+        let coinbase = match self.txdata.first() {
+            Some(coinbase) => coinbase,
+            None => return Err(ValidationError::NoTransactions),
+        };
+        let max = params.block_subsidy(height).checked_add(fees).unwrap_or(Amount::max_value());
+        // A sum which overflows certainly exceeds the maximum
+        let value = coinbase.output.iter().fold(Some(Amount::zero()), |sum, out| {
+            sum.and_then(|sum| sum.checked_add(out.value))
+        }).unwrap_or(Amount::max_value());
+        if value > max {
+            return Err(ValidationError::BadCoinbaseValue { value: value, max: max });
+        }
+        Ok(())
+    }
+
+    /// Computes the merkle root of the witness txids of the block's
+    /// transactions, with that of the coinbase taken to be zero (BIP141)
+    pub fn witness_root(&self) -> Sha256dHash {
+        let hashes = self.txdata.iter().enumerate().map(|(idx, tx)| {
+            if idx == 0 { Default::default() } else { tx.wtxid() }
+        }).collect();
+        bitcoin_merkle_root(hashes)
+    }
+
+    /// Computes the witness commitment for a block with the given witness
+    /// root, from the witness reserved value in the coinbase input's witness
+    pub fn compute_witness_commitment(witness_root: &Sha256dHash, witness_reserved_value: &[u8]) -> Sha256dHash {
+        let mut data = witness_root[..].to_vec();
+        data.extend_from_slice(witness_reserved_value);
+        Sha256dHash::from_data(&data)
+    }
+
+    /// The witness commitment in the coinbase, if any. If several outputs
+    /// carry one, the last is used.
+    pub fn witness_commitment(&self) -> Option<Sha256dHash> {
+        self.txdata.first().and_then(|coinbase| {
+            coinbase.output.iter().rev().map(|output| &output.script_pubkey[..]).find(|script| {
+                script.len() >= 38 && script[0..6] == WITNESS_COMMITMENT_HEADER
+            })
+        }).map(|script| Sha256dHash::from(&script[6..38]))
+    }
+
+    /// Checks the block's witness commitment. A block without one must not
+    /// contain any witness data; otherwise the coinbase input's witness must
+    /// be a single 32-byte reserved value, which together with the witness
+    /// root must hash to the commitment.
+    pub fn check_witness_commitment(&self) -> bool {
+        let commitment = match self.witness_commitment() {
+            Some(commitment) => commitment,
+            None => return self.txdata.iter().all(|tx| tx.input.iter().all(|input| input.witness.is_empty())),
+        };
+        // A coinbase without inputs is invalid, but does decode
+        let witness = match self.txdata[0].input.first() {
+            Some(input) => &input.witness,
+            None => return false,
+        };
+        if witness.len() != 1 || witness[0].len() != 32 {
+            return false;
+        }
+        Block::compute_witness_commitment(&self.witness_root(), &witness[0]) == commitment
+    }
+}
+
 impl BitcoinHash for BlockHeader {
     fn bitcoin_hash(&self) -> Sha256dHash {
         use consensus::encode::serialize;
@@ -157,12 +337,89 @@ impl_consensus_encoding!(BlockHeader, version, prev_blockhash, merkle_root, time
 impl_consensus_encoding!(Block, header, txdata);
 impl_consensus_encoding!(LoneBlockHeader, header, tx_count);
 
+/// A violation of the block rules checked by `Block::validate`, which need
+/// no context, or by `Block::check_coinbase_value`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ValidationError {
+    /// The block hash is above the header's target, or the target is zero
+    BadProofOfWork,
+    /// The block has no transactions
+    NoTransactions,
+    /// The header's merkle root does not match the transactions
+    BadMerkleRoot,
+    /// The merkle tree contains duplicated transactions (CVE-2012-2459)
+    MutatedMerkleRoot,
+    /// The block's weight without witness data is above `MAX_BLOCK_WEIGHT`
+    Weight(u64),
+    /// The first transaction is not a coinbase
+    NoCoinbase,
+    /// The transaction with the given index is a second coinbase
+    MultipleCoinbase(usize),
+    /// The transaction with the given index fails its sanity checks
+    BadTransaction(usize, SanityError),
+    /// The block's legacy sigop count, scaled by `WITNESS_SCALE_FACTOR`, is
+    /// above `MAX_BLOCK_SIGOPS_COST`
+    Sigops(u64),
+    /// The coinbase claims more than the block subsidy plus fees
+    BadCoinbaseValue {
+        /// The total value of the coinbase outputs
+        value: Amount,
+        /// The block subsidy plus fees
+        max: Amount,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::Weight(w) => write!(f, "block weight {} exceeds maximum", w),
+            ValidationError::MultipleCoinbase(idx) => write!(f, "transaction {} is a second coinbase", idx),
+            ValidationError::BadTransaction(idx, ref e) => write!(f, "transaction {}: {}", idx, e),
+            ValidationError::Sigops(n) => write!(f, "{} legacy sigops exceed maximum", n),
+            ValidationError::BadCoinbaseValue { value, max } =>
+                write!(f, "coinbase value {} exceeds subsidy plus fees {}", value, max),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for ValidationError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ValidationError::BadTransaction(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            ValidationError::BadProofOfWork => "proof of work does not meet target",
+            ValidationError::NoTransactions => "block has no transactions",
+            ValidationError::BadMerkleRoot => "merkle root mismatch",
+            ValidationError::MutatedMerkleRoot => "duplicate transaction in merkle tree",
+            ValidationError::Weight(_) => "block too heavy",
+            ValidationError::NoCoinbase => "first transaction is not coinbase",
+            ValidationError::MultipleCoinbase(_) => "more than one coinbase",
+            ValidationError::BadTransaction(..) => "bad transaction",
+            ValidationError::Sigops(_) => "too many sigops",
+            ValidationError::BadCoinbaseValue { .. } => "coinbase pays too much",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use hex::decode as hex_decode;
 
-    use blockdata::block::{Block, BlockHeader};
+    use blockdata::block::{Block, BlockHeader, ValidationError};
+    use blockdata::constants::{genesis_block, COIN_VALUE};
+    use blockdata::script::Script;
+    use blockdata::transaction::{SanityError, Transaction};
     use consensus::encode::{deserialize, serialize};
+    use consensus::params::Params;
+    use network::constants::Network;
+    use util::amount::Amount;
+    use util::hash::BitcoinHash;
 
     #[test]
     fn block_test() {
@@ -186,6 +443,11 @@ mod tests {
         assert_eq!(real_decode.header.bits, 486604799);
         assert_eq!(real_decode.header.nonce, 2067413810);
         // [test] TODO: check the transaction data
+        assert_eq!(real_decode.witness_commitment(), None);
+        assert!(real_decode.check_witness_commitment());
+        assert_eq!(real_decode.compute_merkle_root(), real_decode.header.merkle_root);
+        assert_eq!(real_decode.validate(), Ok(()));
+        assert_eq!(real_decode.txdata[1].wtxid(), real_decode.txdata[1].txid());
     
         assert_eq!(serialize(&real_decode), some_block);
     }
@@ -211,6 +473,163 @@ mod tests {
         // [test] TODO: check the transaction data
 
         assert_eq!(serialize(&real_decode), segwit_block);
+        assert_eq!(real_decode.compute_merkle_root(), real_decode.header.merkle_root);
+        let mut stripped = real_decode.clone();
+        for input in stripped.txdata.iter_mut().flat_map(|tx| tx.input.iter_mut()) {
+            input.witness.clear();
+        }
+        assert_eq!(real_decode.get_weight(), 3 * serialize(&stripped).len() as u64 + segwit_block.len() as u64);
+        assert_eq!(real_decode.get_stripped_size(), serialize(&stripped).len() as u64);
+        assert_eq!(real_decode.validate(), Ok(()));
+
+        let coinbase = &real_decode.txdata[0];
+        assert_eq!(coinbase.txid().be_hex_string(), "4be105f158ea44aec57bf12c5817d073a712ab131df6f37786872cfc70734188");
+        assert!(coinbase.wtxid() != coinbase.txid());
+        assert_eq!(real_decode.witness_commitment().unwrap().be_hex_string(),
+                   "043798a3b27dcd2bacdd4f8bb1333dd6e7576bee020f988990a2b89eb4461cf9");
+        assert!(real_decode.check_witness_commitment());
+
+        // Changing any witness invalidates the commitment
+        let mut bad = real_decode.clone();
+        bad.txdata[1].input[0].witness = vec![vec![1]];
+        assert!(!bad.check_witness_commitment());
+        let mut bad = real_decode.clone();
+        bad.txdata[0].input[0].witness[0][0] = 1;
+        assert!(!bad.check_witness_commitment());
+        let mut bad = real_decode.clone();
+        bad.txdata[0].input[0].witness.clear();
+        assert!(!bad.check_witness_commitment());
+        // Without a commitment there must be no witness data at all
+        let mut bad = real_decode.clone();
+        bad.txdata[0].output.pop();
+        assert_eq!(bad.witness_commitment(), None);
+        assert!(!bad.check_witness_commitment());
+        bad.txdata[0].input[0].witness.clear();
+        assert!(bad.check_witness_commitment());
+
+        // A first transaction with a commitment but no inputs, which decodes
+        // from the segwit serialization with an empty input list
+        let mut coinbase = hex_decode("01000000000100").unwrap();
+        coinbase.extend(serialize(&real_decode.txdata[0].output));
+        coinbase.extend(serialize(&0u32));
+        let mut bad = real_decode.clone();
+        bad.txdata[0] = deserialize(&coinbase).unwrap();
+        assert!(bad.txdata[0].input.is_empty());
+        assert!(bad.witness_commitment().is_some());
+        assert!(!bad.check_witness_commitment());
+    }
+
+    /// Recomputes the merkle root of a regtest block and grinds its nonce
+    /// until it meets its (very easy) target
+    fn remine(block: &mut Block) {
+        block.header.merkle_root = block.compute_merkle_root();
+        while block.header.bitcoin_hash().into_le() > block.header.target() {
+            block.header.nonce += 1;
+        }
+    }
+
+    #[test]
+    fn validate_test() {
+        let spend: Transaction = deserialize(&hex_decode("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        let mut spend2 = spend.clone();
+        spend2.lock_time = 1;
+
+        let genesis = genesis_block(Network::Regtest);
+        assert_eq!(genesis.validate(), Ok(()));
+
+        let mut bad = genesis_block(Network::Bitcoin);
+        assert_eq!(bad.validate(), Ok(()));
+        bad.header.nonce += 1;
+        assert_eq!(bad.validate(), Err(ValidationError::BadProofOfWork));
+
+        let mut block = genesis.clone();
+        block.txdata.push(spend.clone());
+        block.txdata.push(spend2.clone());
+        remine(&mut block);
+        assert_eq!(block.validate(), Ok(()));
+
+        // Duplicating the last of an odd number of transactions leaves the
+        // merkle root unchanged
+        let mut bad = block.clone();
+        bad.txdata.push(spend2.clone());
+        assert_eq!(bad.compute_merkle_root(), block.header.merkle_root);
+        assert_eq!(bad.validate(), Err(ValidationError::MutatedMerkleRoot));
+        bad.txdata.pop();
+        bad.txdata.pop();
+        assert_eq!(bad.validate(), Err(ValidationError::BadMerkleRoot));
+
+        let mut bad = genesis.clone();
+        bad.txdata.clear();
+        remine(&mut bad);
+        assert_eq!(bad.validate(), Err(ValidationError::NoTransactions));
+
+        let mut bad = genesis.clone();
+        bad.txdata[0] = spend.clone();
+        remine(&mut bad);
+        assert_eq!(bad.validate(), Err(ValidationError::NoCoinbase));
+
+        let mut bad = genesis.clone();
+        let mut coinbase2 = genesis.txdata[0].clone();
+        coinbase2.lock_time = 1;
+        bad.txdata.push(coinbase2);
+        remine(&mut bad);
+        assert_eq!(bad.validate(), Err(ValidationError::MultipleCoinbase(1)));
+
+        let mut bad = genesis.clone();
+        bad.txdata[0].input[0].script_sig = Script::from(vec![0]);
+        remine(&mut bad);
+        assert_eq!(bad.validate(), Err(ValidationError::BadTransaction(0, SanityError::CoinbaseScriptSigSize(1))));
+
+        let mut bad = genesis.clone();
+        bad.txdata[0].output[0].script_pubkey = Script::from(vec![0; 1_000_000]);
+        remine(&mut bad);
+        match bad.validate() {
+            Err(ValidationError::Weight(w)) => assert!(w > 4_000_000),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Witness data is not counted, as the block may have been padded
+        let mut padded = genesis.clone();
+        padded.txdata[0].input[0].witness = vec![vec![0; 4_000_000]];
+        assert_eq!(padded.header.merkle_root, padded.compute_merkle_root());
+        assert!(padded.get_weight() > 4_000_000);
+        assert_eq!(padded.get_stripped_size(), genesis.get_stripped_size());
+        assert_eq!(padded.validate(), Ok(()));
+
+        let mut bad = genesis.clone();
+        bad.txdata[0].output[0].script_pubkey = Script::from(vec![0xac; 20_000]);
+        remine(&mut bad);
+        assert_eq!(bad.validate(), Ok(()));
+        bad.txdata[0].output[0].script_pubkey = Script::from(vec![0xac; 20_001]);
+        remine(&mut bad);
+        assert_eq!(bad.validate(), Err(ValidationError::Sigops(20_001)));
+    }
+
+    #[test]
+    fn coinbase_value_test() {
+        let params = Params::new(Network::Bitcoin);
+        let mut block = genesis_block(Network::Bitcoin);
+        assert_eq!(block.check_coinbase_value(&params, 0, Amount::zero()), Ok(()));
+
+        block.txdata[0].output[0].value = Amount::from_sat(25 * COIN_VALUE + 1000);
+        assert_eq!(block.check_coinbase_value(&params, 210_000, Amount::from_sat(1000)), Ok(()));
+        assert_eq!(block.check_coinbase_value(&params, 210_000, Amount::from_sat(999)),
+                   Err(ValidationError::BadCoinbaseValue {
+                       value: Amount::from_sat(25 * COIN_VALUE + 1000),
+                       max: Amount::from_sat(25 * COIN_VALUE + 999),
+                   }));
+
+        let mut output = block.txdata[0].output[0].clone();
+        output.value = Amount::max_value();
+        block.txdata[0].output.push(output);
+        assert_eq!(block.check_coinbase_value(&params, 0, Amount::zero()),
+                   Err(ValidationError::BadCoinbaseValue {
+                       value: Amount::max_value(),
+                       max: Amount::from_sat(50 * COIN_VALUE),
+                   }));
+
+        block.txdata.clear();
+        assert_eq!(block.check_coinbase_value(&params, 0, Amount::zero()), Err(ValidationError::NoTransactions));
     }
 
     #[test]
@@ -29,6 +29,7 @@ use network::constants::Network;
 use util::misc::hex_bytes;
 use util::hash::MerkleRoot;
 use util::uint::Uint256;
+use util::amount::Amount;
 
 /// The maximum allowable sequence number
 pub static MAX_SEQUENCE: u32 = 0xFFFFFFFF;
@@ -40,6 +41,12 @@ pub static TARGET_BLOCK_SPACING: u32 = 600;
 pub static DIFFCHANGE_INTERVAL: u32 = 2016;
 /// How much time on average should occur between diffchanges
 pub static DIFFCHANGE_TIMESPAN: u32 = 14 * 24 * 3600;
+/// The maximum allowed weight for a block, see BIP 141
+pub static MAX_BLOCK_WEIGHT: u64 = 4_000_000;
+/// The factor by which non-witness data is multiplied when computing weight
+pub static WITNESS_SCALE_FACTOR: u64 = 4;
+/// The maximum allowed signature operation cost for a block, see BIP 141
+pub static MAX_BLOCK_SIGOPS_COST: u64 = 80_000;
 
 /// In Bitcoind this is insanely described as ~((u256)0 >> 32)
 pub fn max_target(_: Network) -> Uint256 {
@@ -49,8 +56,8 @@ pub fn max_target(_: Network) -> Uint256 {
 /// The maximum value allowed in an output (useful for sanity checking,
 /// since keeping everything below this value should prevent overflows
 /// if you are doing anything remotely sane with monetary values).
-pub fn max_money(_: Network) -> u64 {
-    21_000_000 * COIN_VALUE
+pub fn max_money(_: Network) -> Amount {
+    Amount::from_sat(21_000_000 * COIN_VALUE)
 }
 
 /// Constructs and returns the coinbase (and only) transaction of the Bitcoin genesis block
@@ -81,7 +88,7 @@ fn bitcoin_genesis_tx() -> Transaction {
         .push_opcode(opcodes::All::OP_CHECKSIG)
         .into_script();
     ret.output.push(TxOut {
-        value: 50 * COIN_VALUE,
+        value: Amount::from_sat(50 * COIN_VALUE),
         script_pubkey: out_script
     });
 
@@ -163,7 +170,7 @@ mod test {
         assert_eq!(gen.output.len(), 1);
         assert_eq!(serialize(&gen.output[0].script_pubkey),
                    hex_decode("434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac").unwrap());
-        assert_eq!(gen.output[0].value, 50 * COIN_VALUE);
+        assert_eq!(gen.output[0].value.as_sat(), 50 * COIN_VALUE);
         assert_eq!(gen.lock_time, 0);
 
         assert_eq!(gen.bitcoin_hash().be_hex_string(),
@@ -0,0 +1,274 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Lock times
+//!
+//! Interpretation of a transaction's `lock_time` field, which sets the
+//! earliest block height or time at which it may be mined, and of its
+//! inputs' `sequence` fields, which under BIP68 set a minimum age for the
+//! outputs they spend.
+//!
+
+use std::fmt;
+
+/// Lock times below this value are block heights, and those at or above it
+/// are UNIX timestamps
+pub const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// The sequence number of a final input, which opts out of the lock time
+pub const SEQUENCE_FINAL: u32 = 0xFFFFFFFF;
+/// If set in a sequence number, the input has no relative lock time (BIP68)
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// If set in a sequence number, the relative lock time is in units of 512
+/// seconds rather than blocks (BIP68)
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// The bits of a sequence number holding the relative lock time value (BIP68)
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000FFFF;
+/// The base 2 logarithm of the number of seconds in a unit of time-based
+/// relative lock time (BIP68)
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// An absolute lock time, as found in a transaction's `lock_time` field
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum LockTime {
+    /// The transaction may be mined in the block after this height
+    Blocks(u32),
+    /// The transaction may be mined once the median time past of the chain
+    /// exceeds this UNIX timestamp (BIP113)
+    Seconds(u32),
+}
+
+impl LockTime {
+    /// Interprets a raw `lock_time` field
+    pub fn from_u32(n: u32) -> LockTime {
+        if n < LOCK_TIME_THRESHOLD {
+            LockTime::Blocks(n)
+        } else {
+            LockTime::Seconds(n)
+        }
+    }
+
+    /// Creates a height-based lock time, or `None` if `height` is too large
+    /// to be interpreted as one
+    pub fn from_height(height: u32) -> Option<LockTime> {
+        if height < LOCK_TIME_THRESHOLD {
+            Some(LockTime::Blocks(height))
+        } else {
+            None
+        }
+    }
+
+    /// Creates a time-based lock time, or `None` if `time` is too small to
+    /// be interpreted as one
+    pub fn from_time(time: u32) -> Option<LockTime> {
+        if time >= LOCK_TIME_THRESHOLD {
+            Some(LockTime::Seconds(time))
+        } else {
+            None
+        }
+    }
+
+    /// The raw `lock_time` field value
+    pub fn as_u32(&self) -> u32 {
+        match *self {
+            LockTime::Blocks(n) | LockTime::Seconds(n) => n,
+        }
+    }
+
+    /// Whether both lock times are heights or both are timestamps, as
+    /// OP_CHECKLOCKTIMEVERIFY requires
+    pub fn is_same_unit(&self, other: LockTime) -> bool {
+        match (*self, other) {
+            (LockTime::Blocks(_), LockTime::Blocks(_)) | (LockTime::Seconds(_), LockTime::Seconds(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this lock time allows inclusion in a block at `height` whose
+    /// predecessor has median time past `median_time_past`
+    pub fn is_satisfied_by(&self, height: u32, median_time_past: u32) -> bool {
+        match *self {
+            LockTime::Blocks(n) => n < height,
+            LockTime::Seconds(n) => n < median_time_past,
+        }
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockTime::Blocks(n) => write!(f, "height {}", n),
+            LockTime::Seconds(n) => write!(f, "time {}", n),
+        }
+    }
+}
+
+/// A relative lock time, requiring the output spent by an input to have a
+/// certain age (BIP68)
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum RelativeLockTime {
+    /// The spent output must have this many confirmations, less one
+    Blocks(u16),
+    /// The spent output must be this many 512-second intervals old
+    Time(u16),
+}
+
+impl RelativeLockTime {
+    /// Creates a time-based relative lock time of at least `seconds`,
+    /// rounding up to a multiple of 512 seconds, or `None` if it is too long
+    pub fn from_seconds_ceil(seconds: u32) -> Option<RelativeLockTime> {
+        let granularity = 1 << SEQUENCE_LOCKTIME_GRANULARITY;
+        let intervals = seconds / granularity + if seconds % granularity == 0 { 0 } else { 1 };
+        if intervals <= SEQUENCE_LOCKTIME_MASK {
+            Some(RelativeLockTime::Time(intervals as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Whether this lock time allows an input spending an output confirmed
+    /// at `spent_height`, in a block whose predecessor has median time past
+    /// `spent_median_time_past`, to be included in a block at `height` whose
+    /// predecessor has median time past `median_time_past`. This follows
+    /// Bitcoin Core's `CalculateSequenceLocks`.
+    pub fn is_satisfied_by(&self, spent_height: u32, spent_median_time_past: u32,
+                           height: u32, median_time_past: u32) -> bool {
+        match *self {
+            // The lock is to the last height at which the input is invalid
+            RelativeLockTime::Blocks(n) => (spent_height as u64 + n as u64) <= height as u64,
+            RelativeLockTime::Time(n) => {
+                let min_time = spent_median_time_past as u64 + ((n as u64) << SEQUENCE_LOCKTIME_GRANULARITY);
+                min_time <= median_time_past as u64
+            }
+        }
+    }
+}
+
+/// An input sequence number, which encodes whether the input opts in to
+/// the transaction's lock time and its relative lock time
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct Sequence(pub u32);
+
+impl Sequence {
+    /// Interprets a raw `sequence` field
+    pub fn from_u32(n: u32) -> Sequence {
+        Sequence(n)
+    }
+
+    /// Creates a sequence number enforcing the given relative lock time
+    pub fn from_relative_lock_time(lock_time: RelativeLockTime) -> Sequence {
+        match lock_time {
+            RelativeLockTime::Blocks(n) => Sequence(n as u32),
+            RelativeLockTime::Time(n) => Sequence(SEQUENCE_LOCKTIME_TYPE_FLAG | n as u32),
+        }
+    }
+
+    /// The raw `sequence` field value
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+
+    /// Whether the input is final, disabling the transaction's lock time
+    /// unless another input is not final
+    pub fn is_final(&self) -> bool {
+        self.0 == SEQUENCE_FINAL
+    }
+
+    /// Whether the input signals that its transaction may be replaced by
+    /// one paying a higher fee (BIP125)
+    pub fn signals_rbf(&self) -> bool {
+        self.0 < SEQUENCE_FINAL - 1
+    }
+
+    /// The relative lock time this sequence number encodes, or `None` if the
+    /// disable flag is set. Relative lock times only apply to transactions
+    /// of version 2 or above.
+    pub fn relative_lock_time(&self) -> Option<RelativeLockTime> {
+        if self.0 & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            None
+        } else if self.0 & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            Some(RelativeLockTime::Time((self.0 & SEQUENCE_LOCKTIME_MASK) as u16))
+        } else {
+            Some(RelativeLockTime::Blocks((self.0 & SEQUENCE_LOCKTIME_MASK) as u16))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_time() {
+        assert_eq!(LockTime::from_u32(0), LockTime::Blocks(0));
+        assert_eq!(LockTime::from_u32(499_999_999), LockTime::Blocks(499_999_999));
+        assert_eq!(LockTime::from_u32(500_000_000), LockTime::Seconds(500_000_000));
+        assert_eq!(LockTime::from_u32(1_234_567_890).as_u32(), 1_234_567_890);
+        assert_eq!(LockTime::from_height(500_000_000), None);
+        assert_eq!(LockTime::from_time(499_999_999), None);
+        assert_eq!(LockTime::from_time(500_000_000), Some(LockTime::Seconds(500_000_000)));
+
+        assert!(LockTime::Blocks(1).is_same_unit(LockTime::Blocks(100)));
+        assert!(!LockTime::Blocks(1).is_same_unit(LockTime::Seconds(600_000_000)));
+
+        assert!(!LockTime::Blocks(100).is_satisfied_by(100, 0));
+        assert!(LockTime::Blocks(100).is_satisfied_by(101, 0));
+        assert!(!LockTime::Seconds(600_000_000).is_satisfied_by(1_000_000, 600_000_000));
+        assert!(LockTime::Seconds(600_000_000).is_satisfied_by(0, 600_000_001));
+
+        assert_eq!(format!("{}", LockTime::Blocks(5)), "height 5");
+    }
+
+    #[test]
+    fn sequence() {
+        assert!(Sequence(SEQUENCE_FINAL).is_final());
+        assert!(!Sequence(0xfffffffe).is_final());
+        assert!(!Sequence(SEQUENCE_FINAL).signals_rbf());
+        assert!(!Sequence(0xfffffffe).signals_rbf());
+        assert!(Sequence(0xfffffffd).signals_rbf());
+        assert!(Sequence(0).signals_rbf());
+        assert_eq!(Sequence(SEQUENCE_FINAL).relative_lock_time(), None);
+        assert_eq!(Sequence(0x80000010).relative_lock_time(), None);
+        assert_eq!(Sequence(0x10).relative_lock_time(), Some(RelativeLockTime::Blocks(16)));
+        // Bits outside the flags and value are ignored
+        assert_eq!(Sequence(0x0041_0010).relative_lock_time(), Some(RelativeLockTime::Time(16)));
+        assert_eq!(Sequence(0x0001_0010).relative_lock_time(), Some(RelativeLockTime::Blocks(16)));
+
+        for &lock_time in [RelativeLockTime::Blocks(0), RelativeLockTime::Blocks(65535),
+                           RelativeLockTime::Time(1), RelativeLockTime::Time(65535)].iter() {
+            assert_eq!(Sequence::from_relative_lock_time(lock_time).relative_lock_time(), Some(lock_time));
+        }
+        assert_eq!(Sequence::from_relative_lock_time(RelativeLockTime::Time(1)).as_u32(), 0x0040_0001);
+    }
+
+    #[test]
+    fn relative_lock_time() {
+        assert_eq!(RelativeLockTime::from_seconds_ceil(0), Some(RelativeLockTime::Time(0)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(512), Some(RelativeLockTime::Time(1)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(513), Some(RelativeLockTime::Time(2)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(65535 * 512), Some(RelativeLockTime::Time(65535)));
+        assert_eq!(RelativeLockTime::from_seconds_ceil(65535 * 512 + 1), None);
+
+        // An output confirmed at height 100 with a 10 block lock can be
+        // spent in block 110, its 11th confirmation
+        assert!(!RelativeLockTime::Blocks(10).is_satisfied_by(100, 0, 109, 0));
+        assert!(RelativeLockTime::Blocks(10).is_satisfied_by(100, 0, 110, 0));
+        // A zero lock allows spending in the same block
+        assert!(RelativeLockTime::Blocks(0).is_satisfied_by(100, 0, 100, 0));
+
+        let mtp = 1_500_000_000;
+        assert!(!RelativeLockTime::Time(2).is_satisfied_by(100, mtp, 200, mtp + 1023));
+        assert!(RelativeLockTime::Time(2).is_satisfied_by(100, mtp, 200, mtp + 1024));
+    }
+}
@@ -19,6 +19,7 @@
 //!
 
 pub mod constants;
+pub mod locktime;
 pub mod opcodes;
 pub mod script;
 pub mod transaction;
@@ -0,0 +1,1786 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Script interpreter
+//!
+//! A native implementation of Bitcoin's script execution engine, following
+//! the behaviour of Bitcoin Core's `EvalScript` and `VerifyScript`. Which
+//! soft-forked rules are enforced is controlled by the `VERIFY_*` flags;
+//! everything touching the spending transaction (signatures and lock times)
+//! goes through the `SignatureChecker` trait.
+//!
+
+use std::cell::RefCell;
+use std::mem;
+
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use secp256k1::{self, Secp256k1, Message, Signature};
+use secp256k1::key::PublicKey;
+
+use blockdata::locktime;
+use blockdata::opcodes;
+use blockdata::script::{Builder, Error, Script, build_scriptint, read_scriptbool};
+use blockdata::transaction::Transaction;
+use util::bip143::SigHashCache;
+use util::amount::Amount;
+use util::hash::{Hash160, Ripemd160Hash, Sha256dHash};
+
+#[cfg(feature="fuzztarget")]      use util::sha2::Sha256;
+#[cfg(not(feature="fuzztarget"))] use crypto::sha2::Sha256;
+
+/// Evaluate P2SH subscripts (BIP16)
+pub const VERIFY_P2SH: u32 = 1 << 0;
+/// Require signatures and public keys to use strict encodings, and signatures
+/// to have a defined sighash type
+pub const VERIFY_STRICTENC: u32 = 1 << 1;
+/// Require strict DER encoding of signatures (BIP66)
+pub const VERIFY_DERSIG: u32 = 1 << 2;
+/// Require the S value of signatures to be in the lower half of the curve order
+pub const VERIFY_LOW_S: u32 = 1 << 3;
+/// Require the extra stack element consumed by CHECKMULTISIG to be empty (BIP147)
+pub const VERIFY_NULLDUMMY: u32 = 1 << 4;
+/// Require the scriptSig to contain only push operations
+pub const VERIFY_SIGPUSHONLY: u32 = 1 << 5;
+/// Require data pushes and numbers to be minimally encoded
+pub const VERIFY_MINIMALDATA: u32 = 1 << 6;
+/// Fail on execution of the NOPs reserved for soft-fork upgrades
+pub const VERIFY_DISCOURAGE_UPGRADABLE_NOPS: u32 = 1 << 7;
+/// Require exactly one element to be left on the stack after evaluation
+pub const VERIFY_CLEANSTACK: u32 = 1 << 8;
+/// Enable OP_CHECKLOCKTIMEVERIFY (BIP65)
+pub const VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+/// Enable OP_CHECKSEQUENCEVERIFY (BIP112)
+pub const VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+/// Evaluate segregated witness programs (BIP141)
+pub const VERIFY_WITNESS: u32 = 1 << 11;
+/// Fail on witness programs with a version reserved for soft-fork upgrades
+pub const VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: u32 = 1 << 12;
+/// Require the argument of OP_IF/OP_NOTIF in segwit scripts to be empty or `0x01`
+pub const VERIFY_MINIMALIF: u32 = 1 << 13;
+/// Require signatures to be empty when a signature check fails (BIP146)
+pub const VERIFY_NULLFAIL: u32 = 1 << 14;
+/// Require public keys in segwit scripts to be compressed
+pub const VERIFY_WITNESS_PUBKEYTYPE: u32 = 1 << 15;
+/// Fail on OP_CODESEPARATOR and on signatures found in the script code of
+/// non-segwit scripts
+pub const VERIFY_CONST_SCRIPTCODE: u32 = 1 << 16;
+
+/// The flags enforced by consensus on blocks since the segwit soft fork
+pub const VERIFY_CONSENSUS: u32 = VERIFY_P2SH | VERIFY_DERSIG | VERIFY_NULLDUMMY |
+                                  VERIFY_CHECKLOCKTIMEVERIFY | VERIFY_CHECKSEQUENCEVERIFY |
+                                  VERIFY_WITNESS;
+
+/// Maximum number of bytes pushable to the stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum number of non-push operations per script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum number of public keys per multisig
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+/// Maximum script length in bytes
+pub const MAX_SCRIPT_SIZE: usize = 10000;
+/// Maximum number of values on the main and alt stacks combined
+pub const MAX_STACK_SIZE: usize = 1000;
+
+const LOCKTIME_THRESHOLD: i64 = locktime::LOCK_TIME_THRESHOLD as i64;
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = locktime::SEQUENCE_LOCKTIME_DISABLE_FLAG as i64;
+const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = locktime::SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+const SEQUENCE_LOCKTIME_MASK: i64 = locktime::SEQUENCE_LOCKTIME_MASK as i64;
+
+/// Half the order of the secp256k1 curve; signatures with a larger S value are "high S"
+static HALF_CURVE_ORDER: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d,
+    0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+
+/// The signature hashing scheme under which a script is being executed
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SigVersion {
+    /// Legacy scripts (scriptSig, scriptPubKey and P2SH redeem scripts)
+    Base,
+    /// Version 0 witness scripts, which sign using BIP143
+    WitnessV0,
+}
+
+/// Provides the interpreter with access to the spending transaction, which
+/// is needed by the signature and lock time opcodes. The default methods
+/// fail every check, which is the right thing for evaluating a script
+/// outside of any transaction.
+pub trait SignatureChecker {
+    /// Checks a signature, including its trailing sighash type byte, against
+    /// a serialized public key. `script_code` is the part of the script being
+    /// executed which follows the last executed OP_CODESEPARATOR.
+    fn check_sig(&self, _sig: &[u8], _pubkey: &[u8], _script_code: &Script, _sigversion: SigVersion) -> bool {
+        false
+    }
+
+    /// Checks that the transaction lock time satisfies an OP_CHECKLOCKTIMEVERIFY argument
+    fn check_lock_time(&self, _lock_time: i64) -> bool {
+        false
+    }
+
+    /// Checks that the input sequence number satisfies an OP_CHECKSEQUENCEVERIFY argument
+    fn check_sequence(&self, _sequence: i64) -> bool {
+        false
+    }
+}
+
+/// A `SignatureChecker` with no transaction, for which every signature and
+/// lock time check fails
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct BaseSignatureChecker;
+
+impl SignatureChecker for BaseSignatureChecker {}
+
+/// A `SignatureChecker` which checks signatures and lock times against a
+/// given input of a transaction
+pub struct TransactionSignatureChecker<'a, C: 'a> {
+    secp: &'a Secp256k1<C>,
+    tx: &'a Transaction,
+    input_index: usize,
+    amount: Amount,
+    cache: RefCell<SigHashCache<'a>>,
+}
+
+impl<'a, C: secp256k1::Verification> TransactionSignatureChecker<'a, C> {
+    /// Creates a checker for input `input_index` of `tx`, which spends an
+    /// output worth `amount`. The amount is only used by segwit
+    /// signature hashes.
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to the number of inputs.
+    pub fn new(secp: &'a Secp256k1<C>, tx: &'a Transaction, input_index: usize, amount: Amount) -> TransactionSignatureChecker<'a, C> {
+        assert!(input_index < tx.input.len());  // Panic on OOB
+        TransactionSignatureChecker {
+            secp: secp,
+            tx: tx,
+            input_index: input_index,
+            amount: amount,
+            cache: RefCell::new(SigHashCache::new(tx)),
+        }
+    }
+}
+
+impl<'a, C: secp256k1::Verification> SignatureChecker for TransactionSignatureChecker<'a, C> {
+    fn check_sig(&self, sig: &[u8], pubkey: &[u8], script_code: &Script, sigversion: SigVersion) -> bool {
+        let pubkey = match PublicKey::from_slice(self.secp, pubkey) {
+            Ok(pk) => pk,
+            Err(_) => return false,
+        };
+        if sig.is_empty() {
+            return false;
+        }
+        let (sighash_byte, sig) = sig.split_last().unwrap();
+
+        let sighash = match sigversion {
+            SigVersion::Base => {
+                let script_code = remove_codeseparators(script_code);
+                self.tx.signature_hash(self.input_index, &script_code, *sighash_byte as u32)
+            }
+            SigVersion::WitnessV0 => {
+                self.cache.borrow_mut().signature_hash(self.input_index, script_code, self.amount, *sighash_byte as u32)
+            }
+        };
+
+        let mut sig = match Signature::from_der_lax(self.secp, sig) {
+            Ok(sig) => sig,
+            Err(_) => return false,
+        };
+        // libsecp256k1 only accepts low-S signatures; whether high-S ones are
+        // acceptable is a policy question answered by VERIFY_LOW_S
+        sig.normalize_s(self.secp);
+        let msg = Message::from_slice(&sighash[..]).unwrap();
+        self.secp.verify(&msg, &sig, &pubkey).is_ok()
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.tx.lock_time as i64;
+        // Both lock times must be of the same type, heights or timestamps
+        if (tx_lock_time < LOCKTIME_THRESHOLD) != (lock_time < LOCKTIME_THRESHOLD) {
+            return false;
+        }
+        if lock_time > tx_lock_time {
+            return false;
+        }
+        // A final input disables the transaction lock time, and with it the
+        // guarantee that the lock time has been reached
+        !self.tx.input[self.input_index].get_sequence().is_final()
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.tx.input[self.input_index].sequence as i64;
+        // Relative lock times are only defined from version 2 (BIP68)
+        if self.tx.version < 2 {
+            return false;
+        }
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return false;
+        }
+        let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let tx_sequence = tx_sequence & mask;
+        let sequence = sequence & mask;
+        if (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+            return false;
+        }
+        sequence <= tx_sequence
+    }
+}
+
+/// Reads the opcode at `*pc`, along with its data if it is a push, and moves
+/// `*pc` past it
+fn read_op<'a>(script: &'a [u8], pc: &mut usize) -> Result<(opcodes::All, Option<&'a [u8]>), Error> {
+    let op = opcodes::All::from(script[*pc]);
+    *pc += 1;
+
+    let len = match op.classify() {
+        opcodes::Class::PushBytes(n) => n as usize,
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA1) => {
+            let n = super::read_uint(&script[*pc..], 1)?;
+            *pc += 1;
+            n
+        }
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA2) => {
+            let n = super::read_uint(&script[*pc..], 2)?;
+            *pc += 2;
+            n
+        }
+        opcodes::Class::Ordinary(opcodes::Ordinary::OP_PUSHDATA4) => {
+            let n = super::read_uint(&script[*pc..], 4)?;
+            *pc += 4;
+            n
+        }
+        _ => return Ok((op, None)),
+    };
+    if script.len() - *pc < len {
+        return Err(Error::EarlyEndOfScript);
+    }
+    let data = &script[*pc..*pc + len];
+    *pc += len;
+    Ok((op, Some(data)))
+}
+
+/// Whether a push uses the shortest possible encoding for its data
+fn is_minimal_push(op: opcodes::All, data: &[u8]) -> bool {
+    let op = op as u8;
+    if data.is_empty() {
+        // Could have used OP_0
+        op == opcodes::All::OP_PUSHBYTES_0 as u8
+    } else if data.len() == 1 && data[0] >= 1 && data[0] <= 16 {
+        // Could have used OP_1 .. OP_16
+        false
+    } else if data.len() == 1 && data[0] == 0x81 {
+        // Could have used OP_1NEGATE
+        false
+    } else if data.len() <= 75 {
+        // Could have used a direct push
+        op as usize == data.len()
+    } else if data.len() <= 255 {
+        // Could have used OP_PUSHDATA1
+        op == opcodes::Ordinary::OP_PUSHDATA1 as u8
+    } else if data.len() <= 65535 {
+        // Could have used OP_PUSHDATA2
+        op == opcodes::Ordinary::OP_PUSHDATA2 as u8
+    } else {
+        true
+    }
+}
+
+/// Decode a script number of at most `max_len` bytes, optionally requiring
+/// that it be minimally encoded. Unlike `read_scriptint` this supports the
+/// five byte arguments of the lock time opcodes.
+fn read_scriptnum(v: &[u8], require_minimal: bool, max_len: usize) -> Result<i64, Error> {
+    if v.len() > max_len {
+        return Err(Error::NumericOverflow);
+    }
+    if v.is_empty() {
+        return Ok(0);
+    }
+    let last = v[v.len() - 1];
+    // The most significant byte may only be zero (or just the sign bit) if
+    // it is needed to hold the sign of the byte below it
+    if require_minimal && last & 0x7f == 0 && (v.len() == 1 || v[v.len() - 2] & 0x80 == 0) {
+        return Err(Error::NonMinimalNumber);
+    }
+    let mut ret = 0i64;
+    for (i, byte) in v.iter().enumerate() {
+        ret |= (*byte as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        ret &= !(0x80i64 << (8 * (v.len() - 1)));
+        ret = -ret;
+    }
+    Ok(ret)
+}
+
+/// Encode a boolean the way script opcodes do
+fn script_bool(b: bool) -> Vec<u8> {
+    if b { vec![1] } else { vec![] }
+}
+
+/// Returns `script` with every occurrence of `pattern` which starts on an
+/// opcode boundary removed, along with whether anything was removed
+fn find_and_delete(script: &[u8], pattern: &[u8]) -> (Vec<u8>, bool) {
+    if pattern.is_empty() {
+        return (script.to_vec(), false);
+    }
+    let mut ret = Vec::with_capacity(script.len());
+    let mut found = false;
+    let mut pc = 0;
+    let mut copied_to = 0;
+    loop {
+        ret.extend_from_slice(&script[copied_to..pc]);
+        while script.len() - pc >= pattern.len() && &script[pc..pc + pattern.len()] == pattern {
+            pc += pattern.len();
+            found = true;
+        }
+        copied_to = pc;
+        if pc >= script.len() || read_op(script, &mut pc).is_err() {
+            break;
+        }
+    }
+    ret.extend_from_slice(&script[copied_to..]);
+    (ret, found)
+}
+
+/// Strip OP_CODESEPARATORs from a script, as legacy signature hashes require
+fn remove_codeseparators(script: &Script) -> Script {
+    let bytes = script.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut pc = 0;
+    while pc < bytes.len() {
+        let start = pc;
+        match read_op(bytes, &mut pc) {
+            Ok((opcodes::All::OP_CODESEPARATOR, _)) => {}
+            Ok(_) => ret.extend_from_slice(&bytes[start..pc]),
+            Err(_) => {
+                ret.extend_from_slice(&bytes[start..]);
+                break;
+            }
+        }
+    }
+    Script::from(ret)
+}
+
+/// Check a signature is strictly DER encoded, followed by a sighash byte, as
+/// required by BIP66
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // Format: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    // A signature is of type 0x30 (compound), and the length covers the entire signature
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    // Make sure the length of the S element is still inside the signature
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    // Verify that the length of the signature matches the sum of the length of the elements
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    // R must be a positive integer, not zero-length and without unnecessary padding
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    // Same for S
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+
+/// Check the S value of a validly encoded signature is at most half the curve order
+fn is_low_s(sig: &[u8]) -> bool {
+    let len_r = sig[3] as usize;
+    let len_s = sig[5 + len_r] as usize;
+    let mut s = &sig[6 + len_r..6 + len_r + len_s];
+    while !s.is_empty() && s[0] == 0 {
+        s = &s[1..];
+    }
+    if s.len() > 32 {
+        return false;
+    }
+    let mut padded = [0; 32];
+    padded[32 - s.len()..].copy_from_slice(s);
+    padded <= HALF_CURVE_ORDER
+}
+
+fn check_signature_encoding(sig: &[u8], flags: u32) -> Result<(), Error> {
+    // An empty signature is a compact way to provide an invalid one
+    if sig.is_empty() {
+        return Ok(());
+    }
+    if flags & (VERIFY_DERSIG | VERIFY_LOW_S | VERIFY_STRICTENC) != 0 && !is_valid_signature_encoding(sig) {
+        return Err(Error::SigDer);
+    }
+    if flags & VERIFY_LOW_S != 0 && !is_low_s(sig) {
+        return Err(Error::SigHighS);
+    }
+    if flags & VERIFY_STRICTENC != 0 {
+        let sighash = sig[sig.len() - 1] & !0x80;
+        if sighash < 1 || sighash > 3 {
+            return Err(Error::SigHashType);
+        }
+    }
+    Ok(())
+}
+
+fn check_pubkey_encoding(pubkey: &[u8], flags: u32, sigversion: SigVersion) -> Result<(), Error> {
+    let compressed = pubkey.len() == 33 && (pubkey[0] == 0x02 || pubkey[0] == 0x03);
+    let uncompressed = pubkey.len() == 65 && pubkey[0] == 0x04;
+    if flags & VERIFY_STRICTENC != 0 && !compressed && !uncompressed {
+        return Err(Error::PubkeyType);
+    }
+    if flags & VERIFY_WITNESS_PUBKEYTYPE != 0 && sigversion == SigVersion::WitnessV0 && !compressed {
+        return Err(Error::WitnessPubkeyType);
+    }
+    Ok(())
+}
+
+/// Remove the signatures in `sigs` from a legacy script code, which is how
+/// pre-segwit signature hashes avoid committing to themselves
+fn remove_signatures(script_code: Script, sigs: &[&[u8]], flags: u32) -> Result<Script, Error> {
+    let mut script_code = script_code.into_bytes();
+    for sig in sigs {
+        let pattern = Builder::new().push_slice(sig).into_script();
+        let (deleted, found) = find_and_delete(&script_code, pattern.as_bytes());
+        if found && flags & VERIFY_CONST_SCRIPTCODE != 0 {
+            return Err(Error::SigFindAndDelete);
+        }
+        script_code = deleted;
+    }
+    Ok(Script::from(script_code))
+}
+
+/// Returns the element `depth` positions from the top of the stack, where 1 is the top
+fn stack_top(stack: &[Vec<u8>], depth: usize) -> Result<&Vec<u8>, Error> {
+    if depth == 0 || stack.len() < depth {
+        Err(Error::InvalidStackOperation)
+    } else {
+        Ok(&stack[stack.len() - depth])
+    }
+}
+
+/// Fail unless the stack has at least `n` elements
+fn require_stack(stack: &[Vec<u8>], n: usize) -> Result<(), Error> {
+    if stack.len() < n {
+        Err(Error::InvalidStackOperation)
+    } else {
+        Ok(())
+    }
+}
+
+/// Pop the top element of the stack
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, Error> {
+    stack.pop().ok_or(Error::InvalidStackOperation)
+}
+
+/// Pop the top element of the stack as a four byte script number
+fn pop_num(stack: &mut Vec<Vec<u8>>, flags: u32) -> Result<i64, Error> {
+    let v = pop(stack)?;
+    read_scriptnum(&v, flags & VERIFY_MINIMALDATA != 0, 4)
+}
+
+/// Execute `script`, operating on `stack`. This does not check whether the
+/// script succeeded, i.e. left a true value on the stack; see `verify_script`
+/// for full verification of a spend.
+pub fn eval_script(stack: &mut Vec<Vec<u8>>, script: &Script, flags: u32,
+                   checker: &SignatureChecker, sigversion: SigVersion) -> Result<(), Error> {
+    use blockdata::opcodes::Ordinary::*;
+
+    let bytes = script.as_bytes();
+    if bytes.len() > MAX_SCRIPT_SIZE {
+        return Err(Error::ScriptSize);
+    }
+    let require_minimal = flags & VERIFY_MINIMALDATA != 0;
+
+    let mut pc = 0;
+    let mut begin_code_hash = 0;
+    let mut op_count = 0;
+    let mut exec_stack: Vec<bool> = vec![];
+    let mut altstack: Vec<Vec<u8>> = vec![];
+
+    while pc < bytes.len() {
+        let executing = !exec_stack.contains(&false);
+
+        let (op, push) = read_op(bytes, &mut pc)?;
+        if let Some(data) = push {
+            if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                return Err(Error::PushSize);
+            }
+        }
+
+        // Note that OP_RESERVED does not count towards the opcode limit
+        if op as u8 > opcodes::All::OP_PUSHNUM_16 as u8 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(Error::OpCount);
+            }
+        }
+
+        // Disabled opcodes fail the script even in unexecuted branches
+        match op {
+            opcodes::All::OP_CAT | opcodes::All::OP_SUBSTR | opcodes::All::OP_LEFT |
+            opcodes::All::OP_RIGHT | opcodes::All::OP_INVERT | opcodes::All::OP_AND |
+            opcodes::All::OP_OR | opcodes::All::OP_XOR | opcodes::All::OP_2MUL |
+            opcodes::All::OP_2DIV | opcodes::All::OP_MUL | opcodes::All::OP_DIV |
+            opcodes::All::OP_MOD | opcodes::All::OP_LSHIFT | opcodes::All::OP_RSHIFT => {
+                return Err(Error::DisabledOpcode(op));
+            }
+            _ => {}
+        }
+
+        if op == opcodes::All::OP_CODESEPARATOR && sigversion == SigVersion::Base &&
+           flags & VERIFY_CONST_SCRIPTCODE != 0 {
+            return Err(Error::OpCodeSeparator);
+        }
+
+        if let Some(data) = push {
+            if executing {
+                if require_minimal && !is_minimal_push(op, data) {
+                    return Err(Error::NonMinimalPush);
+                }
+                stack.push(data.to_vec());
+            }
+        } else if executing ||
+                  (op as u8 >= opcodes::All::OP_IF as u8 && op as u8 <= opcodes::All::OP_ENDIF as u8) {
+            match op.classify() {
+                opcodes::Class::PushNum(n) => stack.push(build_scriptint(n as i64)),
+                opcodes::Class::ReturnOp if op == opcodes::All::OP_RETURN => return Err(Error::OpReturn),
+                opcodes::Class::ReturnOp | opcodes::Class::IllegalOp => return Err(Error::BadOpcode(op)),
+                opcodes::Class::NoOp if op == opcodes::OP_CLTV => {
+                    if flags & VERIFY_CHECKLOCKTIMEVERIFY == 0 {
+                        // Treat as a NOP2 if not enabled
+                        if flags & VERIFY_DISCOURAGE_UPGRADABLE_NOPS != 0 {
+                            return Err(Error::DiscourageUpgradableNops);
+                        }
+                    } else {
+                        // Lock times are compared against the 32-bit nLockTime, so
+                        // the argument is allowed to be five bytes long
+                        let lock_time = read_scriptnum(stack_top(stack, 1)?, require_minimal, 5)?;
+                        if lock_time < 0 {
+                            return Err(Error::NegativeLockTime);
+                        }
+                        if !checker.check_lock_time(lock_time) {
+                            return Err(Error::UnsatisfiedLockTime);
+                        }
+                    }
+                }
+                opcodes::Class::NoOp if op == opcodes::OP_CSV => {
+                    if flags & VERIFY_CHECKSEQUENCEVERIFY == 0 {
+                        // Treat as a NOP3 if not enabled
+                        if flags & VERIFY_DISCOURAGE_UPGRADABLE_NOPS != 0 {
+                            return Err(Error::DiscourageUpgradableNops);
+                        }
+                    } else {
+                        let sequence = read_scriptnum(stack_top(stack, 1)?, require_minimal, 5)?;
+                        if sequence < 0 {
+                            return Err(Error::NegativeLockTime);
+                        }
+                        // With the disable flag set the argument is a NOP, leaving
+                        // room for future soft forks
+                        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0 && !checker.check_sequence(sequence) {
+                            return Err(Error::UnsatisfiedLockTime);
+                        }
+                    }
+                }
+                opcodes::Class::NoOp => {
+                    if op != opcodes::All::OP_NOP && flags & VERIFY_DISCOURAGE_UPGRADABLE_NOPS != 0 {
+                        return Err(Error::DiscourageUpgradableNops);
+                    }
+                }
+                opcodes::Class::PushBytes(_) => unreachable!(),
+                opcodes::Class::Ordinary(op) => match op {
+                    // Pushes are dealt with by the caller
+                    OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => unreachable!(),
+
+                    // Control flow
+                    OP_IF | OP_NOTIF => {
+                        let mut value = false;
+                        if executing {
+                            {
+                                let top = stack_top(stack, 1).map_err(|_| Error::UnbalancedConditional)?;
+                                if sigversion == SigVersion::WitnessV0 && flags & VERIFY_MINIMALIF != 0 &&
+                                   (top.len() > 1 || (top.len() == 1 && top[0] != 1)) {
+                                    return Err(Error::MinimalIf);
+                                }
+                                value = read_scriptbool(top);
+                            }
+                            if op == OP_NOTIF {
+                                value = !value;
+                            }
+                            stack.pop();
+                        }
+                        exec_stack.push(value);
+                    }
+                    OP_ELSE => {
+                        match exec_stack.last_mut() {
+                            Some(value) => *value = !*value,
+                            None => return Err(Error::UnbalancedConditional),
+                        }
+                    }
+                    OP_ENDIF => {
+                        if exec_stack.pop().is_none() {
+                            return Err(Error::UnbalancedConditional);
+                        }
+                    }
+                    OP_VERIFY => {
+                        if !read_scriptbool(stack_top(stack, 1)?) {
+                            return Err(Error::Verify);
+                        }
+                        stack.pop();
+                    }
+
+                    // Stack operations
+                    OP_TOALTSTACK => altstack.push(pop(stack)?),
+                    OP_FROMALTSTACK => stack.push(altstack.pop().ok_or(Error::InvalidAltstackOperation)?),
+                    OP_2DROP => {
+                        require_stack(stack, 2)?;
+                        stack.pop();
+                        stack.pop();
+                    }
+                    OP_2DUP => {
+                        require_stack(stack, 2)?;
+                        let len = stack.len();
+                        let items = stack[len - 2..].to_vec();
+                        stack.extend(items);
+                    }
+                    OP_3DUP => {
+                        require_stack(stack, 3)?;
+                        let len = stack.len();
+                        let items = stack[len - 3..].to_vec();
+                        stack.extend(items);
+                    }
+                    OP_2OVER => {
+                        require_stack(stack, 4)?;
+                        let len = stack.len();
+                        let items = stack[len - 4..len - 2].to_vec();
+                        stack.extend(items);
+                    }
+                    OP_2ROT => {
+                        require_stack(stack, 6)?;
+                        let len = stack.len();
+                        let first = stack.remove(len - 6);
+                        let second = stack.remove(len - 6);
+                        stack.push(first);
+                        stack.push(second);
+                    }
+                    OP_2SWAP => {
+                        require_stack(stack, 4)?;
+                        let len = stack.len();
+                        stack.swap(len - 4, len - 2);
+                        stack.swap(len - 3, len - 1);
+                    }
+                    OP_IFDUP => {
+                        let top = stack_top(stack, 1)?.clone();
+                        if read_scriptbool(&top) {
+                            stack.push(top);
+                        }
+                    }
+                    OP_DEPTH => {
+                        let depth = build_scriptint(stack.len() as i64);
+                        stack.push(depth);
+                    }
+                    OP_DROP => {
+                        pop(stack)?;
+                    }
+                    OP_DUP => {
+                        let top = stack_top(stack, 1)?.clone();
+                        stack.push(top);
+                    }
+                    OP_NIP => {
+                        require_stack(stack, 2)?;
+                        let len = stack.len();
+                        stack.remove(len - 2);
+                    }
+                    OP_OVER => {
+                        let item = stack_top(stack, 2)?.clone();
+                        stack.push(item);
+                    }
+                    OP_PICK | OP_ROLL => {
+                        require_stack(stack, 2)?;
+                        let n = pop_num(stack, flags)?;
+                        if n < 0 || n as usize >= stack.len() {
+                            return Err(Error::InvalidStackOperation);
+                        }
+                        let index = stack.len() - 1 - n as usize;
+                        let item = if op == OP_ROLL {
+                            stack.remove(index)
+                        } else {
+                            stack[index].clone()
+                        };
+                        stack.push(item);
+                    }
+                    OP_ROT => {
+                        require_stack(stack, 3)?;
+                        let len = stack.len();
+                        let item = stack.remove(len - 3);
+                        stack.push(item);
+                    }
+                    OP_SWAP => {
+                        require_stack(stack, 2)?;
+                        let len = stack.len();
+                        stack.swap(len - 2, len - 1);
+                    }
+                    OP_TUCK => {
+                        require_stack(stack, 2)?;
+                        let len = stack.len();
+                        let top = stack[len - 1].clone();
+                        stack.insert(len - 2, top);
+                    }
+                    OP_SIZE => {
+                        let size = build_scriptint(stack_top(stack, 1)?.len() as i64);
+                        stack.push(size);
+                    }
+
+                    // Equality
+                    OP_EQUAL | OP_EQUALVERIFY => {
+                        require_stack(stack, 2)?;
+                        let equal = stack.pop() == stack.pop();
+                        if op == OP_EQUALVERIFY {
+                            if !equal {
+                                return Err(Error::EqualVerify);
+                            }
+                        } else {
+                            stack.push(script_bool(equal));
+                        }
+                    }
+
+                    // Unary arithmetic
+                    OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                        require_stack(stack, 1)?;
+                        let n = pop_num(stack, flags)?;
+                        let result = match op {
+                            OP_1ADD => n + 1,
+                            OP_1SUB => n - 1,
+                            OP_NEGATE => -n,
+                            OP_ABS => n.abs(),
+                            OP_NOT => (n == 0) as i64,
+                            OP_0NOTEQUAL => (n != 0) as i64,
+                            _ => unreachable!(),
+                        };
+                        stack.push(build_scriptint(result));
+                    }
+
+                    // Binary arithmetic
+                    OP_ADD | OP_SUB | OP_BOOLAND | OP_BOOLOR | OP_NUMEQUAL | OP_NUMEQUALVERIFY |
+                    OP_NUMNOTEQUAL | OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL |
+                    OP_GREATERTHANOREQUAL | OP_MIN | OP_MAX => {
+                        require_stack(stack, 2)?;
+                        let b = pop_num(stack, flags)?;
+                        let a = pop_num(stack, flags)?;
+                        let result = match op {
+                            OP_ADD => a + b,
+                            OP_SUB => a - b,
+                            OP_BOOLAND => (a != 0 && b != 0) as i64,
+                            OP_BOOLOR => (a != 0 || b != 0) as i64,
+                            OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                            OP_NUMNOTEQUAL => (a != b) as i64,
+                            OP_LESSTHAN => (a < b) as i64,
+                            OP_GREATERTHAN => (a > b) as i64,
+                            OP_LESSTHANOREQUAL => (a <= b) as i64,
+                            OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                            OP_MIN => if a < b { a } else { b },
+                            OP_MAX => if a > b { a } else { b },
+                            _ => unreachable!(),
+                        };
+                        if op == OP_NUMEQUALVERIFY {
+                            if result == 0 {
+                                return Err(Error::NumEqualVerify);
+                            }
+                        } else {
+                            stack.push(build_scriptint(result));
+                        }
+                    }
+                    OP_WITHIN => {
+                        require_stack(stack, 3)?;
+                        let max = pop_num(stack, flags)?;
+                        let min = pop_num(stack, flags)?;
+                        let x = pop_num(stack, flags)?;
+                        stack.push(script_bool(min <= x && x < max));
+                    }
+
+                    // Crypto
+                    OP_RIPEMD160 => {
+                        let hash = Ripemd160Hash::from_data(&pop(stack)?);
+                        stack.push(hash[..].to_vec());
+                    }
+                    OP_SHA1 => {
+                        let mut hash = [0; 20];
+                        let mut engine = Sha1::new();
+                        engine.input(&pop(stack)?);
+                        engine.result(&mut hash);
+                        stack.push(hash.to_vec());
+                    }
+                    OP_SHA256 => {
+                        let mut hash = [0; 32];
+                        let mut engine = Sha256::new();
+                        engine.input(&pop(stack)?);
+                        engine.result(&mut hash);
+                        stack.push(hash.to_vec());
+                    }
+                    OP_HASH160 => {
+                        let hash = Hash160::from_data(&pop(stack)?);
+                        stack.push(hash[..].to_vec());
+                    }
+                    OP_HASH256 => {
+                        let hash = Sha256dHash::from_data(&pop(stack)?);
+                        stack.push(hash[..].to_vec());
+                    }
+                    OP_CODESEPARATOR => {
+                        // Signatures only commit to the script following the last executed separator
+                        begin_code_hash = pc;
+                    }
+                    OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                        require_stack(stack, 2)?;
+                        let pubkey = pop(stack)?;
+                        let sig = pop(stack)?;
+
+                        let mut script_code = Script::from(bytes[begin_code_hash..].to_vec());
+                        if sigversion == SigVersion::Base {
+                            script_code = remove_signatures(script_code, &[&sig], flags)?;
+                        }
+
+                        check_signature_encoding(&sig, flags)?;
+                        check_pubkey_encoding(&pubkey, flags, sigversion)?;
+                        let success = checker.check_sig(&sig, &pubkey, &script_code, sigversion);
+                        if !success && flags & VERIFY_NULLFAIL != 0 && !sig.is_empty() {
+                            return Err(Error::SigNullFail);
+                        }
+
+                        if op == OP_CHECKSIGVERIFY {
+                            if !success {
+                                return Err(Error::CheckSigVerify);
+                            }
+                        } else {
+                            stack.push(script_bool(success));
+                        }
+                    }
+                    OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                        // Stack layout, from the top: n, n pubkeys, m, m signatures, dummy
+                        let mut i = 1;
+                        let mut n_keys = read_scriptnum(stack_top(stack, i)?, flags & VERIFY_MINIMALDATA != 0, 4)?;
+                        if n_keys < 0 || n_keys > MAX_PUBKEYS_PER_MULTISIG {
+                            return Err(Error::PubkeyCount);
+                        }
+                        op_count += n_keys as usize;
+                        if op_count > MAX_OPS_PER_SCRIPT {
+                            return Err(Error::OpCount);
+                        }
+                        i += 1;
+                        let mut ikey = i;
+                        // The number of pubkeys still to be removed from the stack after
+                        // the signature checks, used for the NULLFAIL check
+                        let mut ikey2 = n_keys as usize + 2;
+                        i += n_keys as usize;
+
+                        let mut n_sigs = read_scriptnum(stack_top(stack, i)?, flags & VERIFY_MINIMALDATA != 0, 4)?;
+                        if n_sigs < 0 || n_sigs > n_keys {
+                            return Err(Error::SigCount);
+                        }
+                        i += 1;
+                        let mut isig = i;
+                        i += n_sigs as usize;
+                        require_stack(stack, i)?;
+
+                        let mut script_code = Script::from(bytes[begin_code_hash..].to_vec());
+                        if sigversion == SigVersion::Base {
+                            let sigs: Vec<&[u8]> = (0..n_sigs as usize).map(|k| &stack[stack.len() - isig - k][..]).collect();
+                            script_code = remove_signatures(script_code, &sigs, flags)?;
+                        }
+
+                        let mut success = true;
+                        while success && n_sigs > 0 {
+                            let sig = &stack[stack.len() - isig];
+                            let pubkey = &stack[stack.len() - ikey];
+                            // Note how this makes the exact order of pubkey/signature evaluation
+                            // distinguishable by CHECKMULTISIG NOT if the STRICTENC flag is set.
+                            check_signature_encoding(sig, flags)?;
+                            check_pubkey_encoding(pubkey, flags, sigversion)?;
+
+                            if checker.check_sig(sig, pubkey, &script_code, sigversion) {
+                                isig += 1;
+                                n_sigs -= 1;
+                            }
+                            ikey += 1;
+                            n_keys -= 1;
+
+                            // If there are more signatures left than keys left, then too
+                            // many signatures have failed
+                            if n_sigs > n_keys {
+                                success = false;
+                            }
+                        }
+
+                        // Clean up stack of actual arguments
+                        while i > 1 {
+                            i -= 1;
+                            // If the operation failed, we require that all signatures are empty
+                            if !success && flags & VERIFY_NULLFAIL != 0 && ikey2 == 0 && !stack_top(stack, 1)?.is_empty() {
+                                return Err(Error::SigNullFail);
+                            }
+                            if ikey2 > 0 {
+                                ikey2 -= 1;
+                            }
+                            stack.pop();
+                        }
+
+                        // A bug causes CHECKMULTISIG to consume one extra argument whose
+                        // contents were not checked in any way; BIP147 requires it be empty
+                        let dummy = pop(stack)?;
+                        if flags & VERIFY_NULLDUMMY != 0 && !dummy.is_empty() {
+                            return Err(Error::SigNullDummy);
+                        }
+
+                        if op == OP_CHECKMULTISIGVERIFY {
+                            if !success {
+                                return Err(Error::CheckMultisigVerify);
+                            }
+                        } else {
+                            stack.push(script_bool(success));
+                        }
+                    }
+                },
+            }
+        }
+
+        if stack.len() + altstack.len() > MAX_STACK_SIZE {
+            return Err(Error::StackSize);
+        }
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(Error::UnbalancedConditional);
+    }
+    Ok(())
+}
+
+/// Extracts the version and program of a witness program output, as defined by BIP141
+pub fn witness_program(script: &Script) -> Option<(u8, &[u8])> {
+    let bytes = script.as_bytes();
+    if bytes.len() < 4 || bytes.len() > 42 {
+        return None;
+    }
+    let version = match opcodes::All::from(bytes[0]).classify() {
+        opcodes::Class::PushBytes(0) => 0,
+        opcodes::Class::PushNum(n) if n >= 1 => n as u8,
+        _ => return None,
+    };
+    if bytes[1] as usize + 2 == bytes.len() {
+        Some((version, &bytes[2..]))
+    } else {
+        None
+    }
+}
+
+fn verify_witness_program(witness: &[Vec<u8>], version: u8, program: &[u8], flags: u32,
+                          checker: &SignatureChecker) -> Result<(), Error> {
+    let (mut stack, script) = if version == 0 {
+        if program.len() == 32 {
+            // Version 0 segregated witness program: SHA256(Script) in program, Script + inputs in witness
+            let (script, stack) = match witness.split_last() {
+                Some((script, stack)) => (Script::from(script.clone()), stack.to_vec()),
+                None => return Err(Error::WitnessProgramWitnessEmpty),
+            };
+            if &script.to_v0_p2wsh()[2..] != program {
+                return Err(Error::WitnessProgramMismatch);
+            }
+            (stack, script)
+        } else if program.len() == 20 {
+            // Special case for pay-to-pubkeyhash; signature + pubkey in witness
+            if witness.len() != 2 {
+                return Err(Error::WitnessProgramMismatch);
+            }
+            let script = Builder::new().push_opcode(opcodes::All::OP_DUP)
+                                       .push_opcode(opcodes::All::OP_HASH160)
+                                       .push_slice(program)
+                                       .push_opcode(opcodes::All::OP_EQUALVERIFY)
+                                       .push_opcode(opcodes::All::OP_CHECKSIG)
+                                       .into_script();
+            (witness.to_vec(), script)
+        } else {
+            return Err(Error::WitnessProgramWrongLength);
+        }
+    } else if flags & VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM != 0 {
+        return Err(Error::DiscourageUpgradableWitnessProgram);
+    } else {
+        // Higher version witness scripts return true for future softfork compatibility
+        return Ok(());
+    };
+
+    // Disallow stack item size > MAX_SCRIPT_ELEMENT_SIZE in witness stack
+    if stack.iter().any(|elem| elem.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+        return Err(Error::PushSize);
+    }
+
+    eval_script(&mut stack, &script, flags, checker, SigVersion::WitnessV0)?;
+
+    // Scripts inside witness implicitly require cleanstack behaviour
+    if stack.len() != 1 || !read_scriptbool(&stack[0]) {
+        return Err(Error::EvalFalse);
+    }
+    Ok(())
+}
+
+/// Verify that `script_sig` and `witness` satisfy `script_pubkey`, evaluating
+/// P2SH redeem scripts and witness programs as enabled by `flags`.
+pub fn verify_script(script_sig: &Script, script_pubkey: &Script, witness: &[Vec<u8>],
+                     flags: u32, checker: &SignatureChecker) -> Result<(), Error> {
+    let mut had_witness = false;
+
+    if flags & VERIFY_SIGPUSHONLY != 0 && !script_sig.is_push_only() {
+        return Err(Error::SigPushOnly);
+    }
+
+    let mut stack = vec![];
+    eval_script(&mut stack, script_sig, flags, checker, SigVersion::Base)?;
+    let mut stack_copy = if flags & VERIFY_P2SH != 0 { stack.clone() } else { vec![] };
+    eval_script(&mut stack, script_pubkey, flags, checker, SigVersion::Base)?;
+    if stack.is_empty() || !read_scriptbool(&stack[stack.len() - 1]) {
+        return Err(Error::EvalFalse);
+    }
+
+    // Bare witness programs
+    if flags & VERIFY_WITNESS != 0 {
+        if let Some((version, program)) = witness_program(script_pubkey) {
+            had_witness = true;
+            // The scriptSig must be _exactly_ empty, otherwise we reintroduce malleability
+            if !script_sig.is_empty() {
+                return Err(Error::WitnessMalleated);
+            }
+            verify_witness_program(witness, version, program, flags, checker)?;
+            // Bypass the cleanstack check at the end. The actual stack is
+            // obviously not clean for witness programs.
+            stack.truncate(1);
+        }
+    }
+
+    // Additional validation for spend-to-script-hash transactions
+    if flags & VERIFY_P2SH != 0 && script_pubkey.is_p2sh() {
+        // scriptSig must be literals-only or validation fails
+        if !script_sig.is_push_only() {
+            return Err(Error::SigPushOnly);
+        }
+
+        // Restore stack, and evaluate the redeem script on the results of the scriptSig
+        mem::swap(&mut stack, &mut stack_copy);
+        // The scriptSig evaluated to a non-empty stack above, as the scriptPubKey
+        // checks the top element against a hash
+        let redeem_script = Script::from(pop(&mut stack)?);
+
+        eval_script(&mut stack, &redeem_script, flags, checker, SigVersion::Base)?;
+        if stack.is_empty() || !read_scriptbool(&stack[stack.len() - 1]) {
+            return Err(Error::EvalFalse);
+        }
+
+        // P2SH witness programs
+        if flags & VERIFY_WITNESS != 0 {
+            if let Some((version, program)) = witness_program(&redeem_script) {
+                had_witness = true;
+                // The scriptSig must be _exactly_ a single push of the redeem script,
+                // otherwise we reintroduce malleability
+                if *script_sig != Builder::new().push_slice(redeem_script.as_bytes()).into_script() {
+                    return Err(Error::WitnessMalleatedP2sh);
+                }
+                verify_witness_program(witness, version, program, flags, checker)?;
+                stack.truncate(1);
+            }
+        }
+    }
+
+    // The CLEANSTACK check is only performed after potential P2SH evaluation,
+    // as the non-P2SH evaluation of a P2SH script will obviously not result in
+    // a clean stack (the P2SH inputs remain).
+    if flags & VERIFY_CLEANSTACK != 0 && stack.len() != 1 {
+        return Err(Error::CleanStack);
+    }
+
+    // Witness data must be absent for spends which do not use it
+    if flags & VERIFY_WITNESS != 0 && !had_witness && !witness.is_empty() {
+        return Err(Error::WitnessUnexpected);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use rustc_serialize::json::Json;
+    use secp256k1::Secp256k1;
+    use secp256k1::key::{PublicKey, SecretKey};
+
+    use super::*;
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Error, Script};
+    use blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut};
+    use consensus::encode::deserialize;
+    use hex::decode as hex_decode;
+
+    fn eval(script: &Script, flags: u32) -> Result<(), Error> {
+        verify_script(&Script::new(), script, &[], flags, &BaseSignatureChecker)
+    }
+
+    #[test]
+    fn arithmetic_and_stack() {
+        // 1 2 ADD 3 EQUAL
+        assert_eq!(eval(&hex_script!("5152935387"), 0), Ok(()));
+        // 2 3 SUB -1 NUMEQUAL
+        assert_eq!(eval(&hex_script!("5253944f9c"), 0), Ok(()));
+        // 5 2 7 WITHIN  (2 <= 5 < 7)
+        assert_eq!(eval(&hex_script!("555257a5"), 0), Ok(()));
+        // 1 2 3 ROT 1 EQUALVERIFY 3 EQUALVERIFY 2 EQUAL
+        assert_eq!(eval(&hex_script!("5152537b518853885287"), 0), Ok(()));
+        // 7 8 9 2 PICK 7 EQUALVERIFY DEPTH 3 EQUAL
+        assert_eq!(eval(&hex_script!("57585952795788745387"), 0), Ok(()));
+        // 7 8 9 2 ROLL 7 EQUALVERIFY DEPTH 2 EQUAL
+        assert_eq!(eval(&hex_script!("575859527a5788745287"), 0), Ok(()));
+        // "abc" SIZE 3 EQUALVERIFY "abc" EQUAL
+        assert_eq!(eval(&hex_script!("036162638253880361626387"), 0), Ok(()));
+        // "" SHA256 <sha256("")> EQUAL
+        assert_eq!(eval(&hex_script!("00a820e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b85587"), 0), Ok(()));
+        // "" SHA1 <sha1("")> EQUAL
+        assert_eq!(eval(&hex_script!("00a714da39a3ee5e6b4b0d3255bfef95601890afd8070987"), 0), Ok(()));
+        // "" RIPEMD160 <ripemd160("")> EQUAL
+        assert_eq!(eval(&hex_script!("00a6149c1185a5c5e9fc54612808977ee8f548b2258d3187"), 0), Ok(()));
+    }
+
+    #[test]
+    fn control_flow() {
+        // 1 IF 2 ELSE 3 ENDIF 2 EQUAL
+        assert_eq!(eval(&hex_script!("5163526753685287"), 0), Ok(()));
+        // 0 IF 2 ELSE 3 ENDIF 3 EQUAL
+        assert_eq!(eval(&hex_script!("0063526753685387"), 0), Ok(()));
+        // 0 NOTIF 1 ENDIF
+        assert_eq!(eval(&hex_script!("00645168"), 0), Ok(()));
+        // 1 IF 1
+        assert_eq!(eval(&hex_script!("516351"), 0), Err(Error::UnbalancedConditional));
+        // 1 ENDIF
+        assert_eq!(eval(&hex_script!("5168"), 0), Err(Error::UnbalancedConditional));
+        // IF with an empty stack
+        assert_eq!(eval(&hex_script!("635168"), 0), Err(Error::UnbalancedConditional));
+        // 0 VERIFY
+        assert_eq!(eval(&hex_script!("0069"), 0), Err(Error::Verify));
+        // 1 RETURN
+        assert_eq!(eval(&hex_script!("516a"), 0), Err(Error::OpReturn));
+        // OP_RETURN, OP_VER and OP_RESERVED are fine in unexecuted branches...
+        assert_eq!(eval(&hex_script!("0063506a626851"), 0), Ok(()));
+        // ...but OP_VERIF and disabled opcodes are not
+        assert_eq!(eval(&hex_script!("0063656851"), 0), Err(Error::BadOpcode(opcodes::All::OP_VERIF)));
+        assert_eq!(eval(&hex_script!("00637e6851"), 0), Err(Error::DisabledOpcode(opcodes::All::OP_CAT)));
+        // 0 leaves false on the stack
+        assert_eq!(eval(&hex_script!("00"), 0), Err(Error::EvalFalse));
+        // DROP on an empty stack
+        assert_eq!(eval(&hex_script!("75"), 0), Err(Error::InvalidStackOperation));
+        assert_eq!(eval(&hex_script!("6c"), 0), Err(Error::InvalidAltstackOperation));
+        // Truncated push
+        assert_eq!(eval(&hex_script!("4c05aabb"), 0), Err(Error::EarlyEndOfScript));
+    }
+
+    #[test]
+    fn limits() {
+        // 202 NOPs exceed the opcode limit
+        let mut script = vec![0x51];
+        script.extend(vec![0x61; 201]);
+        assert_eq!(eval(&Script::from(script.clone()), 0), Ok(()));
+        script.push(0x61);
+        assert_eq!(eval(&Script::from(script), 0), Err(Error::OpCount));
+
+        // A 521 byte push
+        let script = Builder::new().push_slice(&[1; 521]).into_script();
+        assert_eq!(eval(&script, 0), Err(Error::PushSize));
+
+        // 1001 stack elements
+        let mut script = vec![0x51; 1000];
+        assert_eq!(eval(&Script::from(script.clone()), 0), Ok(()));
+        script.push(0x51);
+        assert_eq!(eval(&Script::from(script), 0), Err(Error::StackSize));
+
+        // Numbers longer than 4 bytes can be pushed but not used as numbers
+        assert_eq!(eval(&hex_script!("050000000001"), 0), Ok(()));
+        assert_eq!(eval(&hex_script!("05000000000193"), 0), Err(Error::InvalidStackOperation));
+        assert_eq!(eval(&hex_script!("0500000000018b"), 0), Err(Error::NumericOverflow));
+    }
+
+    #[test]
+    fn minimal_encodings() {
+        // PUSHDATA1 of a single byte
+        assert_eq!(eval(&hex_script!("4c0102"), 0), Ok(()));
+        assert_eq!(eval(&hex_script!("4c0102"), VERIFY_MINIMALDATA), Err(Error::NonMinimalPush));
+        // Direct push of a number which has its own opcode
+        assert_eq!(eval(&hex_script!("0105"), VERIFY_MINIMALDATA), Err(Error::NonMinimalPush));
+        // Non-minimally encoded number used in arithmetic
+        assert_eq!(eval(&hex_script!("0280008b"), VERIFY_MINIMALDATA), Ok(()));
+        assert_eq!(eval(&hex_script!("0201008b"), 0), Ok(()));
+        assert_eq!(eval(&hex_script!("0201008b"), VERIFY_MINIMALDATA), Err(Error::NonMinimalNumber));
+        assert_eq!(eval(&hex_script!("0200008b"), 0), Ok(()));
+        assert_eq!(eval(&hex_script!("0200008b"), VERIFY_MINIMALDATA), Err(Error::NonMinimalNumber));
+        // Negative zero
+        assert_eq!(eval(&hex_script!("01808b"), VERIFY_MINIMALDATA), Err(Error::NonMinimalNumber));
+    }
+
+    #[test]
+    fn upgradable_nops() {
+        assert_eq!(eval(&hex_script!("51b0"), 0), Ok(()));
+        assert_eq!(eval(&hex_script!("51b0"), VERIFY_DISCOURAGE_UPGRADABLE_NOPS),
+                   Err(Error::DiscourageUpgradableNops));
+        assert_eq!(eval(&hex_script!("5161"), VERIFY_DISCOURAGE_UPGRADABLE_NOPS), Ok(()));
+        // Unexecuted NOPs are fine
+        assert_eq!(eval(&hex_script!("0063b0685161"), VERIFY_DISCOURAGE_UPGRADABLE_NOPS), Ok(()));
+    }
+
+    #[test]
+    fn scriptnum() {
+        assert_eq!(read_scriptnum(&[], true, 4), Ok(0));
+        assert_eq!(read_scriptnum(&[0x81], true, 4), Ok(-1));
+        assert_eq!(read_scriptnum(&[0xff, 0x00], true, 4), Ok(255));
+        assert_eq!(read_scriptnum(&[0xff, 0x80], true, 4), Ok(-255));
+        assert_eq!(read_scriptnum(&[0xff, 0xff, 0xff, 0xff, 0x00], true, 5), Ok(0xffffffff));
+        assert_eq!(read_scriptnum(&[0xff, 0xff, 0xff, 0xff, 0x00], true, 4), Err(Error::NumericOverflow));
+        assert_eq!(read_scriptnum(&[0x00], true, 4), Err(Error::NonMinimalNumber));
+        assert_eq!(read_scriptnum(&[0x00], false, 4), Ok(0));
+        assert_eq!(read_scriptnum(&[0x01, 0x80], true, 4), Err(Error::NonMinimalNumber));
+        assert_eq!(read_scriptnum(&[0x80, 0x80], true, 4), Ok(-128));
+    }
+
+    #[test]
+    fn find_and_delete_on_op_boundaries() {
+        // Deletes every aligned occurrence
+        assert_eq!(find_and_delete(&[0x51, 0x52, 0x51, 0x53], &[0x51]), (vec![0x52, 0x53], true));
+        // but not one in the middle of a push
+        assert_eq!(find_and_delete(&[0x02, 0x51, 0x51, 0x53], &[0x51]), (vec![0x02, 0x51, 0x51, 0x53], false));
+        // Consecutive occurrences are removed
+        assert_eq!(find_and_delete(&[0x01, 0x51, 0x01, 0x51], &[0x01, 0x51]), (vec![], true));
+        // A malformed tail is kept as-is
+        assert_eq!(find_and_delete(&[0x51, 0x4c], &[0x51]), (vec![0x4c], true));
+        assert_eq!(remove_codeseparators(&hex_script!("51ab52ab")), hex_script!("5152"));
+    }
+
+    #[test]
+    fn signature_encoding() {
+        let sig = hex_decode("30440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc01").unwrap();
+        assert!(is_valid_signature_encoding(&sig));
+        assert!(is_low_s(&sig));
+        assert_eq!(check_signature_encoding(&sig, VERIFY_STRICTENC | VERIFY_LOW_S), Ok(()));
+
+        // Bad sighash type
+        let mut bad_sighash = sig.clone();
+        *bad_sighash.last_mut().unwrap() = 0x04;
+        assert_eq!(check_signature_encoding(&bad_sighash, VERIFY_DERSIG), Ok(()));
+        assert_eq!(check_signature_encoding(&bad_sighash, VERIFY_STRICTENC), Err(Error::SigHashType));
+
+        // Padded R value
+        let mut padded = vec![0x30, 0x45, 0x02, 0x21, 0x00];
+        padded.extend_from_slice(&sig[4..]);
+        assert!(!is_valid_signature_encoding(&padded));
+        assert_eq!(check_signature_encoding(&padded, 0), Ok(()));
+        assert_eq!(check_signature_encoding(&padded, VERIFY_DERSIG), Err(Error::SigDer));
+
+        // S replaced by n - S
+        let high_s = hex_decode("304502204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd41022100ff0b2dee3f8e23a2f4ef39c4d4e0c8f3d2e49c2a5a1f5e7b8c9d0e1f2a3b4c5d01").unwrap();
+        assert!(is_valid_signature_encoding(&high_s));
+        assert!(!is_low_s(&high_s));
+        assert_eq!(check_signature_encoding(&high_s, VERIFY_LOW_S), Err(Error::SigHighS));
+
+        // Empty signatures are always acceptable
+        assert_eq!(check_signature_encoding(&[], VERIFY_STRICTENC | VERIFY_LOW_S), Ok(()));
+
+        assert_eq!(check_pubkey_encoding(&[0x05; 33], VERIFY_STRICTENC, SigVersion::Base), Err(Error::PubkeyType));
+        let mut uncompressed = vec![0x04];
+        uncompressed.extend_from_slice(&[0; 64]);
+        assert_eq!(check_pubkey_encoding(&uncompressed, VERIFY_WITNESS_PUBKEYTYPE, SigVersion::Base), Ok(()));
+        assert_eq!(check_pubkey_encoding(&uncompressed, VERIFY_WITNESS_PUBKEYTYPE, SigVersion::WitnessV0),
+                   Err(Error::WitnessPubkeyType));
+    }
+
+    #[test]
+    fn bip143_p2pk_and_p2wpkh() {
+        // Native P2WPKH example from BIP143, whose first input is a plain P2PK spend
+        let tx: Transaction = deserialize(&hex_decode(
+            "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000"
+        ).unwrap()).unwrap();
+        let p2pk = hex_script!("2103c9f4836b9a4f77fc0d81f7bcb01b7f1b35916864b9476c241ce9fc198bd25432ac");
+        let p2wpkh = hex_script!("00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1");
+
+        assert_eq!(p2pk.verify_with_flags(0, Amount::from_sat(625000000), &tx, VERIFY_CONSENSUS), Ok(()));
+        assert_eq!(p2wpkh.verify_with_flags(1, Amount::from_sat(600000000), &tx, VERIFY_CONSENSUS), Ok(()));
+        // The amount is committed to by segwit signatures only
+        assert_eq!(p2pk.verify_with_flags(0, Amount::from_sat(1), &tx, VERIFY_CONSENSUS), Ok(()));
+        assert_eq!(p2wpkh.verify_with_flags(1, Amount::from_sat(1), &tx, VERIFY_CONSENSUS), Err(Error::EvalFalse));
+        // Without the witness flag, a witness program is anyone-can-spend
+        assert_eq!(p2wpkh.verify_with_flags(1, Amount::from_sat(1), &tx, VERIFY_P2SH), Ok(()));
+        // NULLFAIL turns the failed check into an error
+        assert_eq!(p2wpkh.verify_with_flags(1, Amount::from_sat(1), &tx, VERIFY_CONSENSUS | VERIFY_NULLFAIL),
+                   Err(Error::SigNullFail));
+        assert_eq!(p2pk.verify_with_flags(0, Amount::from_sat(625000000), &tx, VERIFY_CONSENSUS | VERIFY_STRICTENC | VERIFY_LOW_S),
+                   Ok(()));
+
+        // Witness data on a non-segwit input
+        let mut malleated = tx.clone();
+        malleated.input[0].witness = vec![vec![]];
+        assert_eq!(p2pk.verify_with_flags(0, Amount::from_sat(625000000), &malleated, VERIFY_CONSENSUS),
+                   Err(Error::WitnessUnexpected));
+        // scriptSig on a native segwit input
+        malleated.input[1].script_sig = hex_script!("00");
+        assert_eq!(p2wpkh.verify_with_flags(1, Amount::from_sat(600000000), &malleated, VERIFY_CONSENSUS),
+                   Err(Error::WitnessMalleated));
+    }
+
+    #[test]
+    fn bip143_p2sh_p2wpkh() {
+        let tx: Transaction = deserialize(&hex_decode(
+            "01000000000101db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a5477010000001716001479091972186c449eb1ded22b78e40d009bdf0089feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac02473044022047ac8e878352d3ebbde1c94ce3a10d057c24175747116f8288e5d794d12d482f0220217f36a485cae903c713331d877c1f64677e3622ad4010726870540656fe9dcb012103ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a2687392040000"
+        ).unwrap()).unwrap();
+        let spent = hex_script!("a9144733f37cf4db86fbc2efed2500b4f4e49f31202387");
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(1000000000), &tx, VERIFY_CONSENSUS), Ok(()));
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(999999999), &tx, VERIFY_CONSENSUS), Err(Error::EvalFalse));
+
+        // The redeem script must be pushed exactly
+        let mut malleated = tx.clone();
+        malleated.input[0].script_sig = hex_script!("4c16001479091972186c449eb1ded22b78e40d009bdf0089");
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(1000000000), &malleated, VERIFY_CONSENSUS),
+                   Err(Error::WitnessMalleatedP2sh));
+    }
+
+    #[test]
+    fn p2wsh_multisig() {
+        // A 2-of-3 native P2WSH spend from the blockchain
+        let spent = hex_script!("0020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d");
+        let tx: Transaction = deserialize(&hex_decode(
+            "010000000001011f97548fbbe7a0db7588a66e18d803d0089315aa7d4cc28360b6ec50ef36718a0100000000ffffffff02df1776000000000017a9146c002a686959067f4866b8fb493ad7970290ab728757d29f0000000000220020701a8d401c84fb13e6baf169d59684e17abd9fa216c8cc5b9fc63d622ff8c58d04004730440220565d170eed95ff95027a69b313758450ba84a01224e1f7f130dda46e94d13f8602207bdd20e307f062594022f12ed5017bbf4a055a06aea91c10110a0e3bb23117fc014730440220647d2dc5b15f60bc37dc42618a370b2a1490293f9e5c8464f53ec4fe1dfe067302203598773895b4b16d37485cbe21b337f4e4b650739880098c592553add7dd4355016952210375e00eb72e29da82b89367947f29ef34afb75e8654f6ea368e0acdfd92976b7c2103a1b26313f430c4b15bb1fdce663207659d8cac749a0e53d70eff01874496feff2103c96d495bfdd5ba4145e3e046fee45e84a8a48ad05bd8dbb395c011a32cf9f88053ae00000000"
+        ).unwrap()).unwrap();
+        let all_flags = VERIFY_CONSENSUS | VERIFY_STRICTENC | VERIFY_LOW_S | VERIFY_MINIMALDATA |
+                        VERIFY_CLEANSTACK | VERIFY_NULLFAIL | VERIFY_MINIMALIF | VERIFY_WITNESS_PUBKEYTYPE;
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(18393430), &tx, all_flags), Ok(()));
+
+        // Signatures in the wrong order
+        let mut swapped = tx.clone();
+        swapped.input[0].witness.swap(1, 2);
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(18393430), &swapped, VERIFY_CONSENSUS), Err(Error::EvalFalse));
+        // Non-null dummy
+        let mut dummy = tx.clone();
+        dummy.input[0].witness[0] = vec![1];
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(18393430), &dummy, VERIFY_CONSENSUS), Err(Error::SigNullDummy));
+        // Wrong witness script
+        let mut mismatch = tx.clone();
+        mismatch.input[0].witness[3][1] ^= 1;
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(18393430), &mismatch, VERIFY_CONSENSUS),
+                   Err(Error::WitnessProgramMismatch));
+        let mut empty = tx.clone();
+        empty.input[0].witness.clear();
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(18393430), &empty, VERIFY_CONSENSUS),
+                   Err(Error::WitnessProgramWitnessEmpty));
+    }
+
+    fn keys(secp: &Secp256k1<::secp256k1::All>) -> Vec<(SecretKey, PublicKey)> {
+        (1..4u8).map(|i| {
+            let sk = SecretKey::from_slice(secp, &[i; 32]).unwrap();
+            let pk = PublicKey::from_secret_key(secp, &sk);
+            (sk, pk)
+        }).collect()
+    }
+
+    fn spending_tx(lock_time: u32, sequence: u32, version: u32) -> Transaction {
+        Transaction {
+            version: version,
+            lock_time: lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: sequence,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1000),
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    fn sign(secp: &Secp256k1<::secp256k1::All>, tx: &Transaction, script_code: &Script, sk: &SecretKey) -> Vec<u8> {
+        let sighash = tx.signature_hash(0, script_code, SigHashType::All.as_u32());
+        let msg = Message::from_slice(&sighash[..]).unwrap();
+        let mut sig = secp.sign(&msg, sk).serialize_der(secp);
+        sig.push(SigHashType::All.as_u32() as u8);
+        sig
+    }
+
+    #[test]
+    fn p2sh_multisig_and_codeseparator() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp);
+        let redeem_script = Builder::new().push_int(2)
+                                          .push_slice(&keys[0].1.serialize()[..])
+                                          .push_slice(&keys[1].1.serialize()[..])
+                                          .push_slice(&keys[2].1.serialize()[..])
+                                          .push_int(3)
+                                          .push_opcode(opcodes::All::OP_CHECKMULTISIG)
+                                          .into_script();
+        let spent = redeem_script.to_p2sh();
+
+        let mut tx = spending_tx(0, 0xffffffff, 1);
+        let sig0 = sign(&secp, &tx, &redeem_script, &keys[0].0);
+        let sig2 = sign(&secp, &tx, &redeem_script, &keys[2].0);
+        tx.input[0].script_sig = Builder::new().push_int(0)
+                                               .push_slice(&sig0)
+                                               .push_slice(&sig2)
+                                               .push_slice(redeem_script.as_bytes())
+                                               .into_script();
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(0), &tx, VERIFY_CONSENSUS | VERIFY_CLEANSTACK), Ok(()));
+
+        // Without P2SH the redeem script is left on the stack
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(0), &tx, 0), Ok(()));
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(0), &tx, VERIFY_CLEANSTACK), Err(Error::CleanStack));
+
+        // Non-push scriptSig
+        let mut nonpush = tx.clone();
+        let mut bytes = nonpush.input[0].script_sig.to_bytes();
+        bytes.insert(0, opcodes::All::OP_NOP as u8);
+        nonpush.input[0].script_sig = Script::from(bytes);
+        assert_eq!(spent.verify_with_flags(0, Amount::from_sat(0), &nonpush, VERIFY_CONSENSUS), Err(Error::SigPushOnly));
+
+        // A signature covers only the script after the last executed OP_CODESEPARATOR
+        let script = Builder::new().push_opcode(opcodes::All::OP_NOP)
+                                   .push_opcode(opcodes::All::OP_CODESEPARATOR)
+                                   .push_slice(&keys[0].1.serialize()[..])
+                                   .push_opcode(opcodes::All::OP_CHECKSIG)
+                                   .into_script();
+        let mut tx = spending_tx(0, 0xffffffff, 1);
+        let script_code = Script::from(script[2..].to_vec());
+        let sig = sign(&secp, &tx, &script_code, &keys[0].0);
+        tx.input[0].script_sig = Builder::new().push_slice(&sig).into_script();
+        assert_eq!(script.verify_with_flags(0, Amount::from_sat(0), &tx, VERIFY_CONSENSUS), Ok(()));
+        assert_eq!(script.verify_with_flags(0, Amount::from_sat(0), &tx, VERIFY_CONSENSUS | VERIFY_CONST_SCRIPTCODE),
+                   Err(Error::OpCodeSeparator));
+        let sig = sign(&secp, &tx, &script, &keys[0].0);
+        tx.input[0].script_sig = Builder::new().push_slice(&sig).into_script();
+        assert_eq!(script.verify_with_flags(0, Amount::from_sat(0), &tx, VERIFY_CONSENSUS), Err(Error::EvalFalse));
+    }
+
+    #[test]
+    fn locktime_opcodes() {
+        let cltv = Builder::new().push_int(500).push_opcode(opcodes::OP_CLTV).into_script();
+        let check = |script: &Script, tx: &Transaction, flags: u32| {
+            script.verify_with_flags(0, Amount::from_sat(0), tx, flags)
+        };
+
+        assert_eq!(check(&cltv, &spending_tx(500, 0, 1), VERIFY_CHECKLOCKTIMEVERIFY), Ok(()));
+        assert_eq!(check(&cltv, &spending_tx(499, 0, 1), VERIFY_CHECKLOCKTIMEVERIFY),
+                   Err(Error::UnsatisfiedLockTime));
+        // A final input disables the lock time
+        assert_eq!(check(&cltv, &spending_tx(500, 0xffffffff, 1), VERIFY_CHECKLOCKTIMEVERIFY),
+                   Err(Error::UnsatisfiedLockTime));
+        // Heights and times don't compare
+        assert_eq!(check(&cltv, &spending_tx(500_000_000, 0, 1), VERIFY_CHECKLOCKTIMEVERIFY),
+                   Err(Error::UnsatisfiedLockTime));
+        // Treated as a NOP before BIP65
+        assert_eq!(check(&cltv, &spending_tx(499, 0, 1), 0), Ok(()));
+        let negative = Builder::new().push_int(-1).push_opcode(opcodes::OP_CLTV).into_script();
+        assert_eq!(check(&negative, &spending_tx(0, 0, 1), VERIFY_CHECKLOCKTIMEVERIFY),
+                   Err(Error::NegativeLockTime));
+        assert_eq!(check(&Builder::new().push_opcode(opcodes::OP_CLTV).into_script(), &spending_tx(0, 0, 1),
+                         VERIFY_CHECKLOCKTIMEVERIFY),
+                   Err(Error::InvalidStackOperation));
+
+        let csv = Builder::new().push_int(10).push_opcode(opcodes::OP_CSV).into_script();
+        assert_eq!(check(&csv, &spending_tx(0, 10, 2), VERIFY_CHECKSEQUENCEVERIFY), Ok(()));
+        assert_eq!(check(&csv, &spending_tx(0, 9, 2), VERIFY_CHECKSEQUENCEVERIFY),
+                   Err(Error::UnsatisfiedLockTime));
+        // Relative lock times need version 2 transactions
+        assert_eq!(check(&csv, &spending_tx(0, 10, 1), VERIFY_CHECKSEQUENCEVERIFY),
+                   Err(Error::UnsatisfiedLockTime));
+        // Blocks and 512 second units don't compare
+        assert_eq!(check(&csv, &spending_tx(0, (1 << 22) | 10, 2), VERIFY_CHECKSEQUENCEVERIFY),
+                   Err(Error::UnsatisfiedLockTime));
+        // A disabled argument makes the opcode a NOP
+        let disabled = Builder::new().push_int(1 << 31).push_opcode(opcodes::OP_CSV).into_script();
+        assert_eq!(check(&disabled, &spending_tx(0, 0, 1), VERIFY_CHECKSEQUENCEVERIFY), Ok(()));
+    }
+
+    /// Parses a script in the notation of Bitcoin Core's test data: decimal
+    /// numbers are pushed as script numbers, `0x` hex is inserted as is,
+    /// quoted strings are pushed and anything else is an opcode name, with
+    /// or without its `OP_` prefix
+    fn parse_script(asm: &str) -> Script {
+        let mut names = HashMap::new();
+        for op in (0x61..0xba).chain(Some(opcodes::All::OP_RESERVED as u8)) {
+            let name = format!("{:?}", opcodes::All::from(op));
+            names.insert(name[3..].to_owned(), op);
+            names.insert(name, op);
+        }
+        for &(name, op) in &[("CHECKLOCKTIMEVERIFY", opcodes::OP_CLTV), ("CHECKSEQUENCEVERIFY", opcodes::OP_CSV)] {
+            names.insert(name.to_owned(), op as u8);
+            names.insert(format!("OP_{}", name), op as u8);
+        }
+
+        let mut bytes = vec![];
+        for word in asm.split_whitespace() {
+            let digits = if word.starts_with('-') { &word[1..] } else { word };
+            if !digits.is_empty() && digits.bytes().all(|b| b >= b'0' && b <= b'9') {
+                bytes.extend_from_slice(Builder::new().push_int(word.parse().unwrap()).into_script().as_bytes());
+            } else if word.starts_with("0x") && word.len() > 2 {
+                bytes.extend(hex_decode(&word[2..]).unwrap());
+            } else if word.len() >= 2 && word.starts_with('\'') && word.ends_with('\'') {
+                bytes.extend_from_slice(Builder::new().push_slice(&word.as_bytes()[1..word.len() - 1]).into_script().as_bytes());
+            } else {
+                match names.get(word) {
+                    Some(&op) => bytes.push(op),
+                    None => panic!("unknown script word {}", word),
+                }
+            }
+        }
+        Script::from(bytes)
+    }
+
+    /// Parses the comma-separated names of verification flags used in
+    /// Bitcoin Core's test data
+    fn parse_flags(flags: &str) -> u32 {
+        flags.split(',').filter(|flag| !flag.is_empty()).fold(0, |acc, flag| acc | match flag {
+            "NONE" => 0,
+            "P2SH" => VERIFY_P2SH,
+            "STRICTENC" => VERIFY_STRICTENC,
+            "DERSIG" => VERIFY_DERSIG,
+            "LOW_S" => VERIFY_LOW_S,
+            "NULLDUMMY" => VERIFY_NULLDUMMY,
+            "SIGPUSHONLY" => VERIFY_SIGPUSHONLY,
+            "MINIMALDATA" => VERIFY_MINIMALDATA,
+            "DISCOURAGE_UPGRADABLE_NOPS" => VERIFY_DISCOURAGE_UPGRADABLE_NOPS,
+            "CLEANSTACK" => VERIFY_CLEANSTACK,
+            "CHECKLOCKTIMEVERIFY" => VERIFY_CHECKLOCKTIMEVERIFY,
+            "CHECKSEQUENCEVERIFY" => VERIFY_CHECKSEQUENCEVERIFY,
+            "WITNESS" => VERIFY_WITNESS,
+            "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM" => VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM,
+            "MINIMALIF" => VERIFY_MINIMALIF,
+            "NULLFAIL" => VERIFY_NULLFAIL,
+            "WITNESS_PUBKEYTYPE" => VERIFY_WITNESS_PUBKEYTYPE,
+            "CONST_SCRIPTCODE" => VERIFY_CONST_SCRIPTCODE,
+            _ => panic!("unknown flag {}", flag),
+        })
+    }
+
+    /// The name of the error of a script verification in Bitcoin Core's
+    /// test data
+    fn error_name(result: &Result<(), Error>) -> &'static str {
+        match *result {
+            Ok(()) => "OK",
+            Err(ref e) => match *e {
+                Error::NonMinimalPush => "MINIMALDATA",
+                // Core reads past the end of a script as an invalid opcode,
+                // and fails to parse script numbers with an exception
+                Error::EarlyEndOfScript => "BAD_OPCODE",
+                Error::NumericOverflow | Error::NonMinimalNumber => "UNKNOWN_ERROR",
+                Error::EvalFalse => "EVAL_FALSE",
+                Error::OpReturn => "OP_RETURN",
+                Error::ScriptSize => "SCRIPT_SIZE",
+                Error::PushSize => "PUSH_SIZE",
+                Error::OpCount => "OP_COUNT",
+                Error::StackSize => "STACK_SIZE",
+                Error::SigCount => "SIG_COUNT",
+                Error::PubkeyCount => "PUBKEY_COUNT",
+                Error::Verify => "VERIFY",
+                Error::EqualVerify => "EQUALVERIFY",
+                Error::CheckMultisigVerify => "CHECKMULTISIGVERIFY",
+                Error::CheckSigVerify => "CHECKSIGVERIFY",
+                Error::NumEqualVerify => "NUMEQUALVERIFY",
+                Error::BadOpcode(..) => "BAD_OPCODE",
+                Error::DisabledOpcode(..) => "DISABLED_OPCODE",
+                Error::InvalidStackOperation => "INVALID_STACK_OPERATION",
+                Error::InvalidAltstackOperation => "INVALID_ALTSTACK_OPERATION",
+                Error::UnbalancedConditional => "UNBALANCED_CONDITIONAL",
+                Error::NegativeLockTime => "NEGATIVE_LOCKTIME",
+                Error::UnsatisfiedLockTime => "UNSATISFIED_LOCKTIME",
+                Error::SigHashType => "SIG_HASHTYPE",
+                Error::SigDer => "SIG_DER",
+                Error::SigPushOnly => "SIG_PUSHONLY",
+                Error::SigHighS => "SIG_HIGH_S",
+                Error::SigNullDummy => "SIG_NULLDUMMY",
+                Error::PubkeyType => "PUBKEYTYPE",
+                Error::CleanStack => "CLEANSTACK",
+                Error::MinimalIf => "MINIMALIF",
+                Error::SigNullFail => "NULLFAIL",
+                Error::DiscourageUpgradableNops => "DISCOURAGE_UPGRADABLE_NOPS",
+                Error::DiscourageUpgradableWitnessProgram => "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM",
+                Error::WitnessProgramWrongLength => "WITNESS_PROGRAM_WRONG_LENGTH",
+                Error::WitnessProgramWitnessEmpty => "WITNESS_PROGRAM_WITNESS_EMPTY",
+                Error::WitnessProgramMismatch => "WITNESS_PROGRAM_MISMATCH",
+                Error::WitnessMalleated => "WITNESS_MALLEATED",
+                Error::WitnessMalleatedP2sh => "WITNESS_MALLEATED_P2SH",
+                Error::WitnessUnexpected => "WITNESS_UNEXPECTED",
+                Error::WitnessPubkeyType => "WITNESS_PUBKEYTYPE",
+                Error::OpCodeSeparator => "OP_CODESEPARATOR",
+                Error::SigFindAndDelete => "SIG_FINDANDDELETE",
+                _ => "UNKNOWN_ERROR",
+            },
+        }
+    }
+
+    #[test]
+    fn script_tests_json() {
+        // Rows of an optional witness followed by the amount spent, scriptSig,
+        // scriptPubKey, flags, expected error and a comment, spent and
+        // verified as in Bitcoin Core's script_tests.cpp
+        let secp = Secp256k1::new();
+        let rows = Json::from_str(include_str!("../../../test_data/script_tests.json")).unwrap();
+        let mut tested = 0;
+        for row in rows.as_array().unwrap() {
+            let mut row = &row.as_array().unwrap()[..];
+            if row.len() == 1 {
+                continue;  // a comment
+            }
+            let mut witness = vec![];
+            let mut amount = Amount::from_sat(0);
+            if let Some(items) = row[0].as_array() {
+                let (last, items) = items.split_last().unwrap();
+                for item in items {
+                    let item = item.as_string().unwrap();
+                    witness.push(if item.starts_with("#SCRIPT# ") {
+                        parse_script(&item[9..]).into_bytes()
+                    } else {
+                        hex_decode(item).unwrap()
+                    });
+                }
+                amount = Amount::from_sat((last.as_f64().unwrap() * 100_000_000.0).round() as u64);
+                row = &row[1..];
+            }
+            let script_sig = parse_script(row[0].as_string().unwrap());
+            let script_pubkey = parse_script(row[1].as_string().unwrap());
+            let flags = parse_flags(row[2].as_string().unwrap());
+
+            let credit = Transaction {
+                version: 1,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: Builder::new().push_int(0).push_int(0).into_script(),
+                    sequence: 0xffffffff,
+                    witness: vec![],
+                }],
+                output: vec![TxOut { value: amount, script_pubkey: script_pubkey.clone() }],
+            };
+            let spend = Transaction {
+                version: 1,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint { txid: credit.txid(), vout: 0 },
+                    script_sig: script_sig.clone(),
+                    sequence: 0xffffffff,
+                    witness: witness.clone(),
+                }],
+                output: vec![TxOut { value: amount, script_pubkey: Script::new() }],
+            };
+            let checker = TransactionSignatureChecker::new(&secp, &spend, 0, amount);
+            let result = verify_script(&script_sig, &script_pubkey, &witness, flags, &checker);
+            if error_name(&result) != row[3].as_string().unwrap() {
+                panic!("{:?}: got {:?}", row, result);
+            }
+            tested += 1;
+        }
+        assert!(tested > 0);
+    }
+
+    /// Whether a transaction passes the context-free checks of Bitcoin
+    /// Core's `CheckTransaction`, other than its size limit
+    fn check_transaction(tx: &Transaction) -> bool {
+        const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+        if tx.input.is_empty() || tx.output.is_empty() {
+            return false;
+        }
+        let mut total = 0;
+        for output in &tx.output {
+            if output.value.as_sat() > MAX_MONEY {
+                return false;
+            }
+            total += output.value.as_sat();
+            if total > MAX_MONEY {
+                return false;
+            }
+        }
+        let mut outpoints = HashSet::new();
+        if !tx.input.iter().all(|input| outpoints.insert(input.previous_output)) {
+            return false;
+        }
+        if tx.is_coin_base() {
+            let len = tx.input[0].script_sig.len();
+            len >= 2 && len <= 100
+        } else {
+            tx.input.iter().all(|input| !input.previous_output.is_null())
+        }
+    }
+
+    /// Runs rows of the outputs spent by a transaction, the transaction and
+    /// flags, checking and verifying the transaction as in Bitcoin Core's
+    /// transaction_tests.cpp
+    fn run_tx_tests(data: &str, valid: bool) {
+        let secp = Secp256k1::new();
+        let rows = Json::from_str(data).unwrap();
+        let mut tested = 0;
+        for row in rows.as_array().unwrap() {
+            let row = row.as_array().unwrap();
+            let prevouts = match row[0].as_array() {
+                Some(prevouts) => prevouts,
+                None => continue,  // a comment
+            };
+            let mut spent = HashMap::new();
+            for prevout in prevouts {
+                let prevout = prevout.as_array().unwrap();
+                let outpoint = OutPoint {
+                    txid: Sha256dHash::from_hex(prevout[0].as_string().unwrap()).unwrap(),
+                    vout: prevout[1].as_i64().unwrap() as u32,
+                };
+                let amount = prevout.get(3).map_or(0, |amount| amount.as_i64().unwrap() as u64);
+                spent.insert(outpoint, (parse_script(prevout[2].as_string().unwrap()), Amount::from_sat(amount)));
+            }
+            let tx: Transaction = deserialize(&hex_decode(row[1].as_string().unwrap()).unwrap()).unwrap();
+            let flags = parse_flags(row[2].as_string().unwrap());
+
+            let mut result = check_transaction(&tx);
+            for (index, input) in tx.input.iter().enumerate() {
+                if !result {
+                    break;
+                }
+                let (ref script_pubkey, amount) = spent[&input.previous_output];
+                let checker = TransactionSignatureChecker::new(&secp, &tx, index, amount);
+                result = verify_script(&input.script_sig, script_pubkey, &input.witness, flags, &checker).is_ok();
+            }
+            if result != valid {
+                panic!("{:?}", row);
+            }
+            tested += 1;
+        }
+        assert!(tested > 0);
+    }
+
+    #[test]
+    fn tx_valid_json() {
+        run_tx_tests(include_str!("../../../test_data/tx_valid.json"), true);
+    }
+
+    #[test]
+    fn tx_invalid_json() {
+        run_tx_tests(include_str!("../../../test_data/tx_invalid.json"), false);
+    }
+}
@@ -28,19 +28,34 @@ use std::default::Default;
 use std::{error, fmt};
 
 use crypto::digest::Digest;
+use secp256k1::Secp256k1;
 #[cfg(feature = "serde")] use serde;
 
 use blockdata::opcodes;
 use consensus::encode::{Decodable, Encodable};
 use consensus::encode::{self, Decoder, Encoder};
 use util::hash::Hash160;
+use blockdata::transaction::Transaction;
+use util::hash::Sha256dHash;
+use util::amount::Amount;
 #[cfg(feature="bitcoinconsensus")] use bitcoinconsensus;
 #[cfg(feature="bitcoinconsensus")] use std::convert;
-#[cfg(feature="bitcoinconsensus")] use util::hash::Sha256dHash;
 
 #[cfg(feature="fuzztarget")]      use util::sha2::Sha256;
 #[cfg(not(feature="fuzztarget"))] use crypto::sha2::Sha256;
 
+mod interpreter;
+
+pub use self::interpreter::{SigVersion, SignatureChecker, BaseSignatureChecker, TransactionSignatureChecker};
+pub use self::interpreter::{eval_script, verify_script};
+pub use self::interpreter::{MAX_SCRIPT_ELEMENT_SIZE, MAX_OPS_PER_SCRIPT, MAX_PUBKEYS_PER_MULTISIG};
+pub use self::interpreter::{MAX_SCRIPT_SIZE, MAX_STACK_SIZE};
+pub use self::interpreter::{VERIFY_P2SH, VERIFY_STRICTENC, VERIFY_DERSIG, VERIFY_LOW_S, VERIFY_NULLDUMMY};
+pub use self::interpreter::{VERIFY_SIGPUSHONLY, VERIFY_MINIMALDATA, VERIFY_DISCOURAGE_UPGRADABLE_NOPS};
+pub use self::interpreter::{VERIFY_CLEANSTACK, VERIFY_CHECKLOCKTIMEVERIFY, VERIFY_CHECKSEQUENCEVERIFY};
+pub use self::interpreter::{VERIFY_WITNESS, VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM, VERIFY_MINIMALIF};
+pub use self::interpreter::{VERIFY_NULLFAIL, VERIFY_WITNESS_PUBKEYTYPE, VERIFY_CONST_SCRIPTCODE, VERIFY_CONSENSUS};
+
 #[derive(Clone, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
 /// A Bitcoin script
 pub struct Script(Box<[u8]>);
@@ -158,16 +173,100 @@ pub enum Error {
     EarlyEndOfScript,
     /// Tried to read an array off the stack as a number when it was more than 4 bytes
     NumericOverflow,
-    #[cfg(feature="bitcoinconsensus")]
-    /// Error validating the script with bitcoinconsensus library
-    BitcoinConsensus(bitcoinconsensus::Error),
-    #[cfg(feature="bitcoinconsensus")]
+    /// A number on the stack was not minimally encoded, with VERIFY_MINIMALDATA set
+    NonMinimalNumber,
+    /// The script ran without error but left an empty or false value on top of the stack
+    EvalFalse,
+    /// OP_RETURN was executed
+    OpReturn,
+    /// The script was longer than `MAX_SCRIPT_SIZE`
+    ScriptSize,
+    /// Something pushed more than `MAX_SCRIPT_ELEMENT_SIZE` bytes
+    PushSize,
+    /// The script contained more than `MAX_OPS_PER_SCRIPT` non-push opcodes
+    OpCount,
+    /// The stacks held more than `MAX_STACK_SIZE` elements
+    StackSize,
+    /// OP_CHECKMULTISIG was given a negative number of signatures, or more than of pubkeys
+    SigCount,
+    /// OP_CHECKMULTISIG was given a negative number of pubkeys, or more than `MAX_PUBKEYS_PER_MULTISIG`
+    PubkeyCount,
+    /// OP_VERIFY failed
+    Verify,
+    /// OP_EQUALVERIFY failed
+    EqualVerify,
+    /// OP_CHECKMULTISIGVERIFY failed
+    CheckMultisigVerify,
+    /// OP_CHECKSIGVERIFY failed
+    CheckSigVerify,
+    /// OP_NUMEQUALVERIFY failed
+    NumEqualVerify,
+    /// An invalid or reserved opcode was executed, or OP_VERIF/OP_VERNOTIF was encountered
+    BadOpcode(opcodes::All),
+    /// The script contained a disabled opcode, executed or not
+    DisabledOpcode(opcodes::All),
+    /// An opcode needed more stack elements than there were, or an argument was out of range
+    InvalidStackOperation,
+    /// OP_FROMALTSTACK was executed with an empty alt stack
+    InvalidAltstackOperation,
+    /// An OP_IF had no matching OP_ENDIF, an OP_ELSE or OP_ENDIF had no OP_IF,
+    /// or OP_IF was executed with an empty stack
+    UnbalancedConditional,
+    /// The argument of OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY was negative
+    NegativeLockTime,
+    /// The lock time or sequence number of the spending input was not sufficient
+    UnsatisfiedLockTime,
+    /// A signature had an undefined sighash type, with VERIFY_STRICTENC set
+    SigHashType,
+    /// A signature was not strictly DER encoded
+    SigDer,
+    /// The scriptSig contained non-push opcodes
+    SigPushOnly,
+    /// A signature had a high S value, with VERIFY_LOW_S set
+    SigHighS,
+    /// The extra element consumed by OP_CHECKMULTISIG was not empty, with VERIFY_NULLDUMMY set
+    SigNullDummy,
+    /// A public key was not in a standard encoding, with VERIFY_STRICTENC set
+    PubkeyType,
+    /// More than one element was left on the stack, with VERIFY_CLEANSTACK set
+    CleanStack,
+    /// The argument of OP_IF/OP_NOTIF in a witness script was not empty or `0x01`
+    MinimalIf,
+    /// A failed signature check had a non-empty signature, with VERIFY_NULLFAIL set
+    SigNullFail,
+    /// A NOP reserved for upgrades was executed, with VERIFY_DISCOURAGE_UPGRADABLE_NOPS set
+    DiscourageUpgradableNops,
+    /// A witness program of an unknown version was spent, with
+    /// VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM set
+    DiscourageUpgradableWitnessProgram,
+    /// A version 0 witness program was neither 20 nor 32 bytes long
+    WitnessProgramWrongLength,
+    /// A P2WSH output was spent with an empty witness
+    WitnessProgramWitnessEmpty,
+    /// The witness did not match the witness program
+    WitnessProgramMismatch,
+    /// A native witness program was spent with a non-empty scriptSig
+    WitnessMalleated,
+    /// A P2SH-wrapped witness program was spent with a scriptSig other than a
+    /// single push of the redeem script
+    WitnessMalleatedP2sh,
+    /// An input which does not spend a witness program had witness data
+    WitnessUnexpected,
+    /// A witness script used an uncompressed public key, with VERIFY_WITNESS_PUBKEYTYPE set
+    WitnessPubkeyType,
+    /// OP_CODESEPARATOR was used in a legacy script, with VERIFY_CONST_SCRIPTCODE set
+    OpCodeSeparator,
+    /// A signature was found in the script code of a legacy script, with
+    /// VERIFY_CONST_SCRIPTCODE set
+    SigFindAndDelete,
     /// Can not find the spent transaction
     UnknownSpentTransaction(Sha256dHash),
-    #[cfg(feature="bitcoinconsensus")]
     /// The spent transaction does not have the referred output
     WrongSpentOutputIndex(usize),
     #[cfg(feature="bitcoinconsensus")]
+    /// Error validating the script with bitcoinconsensus library
+    BitcoinConsensus(bitcoinconsensus::Error),
+    #[cfg(feature="bitcoinconsensus")]
     /// Can not serialize the spending transaction
     SerializationError
 }
@@ -186,13 +285,52 @@ impl error::Error for Error {
             Error::NonMinimalPush => "non-minimal datapush",
             Error::EarlyEndOfScript => "unexpected end of script",
             Error::NumericOverflow => "numeric overflow (number on stack larger than 4 bytes)",
-            #[cfg(feature="bitcoinconsensus")]
-            Error::BitcoinConsensus(ref _n) => "bitcoinconsensus verification failed",
-            #[cfg(feature="bitcoinconsensus")]
+            Error::NonMinimalNumber => "non-minimally encoded number",
+            Error::EvalFalse => "script evaluated without error but finished with a false/empty top stack element",
+            Error::OpReturn => "OP_RETURN was encountered",
+            Error::ScriptSize => "script is too big",
+            Error::PushSize => "push value size limit exceeded",
+            Error::OpCount => "operation limit exceeded",
+            Error::StackSize => "stack size limit exceeded",
+            Error::SigCount => "signature count negative or greater than pubkey count",
+            Error::PubkeyCount => "pubkey count negative or limit exceeded",
+            Error::Verify => "script failed an OP_VERIFY operation",
+            Error::EqualVerify => "script failed an OP_EQUALVERIFY operation",
+            Error::CheckMultisigVerify => "script failed an OP_CHECKMULTISIGVERIFY operation",
+            Error::CheckSigVerify => "script failed an OP_CHECKSIGVERIFY operation",
+            Error::NumEqualVerify => "script failed an OP_NUMEQUALVERIFY operation",
+            Error::BadOpcode(_) => "opcode missing or not understood",
+            Error::DisabledOpcode(_) => "attempted to use a disabled opcode",
+            Error::InvalidStackOperation => "operation not valid with the current stack size",
+            Error::InvalidAltstackOperation => "operation not valid with the current altstack size",
+            Error::UnbalancedConditional => "invalid OP_IF construction",
+            Error::NegativeLockTime => "negative locktime",
+            Error::UnsatisfiedLockTime => "locktime requirement not satisfied",
+            Error::SigHashType => "signature hash type missing or not understood",
+            Error::SigDer => "non-canonical DER signature",
+            Error::SigPushOnly => "only push operators allowed in signatures",
+            Error::SigHighS => "non-canonical signature: S value is unnecessarily high",
+            Error::SigNullDummy => "dummy CHECKMULTISIG argument must be zero",
+            Error::PubkeyType => "public key is neither compressed or uncompressed",
+            Error::CleanStack => "extra items left on stack after execution",
+            Error::MinimalIf => "OP_IF/NOTIF argument must be minimal",
+            Error::SigNullFail => "signature must be zero for failed CHECK(MULTI)SIG operation",
+            Error::DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+            Error::DiscourageUpgradableWitnessProgram => "witness version reserved for soft-fork upgrades",
+            Error::WitnessProgramWrongLength => "witness program has incorrect length",
+            Error::WitnessProgramWitnessEmpty => "witness program was passed an empty witness",
+            Error::WitnessProgramMismatch => "witness program hash mismatch",
+            Error::WitnessMalleated => "witness requires empty scriptSig",
+            Error::WitnessMalleatedP2sh => "witness requires only-redeemscript scriptSig",
+            Error::WitnessUnexpected => "witness provided for non-witness script",
+            Error::WitnessPubkeyType => "using non-compressed keys in segwit",
+            Error::OpCodeSeparator => "using OP_CODESEPARATOR in non-witness script",
+            Error::SigFindAndDelete => "signature is found in scriptCode",
             Error::UnknownSpentTransaction (ref _hash) => "unknown transaction referred in Transaction::verify()",
-            #[cfg(feature="bitcoinconsensus")]
             Error::WrongSpentOutputIndex(ref _ix) => "unknown output index {} referred in Transaction::verify()",
             #[cfg(feature="bitcoinconsensus")]
+            Error::BitcoinConsensus(ref _n) => "bitcoinconsensus verification failed",
+            #[cfg(feature="bitcoinconsensus")]
             Error::SerializationError => "can not serialize the spending transaction in Transaction::verify()",
         }
     }
@@ -366,6 +504,13 @@ impl Script {
             self.0[1] == opcodes::All::OP_PUSHBYTES_20 as u8
     }
 
+    /// Checks whether a script pubkey is a segwit output of any version,
+    /// i.e. a version opcode followed by a single 2-40 byte push
+    #[inline]
+    pub fn is_witness_program(&self) -> bool {
+        interpreter::witness_program(self).is_some()
+    }
+
     /// Check if this is an OP_RETURN output
     pub fn is_op_return (&self) -> bool {
         !self.0.is_empty() && (opcodes::All::from(self.0[0]) == opcodes::All::OP_RETURN)
@@ -388,14 +533,141 @@ impl Script {
         }
     }
 
+    /// Checks whether the script consists only of push operations, as P2SH
+    /// scriptSigs must. OP_RESERVED counts as a push here, matching Bitcoin Core.
+    pub fn is_push_only(&self) -> bool {
+        for ins in self.iter(false) {
+            match ins {
+                Instruction::PushBytes(_) => {}
+                Instruction::Op(op) => if op as u8 > opcodes::All::OP_PUSHNUM_16 as u8 {
+                    return false;
+                },
+                Instruction::Error(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Counts the signature operations in the script, as Bitcoin Core's
+    /// `GetSigOpCount`. An OP_CHECKMULTISIG counts as 20 unless `accurate`
+    /// is set and it is preceded by OP_1 to OP_16, in which case it counts as
+    /// that number. Counting stops at the first unparseable opcode.
+    pub fn count_sigops(&self, accurate: bool) -> usize {
+        let mut n = 0;
+        let mut last_pushnum = None;
+        for ins in self.iter(false) {
+            let op = match ins {
+                Instruction::Op(op) => op,
+                Instruction::PushBytes(_) => {
+                    last_pushnum = None;
+                    continue;
+                }
+                Instruction::Error(_) => break,
+            };
+            match op {
+                opcodes::All::OP_CHECKSIG | opcodes::All::OP_CHECKSIGVERIFY => n += 1,
+                opcodes::All::OP_CHECKMULTISIG | opcodes::All::OP_CHECKMULTISIGVERIFY => {
+                    n += match last_pushnum {
+                        Some(keys) if accurate => keys,
+                        _ => MAX_PUBKEYS_PER_MULTISIG as usize,
+                    };
+                }
+                _ => {}
+            }
+            last_pushnum = match op.classify() {
+                opcodes::Class::PushNum(m) if m >= 1 => Some(m as usize),
+                _ => None,
+            };
+        }
+        n
+    }
+
+    /// Counts the signature operations executed when spending this script
+    /// with `script_sig`: those in the redeem script if this is a P2SH
+    /// output, counted accurately, otherwise those in this script. As in
+    /// Bitcoin Core, a P2SH scriptSig which is not push-only counts as none.
+    pub fn count_p2sh_sigops(&self, script_sig: &Script) -> usize {
+        if !self.is_p2sh() {
+            return self.count_sigops(true);
+        }
+        match last_push(script_sig) {
+            Some(redeem_script) => Script::from(redeem_script.to_vec()).count_sigops(true),
+            None => 0,
+        }
+    }
+
+    /// Counts the signature operations in the witness program this script
+    /// pays to, directly or nested in P2SH, when spent with `script_sig` and
+    /// `witness`. A v0 key hash program counts as one and a v0 script hash
+    /// program as the sigops in its witness script, counted accurately;
+    /// other scripts and unknown witness versions count as none.
+    pub fn count_witness_sigops(&self, script_sig: &Script, witness: &[Vec<u8>]) -> usize {
+        if let Some((version, program)) = interpreter::witness_program(self) {
+            return witness_program_sigops(version, program, witness);
+        }
+        if self.is_p2sh() && script_sig.is_push_only() {
+            if let Some(redeem_script) = last_push(script_sig) {
+                let redeem_script = Script::from(redeem_script.to_vec());
+                if let Some((version, program)) = interpreter::witness_program(&redeem_script) {
+                    return witness_program_sigops(version, program, witness);
+                }
+            }
+        }
+        0
+    }
+
+    /// Verify the spend of this script by input `index` of `spending` using the
+    /// native script interpreter, enforcing the rules selected by `flags`
+    /// (`VERIFY_CONSENSUS` for the current consensus rules).
+    /// # Parameters
+    ///  * index - the input index in spending which is spending this transaction
+    ///  * amount - the amount this script guards
+    ///  * spending - the transaction that attempts to spend the output holding this script
+    ///  * flags - the `VERIFY_*` flags to enforce
+    ///
+    /// # Panics
+    /// Panics if `index` is greater than or equal to the number of inputs of `spending`.
+    pub fn verify_with_flags(&self, index: usize, amount: Amount, spending: &Transaction, flags: u32) -> Result<(), Error> {
+        let secp = Secp256k1::verification_only();
+        let checker = TransactionSignatureChecker::new(&secp, spending, index, amount);
+        let input = &spending.input[index];
+        verify_script(&input.script_sig, self, &input.witness, flags, &checker)
+    }
+
     #[cfg(feature="bitcoinconsensus")]
     /// verify spend of an input script
     /// # Parameters
     ///  * index - the input index in spending which is spending this transaction
     ///  * amount - the amount this script guards
     ///  * spending - the transaction that attempts to spend the output holding this script
-    pub fn verify (&self, index: usize, amount: u64, spending: &[u8]) -> Result<(), Error> {
-        Ok(bitcoinconsensus::verify (&self.0[..], amount, spending, index)?)
+    pub fn verify (&self, index: usize, amount: Amount, spending: &[u8]) -> Result<(), Error> {
+        Ok(bitcoinconsensus::verify (&self.0[..], amount.as_sat(), spending, index)?)
+    }
+}
+
+/// The data pushed last by a scriptSig, or `None` if it contains anything
+/// other than pushes. OP_1 to OP_16 and OP_1NEGATE count as empty pushes.
+fn last_push(script_sig: &Script) -> Option<&[u8]> {
+    let mut last = &[][..];
+    for ins in script_sig.iter(false) {
+        match ins {
+            Instruction::PushBytes(data) => last = data,
+            Instruction::Op(op) if op as u8 <= opcodes::All::OP_PUSHNUM_16 as u8 => last = &[],
+            _ => return None,
+        }
+    }
+    Some(last)
+}
+
+/// Counts the signature operations of a witness program (BIP141)
+fn witness_program_sigops(version: u8, program: &[u8], witness: &[Vec<u8>]) -> usize {
+    if version != 0 {
+        return 0;
+    }
+    match (program.len(), witness.last()) {
+        (20, _) => 1,
+        (32, Some(witness_script)) => Script::from(witness_script.clone()).count_sigops(true),
+        _ => 0,
     }
 }
 
@@ -812,6 +1084,50 @@ mod test {
         assert!(hex_script!("a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").is_p2sh());
         assert!(!hex_script!("a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").is_p2pkh());
         assert!(!hex_script!("a314acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").is_p2sh());
+
+        assert!(hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd6").is_witness_program());
+        assert!(hex_script!("5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6").is_witness_program());
+        assert!(!hex_script!("0015751e76e8199196d454941c45d1b3a323f1433bd6").is_witness_program());
+        assert!(!hex_script!("a914acc91e6fef5c7f24e5c8b3f11a664aa8f1352ffd87").is_witness_program());
+    }
+
+    #[test]
+    fn count_sigops() {
+        assert_eq!(hex_script!("76a91402306a7c23f3e8010de41e9e591348bb83f11daa88ac").count_sigops(true), 1);
+        let multisig = hex_script!("52210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f817982102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b69952ae");
+        assert_eq!(multisig.count_sigops(true), 2);
+        assert_eq!(multisig.count_sigops(false), 20);
+        // OP_0 is not a valid key count, so is not counted accurately
+        assert_eq!(hex_script!("00ae").count_sigops(true), 20);
+        assert_eq!(hex_script!("ad51af").count_sigops(true), 2);
+        assert_eq!(hex_script!("ad51af").count_sigops(false), 21);
+        // Counting stops at a truncated push
+        assert_eq!(hex_script!("ac4cac").count_sigops(true), 1);
+        assert_eq!(hex_script!("").count_sigops(true), 0);
+
+        let p2pkh = hex_script!("76a91402306a7c23f3e8010de41e9e591348bb83f11daa88ac");
+        let redeem_p2sh = multisig.to_p2sh();
+        let p2sh_sig = Builder::new().push_opcode(opcodes::All::OP_PUSHBYTES_0).push_slice(&[1; 72])
+                                     .push_slice(&multisig[..]).into_script();
+        assert_eq!(redeem_p2sh.count_p2sh_sigops(&p2sh_sig), 2);
+        assert_eq!(redeem_p2sh.count_p2sh_sigops(&hex_script!("51")), 0);
+        assert_eq!(redeem_p2sh.count_p2sh_sigops(&Builder::new().push_slice(&multisig[..]).push_opcode(opcodes::All::OP_NOP)
+                                                                 .into_script()), 0);
+        assert_eq!(p2pkh.count_p2sh_sigops(&p2sh_sig), 1);
+
+        let p2wpkh = hex_script!("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let p2wsh = multisig.to_v0_p2wsh();
+        let wsh_witness = vec![vec![], vec![1; 72], multisig[..].to_vec()];
+        assert_eq!(p2wpkh.count_witness_sigops(&Script::new(), &[]), 1);
+        assert_eq!(p2wsh.count_witness_sigops(&Script::new(), &wsh_witness), 2);
+        assert_eq!(p2wsh.count_witness_sigops(&Script::new(), &[]), 0);
+        assert_eq!(hex_script!("5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6")
+                       .count_witness_sigops(&Script::new(), &wsh_witness), 0);
+        assert_eq!(p2wpkh.to_p2sh().count_witness_sigops(&Builder::new().push_slice(&p2wpkh[..]).into_script(), &[]), 1);
+        assert_eq!(p2wsh.to_p2sh().count_witness_sigops(&Builder::new().push_slice(&p2wsh[..]).into_script(),
+                                                       &wsh_witness), 2);
+        assert_eq!(redeem_p2sh.count_witness_sigops(&p2sh_sig, &wsh_witness), 0);
+        assert_eq!(p2pkh.count_witness_sigops(&Script::new(), &wsh_witness), 0);
     }
 
     #[test]
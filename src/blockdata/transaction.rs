@@ -25,12 +25,18 @@
 
 use byteorder::{LittleEndian, WriteBytesExt};
 use std::default::Default;
-use std::fmt;
-#[cfg(feature="bitcoinconsensus")] use std::collections::HashMap;
+use std::{error, fmt};
+use std::collections::{HashMap, HashSet};
 
+use secp256k1::Secp256k1;
+
+use util::amount::Amount;
+use util::feerate::FeeRate;
 use util::hash::{BitcoinHash, Sha256dHash};
-#[cfg(feature="bitcoinconsensus")] use blockdata::script;
-use blockdata::script::Script;
+use network::constants::Network;
+use blockdata::locktime::{LockTime, Sequence};
+use blockdata::constants::{max_money, MAX_BLOCK_WEIGHT, WITNESS_SCALE_FACTOR};
+use blockdata::script::{self, Script};
 use consensus::encode::{self, serialize, Encoder, Decoder};
 use consensus::encode::{Encodable, Decodable, VarInt};
 
@@ -111,11 +117,18 @@ pub struct TxIn {
 }
 serde_struct_impl!(TxIn, previous_output, script_sig, sequence, witness);
 
+impl TxIn {
+    /// The sequence number, interpreted as a possible relative lock time
+    pub fn get_sequence(&self) -> Sequence {
+        Sequence::from_u32(self.sequence)
+    }
+}
+
 /// A transaction output, which defines new coins to be created from old ones.
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 pub struct TxOut {
-    /// The value of the output, in satoshis
-    pub value: u64,
+    /// The value of the output
+    pub value: Amount,
     /// The script which must satisfy for the output to be spent
     pub script_pubkey: Script
 }
@@ -124,7 +137,7 @@ serde_struct_impl!(TxOut, value, script_pubkey);
 // This is used as a "null txout" in consensus signing code
 impl Default for TxOut {
     fn default() -> TxOut {
-        TxOut { value: 0xffffffffffffffff, script_pubkey: Script::new() }
+        TxOut { value: Amount::max_value(), script_pubkey: Script::new() }
     }
 }
 
@@ -172,6 +185,13 @@ impl Transaction {
         enc.into_hash()
     }
 
+    /// Computes the witness txid, which commits to the witnesses as well as
+    /// the rest of the transaction (BIP141). For transactions without
+    /// witnesses this is the txid.
+    pub fn wtxid(&self) -> Sha256dHash {
+        self.bitcoin_hash()
+    }
+
     /// Computes a signature hash for a given input index with a given sighash flag.
     /// To actually produce a scriptSig, this hash needs to be run through an
     /// ECDSA signer, the SigHashType appended to the resulting sig, and a
@@ -285,6 +305,187 @@ impl Transaction {
         }
     }
 
+    /// Gets the size of this transaction in bytes, as serialized including any witness data
+    #[inline]
+    pub fn get_size(&self) -> u64 {
+        serialize(self).len() as u64
+    }
+
+    /// Gets the size of this transaction in bytes, as serialized without witness data, which is
+    /// the serialization used to compute the txid
+    #[inline]
+    pub fn get_stripped_size(&self) -> u64 {
+        // weight = 3 * stripped size + total size
+        (self.get_weight() - self.get_size()) / 3
+    }
+
+    /// Gets the virtual size of this transaction, as defined by BIP141: its weight divided by 4,
+    /// rounded up
+    #[inline]
+    pub fn get_vsize(&self) -> u64 {
+        (self.get_weight() + 3) / 4
+    }
+
+    /// Gets the fee paid by this transaction, given the outputs spent by each of its inputs in
+    /// order. Returns `None` if the number of spent outputs does not match the number of inputs,
+    /// or if the outputs are worth more than the inputs.
+    pub fn fee(&self, spent: &[TxOut]) -> Option<Amount> {
+        if spent.len() != self.input.len() {
+            return None;
+        }
+        let mut input_value = Amount::zero();
+        for output in spent {
+            input_value = input_value.checked_add(output.value)?;
+        }
+        let mut output_value = Amount::zero();
+        for output in &self.output {
+            output_value = output_value.checked_add(output.value)?;
+        }
+        input_value.checked_sub(output_value)
+    }
+
+    /// Gets the fee rate paid by this transaction, given the outputs spent by each of its inputs
+    /// in order, assuming it is fully signed
+    pub fn fee_rate(&self, spent: &[TxOut]) -> Option<FeeRate> {
+        self.fee(spent).and_then(|fee| FeeRate::from_fee_and_weight(fee, self.get_weight()))
+    }
+
+    /// Counts the signature operations in the transaction's scriptSigs and output scripts,
+    /// counting each OP_CHECKMULTISIG as 20
+    pub fn get_legacy_sigop_count(&self) -> usize {
+        self.input.iter().map(|input| input.script_sig.count_sigops(false)).sum::<usize>() +
+            self.output.iter().map(|output| output.script_pubkey.count_sigops(false)).sum::<usize>()
+    }
+
+    /// Gets the signature operation cost of this transaction (BIP141), given the outputs spent
+    /// by each of its inputs in order: its legacy sigops and those of the P2SH redeem scripts it
+    /// executes, scaled by `WITNESS_SCALE_FACTOR`, plus its witness sigops. A coinbase spends no
+    /// outputs, so `spent` is ignored for it. Returns `None` if the number of spent outputs does
+    /// not match the number of inputs.
+    pub fn get_sigop_cost(&self, spent: &[TxOut]) -> Option<u64> {
+        let legacy = self.get_legacy_sigop_count() as u64 * WITNESS_SCALE_FACTOR;
+        if self.is_coin_base() {
+            return Some(legacy);
+        }
+        if spent.len() != self.input.len() {
+            return None;
+        }
+        let mut cost = legacy;
+        for (input, output) in self.input.iter().zip(spent.iter()) {
+            if output.script_pubkey.is_p2sh() {
+                cost += output.script_pubkey.count_p2sh_sigops(&input.script_sig) as u64 * WITNESS_SCALE_FACTOR;
+            }
+            cost += output.script_pubkey.count_witness_sigops(&input.script_sig, &input.witness) as u64;
+        }
+        Some(cost)
+    }
+
+    /// Checks the rules a transaction must follow regardless of the chain state, as Bitcoin
+    /// Core's `CheckTransaction`. Passing these checks does not mean the transaction is valid;
+    /// its inputs must also exist and its scripts verify.
+    pub fn check_sanity(&self) -> Result<(), SanityError> {
+        if self.input.is_empty() {
+            return Err(SanityError::NoInputs);
+        }
+        if self.output.is_empty() {
+            return Err(SanityError::NoOutputs);
+        }
+        // Size limits, ignoring the witness since it is not yet verified to be unmalleated
+        let stripped_weight = self.get_stripped_size() * WITNESS_SCALE_FACTOR;
+        if stripped_weight > MAX_BLOCK_WEIGHT {
+            return Err(SanityError::Oversized(stripped_weight));
+        }
+
+        // The money supply limit is the same on every network
+        let max_money = max_money(Network::Bitcoin);
+        let mut total = Amount::zero();
+        for (idx, output) in self.output.iter().enumerate() {
+            if output.value > max_money {
+                return Err(SanityError::OutputTooLarge(idx));
+            }
+            total = total + output.value;  // cannot overflow, with each output below max_money
+            if total > max_money {
+                return Err(SanityError::TotalOutputTooLarge);
+            }
+        }
+
+        let mut outpoints = HashSet::with_capacity(self.input.len());
+        for input in &self.input {
+            if !outpoints.insert(input.previous_output) {
+                return Err(SanityError::DuplicateInput(input.previous_output));
+            }
+        }
+
+        if self.is_coin_base() {
+            let len = self.input[0].script_sig.len();
+            if len < 2 || len > 100 {
+                return Err(SanityError::CoinbaseScriptSigSize(len));
+            }
+        } else {
+            for (idx, input) in self.input.iter().enumerate() {
+                if input.previous_output.is_null() {
+                    return Err(SanityError::NullPrevout(idx));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The lock time, interpreted as either a block height or a timestamp
+    pub fn get_lock_time(&self) -> LockTime {
+        LockTime::from_u32(self.lock_time)
+    }
+
+    /// Whether the transaction's lock time allows it to be included in a block
+    /// at `height` whose predecessor has median time past `median_time_past`.
+    /// The lock time is ignored if every input has a final sequence number.
+    pub fn is_final(&self, height: u32, median_time_past: u32) -> bool {
+        self.lock_time == 0 ||
+            self.get_lock_time().is_satisfied_by(height, median_time_past) ||
+            self.input.iter().all(|input| input.get_sequence().is_final())
+    }
+
+    /// Whether the relative lock time (BIP68) of input `input_index` allows
+    /// the transaction to be included in a block at `height` whose predecessor
+    /// has median time past `median_time_past`, given that the spent output
+    /// was confirmed at `spent_height` in a block whose predecessor has median
+    /// time past `spent_median_time_past`. Relative lock times are not enforced
+    /// for transactions of version below 2.
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to `self.input.len()`
+    pub fn is_relative_lock_satisfied(&self, input_index: usize, spent_height: u32, spent_median_time_past: u32,
+                                      height: u32, median_time_past: u32) -> bool {
+        assert!(input_index < self.input.len());  // Panic on OOB
+        if self.version < 2 {
+            return true;
+        }
+        match self.input[input_index].get_sequence().relative_lock_time() {
+            Some(lock) => lock.is_satisfied_by(spent_height, spent_median_time_past, height, median_time_past),
+            None => true,
+        }
+    }
+
+    /// Verify that this transaction is able to spend some outputs of spent transactions,
+    /// using the native script interpreter and enforcing the script rules selected by
+    /// `flags` (see `script::VERIFY_CONSENSUS`)
+    pub fn verify_with_flags(&self, spent: &HashMap<Sha256dHash, Transaction>, flags: u32) -> Result<(), script::Error> {
+        let secp = Secp256k1::verification_only();
+        for (idx, input) in self.input.iter().enumerate() {
+            if let Some(ref s) = spent.get(&input.previous_output.txid) {
+                if let Some(ref output) = s.output.get(input.previous_output.vout as usize) {
+                    let checker = script::TransactionSignatureChecker::new(&secp, self, idx, output.value);
+                    script::verify_script(&input.script_sig, &output.script_pubkey, &input.witness, flags, &checker)?;
+                } else {
+                    return Err(script::Error::WrongSpentOutputIndex(input.previous_output.vout as usize));
+                }
+            } else {
+                return Err(script::Error::UnknownSpentTransaction(input.previous_output.txid));
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(feature="bitcoinconsensus")]
     /// Verify that this transaction is able to spend some outputs of spent transactions
     pub fn verify(&self, spent: &HashMap<Sha256dHash, Transaction>) -> Result<(), script::Error> {
@@ -455,7 +656,7 @@ pub enum SigHashType {
 
 impl SigHashType {
      /// Break the sighash flag into the "real" sighash flag and the ANYONECANPAY boolean
-     fn split_anyonecanpay_flag(&self) -> (SigHashType, bool) {
+     pub fn split_anyonecanpay_flag(&self) -> (SigHashType, bool) {
          match *self {
              SigHashType::All		=> (SigHashType::All, false),
              SigHashType::None		=> (SigHashType::None, false),
@@ -487,17 +688,72 @@ impl SigHashType {
 }
 
 
+/// A violation of the context-free transaction rules checked by `Transaction::check_sanity`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SanityError {
+    /// The transaction has no inputs
+    NoInputs,
+    /// The transaction has no outputs
+    NoOutputs,
+    /// The transaction's weight without witness data, given, exceeds the block weight limit
+    Oversized(u64),
+    /// The output with the given index is worth more than the total money supply
+    OutputTooLarge(usize),
+    /// The outputs are together worth more than the total money supply
+    TotalOutputTooLarge,
+    /// The given outpoint is spent more than once
+    DuplicateInput(OutPoint),
+    /// The coinbase scriptSig, of the given length, is not between 2 and 100 bytes
+    CoinbaseScriptSigSize(usize),
+    /// The non-coinbase input with the given index spends the null outpoint
+    NullPrevout(usize),
+}
+
+impl fmt::Display for SanityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SanityError::Oversized(weight) => write!(f, "transaction too large: stripped weight {}", weight),
+            SanityError::OutputTooLarge(idx) => write!(f, "output {} exceeds the money supply", idx),
+            SanityError::DuplicateInput(ref outpoint) =>
+                write!(f, "duplicate input {}:{}", outpoint.txid, outpoint.vout),
+            SanityError::CoinbaseScriptSigSize(len) => write!(f, "coinbase scriptSig has bad length {}", len),
+            SanityError::NullPrevout(idx) => write!(f, "input {} spends the null outpoint", idx),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for SanityError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            SanityError::NoInputs => "transaction has no inputs",
+            SanityError::NoOutputs => "transaction has no outputs",
+            SanityError::Oversized(_) => "transaction too large",
+            SanityError::OutputTooLarge(_) => "output exceeds the money supply",
+            SanityError::TotalOutputTooLarge => "total output exceeds the money supply",
+            SanityError::DuplicateInput(_) => "duplicate input",
+            SanityError::CoinbaseScriptSigSize(_) => "coinbase scriptSig has bad length",
+            SanityError::NullPrevout(_) => "input spends the null outpoint",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(all(feature = "serde", feature = "strason"))]
     use strason::Json;
 
-    use super::{Transaction, TxIn};
+    use super::{Transaction, TxIn, TxOut};
 
     use blockdata::script::Script;
-    #[cfg(all(feature = "serde", feature = "strason"))]
     use consensus::encode::serialize;
     use consensus::encode::deserialize;
+    use util::amount::Amount;
+    use util::feerate::FeeRate;
     use util::hash::{BitcoinHash, Sha256dHash};
     use util::misc::hex_bytes;
 
@@ -595,6 +851,15 @@ mod tests {
         assert_eq!(tx.bitcoin_hash().be_hex_string(), "d6ac4a5e61657c4c604dcde855a1db74ec6b3e54f32695d72c5e11c7761ea1b4");
         assert_eq!(tx.txid().be_hex_string(), "9652aa62b0e748caeec40c4cb7bc17c6792435cc3dfe447dd1ca24f912a1c6ec");
         assert_eq!(tx.get_weight(), 2718);
+        assert_eq!(tx.get_size(), hex_tx.len() as u64);
+        assert_eq!(tx.get_vsize(), 680);
+        let mut stripped = tx.clone();
+        for input in &mut stripped.input {
+            input.witness.clear();
+        }
+        assert_eq!(tx.get_stripped_size(), serialize(&stripped).len() as u64);
+        assert_eq!(stripped.get_stripped_size(), stripped.get_size());
+        assert_eq!(stripped.get_weight(), 4 * stripped.get_size());
 
         // non-segwit tx from my mempool
         let hex_tx = hex_bytes(
@@ -611,6 +876,138 @@ mod tests {
         assert_eq!(tx.txid().be_hex_string(), "971ed48a62c143bbd9c87f4bafa2ef213cfa106c6e140f111931d0be307468dd");
     }
 
+    #[test]
+    fn test_check_sanity() {
+        use blockdata::constants::{self, COIN_VALUE};
+        use network::constants::Network;
+        use super::{OutPoint, SanityError};
+
+        let coinbase = constants::genesis_block(Network::Bitcoin).txdata[0].clone();
+        assert_eq!(coinbase.check_sanity(), Ok(()));
+
+        let tx: Transaction = deserialize(&hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        assert_eq!(tx.check_sanity(), Ok(()));
+
+        let mut bad = tx.clone();
+        bad.input.clear();
+        assert_eq!(bad.check_sanity(), Err(SanityError::NoInputs));
+        let mut bad = tx.clone();
+        bad.output.clear();
+        assert_eq!(bad.check_sanity(), Err(SanityError::NoOutputs));
+
+        let mut bad = tx.clone();
+        bad.output[0].script_pubkey = Script::from(vec![0; 1_000_000]);
+        match bad.check_sanity() {
+            Err(SanityError::Oversized(weight)) => assert!(weight > 4_000_000),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // Witness data does not count towards the limit
+        let mut witness = tx.clone();
+        witness.input[0].witness = vec![vec![0; 1_000_000]];
+        assert_eq!(witness.check_sanity(), Ok(()));
+
+        let mut bad = tx.clone();
+        bad.output[0].value = Amount::from_sat(21_000_000 * COIN_VALUE + 1);
+        assert_eq!(bad.check_sanity(), Err(SanityError::OutputTooLarge(0)));
+        bad.output[0].value = Amount::from_sat(21_000_000 * COIN_VALUE);
+        assert_eq!(bad.check_sanity(), Ok(()));
+        bad.output.push(TxOut { value: Amount::one_sat(), script_pubkey: Script::new() });
+        assert_eq!(bad.check_sanity(), Err(SanityError::TotalOutputTooLarge));
+
+        let mut bad = tx.clone();
+        let input = bad.input[0].clone();
+        bad.input.push(input);
+        assert_eq!(bad.check_sanity(), Err(SanityError::DuplicateInput(tx.input[0].previous_output)));
+
+        let mut bad = tx.clone();
+        let mut input = bad.input[0].clone();
+        input.previous_output = OutPoint::null();
+        bad.input.push(input);
+        assert_eq!(bad.check_sanity(), Err(SanityError::NullPrevout(1)));
+
+        let mut bad = coinbase.clone();
+        bad.input[0].script_sig = Script::from(vec![0]);
+        assert_eq!(bad.check_sanity(), Err(SanityError::CoinbaseScriptSigSize(1)));
+        bad.input[0].script_sig = Script::from(vec![0; 101]);
+        assert_eq!(bad.check_sanity(), Err(SanityError::CoinbaseScriptSigSize(101)));
+        bad.input[0].script_sig = Script::from(vec![0; 100]);
+        assert_eq!(bad.check_sanity(), Ok(()));
+    }
+
+    #[test]
+    fn test_sigop_cost() {
+        use blockdata::constants;
+        use blockdata::script::Builder;
+        use network::constants::Network;
+
+        let coinbase = constants::genesis_block(Network::Bitcoin).txdata[0].clone();
+        // A single P2PK output
+        assert_eq!(coinbase.get_legacy_sigop_count(), 1);
+        assert_eq!(coinbase.get_sigop_cost(&[]), Some(4));
+
+        let mut tx: Transaction = deserialize(&hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        let p2pkh = TxOut { value: Amount::zero(), script_pubkey: tx.output[0].script_pubkey.clone() };
+        assert_eq!(tx.get_legacy_sigop_count(), 1);
+        assert_eq!(tx.get_sigop_cost(&[p2pkh.clone()]), Some(4));
+        assert_eq!(tx.get_sigop_cost(&[]), None);
+
+        // Add a P2SH 1-of-2 multisig input and a P2WPKH input
+        let multisig = Script::from(hex_bytes("51210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f817982102715e91d37d239dea832f1460e91e368115d8ca6cc23a7da966795abad9e3b69952ae").unwrap());
+        let mut input = tx.input[0].clone();
+        input.previous_output.vout = 1;
+        input.script_sig = Builder::new().push_slice(&[]).push_slice(&[1; 72]).push_slice(&multisig[..]).into_script();
+        tx.input.push(input);
+        let mut input = tx.input[0].clone();
+        input.previous_output.vout = 2;
+        input.script_sig = Script::new();
+        input.witness = vec![vec![1; 72], vec![2; 33]];
+        tx.input.push(input);
+        let spent = [
+            p2pkh,
+            TxOut { value: Amount::zero(), script_pubkey: multisig.to_p2sh() },
+            TxOut { value: Amount::zero(), script_pubkey: Script::from(hex_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()) },
+        ];
+        assert_eq!(tx.get_legacy_sigop_count(), 1);
+        assert_eq!(tx.get_sigop_cost(&spent), Some(4 + 2 * 4 + 1));
+    }
+
+    #[test]
+    fn test_lock_time() {
+        use blockdata::locktime::{LockTime, RelativeLockTime, Sequence};
+
+        let mut tx: Transaction = deserialize(&hex_bytes("0100000001a15d57094aa7a21a28cb20b59aab8fc7d1149a3bdbcddba9c622e4f5f6a99ece010000006c493046022100f93bb0e7d8db7bd46e40132d1f8242026e045f03a0efe71bbb8e3f475e970d790221009337cd7f1f929f00cc6ff01f03729b069a7c21b59b1736ddfee5db5946c5da8c0121033b9b137ee87d5a812d6f506efdd37f0affa7ffc310711c06c7f3e097c9447c52ffffffff0100e1f505000000001976a9140389035a9225b3839e2bbf32d826a1e222031fd888ac00000000").unwrap()).unwrap();
+        assert_eq!(tx.get_lock_time(), LockTime::Blocks(0));
+        assert!(tx.is_final(0, 0));
+
+        // A final sequence number disables the lock time
+        tx.lock_time = 100;
+        assert!(tx.is_final(50, 0));
+        tx.input[0].sequence = 0xfffffffe;
+        assert!(!tx.is_final(50, 0));
+        assert!(!tx.is_final(100, 2_000_000_000));
+        assert!(tx.is_final(101, 0));
+
+        tx.lock_time = 1_500_000_000;
+        assert_eq!(tx.get_lock_time(), LockTime::Seconds(1_500_000_000));
+        assert!(!tx.is_final(1_000_000, 1_500_000_000));
+        assert!(tx.is_final(0, 1_500_000_001));
+
+        // Relative lock times only apply from version 2
+        tx.input[0].sequence = Sequence::from_relative_lock_time(RelativeLockTime::Blocks(10)).as_u32();
+        assert_eq!(tx.input[0].get_sequence().relative_lock_time(), Some(RelativeLockTime::Blocks(10)));
+        assert!(tx.is_relative_lock_satisfied(0, 100, 0, 100, 0));
+        tx.version = 2;
+        assert!(!tx.is_relative_lock_satisfied(0, 100, 0, 109, 0));
+        assert!(tx.is_relative_lock_satisfied(0, 100, 0, 110, 0));
+
+        tx.input[0].sequence = Sequence::from_relative_lock_time(RelativeLockTime::Time(1)).as_u32();
+        assert!(!tx.is_relative_lock_satisfied(0, 100, 1_500_000_000, 200, 1_500_000_511));
+        assert!(tx.is_relative_lock_satisfied(0, 100, 1_500_000_000, 200, 1_500_000_512));
+
+        tx.input[0].sequence |= 1 << 31;
+        assert!(tx.is_relative_lock_satisfied(0, 100, 1_500_000_000, 100, 0));
+    }
+
     #[test]
     #[cfg(all(feature = "serde", feature = "strason"))]
     fn test_txn_encode_decode() {
@@ -948,7 +1345,6 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature="bitcoinconsensus")]
     fn test_transaction_verify () {
         use hex::decode as hex_decode;
         use std::collections::HashMap;
@@ -968,14 +1364,36 @@ mod tests {
         spent.insert(spent2.txid(), spent2);
         spent.insert(spent3.txid(), spent3);
 
+        let spent_outputs: Vec<TxOut> = spending.input.iter().map(|input| {
+            spent[&input.previous_output.txid].output[input.previous_output.vout as usize].clone()
+        }).collect();
+        assert_eq!(spending.fee(&spent_outputs), Some(Amount::from_sat(16833)));
+        assert_eq!(spending.fee_rate(&spent_outputs),
+                   FeeRate::from_fee_and_weight(Amount::from_sat(16833), spending.get_weight()));
+        assert_eq!(spending.fee(&spent_outputs[..2]), None);
+        let mut outputs_too_big = spent_outputs.clone();
+        outputs_too_big[0].value = Amount::from_sat(0);
+        assert_eq!(spending.fee(&outputs_too_big), None);
+
+        #[cfg(feature="bitcoinconsensus")]
         spending.verify(&spent).unwrap();
+        spending.verify_with_flags(&spent, script::VERIFY_CONSENSUS).unwrap();
 
         // test that we get a failure if we corrupt a signature
         spending.input[1].witness[0][10] = 42;
+        #[cfg(feature="bitcoinconsensus")]
         match spending.verify(&spent).err().unwrap() {
             script::Error::BitcoinConsensus(_) => {},
             _ => panic!("Wrong error type"),
         }
+        assert_eq!(spending.verify_with_flags(&spent, script::VERIFY_CONSENSUS),
+                   Err(script::Error::EvalFalse));
+
+        // and an error for a missing spent transaction
+        let txid = spending.input[0].previous_output.txid;
+        spent.remove(&txid);
+        assert_eq!(spending.verify_with_flags(&spent, script::VERIFY_CONSENSUS),
+                   Err(script::Error::UnknownSpentTransaction(txid)));
     }
 }
 
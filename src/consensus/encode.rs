@@ -45,6 +45,7 @@ use hex::encode as hex_encode;
 use bitcoin_bech32;
 
 use util::base58;
+use util::psbt;
 
 /// Encoding error
 #[derive(Debug)]
@@ -55,6 +56,8 @@ pub enum Error {
     Base58(base58::Error),
     /// Bech32 encoding error
     Bech32(bitcoin_bech32::Error),
+    /// PSBT-related error
+    Psbt(psbt::Error),
     /// Error from the `byteorder` crate
     ByteOrder(io::Error),
     /// Network magic was not expected
@@ -98,6 +101,7 @@ impl fmt::Display for Error {
             Error::Io(ref e) => fmt::Display::fmt(e, f),
             Error::Base58(ref e) => fmt::Display::fmt(e, f),
             Error::Bech32(ref e) => fmt::Display::fmt(e, f),
+            Error::Psbt(ref e) => fmt::Display::fmt(e, f),
             Error::ByteOrder(ref e) => fmt::Display::fmt(e, f),
             Error::UnexpectedNetworkMagic { expected: ref e, actual: ref a } => write!(f, "{}: expected {}, actual {}", error::Error::description(self), e, a),
             Error::OversizedVectorAllocation { requested: ref r, max: ref m } => write!(f, "{}: requested {}, maximum {}", error::Error::description(self), r, m),
@@ -118,6 +122,7 @@ impl error::Error for Error {
             Error::Io(ref e) => Some(e),
             Error::Base58(ref e) => Some(e),
             Error::Bech32(ref e) => Some(e),
+            Error::Psbt(ref e) => Some(e),
             Error::ByteOrder(ref e) => Some(e),
             Error::UnexpectedNetworkMagic { .. }
             | Error::OversizedVectorAllocation { .. }
@@ -136,6 +141,7 @@ impl error::Error for Error {
             Error::Io(ref e) => e.description(),
             Error::Base58(ref e) => e.description(),
             Error::Bech32(ref e) => e.description(),
+            Error::Psbt(ref e) => e.description(),
             Error::ByteOrder(ref e) => e.description(),
             Error::UnexpectedNetworkMagic { .. } => "unexpected network magic",
             Error::OversizedVectorAllocation { .. } => "allocation of oversized vector requested",
@@ -164,6 +170,12 @@ impl From<bitcoin_bech32::Error> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<psbt::Error> for Error {
+    fn from(e: psbt::Error) -> Error {
+        Error::Psbt(e)
+    }
+}
 
 #[doc(hidden)]
 impl From<io::Error> for Error {
@@ -20,6 +20,8 @@
 
 pub mod encode;
 pub mod params;
+pub mod pow;
+pub mod versionbits;
 
 pub use self::encode::{Encodable, Decodable, Encoder, Decoder, serialize, deserialize};
 pub use self::params::Params;
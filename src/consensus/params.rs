@@ -17,7 +17,9 @@
 //! This module provides predefined set of parameters for different chains.
 //!
 
+use blockdata::constants::COIN_VALUE;
 use network::constants::Network;
+use util::amount::Amount;
 use util::uint::Uint256;
 
 /// Lowest possible difficulty for Mainnet.
@@ -42,6 +44,30 @@ const MAX_BITS_REGTEST: Uint256 = Uint256([
     0x7fffffffffffffffu64,
 ]);
 
+/// Start time of a deployment which is active from the genesis block.
+pub const ALWAYS_ACTIVE: i64 = -1;
+/// Start time of a deployment which can never activate.
+pub const NEVER_ACTIVE: i64 = -2;
+/// Timeout of a deployment which never times out.
+pub const NO_TIMEOUT: i64 = ::std::i64::MAX;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A soft fork deployment signalled with version bits (BIP9).
+pub struct Bip9Deployment {
+    /// Name of the deployment, as reported by Bitcoin Core.
+    pub name: &'static str,
+    /// Bit of the block version which signals readiness for the deployment.
+    pub bit: u8,
+    /// Median time past from which signalling starts to count, or
+    /// `ALWAYS_ACTIVE` or `NEVER_ACTIVE`.
+    pub start_time: i64,
+    /// Median time past after which the deployment fails if not locked in.
+    pub timeout: i64,
+    /// Lowest height at which the deployment may become active, delaying it
+    /// after lock-in if necessary.
+    pub min_activation_height: u32,
+}
+
 #[derive(Debug, Clone)]
 /// Parameters that influence chain consensus.
 pub struct Params {
@@ -49,6 +75,8 @@ pub struct Params {
     pub network: Network,
     /// Time when BIP16 becomes active.
     pub bip16_time: u32,
+    /// Number of blocks after which the block subsidy halves.
+    pub subsidy_halving_interval: u32,
     /// Block height at which BIP34 becomes active.
     pub bip34_height: u32,
     /// Block height at which BIP65 becomes active.
@@ -71,6 +99,8 @@ pub struct Params {
     pub allow_min_difficulty_blocks: bool,
     /// Determines whether retargeting is disabled for this network or not.
     pub no_pow_retargeting: bool,
+    /// Version bits deployments.
+    pub deployments: Vec<Bip9Deployment>,
 }
 
 impl Params {
@@ -80,6 +110,7 @@ impl Params {
             Network::Bitcoin => Params {
                 network: Network::Bitcoin,
                 bip16_time: 1333238400,                 // Apr 1 2012
+                subsidy_halving_interval: 210000,
                 bip34_height: 227931, // 000000000000024b89b42a942fe0d9fea3bb44ab7bd1b19115dd6a759c0808b8
                 bip65_height: 388381, // 000000000000000004c2b624ed5d7756c508d90fd0da2c7c679febfa6c4735f0
                 bip66_height: 363725, // 00000000000000000379eaa19dce8c9b722d46ae6a57c2f1a988119488b50931
@@ -90,10 +121,27 @@ impl Params {
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: false,
                 no_pow_retargeting: false,
+                deployments: vec![
+                    Bip9Deployment {
+                        name: "testdummy",
+                        bit: 28,
+                        start_time: NEVER_ACTIVE,
+                        timeout: NO_TIMEOUT,
+                        min_activation_height: 0,
+                    },
+                    Bip9Deployment {
+                        name: "taproot",
+                        bit: 2,
+                        start_time: 1619222400, // Apr 24 2021
+                        timeout: 1628640000,    // Aug 11 2021
+                        min_activation_height: 709632,
+                    },
+                ],
             },
             Network::Testnet => Params {
                 network: Network::Testnet,
                 bip16_time: 1333238400,                 // Apr 1 2012
+                subsidy_halving_interval: 210000,
                 bip34_height: 21111, // 0000000023b3a96d3484e5abb3755c413e7d41500f8e2a5c3f0dd01299cd8ef8
                 bip65_height: 581885, // 00000000007f6655f22f98e72ed80d8b06dc761d5da09df0fa1dc4be4f861eb6
                 bip66_height: 330776, // 000000002104c8c45e99a8853285a3b592602a3ccde2b832481da85e9e4ba182
@@ -104,10 +152,27 @@ impl Params {
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: false,
+                deployments: vec![
+                    Bip9Deployment {
+                        name: "testdummy",
+                        bit: 28,
+                        start_time: NEVER_ACTIVE,
+                        timeout: NO_TIMEOUT,
+                        min_activation_height: 0,
+                    },
+                    Bip9Deployment {
+                        name: "taproot",
+                        bit: 2,
+                        start_time: 1619222400, // Apr 24 2021
+                        timeout: 1628640000,    // Aug 11 2021
+                        min_activation_height: 0,
+                    },
+                ],
             },
             Network::Regtest => Params {
                 network: Network::Regtest,
                 bip16_time: 1333238400,  // Apr 1 2012
+                subsidy_halving_interval: 150,
                 bip34_height: 100000000, // not activated on regtest
                 bip65_height: 1351,
                 bip66_height: 1251,                    // used only in rpc tests
@@ -118,6 +183,22 @@ impl Params {
                 pow_target_timespan: 14 * 24 * 60 * 60, // 2 weeks.
                 allow_min_difficulty_blocks: true,
                 no_pow_retargeting: true,
+                deployments: vec![
+                    Bip9Deployment {
+                        name: "testdummy",
+                        bit: 28,
+                        start_time: 0,
+                        timeout: NO_TIMEOUT,
+                        min_activation_height: 0,
+                    },
+                    Bip9Deployment {
+                        name: "taproot",
+                        bit: 2,
+                        start_time: ALWAYS_ACTIVE,
+                        timeout: NO_TIMEOUT,
+                        min_activation_height: 0,
+                    },
+                ],
             },
         }
     }
@@ -126,4 +207,49 @@ impl Params {
     pub fn difficulty_adjustment_interval(&self) -> u64 {
         self.pow_target_timespan / self.pow_target_spacing
     }
+
+    /// Calculates the new coins a block at the given height may create.
+    pub fn block_subsidy(&self, height: u32) -> Amount {
+        let halvings = height / self.subsidy_halving_interval;
+        // Shifting a u64 by 64 or more bits overflows
+        if halvings >= 64 {
+            return Amount::zero();
+        }
+        Amount::from_sat((50 * COIN_VALUE) >> halvings)
+    }
+
+    /// Looks up a version bits deployment by name.
+    pub fn deployment(&self, name: &str) -> Option<&Bip9Deployment> {
+        self.deployments.iter().find(|deployment| deployment.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::constants::COIN_VALUE;
+    use network::constants::Network;
+    use util::amount::Amount;
+
+    use super::Params;
+
+    #[test]
+    fn block_subsidy() {
+        let params = Params::new(Network::Bitcoin);
+        assert_eq!(params.block_subsidy(0), Amount::from_sat(50 * COIN_VALUE));
+        assert_eq!(params.block_subsidy(209_999), Amount::from_sat(50 * COIN_VALUE));
+        assert_eq!(params.block_subsidy(210_000), Amount::from_sat(25 * COIN_VALUE));
+        assert_eq!(params.block_subsidy(630_000), Amount::from_sat(625_000_000));
+        assert_eq!(params.block_subsidy(840_000), Amount::from_sat(312_500_000));
+        assert_eq!(params.block_subsidy(6_720_000), Amount::from_sat(1));
+        assert_eq!(params.block_subsidy(6_930_000), Amount::zero());
+        assert_eq!(params.block_subsidy(::std::u32::MAX), Amount::zero());
+
+        // The subsidies sum to just under 21 million bitcoin
+        let total: u64 = (0..64).map(|halving| params.block_subsidy(halving * 210_000).as_sat() * 210_000).sum();
+        assert_eq!(total, 2_099_999_997_690_000);
+
+        let params = Params::new(Network::Regtest);
+        assert_eq!(params.block_subsidy(149), Amount::from_sat(50 * COIN_VALUE));
+        assert_eq!(params.block_subsidy(150), Amount::from_sat(25 * COIN_VALUE));
+    }
 }
@@ -0,0 +1,192 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Proof of work
+//!
+//! Computation of the target which each block's proof of work must meet,
+//! following the difficulty adjustment rules of the given chain.
+//!
+
+use std::cmp;
+
+use blockdata::block::BlockHeader;
+use consensus::params::Params;
+use util::uint::Uint256;
+
+/// Computes the compact target (`bits`) required of the block at `height`
+/// with timestamp `time`, whose parent is `last`.
+///
+/// `parent` looks up the parent of a header. It is called only as far back
+/// as needed, at most to the first block of the current retargeting period,
+/// so that callers can walk back through whatever structure holds their
+/// headers. Returns `None` if an ancestor needed to determine the target is
+/// not found.
+///
+/// # Panics
+/// Panics if `height` is zero: the genesis block has no predecessor.
+pub fn next_work_required<F>(params: &Params, last: &BlockHeader, height: u32, time: u32, parent: F) -> Option<u32>
+    where F: Fn(&BlockHeader) -> Option<BlockHeader>
+{
+    assert!(height > 0, "the genesis block has no required target");
+    let interval = params.difficulty_adjustment_interval();
+    let pow_limit_bits = BlockHeader::compact_target_from_u256(&params.pow_limit);
+
+    if height as u64 % interval != 0 {
+        if params.allow_min_difficulty_blocks {
+            // If the new block's timestamp is more than twice the target
+            // spacing after the previous block, it may be mined at the
+            // minimum difficulty
+            if time as u64 > last.time as u64 + params.pow_target_spacing * 2 {
+                return Some(pow_limit_bits);
+            }
+            // Otherwise it uses the target of the last block which was not
+            // mined at the minimum difficulty under this rule
+            let mut header = *last;
+            let mut header_height = height - 1;
+            while header_height > 0 && header_height as u64 % interval != 0 && header.bits == pow_limit_bits {
+                header = match parent(&header) {
+                    Some(header) => header,
+                    None => return None,
+                };
+                header_height -= 1;
+            }
+            return Some(header.bits);
+        }
+        return Some(last.bits);
+    }
+
+    if params.no_pow_retargeting {
+        return Some(last.bits);
+    }
+    let mut first = *last;
+    for _ in 1..interval {
+        first = match parent(&first) {
+            Some(header) => header,
+            None => return None,
+        };
+    }
+    Some(calculate_next_work_required(params, last, first.time))
+}
+
+/// Computes the compact target for the first block of a retargeting period,
+/// given the last header of the previous period and the timestamp of its
+/// first block
+pub fn calculate_next_work_required(params: &Params, last: &BlockHeader, first_time: u32) -> u32 {
+    if params.no_pow_retargeting {
+        return last.bits;
+    }
+
+    // Limit the adjustment to a factor of four either way
+    let timespan = params.pow_target_timespan;
+    let actual_timespan = last.time as i64 - first_time as i64;
+    let actual_timespan = cmp::min(cmp::max(actual_timespan, (timespan / 4) as i64), (timespan * 4) as i64);
+
+    let target = last.target().mul_u32(actual_timespan as u32) / Uint256::from_u64(timespan).unwrap();
+    if target > params.pow_limit {
+        BlockHeader::compact_target_from_u256(&params.pow_limit)
+    } else {
+        BlockHeader::compact_target_from_u256(&target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use blockdata::block::BlockHeader;
+    use consensus::params::Params;
+    use network::constants::Network;
+    use util::hash::{BitcoinHash, Sha256dHash};
+
+    use super::*;
+
+    fn header(time: u32, bits: u32) -> BlockHeader {
+        BlockHeader {
+            version: 1,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time: time,
+            bits: bits,
+            nonce: 0,
+        }
+    }
+
+    /// Links consecutive headers, returning them by hash
+    fn link(headers: &mut [BlockHeader]) -> HashMap<Sha256dHash, BlockHeader> {
+        for i in 1..headers.len() {
+            headers[i].prev_blockhash = headers[i - 1].bitcoin_hash();
+        }
+        headers.iter().map(|header| (header.bitcoin_hash(), *header)).collect()
+    }
+
+    #[test]
+    fn calculate_retarget() {
+        let params = Params::new(Network::Bitcoin);
+        // Block 32256
+        assert_eq!(calculate_next_work_required(&params, &header(1262152739, 0x1d00ffff), 1261130161), 0x1d00d86a);
+        // Block 2016 is limited by the proof of work limit
+        assert_eq!(calculate_next_work_required(&params, &header(1233061996, 0x1d00ffff), 1231006505), 0x1d00ffff);
+        // Block 68544 is limited to a four times increase in difficulty
+        assert_eq!(calculate_next_work_required(&params, &header(1279297671, 0x1c05a3f4), 1279008237), 0x1c0168fd);
+        // Block 46368 is limited to a four times decrease in difficulty
+        assert_eq!(calculate_next_work_required(&params, &header(1269211443, 0x1c387f6f), 1263163443), 0x1d00e1fd);
+    }
+
+    #[test]
+    fn next_work() {
+        let params = Params::new(Network::Bitcoin);
+        let mut headers: Vec<BlockHeader> = (0..2016).map(|i| header(1261130161 + i, 0x1d00ffff)).collect();
+        headers[2015].time = 1262152739;
+        let mut by_hash = link(&mut headers);
+        let last = headers[2015];
+
+        // Mainnet only changes the target at retargeting boundaries
+        assert_eq!(next_work_required(&params, &last, 32256, 1262153000, |h| by_hash.get(&h.prev_blockhash).cloned()),
+                   Some(0x1d00d86a));
+        assert_eq!(next_work_required(&params, &last, 32255, 1262153000, |_| None), Some(0x1d00ffff));
+        assert_eq!(next_work_required(&params, &last, 32257, 1262999999, |_| None), Some(0x1d00ffff));
+        by_hash.remove(&headers[0].bitcoin_hash());
+        assert_eq!(next_work_required(&params, &last, 32256, 1262153000, |h| by_hash.get(&h.prev_blockhash).cloned()),
+                   None);
+
+        // Regtest never retargets
+        let params = Params::new(Network::Regtest);
+        let last = header(1296688602 + 2015 * 600, 0x207fffff);
+        assert_eq!(next_work_required(&params, &last, 2016, 1296688602 + 2016 * 600, |_| None), Some(0x207fffff));
+    }
+
+    #[test]
+    fn testnet_min_difficulty() {
+        let params = Params::new(Network::Testnet);
+        let mut headers: Vec<BlockHeader> = (0..10).map(|i| header(1300000000 + i * 600, 0x1c0ffff0)).collect();
+        headers[8].bits = 0x1d00ffff;
+        headers[9].bits = 0x1d00ffff;
+        let mut by_hash = link(&mut headers);
+        let last = headers[9];
+        let next_time = last.time + 600;
+
+        // A block more than twenty minutes after its predecessor may use the
+        // minimum difficulty
+        assert_eq!(next_work_required(&params, &last, 4042, next_time + 601, |_| None), Some(0x1d00ffff));
+        // Otherwise it uses the last target not set by that rule
+        assert_eq!(next_work_required(&params, &last, 4042, next_time, |h| by_hash.get(&h.prev_blockhash).cloned()),
+                   Some(0x1c0ffff0));
+        by_hash.remove(&headers[7].bitcoin_hash());
+        assert_eq!(next_work_required(&params, &last, 4042, next_time, |h| by_hash.get(&h.prev_blockhash).cloned()),
+                   None);
+        // The search stops at the start of the retargeting period
+        assert_eq!(next_work_required(&params, &last, 4034, next_time, |h| by_hash.get(&h.prev_blockhash).cloned()),
+                   Some(0x1d00ffff));
+    }
+}
@@ -0,0 +1,313 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Version bits
+//!
+//! The state machine deciding when soft forks deployed with version bits
+//! signalling activate (BIP9). A deployment's state changes only at the
+//! boundaries of retargeting periods, according to the number of blocks in
+//! the previous period which signalled for it and to that period's median
+//! time past.
+//!
+
+use std::fmt;
+
+use blockdata::block::BlockHeader;
+use consensus::params::{Bip9Deployment, Params, ALWAYS_ACTIVE, NEVER_ACTIVE};
+
+/// The bits of the block version which must be set to `VERSIONBITS_TOP_BITS`
+/// for the other bits to signal for deployments
+pub const VERSIONBITS_TOP_MASK: u32 = 0xE0000000;
+/// The top bits of a block version signalling with version bits
+pub const VERSIONBITS_TOP_BITS: u32 = 0x20000000;
+/// The number of blocks whose median timestamp is compared against a
+/// deployment's start time and timeout
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// The state of a version bits deployment
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ThresholdState {
+    /// The deployment's start time has not been reached
+    Defined,
+    /// Blocks are counted towards the activation threshold
+    Started,
+    /// The threshold was met, and the deployment will activate
+    LockedIn,
+    /// The deployment's rules are enforced
+    Active,
+    /// The deployment timed out before reaching the threshold
+    Failed,
+}
+
+impl fmt::Display for ThresholdState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ThresholdState::Defined => "defined",
+            ThresholdState::Started => "started",
+            ThresholdState::LockedIn => "locked_in",
+            ThresholdState::Active => "active",
+            ThresholdState::Failed => "failed",
+        })
+    }
+}
+
+/// Signalling statistics for a deployment within a retargeting period, as
+/// reported by Bitcoin Core's `getdeploymentinfo`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Bip9Stats {
+    /// The length of the period in blocks
+    pub period: u32,
+    /// The number of signalling blocks required to lock in
+    pub threshold: u32,
+    /// The number of blocks of the period so far
+    pub elapsed: u32,
+    /// The number of those blocks which signalled
+    pub count: u32,
+    /// Whether the threshold can still be met in this period
+    pub possible: bool,
+}
+
+/// Whether a block version signals for the deployment using the given bit
+pub fn signals(version: u32, bit: u8) -> bool {
+    version & VERSIONBITS_TOP_MASK == VERSIONBITS_TOP_BITS && (version >> bit) & 1 == 1
+}
+
+/// Computes the state of a deployment for the block following `headers`,
+/// which must be a chain starting at the genesis block
+pub fn deployment_state(params: &Params, deployment: &Bip9Deployment, headers: &[BlockHeader]) -> ThresholdState {
+    state_and_since(params, deployment, headers).0
+}
+
+/// Computes the height of the first block of the period in which the
+/// deployment entered its state for the block following `headers`, which
+/// must be a chain starting at the genesis block
+pub fn deployment_state_since(params: &Params, deployment: &Bip9Deployment, headers: &[BlockHeader]) -> u32 {
+    state_and_since(params, deployment, headers).1
+}
+
+/// Computes the signalling statistics for a deployment over the period
+/// containing the last of `headers`, which must be a chain starting at the
+/// genesis block
+pub fn deployment_statistics(params: &Params, deployment: &Bip9Deployment, headers: &[BlockHeader]) -> Bip9Stats {
+    let period = params.miner_confirmation_window;
+    let threshold = params.rule_change_activation_threshold;
+    let elapsed = if headers.is_empty() { 0 } else { 1 + (headers.len() as u32 - 1) % period };
+    let count = headers[headers.len() - elapsed as usize..].iter()
+                                                           .filter(|header| signals(header.version, deployment.bit))
+                                                           .count() as u32;
+    Bip9Stats {
+        period: period,
+        threshold: threshold,
+        elapsed: elapsed,
+        count: count,
+        possible: period - threshold >= elapsed - count,
+    }
+}
+
+/// The median timestamp of the header at the given height and up to ten of
+/// its ancestors
+fn median_time_past(headers: &[BlockHeader], height: usize) -> i64 {
+    let start = (height + 1).saturating_sub(MEDIAN_TIME_SPAN);
+    let mut times: Vec<u32> = headers[start..height + 1].iter().map(|header| header.time).collect();
+    times.sort();
+    times[times.len() / 2] as i64
+}
+
+fn state_and_since(params: &Params, deployment: &Bip9Deployment, headers: &[BlockHeader]) -> (ThresholdState, u32) {
+    if deployment.start_time == ALWAYS_ACTIVE {
+        return (ThresholdState::Active, 0);
+    }
+    if deployment.start_time == NEVER_ACTIVE {
+        return (ThresholdState::Failed, 0);
+    }
+    let period = params.miner_confirmation_window as usize;
+    let threshold = params.rule_change_activation_threshold as usize;
+
+    // Walk back over the ends of completed periods until one which is known
+    // to be in the defined state, since its median time past is before the
+    // start time
+    let mut period_ends = vec![];
+    let mut end = (headers.len() / period * period).checked_sub(1);
+    while let Some(height) = end {
+        if median_time_past(headers, height) < deployment.start_time {
+            break;
+        }
+        period_ends.push(height);
+        end = height.checked_sub(period);
+    }
+
+    // Then walk forward applying the state transitions
+    let mut state = ThresholdState::Defined;
+    let mut since = 0;
+    for &height in period_ends.iter().rev() {
+        let median_time_past = median_time_past(headers, height);
+        let next = match state {
+            ThresholdState::Defined => {
+                if median_time_past >= deployment.start_time {
+                    ThresholdState::Started
+                } else {
+                    ThresholdState::Defined
+                }
+            }
+            ThresholdState::Started => {
+                let count = headers[height + 1 - period..height + 1].iter()
+                                                                    .filter(|header| signals(header.version, deployment.bit))
+                                                                    .count();
+                if count >= threshold {
+                    ThresholdState::LockedIn
+                } else if median_time_past >= deployment.timeout {
+                    ThresholdState::Failed
+                } else {
+                    ThresholdState::Started
+                }
+            }
+            ThresholdState::LockedIn => {
+                if height as u32 + 1 >= deployment.min_activation_height {
+                    ThresholdState::Active
+                } else {
+                    ThresholdState::LockedIn
+                }
+            }
+            ThresholdState::Active | ThresholdState::Failed => state,
+        };
+        if next != state {
+            state = next;
+            since = height as u32 + 1;
+        }
+    }
+    (state, since)
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::block::BlockHeader;
+    use consensus::params::{Bip9Deployment, Params, NO_TIMEOUT};
+    use network::constants::Network;
+
+    use super::*;
+
+    /// A regtest chain whose block at each height has the given version,
+    /// with blocks ten minutes apart from the given start time
+    fn chain(start_time: u32, versions: &[u32]) -> Vec<BlockHeader> {
+        versions.iter().enumerate().map(|(height, &version)| BlockHeader {
+            version: version,
+            prev_blockhash: Default::default(),
+            merkle_root: Default::default(),
+            time: start_time + height as u32 * 600,
+            bits: 0x207fffff,
+            nonce: 0,
+        }).collect()
+    }
+
+    fn deployment(start_time: i64, timeout: i64, min_activation_height: u32) -> Bip9Deployment {
+        Bip9Deployment {
+            name: "test",
+            bit: 5,
+            start_time: start_time,
+            timeout: timeout,
+            min_activation_height: min_activation_height,
+        }
+    }
+
+    #[test]
+    fn signalling() {
+        assert!(signals(0x20000020, 5));
+        assert!(!signals(0x20000020, 4));
+        assert!(!signals(0x40000020, 5));
+        assert!(!signals(0x00000020, 5));
+    }
+
+    #[test]
+    fn params_deployments() {
+        let params = Params::new(Network::Regtest);
+        assert_eq!(params.deployment("taproot").unwrap().bit, 2);
+        assert!(params.deployment("segwit").is_none());
+        assert_eq!(deployment_state(&params, params.deployment("taproot").unwrap(), &[]), ThresholdState::Active);
+
+        let params = Params::new(Network::Bitcoin);
+        let dummy = params.deployment("testdummy").unwrap();
+        assert_eq!(deployment_state(&params, dummy, &[]), ThresholdState::Failed);
+    }
+
+    #[test]
+    fn activation() {
+        // Regtest has periods of 144 blocks, with a threshold of 108
+        let params = Params::new(Network::Regtest);
+        let start = 1_500_000_000;
+        let signal = 0x20000020;
+        let deploy = deployment(start as i64 + 144 * 600, NO_TIMEOUT, 144 * 5);
+
+        // Defined until a period ends with median time past after the start
+        let mut versions = vec![signal; 144 * 2 - 1];
+        let headers = chain(start, &versions);
+        assert_eq!(deployment_state(&params, &deploy, &headers[..144]), ThresholdState::Defined);
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::Defined);
+
+        // Started in the third period, in which too few blocks signal
+        versions.push(signal);
+        versions.extend(vec![signal; 107]);
+        versions.extend(vec![0x20000000; 37]);
+        let headers = chain(start, &versions);
+        assert_eq!(deployment_state(&params, &deploy, &headers[..144 * 2]), ThresholdState::Started);
+        assert_eq!(deployment_state_since(&params, &deploy, &headers[..144 * 2]), 144 * 2);
+        let stats = deployment_statistics(&params, &deploy, &headers[..144 * 2 + 100]);
+        assert_eq!(stats, Bip9Stats { period: 144, threshold: 108, elapsed: 100, count: 100, possible: true });
+        let stats = deployment_statistics(&params, &deploy, &headers);
+        assert_eq!(stats, Bip9Stats { period: 144, threshold: 108, elapsed: 144, count: 107, possible: false });
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::Started);
+
+        // Locked in after a period with enough signalling blocks, and
+        // active at the minimum activation height
+        versions.extend(vec![signal; 108]);
+        versions.extend(vec![0x20000000; 36]);
+        let headers = chain(start, &versions);
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::LockedIn);
+        assert_eq!(deployment_state_since(&params, &deploy, &headers), 144 * 4);
+        versions.extend(vec![0x20000000; 144]);
+        let headers = chain(start, &versions);
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::Active);
+        assert_eq!(deployment_state_since(&params, &deploy, &headers), 144 * 5);
+        versions.extend(vec![0x20000000; 1000]);
+        let headers = chain(start, &versions);
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::Active);
+        assert_eq!(deployment_state_since(&params, &deploy, &headers), 144 * 5);
+
+        // Activation is delayed until the minimum activation height
+        let delayed = deployment(start as i64 + 144 * 600, NO_TIMEOUT, 144 * 7);
+        let headers = chain(start, &versions[..144 * 6]);
+        assert_eq!(deployment_state(&params, &delayed, &headers), ThresholdState::LockedIn);
+        let headers = chain(start, &versions[..144 * 7]);
+        assert_eq!(deployment_state(&params, &delayed, &headers), ThresholdState::Active);
+    }
+
+    #[test]
+    fn timeout() {
+        let params = Params::new(Network::Regtest);
+        let start = 1_500_000_000;
+        let deploy = deployment(start as i64, start as i64 + 144 * 2 * 600, 0);
+
+        let headers = chain(start, &vec![0x20000000; 144 * 2]);
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::Started);
+        let headers = chain(start, &vec![0x20000000; 144 * 3]);
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::Failed);
+        assert_eq!(deployment_state_since(&params, &deploy, &headers), 144 * 3);
+
+        // Meeting the threshold in the last period takes precedence over
+        // the timeout
+        let mut versions = vec![0x20000000; 144 * 2];
+        versions.extend(vec![0x20000020; 144]);
+        let headers = chain(start, &versions);
+        assert_eq!(deployment_state(&params, &deploy, &headers), ThresholdState::LockedIn);
+    }
+}
@@ -41,6 +41,7 @@
 #![deny(unused_mut)]
 #![deny(missing_docs)]
 
+#[cfg(feature = "base64")] extern crate base64;
 extern crate bitcoin_bech32;
 extern crate byteorder;
 extern crate crypto;
@@ -50,6 +51,7 @@ extern crate secp256k1;
 #[cfg(feature = "serde")] extern crate serde;
 #[cfg(feature = "strason")] extern crate strason;
 #[cfg(all(test, feature = "unstable"))] extern crate test;
+#[cfg(test)] extern crate rustc_serialize;
 #[cfg(feature="bitcoinconsensus")] extern crate bitcoinconsensus;
 
 #[cfg(test)]
@@ -75,6 +77,9 @@ pub use blockdata::transaction::SigHashType;
 pub use consensus::encode::VarInt;
 pub use util::Error;
 pub use util::address::Address;
+pub use util::amount::Amount;
+pub use util::amount::SignedAmount;
+pub use util::feerate::FeeRate;
 pub use util::hash::BitcoinHash;
 pub use util::privkey::Privkey;
 pub use util::decimal::Decimal;
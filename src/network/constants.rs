@@ -44,6 +44,10 @@ use consensus::encode::{self, Encoder, Decoder};
 pub const PROTOCOL_VERSION: u32 = 70001;
 /// Bitfield of services provided by this node
 pub const SERVICES: u64 = 0;
+/// Service bit of nodes which support bloom filtering (BIP111)
+pub const NODE_BLOOM: u64 = 1 << 2;
+/// Service bit of nodes which serve compact block filters (BIP157)
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
 /// User agent as it appears in the version message
 pub const USER_AGENT: &'static str = "bitcoin-rust v0.1";
 
@@ -28,10 +28,12 @@ use blockdata::transaction;
 use network::address::Address;
 use network::message_network;
 use network::message_blockdata;
+use network::message_filter;
 use consensus::encode::{Decodable, Encodable};
 use consensus::encode::CheckedData;
 use consensus::encode::{self, serialize, Encoder, Decoder};
 use util;
+use util::{bloom, merkleblock};
 
 /// Serializer for command string
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -116,10 +118,29 @@ pub enum NetworkMessage {
     /// `pong`
     Pong(u64),
     // TODO: reject,
-    // TODO: bloom filtering
+    /// `filterload`
+    FilterLoad(bloom::BloomFilter),
+    /// `filteradd`
+    FilterAdd(Vec<u8>),
+    /// `filterclear`
+    FilterClear,
+    /// `merkleblock`
+    MerkleBlock(merkleblock::MerkleBlock),
     // TODO: alert
     /// `alert`
-    Alert(Vec<u8>)
+    Alert(Vec<u8>),
+    /// `getcfilters`
+    GetCFilters(message_filter::GetCFilters),
+    /// `cfilter`
+    CFilter(message_filter::CFilter),
+    /// `getcfheaders`
+    GetCFHeaders(message_filter::GetCFHeaders),
+    /// `cfheaders`
+    CFHeaders(message_filter::CFHeaders),
+    /// `getcfcheckpt`
+    GetCFCheckpt(message_filter::GetCFCheckpt),
+    /// `cfcheckpt`
+    CFCheckpt(message_filter::CFCheckpt),
 }
 
 impl RawNetworkMessage {
@@ -141,7 +162,17 @@ impl RawNetworkMessage {
             NetworkMessage::GetAddr    => "getaddr",
             NetworkMessage::Ping(_)    => "ping",
             NetworkMessage::Pong(_)    => "pong",
+            NetworkMessage::FilterLoad(_) => "filterload",
+            NetworkMessage::FilterAdd(_) => "filteradd",
+            NetworkMessage::FilterClear => "filterclear",
+            NetworkMessage::MerkleBlock(_) => "merkleblock",
             NetworkMessage::Alert(_)    => "alert",
+            NetworkMessage::GetCFilters(_) => "getcfilters",
+            NetworkMessage::CFilter(_) => "cfilter",
+            NetworkMessage::GetCFHeaders(_) => "getcfheaders",
+            NetworkMessage::CFHeaders(_) => "cfheaders",
+            NetworkMessage::GetCFCheckpt(_) => "getcfcheckpt",
+            NetworkMessage::CFCheckpt(_) => "cfcheckpt",
         }.to_owned()
     }
 }
@@ -163,10 +194,20 @@ impl<S: Encoder> Encodable<S> for RawNetworkMessage {
             NetworkMessage::Headers(ref dat) => serialize(dat),
             NetworkMessage::Ping(ref dat)    => serialize(dat),
             NetworkMessage::Pong(ref dat)    => serialize(dat),
+            NetworkMessage::FilterLoad(ref dat) => serialize(dat),
+            NetworkMessage::FilterAdd(ref dat) => serialize(dat),
+            NetworkMessage::MerkleBlock(ref dat) => serialize(dat),
             NetworkMessage::Alert(ref dat)    => serialize(dat),
+            NetworkMessage::GetCFilters(ref dat) => serialize(dat),
+            NetworkMessage::CFilter(ref dat) => serialize(dat),
+            NetworkMessage::GetCFHeaders(ref dat) => serialize(dat),
+            NetworkMessage::CFHeaders(ref dat) => serialize(dat),
+            NetworkMessage::GetCFCheckpt(ref dat) => serialize(dat),
+            NetworkMessage::CFCheckpt(ref dat) => serialize(dat),
             NetworkMessage::Verack
             | NetworkMessage::MemPool
-            | NetworkMessage::GetAddr => vec![],
+            | NetworkMessage::GetAddr
+            | NetworkMessage::FilterClear => vec![],
         }).consensus_encode(s)
     }
 }
@@ -194,7 +235,17 @@ impl<D: Decoder> Decodable<D> for RawNetworkMessage {
             "ping"    => NetworkMessage::Ping(Decodable::consensus_decode(&mut mem_d)?),
             "pong"    => NetworkMessage::Pong(Decodable::consensus_decode(&mut mem_d)?),
             "tx"      => NetworkMessage::Tx(Decodable::consensus_decode(&mut mem_d)?),
+            "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode(&mut mem_d)?),
+            "filteradd" => NetworkMessage::FilterAdd(Decodable::consensus_decode(&mut mem_d)?),
+            "filterclear" => NetworkMessage::FilterClear,
+            "merkleblock" => NetworkMessage::MerkleBlock(Decodable::consensus_decode(&mut mem_d)?),
             "alert"   => NetworkMessage::Alert(Decodable::consensus_decode(&mut mem_d)?),
+            "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode(&mut mem_d)?),
+            "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode(&mut mem_d)?),
+            "getcfheaders" => NetworkMessage::GetCFHeaders(Decodable::consensus_decode(&mut mem_d)?),
+            "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode(&mut mem_d)?),
+            "getcfcheckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
+            "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode(&mut mem_d)?),
             _ => return Err(encode::Error::UnrecognizedNetworkCommand(cmd)),
         };
         Ok(RawNetworkMessage {
@@ -209,6 +260,12 @@ mod test {
     use super::{RawNetworkMessage, NetworkMessage, CommandString};
 
     use consensus::encode::{deserialize, serialize};
+    use network::message_filter::{GetCFilters, CFilter, GetCFHeaders, CFHeaders, GetCFCheckpt, CFCheckpt};
+    use blockdata::constants::genesis_block;
+    use network::constants::Network;
+    use util::bip158::BlockFilter;
+    use util::bloom::{BloomFilter, BloomFlags};
+    use util::merkleblock::MerkleBlock;
 
     #[test]
     fn serialize_commandstring_test() {
@@ -260,4 +317,39 @@ mod test {
                                   0x00, 0x00, 0x00, 0x00, 0x5d, 0xf6, 0xe0, 0xe2]);
     }
 
+    #[test]
+    fn filter_messages_round_trip_test() {
+        let hash = Default::default();
+        let block = genesis_block(Network::Bitcoin);
+        let matches = vec![block.txdata[0].txid()].into_iter().collect();
+        let messages = vec![
+            (NetworkMessage::GetCFilters(GetCFilters { filter_type: 0, start_height: 1, stop_hash: hash }), "getcfilters"),
+            (NetworkMessage::CFilter(CFilter { filter_type: 0, block_hash: hash, filter: BlockFilter::new(&[0]) }), "cfilter"),
+            (NetworkMessage::GetCFHeaders(GetCFHeaders { filter_type: 0, start_height: 1, stop_hash: hash }), "getcfheaders"),
+            (NetworkMessage::CFHeaders(CFHeaders {
+                filter_type: 0,
+                stop_hash: hash,
+                previous_filter_header: hash,
+                filter_hashes: vec![hash],
+            }), "cfheaders"),
+            (NetworkMessage::GetCFCheckpt(GetCFCheckpt { filter_type: 0, stop_hash: hash }), "getcfcheckpt"),
+            (NetworkMessage::CFCheckpt(CFCheckpt { filter_type: 0, stop_hash: hash, filter_headers: vec![] }), "cfcheckpt"),
+            (NetworkMessage::FilterLoad(BloomFilter::new(10, 0.0001, 5, BloomFlags::All)), "filterload"),
+            (NetworkMessage::FilterAdd(vec![1, 2, 3]), "filteradd"),
+            (NetworkMessage::FilterClear, "filterclear"),
+            (NetworkMessage::MerkleBlock(MerkleBlock::from_block(&block, &matches)), "merkleblock"),
+        ];
+        for (payload, command) in messages {
+            let msg = RawNetworkMessage { magic: Network::Bitcoin.magic(), payload: payload };
+            assert_eq!(msg.command(), command);
+            let encoded = serialize(&msg);
+            assert_eq!(&encoded[4..4 + command.len()], command.as_bytes());
+            let decoded: RawNetworkMessage = deserialize(&encoded).unwrap();
+            assert_eq!(decoded.magic, msg.magic);
+            assert_eq!(decoded.command(), command);
+            // Compare encodings, since a decoded partial merkle tree keeps
+            // the padding of its flag bits
+            assert_eq!(serialize(&decoded), encoded);
+        }
+    }
 }
@@ -0,0 +1,165 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Compact block filter network messages
+//!
+//! This module describes the network messages with which light clients
+//! request compact block filters and their headers (BIP157). The filters
+//! themselves are described in `util::bip158`.
+//!
+
+use util::bip158::BlockFilter;
+use util::hash::Sha256dHash;
+
+/// The `getcfilters` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFilters {
+    /// The type of the filters requested
+    pub filter_type: u8,
+    /// The height of the first block requested
+    pub start_height: u32,
+    /// The hash of the last block requested
+    pub stop_hash: Sha256dHash,
+}
+impl_consensus_encoding!(GetCFilters, filter_type, start_height, stop_hash);
+
+/// The `cfilter` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFilter {
+    /// The type of the filter
+    pub filter_type: u8,
+    /// The hash of the block the filter is for
+    pub block_hash: Sha256dHash,
+    /// The filter
+    pub filter: BlockFilter,
+}
+impl_consensus_encoding!(CFilter, filter_type, block_hash, filter);
+
+/// The `getcfheaders` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFHeaders {
+    /// The type of the filter headers requested
+    pub filter_type: u8,
+    /// The height of the first block requested
+    pub start_height: u32,
+    /// The hash of the last block requested
+    pub stop_hash: Sha256dHash,
+}
+impl_consensus_encoding!(GetCFHeaders, filter_type, start_height, stop_hash);
+
+/// The `cfheaders` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFHeaders {
+    /// The type of the filter headers
+    pub filter_type: u8,
+    /// The hash of the last block covered
+    pub stop_hash: Sha256dHash,
+    /// The filter header of the block before the first block covered
+    pub previous_filter_header: Sha256dHash,
+    /// The hashes of the filters of the blocks covered, from which their
+    /// filter headers can be computed
+    pub filter_hashes: Vec<Sha256dHash>,
+}
+impl_consensus_encoding!(CFHeaders, filter_type, stop_hash, previous_filter_header, filter_hashes);
+
+/// The `getcfcheckpt` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GetCFCheckpt {
+    /// The type of the filter headers requested
+    pub filter_type: u8,
+    /// The hash of the last block of the chain to checkpoint
+    pub stop_hash: Sha256dHash,
+}
+impl_consensus_encoding!(GetCFCheckpt, filter_type, stop_hash);
+
+/// The `cfcheckpt` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CFCheckpt {
+    /// The type of the filter headers
+    pub filter_type: u8,
+    /// The hash of the last block of the checkpointed chain
+    pub stop_hash: Sha256dHash,
+    /// The filter headers of every 1000th block of the chain
+    pub filter_headers: Vec<Sha256dHash>,
+}
+impl_consensus_encoding!(CFCheckpt, filter_type, stop_hash, filter_headers);
+
+#[cfg(test)]
+mod tests {
+    use super::{GetCFilters, CFilter, GetCFHeaders, CFHeaders, GetCFCheckpt, CFCheckpt};
+
+    use hex::decode as hex_decode;
+
+    use blockdata::constants::genesis_block;
+    use consensus::encode::{deserialize, serialize};
+    use network::constants::Network;
+    use util::bip158::BlockFilter;
+    use util::hash::{BitcoinHash, Sha256dHash};
+
+    #[test]
+    fn getcfilters_message_test() {
+        let from_sat = hex_decode("00e80300004a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b").unwrap();
+        let genhash = hex_decode("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b").unwrap();
+
+        let real_decode: GetCFilters = deserialize(&from_sat).unwrap();
+        assert_eq!(real_decode.filter_type, 0);
+        assert_eq!(real_decode.start_height, 1000);
+        assert_eq!(real_decode.stop_hash, Sha256dHash::from(&genhash[..]));
+        assert_eq!(serialize(&real_decode), from_sat);
+
+        let headers: GetCFHeaders = deserialize(&from_sat).unwrap();
+        assert_eq!(headers.start_height, 1000);
+        assert_eq!(serialize(&headers), from_sat);
+    }
+
+    #[test]
+    fn cfilter_message_test() {
+        let from_sat = hex_decode("004a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b04019dfca8").unwrap();
+        let real_decode: CFilter = deserialize(&from_sat).unwrap();
+        assert_eq!(real_decode.filter_type, 0);
+        assert_eq!(real_decode.filter, BlockFilter::new(&[0x01, 0x9d, 0xfc, 0xa8]));
+        assert_eq!(serialize(&real_decode), from_sat);
+
+        // This is the filter of the testnet genesis block, and can be matched
+        // as received
+        let genesis = genesis_block(Network::Testnet);
+        let script = &genesis.txdata[0].output[0].script_pubkey[..];
+        assert_eq!(real_decode.filter.match_any(&genesis.bitcoin_hash(), &[script]), Ok(true));
+    }
+
+    #[test]
+    fn cfheaders_message_test() {
+        let hash = |byte| Sha256dHash::from(&[byte; 32][..]);
+        let message = CFHeaders {
+            filter_type: 0,
+            stop_hash: hash(1),
+            previous_filter_header: hash(2),
+            filter_hashes: vec![hash(3), hash(4)],
+        };
+        let encoded = serialize(&message);
+        assert_eq!(encoded.len(), 1 + 32 + 32 + 1 + 2 * 32);
+        assert_eq!(encoded[65], 2);
+        assert_eq!(deserialize::<CFHeaders>(&encoded).unwrap(), message);
+
+        let checkpt = CFCheckpt {
+            filter_type: 0,
+            stop_hash: hash(1),
+            filter_headers: vec![hash(5); 3],
+        };
+        assert_eq!(deserialize::<CFCheckpt>(&serialize(&checkpt)).unwrap(), checkpt);
+        let get = GetCFCheckpt { filter_type: 0, stop_hash: hash(1) };
+        assert_eq!(serialize(&get), serialize(&(0u8, hash(1))));
+        assert_eq!(deserialize::<GetCFCheckpt>(&serialize(&get)).unwrap(), get);
+    }
+}
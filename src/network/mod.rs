@@ -27,6 +27,7 @@ pub mod constants;
 pub mod address;
 pub mod message;
 pub mod message_blockdata;
+pub mod message_filter;
 pub mod message_network;
 
 /// Network error
@@ -0,0 +1,1188 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Amounts
+//!
+//! This module mainly introduces the `Amount` and `SignedAmount` types.
+//! We refer to the documentation on the types for more information.
+//!
+
+use std::{error, fmt, iter, ops};
+use std::fmt::Write;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")] use serde;
+
+use consensus::encode::Decodable;
+use util::decimal::{Decimal, UDecimal};
+
+/// A set of denominations in which amounts can be expressed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Denomination {
+    /// BTC
+    Bitcoin,
+    /// mBTC
+    MilliBitcoin,
+    /// uBTC
+    MicroBitcoin,
+    /// bits
+    Bit,
+    /// satoshi
+    Satoshi,
+    /// msat
+    MilliSatoshi,
+}
+
+impl Denomination {
+    /// The number of decimal places more than a satoshi.
+    fn precision(self) -> i32 {
+        match self {
+            Denomination::Bitcoin => -8,
+            Denomination::MilliBitcoin => -5,
+            Denomination::MicroBitcoin => -2,
+            Denomination::Bit => -2,
+            Denomination::Satoshi => 0,
+            Denomination::MilliSatoshi => 3,
+        }
+    }
+}
+
+impl fmt::Display for Denomination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Denomination::Bitcoin => "BTC",
+            Denomination::MilliBitcoin => "mBTC",
+            Denomination::MicroBitcoin => "uBTC",
+            Denomination::Bit => "bits",
+            Denomination::Satoshi => "satoshi",
+            Denomination::MilliSatoshi => "msat",
+        })
+    }
+}
+
+impl FromStr for Denomination {
+    type Err = ParseAmountError;
+
+    /// Parses a denomination. Matching is case sensitive, to avoid
+    /// confusing e.g. mBTC with MBTC.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "BTC" => Ok(Denomination::Bitcoin),
+            "mBTC" => Ok(Denomination::MilliBitcoin),
+            "uBTC" => Ok(Denomination::MicroBitcoin),
+            "bits" | "bit" => Ok(Denomination::Bit),
+            "satoshi" | "sat" | "sats" => Ok(Denomination::Satoshi),
+            "msat" => Ok(Denomination::MilliSatoshi),
+            d => Err(ParseAmountError::UnknownDenomination(d.to_owned())),
+        }
+    }
+}
+
+/// An error during amount parsing or conversion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseAmountError {
+    /// Amount is negative.
+    Negative,
+    /// Amount is too big to fit in the target type.
+    TooBig,
+    /// Amount has higher precision than supported by the type.
+    TooPrecise,
+    /// Invalid number format.
+    InvalidFormat,
+    /// Input string was too large.
+    InputTooLarge,
+    /// Invalid character in input.
+    InvalidCharacter(char),
+    /// The denomination was unknown.
+    UnknownDenomination(String),
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = error::Error::description(self);
+        match *self {
+            ParseAmountError::InvalidCharacter(c) => write!(f, "{}: {}", desc, c),
+            ParseAmountError::UnknownDenomination(ref d) => write!(f, "{}: {}", desc, d),
+            _ => f.write_str(desc),
+        }
+    }
+}
+
+impl error::Error for ParseAmountError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            ParseAmountError::Negative => "amount is negative",
+            ParseAmountError::TooBig => "amount is too big",
+            ParseAmountError::TooPrecise => "amount has a too high precision",
+            ParseAmountError::InvalidFormat => "invalid number format",
+            ParseAmountError::InputTooLarge => "input string was too large",
+            ParseAmountError::InvalidCharacter(_) => "invalid character in input",
+            ParseAmountError::UnknownDenomination(_) => "unknown denomination",
+        }
+    }
+}
+
+/// Whether dropping the last `precision` digits of `s` would lose information
+fn is_too_precise(s: &str, precision: usize) -> bool {
+    s.contains('.') || precision >= s.len() || s.chars().rev().take(precision).any(|d| d != '0')
+}
+
+/// Parse decimal string in the given denomination into a satoshi value and a
+/// bool indicator for a negative amount.
+fn parse_signed_to_satoshi(mut s: &str, denom: Denomination) -> Result<(bool, u64), ParseAmountError> {
+    if s.is_empty() {
+        return Err(ParseAmountError::InvalidFormat);
+    }
+    if s.len() > 50 {
+        return Err(ParseAmountError::InputTooLarge);
+    }
+
+    let is_negative = s.starts_with('-');
+    if is_negative {
+        s = &s[1..];
+    }
+    if s.is_empty() || s == "." {
+        return Err(ParseAmountError::InvalidFormat);
+    }
+
+    let max_decimals = {
+        // The difference in precision between native (satoshi)
+        // and desired denomination.
+        let precision_diff = -denom.precision();
+        if precision_diff < 0 {
+            // If precision diff is negative, this means we are parsing
+            // into a less precise amount. That is not allowed unless
+            // there are no decimals and the last digits are zeroes as
+            // many as the difference in precision.
+            let last_n = precision_diff.abs() as usize;
+            if s.chars().all(|d| d == '0') {
+                // Zero is exact in any denomination
+                s = "0";
+            } else if is_too_precise(s, last_n) {
+                return Err(ParseAmountError::TooPrecise);
+            } else {
+                s = &s[0..s.len() - last_n];
+            }
+            0
+        } else {
+            precision_diff
+        }
+    };
+
+    let mut decimals = None;
+    let mut value: u64 = 0; // as satoshis
+    for c in s.chars() {
+        match c {
+            '0'...'9' => {
+                // Do `value = 10 * value + digit`, catching overflows.
+                value = value.checked_mul(10)
+                             .and_then(|v| v.checked_add((c as u8 - b'0') as u64))
+                             .ok_or(ParseAmountError::TooBig)?;
+                // Increment the decimal digit counter if past decimal.
+                decimals = match decimals {
+                    None => None,
+                    Some(d) if d < max_decimals => Some(d + 1),
+                    _ => return Err(ParseAmountError::TooPrecise),
+                };
+            }
+            '.' => match decimals {
+                None => decimals = Some(0),
+                // Double decimal dot.
+                _ => return Err(ParseAmountError::InvalidFormat),
+            },
+            c => return Err(ParseAmountError::InvalidCharacter(c)),
+        }
+    }
+
+    // Decimally shift left by `max_decimals - decimals`.
+    let scale_factor = max_decimals - decimals.unwrap_or(0);
+    for _ in 0..scale_factor {
+        value = value.checked_mul(10).ok_or(ParseAmountError::TooBig)?;
+    }
+
+    Ok((is_negative, value))
+}
+
+/// Format the given satoshi amount in the given denomination.
+fn fmt_satoshi_in(satoshi: u64, negative: bool, f: &mut fmt::Write, denom: Denomination) -> fmt::Result {
+    if negative {
+        f.write_str("-")?;
+    }
+
+    let precision = denom.precision();
+    match precision.cmp(&0) {
+        // Zero needs no trailing zeroes
+        Ordering::Greater if satoshi == 0 => f.write_str("0"),
+        Ordering::Greater => {
+            // add zeroes in the end
+            let width = precision as usize;
+            write!(f, "{}{:0width$}", satoshi, 0, width = width)
+        }
+        Ordering::Less => {
+            // need to inject a comma in the number
+            let nb_decimals = precision.abs() as usize;
+            let real = format!("{:0width$}", satoshi, width = nb_decimals);
+            if real.len() == nb_decimals {
+                write!(f, "0.{}", real)
+            } else {
+                let (int_part, dec_part) = real.split_at(real.len() - nb_decimals);
+                write!(f, "{}.{}", int_part, dec_part)
+            }
+        }
+        Ordering::Equal => write!(f, "{}", satoshi),
+    }
+}
+
+/// Amount
+///
+/// The `Amount` type can be used to express Bitcoin amounts that supports
+/// arithmetic and conversion to various denominations.
+///
+/// Warning!
+///
+/// This type implements several arithmetic operations from `std::ops`.
+/// To prevent errors due to overflow or underflow when using these operations,
+/// it is advised to instead use the checked arithmetic methods whose names
+/// start with `checked_`. The operations from `std::ops` that `Amount`
+/// implements will panic when overflow or underflow occurs.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Amount(u64);
+
+impl Amount {
+    /// The zero amount.
+    pub fn zero() -> Amount {
+        Amount(0)
+    }
+
+    /// Exactly one satoshi.
+    pub fn one_sat() -> Amount {
+        Amount(1)
+    }
+
+    /// Exactly one bitcoin.
+    pub fn one_btc() -> Amount {
+        Amount(100_000_000)
+    }
+
+    /// Create an `Amount` with satoshi precision and the given number of satoshis.
+    pub fn from_sat(satoshi: u64) -> Amount {
+        Amount(satoshi)
+    }
+
+    /// Get the number of satoshis in this `Amount`.
+    pub fn as_sat(self) -> u64 {
+        self.0
+    }
+
+    /// The maximum value of an `Amount`.
+    pub fn max_value() -> Amount {
+        Amount(u64::max_value())
+    }
+
+    /// The minimum value of an `Amount`.
+    pub fn min_value() -> Amount {
+        Amount(u64::min_value())
+    }
+
+    /// Convert from a value expressing bitcoins to an `Amount`.
+    pub fn from_btc(btc: f64) -> Result<Amount, ParseAmountError> {
+        Amount::from_float_in(btc, Denomination::Bitcoin)
+    }
+
+    /// Parse a decimal string as a value in the given denomination.
+    ///
+    /// Note: This only parses the value string.  If you want to parse a value
+    /// with denomination, use `FromStr`.
+    pub fn from_str_in(s: &str, denom: Denomination) -> Result<Amount, ParseAmountError> {
+        let (negative, satoshi) = parse_signed_to_satoshi(s, denom)?;
+        if negative {
+            return Err(ParseAmountError::Negative);
+        }
+        Ok(Amount::from_sat(satoshi))
+    }
+
+    /// Parses amounts with denomination suffix like they are produced with
+    /// `to_string_with_denomination` or with `fmt::Display`.
+    /// If you want to parse only the amount without the denomination,
+    /// use `from_str_in`.
+    pub fn from_str_with_denomination(s: &str) -> Result<Amount, ParseAmountError> {
+        let mut split = s.splitn(3, ' ');
+        let amt_str = split.next().unwrap();
+        let denom_str = split.next().ok_or(ParseAmountError::InvalidFormat)?;
+        if split.next().is_some() {
+            return Err(ParseAmountError::InvalidFormat);
+        }
+
+        Amount::from_str_in(amt_str, denom_str.parse()?)
+    }
+
+    /// Express this `Amount` as a floating-point value in the given denomination.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn to_float_in(self, denom: Denomination) -> f64 {
+        f64::from_str(&self.to_string_in(denom)).unwrap()
+    }
+
+    /// Express this `Amount` as a floating-point value in Bitcoin.
+    ///
+    /// Equivalent to `to_float_in(Denomination::Bitcoin)`.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn as_btc(self) -> f64 {
+        self.to_float_in(Denomination::Bitcoin)
+    }
+
+    /// Convert this `Amount` in floating-point notation with a given
+    /// denomination.
+    /// Can return error if the amount is too big, too precise or negative.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn from_float_in(value: f64, denom: Denomination) -> Result<Amount, ParseAmountError> {
+        if value < 0.0 {
+            return Err(ParseAmountError::Negative);
+        }
+        // This is inefficient, but the safest way to deal with this. The parsing logic is safe.
+        // Any performance-critical application should not be dealing with floats.
+        Amount::from_str_in(&value.to_string(), denom)
+    }
+
+    /// Convert a `UDecimal` expressing bitcoins to an `Amount`, failing if it
+    /// has more than eight decimal places or overflows.
+    pub fn from_udecimal(value: &UDecimal) -> Result<Amount, ParseAmountError> {
+        let (negative, satoshi) = decimal_to_satoshi(value.mantissa(), false, value.exponent())?;
+        debug_assert!(!negative);
+        Ok(Amount::from_sat(satoshi))
+    }
+
+    /// Format the value of this `Amount` in the given denomination.
+    ///
+    /// Does not include the denomination.
+    pub fn fmt_value_in(self, f: &mut fmt::Write, denom: Denomination) -> fmt::Result {
+        fmt_satoshi_in(self.as_sat(), false, f, denom)
+    }
+
+    /// Get a string number of this `Amount` in the given denomination.
+    ///
+    /// Does not include the denomination.
+    pub fn to_string_in(self, denom: Denomination) -> String {
+        let mut buf = String::new();
+        self.fmt_value_in(&mut buf, denom).unwrap();
+        buf
+    }
+
+    /// Get a formatted string of this `Amount` in the given denomination,
+    /// suffixed with the abbreviation for the denomination.
+    pub fn to_string_with_denomination(self, denom: Denomination) -> String {
+        let mut buf = String::new();
+        self.fmt_value_in(&mut buf, denom).unwrap();
+        write!(buf, " {}", denom).unwrap();
+        buf
+    }
+
+    // Some arithmetic that doesn't fit in `std::ops` traits.
+
+    /// Checked addition.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    /// Checked subtraction.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    /// Checked multiplication.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<Amount> {
+        self.0.checked_mul(rhs).map(Amount)
+    }
+
+    /// Checked integer division.
+    /// Be aware that integer division loses the remainder if no exact division
+    /// can be made.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_div(self, rhs: u64) -> Option<Amount> {
+        self.0.checked_div(rhs).map(Amount)
+    }
+
+    /// Checked remainder.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_rem(self, rhs: u64) -> Option<Amount> {
+        self.0.checked_rem(rhs).map(Amount)
+    }
+
+    /// Convert to a signed amount.
+    pub fn to_signed(self) -> Result<SignedAmount, ParseAmountError> {
+        if self.as_sat() > SignedAmount::max_value().as_sat() as u64 {
+            Err(ParseAmountError::TooBig)
+        } else {
+            Ok(SignedAmount::from_sat(self.as_sat() as i64))
+        }
+    }
+}
+
+impl fmt::Debug for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Amount({} satoshi)", self.as_sat())
+    }
+}
+
+// No one should depend on a binding contract for Display for this type.
+// Just using Bitcoin denominated string.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_value_in(f, Denomination::Bitcoin)?;
+        write!(f, " {}", Denomination::Bitcoin)
+    }
+}
+
+impl ops::Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Self::Output {
+        self.checked_add(rhs).expect("Amount addition error")
+    }
+}
+
+impl ops::AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other
+    }
+}
+
+impl ops::Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Amount) -> Self::Output {
+        self.checked_sub(rhs).expect("Amount subtraction error")
+    }
+}
+
+impl ops::SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other
+    }
+}
+
+impl ops::Rem<u64> for Amount {
+    type Output = Amount;
+
+    fn rem(self, modulus: u64) -> Self {
+        self.checked_rem(modulus).expect("Amount remainder error")
+    }
+}
+
+impl ops::RemAssign<u64> for Amount {
+    fn rem_assign(&mut self, modulus: u64) {
+        *self = *self % modulus
+    }
+}
+
+impl ops::Mul<u64> for Amount {
+    type Output = Amount;
+
+    fn mul(self, rhs: u64) -> Self::Output {
+        self.checked_mul(rhs).expect("Amount multiplication error")
+    }
+}
+
+impl ops::MulAssign<u64> for Amount {
+    fn mul_assign(&mut self, rhs: u64) {
+        *self = *self * rhs
+    }
+}
+
+impl ops::Div<u64> for Amount {
+    type Output = Amount;
+
+    fn div(self, rhs: u64) -> Self::Output {
+        self.checked_div(rhs).expect("Amount division error")
+    }
+}
+
+impl ops::DivAssign<u64> for Amount {
+    fn div_assign(&mut self, rhs: u64) {
+        *self = *self / rhs
+    }
+}
+
+impl iter::Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::zero(), |acc, amt| acc + amt)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::from_str_with_denomination(s)
+    }
+}
+
+impl From<Amount> for UDecimal {
+    fn from(amount: Amount) -> UDecimal {
+        UDecimal::new(amount.as_sat(), 8)
+    }
+}
+
+impl_newtype_consensus_encoding!(Amount);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Amount {
+    /// Amounts are serialized as an integer number of satoshis, which is
+    /// lossless and matches the consensus encoding
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u64(self.as_sat())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        u64::deserialize(deserializer).map(Amount::from_sat)
+    }
+}
+
+/// SignedAmount
+///
+/// The `SignedAmount` type can be used to express Bitcoin amounts that supports
+/// arithmetic and conversion to various denominations.
+///
+/// Warning!
+///
+/// This type implements several arithmetic operations from `std::ops`.
+/// To prevent errors due to overflow or underflow when using these operations,
+/// it is advised to instead use the checked arithmetic methods whose names
+/// start with `checked_`. The operations from `std::ops` that `SignedAmount`
+/// implements will panic when overflow or underflow occurs.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct SignedAmount(i64);
+
+impl SignedAmount {
+    /// The zero amount.
+    pub fn zero() -> SignedAmount {
+        SignedAmount(0)
+    }
+
+    /// Exactly one satoshi.
+    pub fn one_sat() -> SignedAmount {
+        SignedAmount(1)
+    }
+
+    /// Exactly one bitcoin.
+    pub fn one_btc() -> SignedAmount {
+        SignedAmount(100_000_000)
+    }
+
+    /// Create a `SignedAmount` with satoshi precision and the given number of satoshis.
+    pub fn from_sat(satoshi: i64) -> SignedAmount {
+        SignedAmount(satoshi)
+    }
+
+    /// Get the number of satoshis in this `SignedAmount`.
+    pub fn as_sat(self) -> i64 {
+        self.0
+    }
+
+    /// The maximum value of a `SignedAmount`.
+    pub fn max_value() -> SignedAmount {
+        SignedAmount(i64::max_value())
+    }
+
+    /// The minimum value of a `SignedAmount`.
+    pub fn min_value() -> SignedAmount {
+        SignedAmount(i64::min_value())
+    }
+
+    /// Convert from a value expressing bitcoins to a `SignedAmount`.
+    pub fn from_btc(btc: f64) -> Result<SignedAmount, ParseAmountError> {
+        SignedAmount::from_float_in(btc, Denomination::Bitcoin)
+    }
+
+    /// Parse a decimal string as a value in the given denomination.
+    ///
+    /// Note: This only parses the value string.  If you want to parse a value
+    /// with denomination, use `FromStr`.
+    pub fn from_str_in(s: &str, denom: Denomination) -> Result<SignedAmount, ParseAmountError> {
+        let (negative, satoshi) = parse_signed_to_satoshi(s, denom)?;
+        signed_from_parts(negative, satoshi)
+    }
+
+    /// Parses amounts with denomination suffix like they are produced with
+    /// `to_string_with_denomination` or with `fmt::Display`.
+    /// If you want to parse only the amount without the denomination,
+    /// use `from_str_in`.
+    pub fn from_str_with_denomination(s: &str) -> Result<SignedAmount, ParseAmountError> {
+        let mut split = s.splitn(3, ' ');
+        let amt_str = split.next().unwrap();
+        let denom_str = split.next().ok_or(ParseAmountError::InvalidFormat)?;
+        if split.next().is_some() {
+            return Err(ParseAmountError::InvalidFormat);
+        }
+
+        SignedAmount::from_str_in(amt_str, denom_str.parse()?)
+    }
+
+    /// Express this `SignedAmount` as a floating-point value in the given denomination.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn to_float_in(self, denom: Denomination) -> f64 {
+        f64::from_str(&self.to_string_in(denom)).unwrap()
+    }
+
+    /// Express this `SignedAmount` as a floating-point value in Bitcoin.
+    ///
+    /// Equivalent to `to_float_in(Denomination::Bitcoin)`.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn as_btc(self) -> f64 {
+        self.to_float_in(Denomination::Bitcoin)
+    }
+
+    /// Convert this `SignedAmount` in floating-point notation with a given
+    /// denomination.
+    /// Can return error if the amount is too big or too precise.
+    ///
+    /// Please be aware of the risk of using floating-point numbers.
+    pub fn from_float_in(value: f64, denom: Denomination) -> Result<SignedAmount, ParseAmountError> {
+        // This is inefficient, but the safest way to deal with this. The parsing logic is safe.
+        // Any performance-critical application should not be dealing with floats.
+        SignedAmount::from_str_in(&value.to_string(), denom)
+    }
+
+    /// Convert a `Decimal` expressing bitcoins to a `SignedAmount`, failing if
+    /// it has more than eight decimal places or overflows.
+    pub fn from_decimal(value: &Decimal) -> Result<SignedAmount, ParseAmountError> {
+        let mantissa = value.mantissa();
+        let abs = if mantissa < 0 { unsigned_abs(mantissa) } else { mantissa as u64 };
+        let (negative, satoshi) = decimal_to_satoshi(abs, mantissa < 0, value.exponent())?;
+        signed_from_parts(negative, satoshi)
+    }
+
+    /// Format the value of this `SignedAmount` in the given denomination.
+    ///
+    /// Does not include the denomination.
+    pub fn fmt_value_in(self, f: &mut fmt::Write, denom: Denomination) -> fmt::Result {
+        fmt_satoshi_in(unsigned_abs(self.as_sat()), self.is_negative(), f, denom)
+    }
+
+    /// Get a string number of this `SignedAmount` in the given denomination.
+    ///
+    /// Does not include the denomination.
+    pub fn to_string_in(self, denom: Denomination) -> String {
+        let mut buf = String::new();
+        self.fmt_value_in(&mut buf, denom).unwrap();
+        buf
+    }
+
+    /// Get a formatted string of this `SignedAmount` in the given denomination,
+    /// suffixed with the abbreviation for the denomination.
+    pub fn to_string_with_denomination(self, denom: Denomination) -> String {
+        let mut buf = String::new();
+        self.fmt_value_in(&mut buf, denom).unwrap();
+        write!(buf, " {}", denom).unwrap();
+        buf
+    }
+
+    // Some arithmetic that doesn't fit in `std::ops` traits.
+
+    /// Get the absolute value of this `SignedAmount`.
+    pub fn abs(self) -> SignedAmount {
+        SignedAmount(self.0.abs())
+    }
+
+    /// Returns a number representing sign of this `SignedAmount`.
+    ///
+    /// - `0` if the amount is zero
+    /// - `1` if the amount is positive
+    /// - `-1` if the amount is negative
+    pub fn signum(self) -> i64 {
+        self.0.signum()
+    }
+
+    /// Returns `true` if this `SignedAmount` is positive and `false` if
+    /// this `SignedAmount` is zero or negative.
+    pub fn is_positive(self) -> bool {
+        self.0.is_positive()
+    }
+
+    /// Returns `true` if this `SignedAmount` is negative and `false` if
+    /// this `SignedAmount` is zero or positive.
+    pub fn is_negative(self) -> bool {
+        self.0.is_negative()
+    }
+
+    /// Get the absolute value of this `SignedAmount`.
+    /// Returns `None` if overflow occurred. (`self == min_value()`)
+    pub fn checked_abs(self) -> Option<SignedAmount> {
+        self.0.checked_abs().map(SignedAmount)
+    }
+
+    /// Checked addition.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_add(self, rhs: SignedAmount) -> Option<SignedAmount> {
+        self.0.checked_add(rhs.0).map(SignedAmount)
+    }
+
+    /// Checked subtraction.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_sub(self, rhs: SignedAmount) -> Option<SignedAmount> {
+        self.0.checked_sub(rhs.0).map(SignedAmount)
+    }
+
+    /// Checked multiplication.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_mul(self, rhs: i64) -> Option<SignedAmount> {
+        self.0.checked_mul(rhs).map(SignedAmount)
+    }
+
+    /// Checked integer division.
+    /// Be aware that integer division loses the remainder if no exact division
+    /// can be made.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_div(self, rhs: i64) -> Option<SignedAmount> {
+        self.0.checked_div(rhs).map(SignedAmount)
+    }
+
+    /// Checked remainder.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_rem(self, rhs: i64) -> Option<SignedAmount> {
+        self.0.checked_rem(rhs).map(SignedAmount)
+    }
+
+    /// Subtraction that doesn't allow negative `SignedAmount`s.
+    /// Returns `None` if either `self`, `rhs` or the result is strictly negative.
+    pub fn positive_sub(self, rhs: SignedAmount) -> Option<SignedAmount> {
+        if self.is_negative() || rhs.is_negative() || rhs > self {
+            None
+        } else {
+            self.checked_sub(rhs)
+        }
+    }
+
+    /// Convert to an unsigned amount.
+    pub fn to_unsigned(self) -> Result<Amount, ParseAmountError> {
+        if self.is_negative() {
+            Err(ParseAmountError::Negative)
+        } else {
+            Ok(Amount::from_sat(self.as_sat() as u64))
+        }
+    }
+}
+
+/// The absolute value of `n`, which unlike `i64::abs` cannot overflow
+fn unsigned_abs(n: i64) -> u64 {
+    if n < 0 {
+        (-(n + 1)) as u64 + 1
+    } else {
+        n as u64
+    }
+}
+
+/// Combine a sign and magnitude into a `SignedAmount`, checking for overflow
+fn signed_from_parts(negative: bool, satoshi: u64) -> Result<SignedAmount, ParseAmountError> {
+    if negative && satoshi == unsigned_abs(i64::min_value()) {
+        Ok(SignedAmount::min_value())
+    } else if satoshi > i64::max_value() as u64 {
+        Err(ParseAmountError::TooBig)
+    } else if negative {
+        Ok(SignedAmount(-(satoshi as i64)))
+    } else {
+        Ok(SignedAmount(satoshi as i64))
+    }
+}
+
+/// Rescale a decimal bitcoin value with `exponent` decimal places to satoshis
+fn decimal_to_satoshi(mut mantissa: u64, negative: bool, exponent: usize) -> Result<(bool, u64), ParseAmountError> {
+    let mut exponent = exponent;
+    while exponent > 8 {
+        if mantissa % 10 != 0 {
+            return Err(ParseAmountError::TooPrecise);
+        }
+        mantissa /= 10;
+        exponent -= 1;
+    }
+    while exponent < 8 {
+        mantissa = mantissa.checked_mul(10).ok_or(ParseAmountError::TooBig)?;
+        exponent += 1;
+    }
+    Ok((negative, mantissa))
+}
+
+impl fmt::Debug for SignedAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SignedAmount({} satoshi)", self.as_sat())
+    }
+}
+
+// No one should depend on a binding contract for Display for this type.
+// Just using Bitcoin denominated string.
+impl fmt::Display for SignedAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_value_in(f, Denomination::Bitcoin)?;
+        write!(f, " {}", Denomination::Bitcoin)
+    }
+}
+
+impl ops::Add for SignedAmount {
+    type Output = SignedAmount;
+
+    fn add(self, rhs: SignedAmount) -> Self::Output {
+        self.checked_add(rhs).expect("SignedAmount addition error")
+    }
+}
+
+impl ops::AddAssign for SignedAmount {
+    fn add_assign(&mut self, other: SignedAmount) {
+        *self = *self + other
+    }
+}
+
+impl ops::Sub for SignedAmount {
+    type Output = SignedAmount;
+
+    fn sub(self, rhs: SignedAmount) -> Self::Output {
+        self.checked_sub(rhs).expect("SignedAmount subtraction error")
+    }
+}
+
+impl ops::SubAssign for SignedAmount {
+    fn sub_assign(&mut self, other: SignedAmount) {
+        *self = *self - other
+    }
+}
+
+impl ops::Rem<i64> for SignedAmount {
+    type Output = SignedAmount;
+
+    fn rem(self, modulus: i64) -> Self {
+        self.checked_rem(modulus).expect("SignedAmount remainder error")
+    }
+}
+
+impl ops::RemAssign<i64> for SignedAmount {
+    fn rem_assign(&mut self, modulus: i64) {
+        *self = *self % modulus
+    }
+}
+
+impl ops::Mul<i64> for SignedAmount {
+    type Output = SignedAmount;
+
+    fn mul(self, rhs: i64) -> Self::Output {
+        self.checked_mul(rhs).expect("SignedAmount multiplication error")
+    }
+}
+
+impl ops::MulAssign<i64> for SignedAmount {
+    fn mul_assign(&mut self, rhs: i64) {
+        *self = *self * rhs
+    }
+}
+
+impl ops::Div<i64> for SignedAmount {
+    type Output = SignedAmount;
+
+    fn div(self, rhs: i64) -> Self::Output {
+        self.checked_div(rhs).expect("SignedAmount division error")
+    }
+}
+
+impl ops::DivAssign<i64> for SignedAmount {
+    fn div_assign(&mut self, rhs: i64) {
+        *self = *self / rhs
+    }
+}
+
+impl ops::Neg for SignedAmount {
+    type Output = SignedAmount;
+
+    fn neg(self) -> Self::Output {
+        self.checked_mul(-1).expect("SignedAmount negation error")
+    }
+}
+
+impl iter::Sum for SignedAmount {
+    fn sum<I: Iterator<Item = SignedAmount>>(iter: I) -> SignedAmount {
+        iter.fold(SignedAmount::zero(), |acc, amt| acc + amt)
+    }
+}
+
+impl FromStr for SignedAmount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SignedAmount::from_str_with_denomination(s)
+    }
+}
+
+impl From<SignedAmount> for Decimal {
+    fn from(amount: SignedAmount) -> Decimal {
+        Decimal::new(amount.as_sat(), 8)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SignedAmount {
+    /// Amounts are serialized as an integer number of satoshis
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_i64(self.as_sat())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SignedAmount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        i64::deserialize(deserializer).map(SignedAmount::from_sat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::encode::{deserialize, serialize};
+    use util::decimal::{Decimal, UDecimal};
+
+    #[test]
+    fn add_sub_mul_div() {
+        let sat = Amount::from_sat;
+        let ssat = SignedAmount::from_sat;
+
+        assert_eq!(sat(15) + sat(15), sat(30));
+        assert_eq!(sat(15) - sat(15), sat(0));
+        assert_eq!(sat(14) * 3, sat(42));
+        assert_eq!(sat(14) / 2, sat(7));
+        assert_eq!(sat(14) % 3, sat(2));
+        assert_eq!(ssat(15) - ssat(20), ssat(-5));
+        assert_eq!(ssat(-14) * 3, ssat(-42));
+        assert_eq!(ssat(-14) / 2, ssat(-7));
+        assert_eq!(ssat(-14) % 3, ssat(-2));
+        assert_eq!(-ssat(5), ssat(-5));
+
+        let mut b = ssat(-5);
+        b += ssat(13);
+        assert_eq!(b, ssat(8));
+        b -= ssat(3);
+        assert_eq!(b, ssat(5));
+        b *= 6;
+        assert_eq!(b, ssat(30));
+        b /= 3;
+        assert_eq!(b, ssat(10));
+        b %= 3;
+        assert_eq!(b, ssat(1));
+
+        assert_eq!(vec![sat(1), sat(2), sat(3)].into_iter().sum::<Amount>(), sat(6));
+        assert_eq!(vec![ssat(1), ssat(-2)].into_iter().sum::<SignedAmount>(), ssat(-1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn sub_underflow() {
+        let _ = Amount::from_sat(1) - Amount::from_sat(2);
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let sat = Amount::from_sat;
+        let ssat = SignedAmount::from_sat;
+
+        assert_eq!(Amount::max_value().checked_add(sat(1)), None);
+        assert_eq!(sat(1).checked_sub(sat(2)), None);
+        assert_eq!(sat(5).checked_div(0), None);
+        assert_eq!(Amount::max_value().checked_mul(2), None);
+        assert_eq!(sat(42).checked_div(2), Some(sat(21)));
+        assert_eq!(SignedAmount::max_value().checked_add(ssat(1)), None);
+        assert_eq!(SignedAmount::min_value().checked_sub(ssat(1)), None);
+        assert_eq!(SignedAmount::min_value().checked_abs(), None);
+        assert_eq!(ssat(-5).checked_abs(), Some(ssat(5)));
+
+        assert_eq!(ssat(-5).positive_sub(ssat(3)), None);
+        assert_eq!(ssat(5).positive_sub(ssat(-3)), None);
+        assert_eq!(ssat(3).positive_sub(ssat(5)), None);
+        assert_eq!(ssat(5).positive_sub(ssat(3)), Some(ssat(2)));
+
+        assert_eq!(sat(5).to_signed(), Ok(ssat(5)));
+        assert_eq!(Amount::max_value().to_signed(), Err(ParseAmountError::TooBig));
+        assert_eq!(ssat(-5).to_unsigned(), Err(ParseAmountError::Negative));
+        assert_eq!(ssat(5).to_unsigned(), Ok(sat(5)));
+    }
+
+    #[test]
+    fn floating_point() {
+        use super::Denomination as D;
+        let f = Amount::from_float_in;
+        let sf = SignedAmount::from_float_in;
+        let sat = Amount::from_sat;
+        let ssat = SignedAmount::from_sat;
+
+        assert_eq!(f(11.22, D::Bitcoin), Ok(sat(1122000000)));
+        assert_eq!(sf(-11.22, D::MilliBitcoin), Ok(ssat(-1122000)));
+        assert_eq!(f(11.22, D::Bit), Ok(sat(1122)));
+        assert_eq!(sf(-1000.0, D::MilliSatoshi), Ok(ssat(-1)));
+        assert_eq!(f(0.0001234, D::Bitcoin), Ok(sat(12340)));
+        assert_eq!(sf(-0.00012345, D::Bitcoin), Ok(ssat(-12345)));
+
+        assert_eq!(f(-100.0, D::MilliSatoshi), Err(ParseAmountError::Negative));
+        assert_eq!(f(11.22, D::Satoshi), Err(ParseAmountError::TooPrecise));
+        assert_eq!(sf(-100.0, D::MilliSatoshi), Err(ParseAmountError::TooPrecise));
+        assert_eq!(f(42.123456781, D::Bitcoin), Err(ParseAmountError::TooPrecise));
+        assert_eq!(sf(-184467440738.0, D::Bitcoin), Err(ParseAmountError::TooBig));
+        assert_eq!(f(18446744073709551617.0, D::Satoshi), Err(ParseAmountError::TooBig));
+
+        assert_eq!(sat(2500000000).as_btc(), 25.0);
+        assert_eq!(ssat(-100).to_float_in(D::MilliSatoshi), -100000.0);
+        assert_eq!(sat(1).to_float_in(D::Bitcoin), 0.00000001);
+    }
+
+    #[test]
+    fn parsing() {
+        use super::ParseAmountError as E;
+        let btc = Denomination::Bitcoin;
+        let p = Amount::from_str_in;
+        let sp = SignedAmount::from_str_in;
+
+        assert_eq!(p("x", btc), Err(E::InvalidCharacter('x')));
+        assert_eq!(p("-", btc), Err(E::InvalidFormat));
+        assert_eq!(sp("-", btc), Err(E::InvalidFormat));
+        assert_eq!(p(".", btc), Err(E::InvalidFormat));
+        assert_eq!(p("", btc), Err(E::InvalidFormat));
+        assert_eq!(p("-1.0x", btc), Err(E::InvalidCharacter('x')));
+        assert_eq!(p("0.0 ", btc), Err(E::InvalidCharacter(' ')));
+        assert_eq!(p("0.000.000", btc), Err(E::InvalidFormat));
+        let more_than_max = format!("1{}", Amount::max_value());
+        assert_eq!(p(&more_than_max, btc), Err(E::TooBig));
+        assert_eq!(p("0.000000042", btc), Err(E::TooPrecise));
+        assert_eq!(p(&iter::repeat('1').take(51).collect::<String>(), btc), Err(E::InputTooLarge));
+
+        assert_eq!(p("1", btc), Ok(Amount::from_sat(1_000_000_00)));
+        assert_eq!(sp("-.5", btc), Ok(SignedAmount::from_sat(-500_000_00)));
+        assert_eq!(p("1.1", btc), Ok(Amount::from_sat(1_100_000_00)));
+        assert_eq!(p("100", Denomination::Satoshi), Ok(Amount::from_sat(100)));
+        assert_eq!(p("55", Denomination::Satoshi), Ok(Amount::from_sat(55)));
+        assert_eq!(p("5500000000000000000", Denomination::Satoshi), Ok(Amount::from_sat(5_500_000_000_000_000_000)));
+        // Should this even pass?
+        assert_eq!(p("5500000000000000000.", Denomination::Satoshi), Ok(Amount::from_sat(5_500_000_000_000_000_000)));
+        assert_eq!(p("12345678901.12345678", btc), Ok(Amount::from_sat(12_345_678_901__123_456_78)));
+        assert_eq!(sp("-9223372036854775808", Denomination::Satoshi), Ok(SignedAmount::min_value()));
+        assert_eq!(sp("9223372036854775808", Denomination::Satoshi), Err(E::TooBig));
+    }
+
+    #[test]
+    fn to_string() {
+        use super::Denomination as D;
+
+        assert_eq!(Amount::one_btc().to_string_in(D::Bitcoin), "1.00000000");
+        assert_eq!(Amount::one_btc().to_string_in(D::Satoshi), "100000000");
+        assert_eq!(Amount::one_sat().to_string_in(D::Bitcoin), "0.00000001");
+        assert_eq!(SignedAmount::from_sat(-42).to_string_in(D::Bitcoin), "-0.00000042");
+        assert_eq!(SignedAmount::min_value().to_string_in(D::Satoshi), "-9223372036854775808");
+
+        assert_eq!(Amount::one_btc().to_string_with_denomination(D::Bitcoin), "1.00000000 BTC");
+        assert_eq!(Amount::one_sat().to_string_with_denomination(D::MilliSatoshi), "1000 msat");
+        assert_eq!(SignedAmount::one_btc().to_string_with_denomination(D::Satoshi), "100000000 satoshi");
+        assert_eq!(Amount::one_sat().to_string_with_denomination(D::Bit), "0.01 bits");
+        assert_eq!(SignedAmount::from_sat(-42).to_string_with_denomination(D::MilliBitcoin), "-0.00042 mBTC");
+
+        assert_eq!(format!("{}", Amount::from_sat(150_000_000)), "1.50000000 BTC");
+        assert_eq!(format!("{:?}", Amount::from_sat(42)), "Amount(42 satoshi)");
+    }
+
+    #[test]
+    fn from_str() {
+        use super::ParseAmountError as E;
+        let p = Amount::from_str;
+        let sp = SignedAmount::from_str;
+
+        assert_eq!(p("x BTC"), Err(E::InvalidCharacter('x')));
+        assert_eq!(p("5 BTC BTC"), Err(E::InvalidFormat));
+        assert_eq!(p("5"), Err(E::InvalidFormat));
+        assert_eq!(p("5 BCH"), Err(E::UnknownDenomination("BCH".to_owned())));
+        assert_eq!(p("5 MBTC"), Err(E::UnknownDenomination("MBTC".to_owned())));
+        assert_eq!(p("-1 BTC"), Err(E::Negative));
+        assert_eq!(p("-0.0 BTC"), Err(E::Negative));
+        assert_eq!(p("0.123456789 BTC"), Err(E::TooPrecise));
+        assert_eq!(sp("-0.1 satoshi"), Err(E::TooPrecise));
+        assert_eq!(p("0.123456 mBTC"), Err(E::TooPrecise));
+        assert_eq!(sp("-1.001 bits"), Err(E::TooPrecise));
+        assert_eq!(sp("-200000000000 BTC"), Err(E::TooBig));
+        assert_eq!(p("18446744073709551616 sat"), Err(E::TooBig));
+
+        assert_eq!(p("0.00253583 BTC"), Ok(Amount::from_sat(253583)));
+        assert_eq!(sp("-5 satoshi"), Ok(SignedAmount::from_sat(-5)));
+        assert_eq!(p("0.10000000 BTC"), Ok(Amount::from_sat(100_000_00)));
+        assert_eq!(sp("-100 bits"), Ok(SignedAmount::from_sat(-10_000)));
+        assert_eq!(p("25 mBTC"), Ok(Amount::from_sat(2_500_000)));
+        assert_eq!(p("1000 msat"), Ok(Amount::from_sat(1)));
+
+        // Round trips through `Display`
+        for &amt in [0, 1, 42, 100_000_000, 2_100_000_000_000_000].iter() {
+            let amt = Amount::from_sat(amt);
+            assert_eq!(p(&amt.to_string()), Ok(amt));
+        }
+
+        // Zero round trips in every denomination
+        use super::Denomination as D;
+        assert_eq!(Amount::zero().to_string_with_denomination(D::MilliSatoshi), "0 msat");
+        assert_eq!(p("000 msat"), Ok(Amount::zero()));
+        assert_eq!(p("100 msat"), Err(E::TooPrecise));
+        for &denom in [D::Bitcoin, D::MilliBitcoin, D::MicroBitcoin, D::Bit, D::Satoshi, D::MilliSatoshi].iter() {
+            let zero = Amount::zero().to_string_with_denomination(denom);
+            assert_eq!(p(&zero), Ok(Amount::zero()));
+            assert_eq!(Amount::from_str_in(&Amount::zero().to_string_in(denom), denom), Ok(Amount::zero()));
+            let zero = SignedAmount::zero().to_string_with_denomination(denom);
+            assert_eq!(sp(&zero), Ok(SignedAmount::zero()));
+        }
+    }
+
+    #[test]
+    fn decimal_conversions() {
+        assert_eq!(UDecimal::from(Amount::from_sat(123_456_789)), UDecimal::new(123_456_789, 8));
+        assert_eq!(Decimal::from(SignedAmount::from_sat(-5)), Decimal::new(-5, 8));
+
+        assert_eq!(Amount::from_udecimal(&UDecimal::new(15, 1)), Ok(Amount::from_sat(150_000_000)));
+        assert_eq!(Amount::from_udecimal(&UDecimal::new(1_000, 11)), Ok(Amount::from_sat(1)));
+        assert_eq!(Amount::from_udecimal(&UDecimal::new(1_001, 11)), Err(ParseAmountError::TooPrecise));
+        assert_eq!(Amount::from_udecimal(&UDecimal::new(u64::max_value(), 0)), Err(ParseAmountError::TooBig));
+        assert_eq!(SignedAmount::from_decimal(&Decimal::new(-25, 2)), Ok(SignedAmount::from_sat(-25_000_000)));
+        assert_eq!(SignedAmount::from_decimal(&Decimal::new(i64::min_value(), 8)), Ok(SignedAmount::min_value()));
+        assert_eq!(SignedAmount::from_decimal(&Decimal::new(i64::min_value(), 7)), Err(ParseAmountError::TooBig));
+    }
+
+    #[test]
+    fn consensus_encoding() {
+        let amount = Amount::from_sat(0x0102030405060708);
+        assert_eq!(serialize(&amount), vec![8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(deserialize::<Amount>(&[8, 7, 6, 5, 4, 3, 2, 1]).unwrap(), amount);
+    }
+}
@@ -20,12 +20,15 @@
 //!
 
 use blockdata::script::Script;
-use blockdata::transaction::{Transaction, TxIn};
+use blockdata::transaction::{Transaction, TxIn, SigHashType};
 use consensus::encode::Encodable;
+use util::amount::Amount;
 use util::hash::{Sha256dHash, Sha256dEncoder};
 
 /// Parts of a sighash which are common across inputs or signatures, and which are
-/// sufficient (in conjunction with a private key) to sign the transaction
+/// sufficient (in conjunction with a private key) to sign the transaction.
+///
+/// This only supports `SIGHASH_ALL`; use `SigHashCache` for the other sighash types.
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct SighashComponents {
     tx_version: u32,
@@ -79,7 +82,7 @@ impl SighashComponents {
 
     /// Compute the BIP143 sighash for a `SIGHASH_ALL` signature for the given
     /// input.
-    pub fn sighash_all(&self, txin: &TxIn, witness_script: &Script, value: u64) -> Sha256dHash {
+    pub fn sighash_all(&self, txin: &TxIn, witness_script: &Script, value: Amount) -> Sha256dHash {
         let mut enc = Sha256dEncoder::new();
         self.tx_version.consensus_encode(&mut enc).unwrap();
         self.hash_prevouts.consensus_encode(&mut enc).unwrap();
@@ -98,6 +101,131 @@ impl SighashComponents {
     }
 }
 
+/// A replacement for `SighashComponents` which supports all sighash types.
+/// The parts of the sighash which are common across inputs are computed the
+/// first time they are needed and then reused for every other input.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigHashCache<'a> {
+    /// Access to transaction required for various introspection
+    tx: &'a Transaction,
+    /// Hash of all the previous outputs, computed as required
+    hash_prevouts: Option<Sha256dHash>,
+    /// Hash of all the input sequence nos, computed as required
+    hash_sequence: Option<Sha256dHash>,
+    /// Hash of all the outputs in this transaction, computed as required
+    hash_outputs: Option<Sha256dHash>,
+}
+
+impl<'a> SigHashCache<'a> {
+    /// Compute the sighash components from an unsigned transaction and auxiliary
+    /// in a lazy manner when required.
+    /// For the generated sighashes to be valid, no fields in the transaction may change except for
+    /// script_sig and witnesses.
+    pub fn new(tx: &Transaction) -> SigHashCache {
+        SigHashCache {
+            tx: tx,
+            hash_prevouts: None,
+            hash_sequence: None,
+            hash_outputs: None,
+        }
+    }
+
+    /// Calculate hash for prevouts
+    pub fn hash_prevouts(&mut self) -> Sha256dHash {
+        if self.hash_prevouts.is_none() {
+            let mut enc = Sha256dEncoder::new();
+            for txin in &self.tx.input {
+                txin.previous_output.consensus_encode(&mut enc).unwrap();
+            }
+            self.hash_prevouts = Some(enc.into_hash());
+        }
+        self.hash_prevouts.unwrap()
+    }
+
+    /// Calculate hash for input sequence values
+    pub fn hash_sequence(&mut self) -> Sha256dHash {
+        if self.hash_sequence.is_none() {
+            let mut enc = Sha256dEncoder::new();
+            for txin in &self.tx.input {
+                txin.sequence.consensus_encode(&mut enc).unwrap();
+            }
+            self.hash_sequence = Some(enc.into_hash());
+        }
+        self.hash_sequence.unwrap()
+    }
+
+    /// Calculate hash for outputs
+    pub fn hash_outputs(&mut self) -> Sha256dHash {
+        if self.hash_outputs.is_none() {
+            let mut enc = Sha256dEncoder::new();
+            for txout in &self.tx.output {
+                txout.consensus_encode(&mut enc).unwrap();
+            }
+            self.hash_outputs = Some(enc.into_hash());
+        }
+        self.hash_outputs.unwrap()
+    }
+
+    /// Compute the BIP143 sighash for any flag type. See `SighashComponents::sighash_all`
+    /// for the parameters; `script_code` is the witness script for P2WSH spends and the
+    /// equivalent P2PKH script for P2WPKH ones. As with `Transaction::signature_hash`,
+    /// `sighash_u32` is committed to as given, so non-standard values are hashed the
+    /// same way consensus code hashes them.
+    ///
+    /// Unlike legacy signature hashes, `SIGHASH_SINGLE` for an input with no corresponding
+    /// output is not special-cased to the hash of 1: as specified in BIP143 it simply
+    /// commits to a zero `hashOutputs`.
+    ///
+    /// # Panics
+    /// Panics if `input_index` is greater than or equal to the number of inputs.
+    pub fn signature_hash(&mut self, input_index: usize, script_code: &Script, value: Amount, sighash_u32: u32) -> Sha256dHash {
+        let zero_hash = Sha256dHash::default();
+
+        let (sighash, anyone_can_pay) = SigHashType::from_u32(sighash_u32).split_anyonecanpay_flag();
+
+        let mut enc = Sha256dEncoder::new();
+        self.tx.version.consensus_encode(&mut enc).unwrap();
+
+        if !anyone_can_pay {
+            self.hash_prevouts().consensus_encode(&mut enc).unwrap();
+        } else {
+            zero_hash.consensus_encode(&mut enc).unwrap();
+        }
+
+        if !anyone_can_pay && sighash != SigHashType::Single && sighash != SigHashType::None {
+            self.hash_sequence().consensus_encode(&mut enc).unwrap();
+        } else {
+            zero_hash.consensus_encode(&mut enc).unwrap();
+        }
+
+        {
+            let txin = &self.tx.input[input_index];
+
+            txin
+                .previous_output
+                .consensus_encode(&mut enc)
+                .unwrap();
+            script_code.consensus_encode(&mut enc).unwrap();
+            value.consensus_encode(&mut enc).unwrap();
+            txin.sequence.consensus_encode(&mut enc).unwrap();
+        }
+
+        if sighash != SigHashType::Single && sighash != SigHashType::None {
+            self.hash_outputs().consensus_encode(&mut enc).unwrap();
+        } else if sighash == SigHashType::Single && input_index < self.tx.output.len() {
+            let mut single_enc = Sha256dEncoder::new();
+            self.tx.output[input_index].consensus_encode(&mut single_enc).unwrap();
+            single_enc.into_hash().consensus_encode(&mut enc).unwrap();
+        } else {
+            zero_hash.consensus_encode(&mut enc).unwrap();
+        }
+
+        self.tx.lock_time.consensus_encode(&mut enc).unwrap();
+        sighash_u32.consensus_encode(&mut enc).unwrap();
+        enc.into_hash()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use blockdata::script::Script;
@@ -131,7 +259,7 @@ mod tests {
         ).unwrap();
 
         let witness_script = p2pkh_hex("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357");
-        let value = 600_000_000;
+        let value = Amount::from_sat(600_000_000);
 
         let comp = SighashComponents::new(&tx);
         assert_eq!(
@@ -168,7 +296,7 @@ mod tests {
         ).unwrap();
 
         let witness_script = p2pkh_hex("03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873");
-        let value = 1_000_000_000;
+        let value = Amount::from_sat(1_000_000_000);
 
         let comp = SighashComponents::new(&tx);
         assert_eq!(
@@ -211,7 +339,7 @@ mod tests {
              2c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b\
              56ae"
         );
-        let value = 987654321;
+        let value = Amount::from_sat(987654321);
 
         let comp = SighashComponents::new(&tx);
         assert_eq!(
@@ -235,5 +363,51 @@ mod tests {
             comp.sighash_all(&tx.input[0], &witness_script, value),
             hex_hash!("185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c")
         );
+
+        let mut cache = SigHashCache::new(&tx);
+        let sighashes = [
+            (SigHashType::All, "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c"),
+            (SigHashType::None, "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36"),
+            (SigHashType::Single, "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea"),
+            (SigHashType::AllPlusAnyoneCanPay, "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e"),
+            (SigHashType::NonePlusAnyoneCanPay, "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a"),
+            (SigHashType::SinglePlusAnyoneCanPay, "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b"),
+        ];
+        for &(sighash_type, expected) in sighashes.iter() {
+            assert_eq!(
+                cache.signature_hash(0, &witness_script, value, sighash_type.as_u32()),
+                hex_hash!(expected)
+            );
+        }
+        assert_eq!(cache.hash_prevouts(), comp.hash_prevouts);
+        assert_eq!(cache.hash_sequence(), comp.hash_sequence);
+        assert_eq!(cache.hash_outputs(), comp.hash_outputs);
+    }
+
+    #[test]
+    fn bip143_sighash_single_no_output() {
+        let tx = deserialize::<Transaction>(
+            &hex_bytes(
+                "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f000000\
+                0000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01000000\
+                00ffffffff01202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac11000000",
+            ).unwrap()[..],
+        ).unwrap();
+        let script_code = p2pkh_hex("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357");
+
+        // With no output at index 1, SIGHASH_SINGLE commits to no outputs at all
+        let mut no_outputs = tx.clone();
+        no_outputs.output.clear();
+        for &sighash_type in [SigHashType::Single, SigHashType::SinglePlusAnyoneCanPay].iter() {
+            assert_eq!(
+                SigHashCache::new(&tx).signature_hash(1, &script_code, Amount::from_sat(600_000_000), sighash_type.as_u32()),
+                SigHashCache::new(&no_outputs).signature_hash(1, &script_code, Amount::from_sat(600_000_000), sighash_type.as_u32())
+            );
+        }
+        // ...whereas input 0 does commit to its output
+        assert!(
+            SigHashCache::new(&tx).signature_hash(0, &script_code, Amount::from_sat(600_000_000), SigHashType::Single.as_u32()) !=
+            SigHashCache::new(&no_outputs).signature_hash(0, &script_code, Amount::from_sat(600_000_000), SigHashType::Single.as_u32())
+        );
     }
 }
@@ -0,0 +1,655 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! BIP158 Compact Block Filters
+//!
+//! Golomb-coded sets, probabilistic filters which a light client can
+//! download in place of a block to learn whether the block may be of
+//! interest to it, and the basic filter type, which commits to the scripts
+//! of the outputs a block creates and spends.
+//!
+
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::{error, fmt};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use blockdata::block::Block;
+use blockdata::script::Script;
+use blockdata::transaction::OutPoint;
+use consensus::encode::{self, Encodable, Decodable, Encoder, Decoder, VarInt};
+use util::hash::{BitcoinHash, Sha256dEncoder, Sha256dHash};
+
+/// The type of the basic filter
+pub const BASIC_FILTER_TYPE: u8 = 0x00;
+/// The Golomb-Rice parameter of the basic filter
+pub const BASIC_FILTER_P: u8 = 19;
+/// The inverse false positive rate of the basic filter
+pub const BASIC_FILTER_M: u64 = 784931;
+
+/// An error building or querying a compact block filter
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The script of an output spent by the block was not provided
+    UtxoMissing(OutPoint),
+    /// The filter's encoding is invalid
+    MalformedFilter,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UtxoMissing(ref outpoint) => write!(f, "missing spent output {}:{}", outpoint.txid, outpoint.vout),
+            Error::MalformedFilter => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::UtxoMissing(..) => "missing spent output",
+            Error::MalformedFilter => "malformed filter",
+        }
+    }
+}
+
+/// A compact block filter, as carried by the `cfilter` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockFilter {
+    /// The number of elements followed by their Golomb-coded set
+    pub content: Vec<u8>,
+}
+
+impl BlockFilter {
+    /// Wraps a serialized filter
+    pub fn new(content: &[u8]) -> BlockFilter {
+        BlockFilter { content: content.to_vec() }
+    }
+
+    /// Builds the basic filter of a block, which contains the scripts of
+    /// the outputs it creates, except empty and OP_RETURN scripts, and of
+    /// the outputs it spends, which `spent_script` must look up.
+    pub fn new_basic<F>(block: &Block, spent_script: F) -> Result<BlockFilter, Error>
+        where F: Fn(&OutPoint) -> Option<Script>
+    {
+        let mut elements = HashSet::new();
+        for tx in &block.txdata {
+            for output in &tx.output {
+                if !output.script_pubkey.is_empty() && !output.script_pubkey.is_op_return() {
+                    elements.insert(output.script_pubkey.to_bytes());
+                }
+            }
+            if tx.is_coin_base() {
+                continue;
+            }
+            for input in &tx.input {
+                match spent_script(&input.previous_output) {
+                    Some(script) => if !script.is_empty() {
+                        elements.insert(script.into_bytes());
+                    },
+                    None => return Err(Error::UtxoMissing(input.previous_output)),
+                }
+            }
+        }
+        let filter = GcsFilter::basic(&block.bitcoin_hash());
+        Ok(BlockFilter { content: filter.build(elements.iter().map(|element| &element[..])) })
+    }
+
+    /// Computes the hash of the filter
+    pub fn filter_hash(&self) -> Sha256dHash {
+        Sha256dHash::from_data(&self.content)
+    }
+
+    /// Computes the filter header, which commits to this filter and, through
+    /// the previous block's filter header, to those of all previous blocks
+    pub fn filter_header(&self, previous_header: &Sha256dHash) -> Sha256dHash {
+        let mut encoder = Sha256dEncoder::new();
+        self.filter_hash().consensus_encode(&mut encoder).unwrap();
+        previous_header.consensus_encode(&mut encoder).unwrap();
+        encoder.into_hash()
+    }
+
+    /// Whether any of the query scripts may be in this basic filter of the
+    /// block with the given hash
+    pub fn match_any(&self, block_hash: &Sha256dHash, query: &[&[u8]]) -> Result<bool, Error> {
+        GcsFilter::basic(block_hash).match_any(&self.content, query)
+    }
+
+    /// Whether all of the query scripts may be in this basic filter of the
+    /// block with the given hash
+    pub fn match_all(&self, block_hash: &Sha256dHash, query: &[&[u8]]) -> Result<bool, Error> {
+        GcsFilter::basic(block_hash).match_all(&self.content, query)
+    }
+}
+
+impl<S: Encoder> Encodable<S> for BlockFilter {
+    fn consensus_encode(&self, s: &mut S) -> Result<(), encode::Error> {
+        self.content.consensus_encode(s)
+    }
+}
+
+impl<D: Decoder> Decodable<D> for BlockFilter {
+    fn consensus_decode(d: &mut D) -> Result<BlockFilter, encode::Error> {
+        Ok(BlockFilter { content: Decodable::consensus_decode(d)? })
+    }
+}
+
+/// The parameters of a Golomb-coded set: the SipHash key with which its
+/// elements are hashed, the Golomb-Rice parameter `p` and the inverse false
+/// positive rate `m`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct GcsFilter {
+    k0: u64,
+    k1: u64,
+    p: u8,
+    m: u64,
+}
+
+impl GcsFilter {
+    /// Creates a Golomb-coded set with the given parameters
+    pub fn new(k0: u64, k1: u64, p: u8, m: u64) -> GcsFilter {
+        GcsFilter { k0: k0, k1: k1, p: p, m: m }
+    }
+
+    /// Creates a Golomb-coded set with the parameters of the basic filter
+    /// of the block with the given hash, whose first 16 bytes are the key
+    pub fn basic(block_hash: &Sha256dHash) -> GcsFilter {
+        GcsFilter::new(LittleEndian::read_u64(&block_hash[0..8]),
+                       LittleEndian::read_u64(&block_hash[8..16]),
+                       BASIC_FILTER_P,
+                       BASIC_FILTER_M)
+    }
+
+    /// Builds the set of the given distinct elements, serialized as the
+    /// number of elements followed by the Golomb-Rice coded differences
+    /// between their sorted hashes
+    pub fn build<'a, I: Iterator<Item = &'a [u8]>>(&self, elements: I) -> Vec<u8> {
+        let elements: Vec<&[u8]> = elements.collect();
+        let range = elements.len() as u64 * self.m;
+        let mut hashes: Vec<u64> = elements.iter().map(|element| self.hash_to_range(element, range)).collect();
+        hashes.sort();
+
+        let mut content = vec![];
+        VarInt(hashes.len() as u64).consensus_encode(&mut content).unwrap();
+        let mut writer = BitStreamWriter::new(content);
+        let mut last = 0;
+        for hash in hashes {
+            self.golomb_rice_encode(&mut writer, hash - last);
+            last = hash;
+        }
+        writer.finish()
+    }
+
+    /// Whether any of the query elements may be in the serialized set
+    pub fn match_any(&self, filter: &[u8], query: &[&[u8]]) -> Result<bool, Error> {
+        let (mut reader, count) = self.reader(filter)?;
+        if count == 0 || query.is_empty() {
+            return Ok(false);
+        }
+        let queries = self.sorted_query(count, query)?;
+
+        let mut value = self.golomb_rice_decode(&mut reader)?;
+        let mut read = 1;
+        for query in queries {
+            while value < query {
+                if read == count {
+                    return Ok(false);
+                }
+                value = match value.checked_add(self.golomb_rice_decode(&mut reader)?) {
+                    Some(value) => value,
+                    None => return Err(Error::MalformedFilter),
+                };
+                read += 1;
+            }
+            if value == query {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether all of the query elements may be in the serialized set
+    pub fn match_all(&self, filter: &[u8], query: &[&[u8]]) -> Result<bool, Error> {
+        let (mut reader, count) = self.reader(filter)?;
+        if query.is_empty() {
+            return Ok(true);
+        }
+        if count == 0 {
+            return Ok(false);
+        }
+        let queries = self.sorted_query(count, query)?;
+
+        let mut value = self.golomb_rice_decode(&mut reader)?;
+        let mut read = 1;
+        for query in queries {
+            while value < query {
+                if read == count {
+                    return Ok(false);
+                }
+                value = match value.checked_add(self.golomb_rice_decode(&mut reader)?) {
+                    Some(value) => value,
+                    None => return Err(Error::MalformedFilter),
+                };
+                read += 1;
+            }
+            if value != query {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Reads the number of elements of a serialized set, returning a reader
+    /// for its Golomb-Rice coded values. As in Bitcoin Core, sets of 2^32
+    /// elements or more are rejected.
+    fn reader<'a>(&self, filter: &'a [u8]) -> Result<(BitStreamReader<'a>, u64), Error> {
+        let mut cursor = Cursor::new(filter);
+        let count = match VarInt::consensus_decode(&mut cursor) {
+            Ok(VarInt(count)) if count <= ::std::u32::MAX as u64 => count,
+            _ => return Err(Error::MalformedFilter),
+        };
+        Ok((BitStreamReader::new(&filter[cursor.position() as usize..]), count))
+    }
+
+    /// Hashes query elements into the range of a set with `count` elements
+    fn sorted_query(&self, count: u64, query: &[&[u8]]) -> Result<Vec<u64>, Error> {
+        let range = match count.checked_mul(self.m) {
+            Some(range) => range,
+            None => return Err(Error::MalformedFilter),
+        };
+        let mut hashes: Vec<u64> = query.iter().map(|element| self.hash_to_range(element, range)).collect();
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    /// Hashes an element uniformly into [0, range)
+    fn hash_to_range(&self, element: &[u8], range: u64) -> u64 {
+        mul_high(siphash24(self.k0, self.k1, element), range)
+    }
+
+    fn golomb_rice_encode(&self, writer: &mut BitStreamWriter, value: u64) {
+        let mut quotient = value >> self.p;
+        while quotient > 0 {
+            let bits = if quotient >= 64 { 64 } else { quotient as u8 };
+            writer.write(::std::u64::MAX, bits);
+            quotient -= bits as u64;
+        }
+        writer.write(0, 1);
+        writer.write(value, self.p);
+    }
+
+    fn golomb_rice_decode(&self, reader: &mut BitStreamReader) -> Result<u64, Error> {
+        let mut quotient = 0u64;
+        while reader.read(1)? == 1 {
+            quotient += 1;
+        }
+        let remainder = reader.read(self.p)?;
+        match quotient.checked_shl(self.p as u32) {
+            Some(value) if value >> self.p == quotient => Ok(value + remainder),
+            _ => Err(Error::MalformedFilter),
+        }
+    }
+}
+
+/// Computes the upper 64 bits of the 128-bit product of two integers
+fn mul_high(a: u64, b: u64) -> u64 {
+    let (a_hi, a_lo) = (a >> 32, a & 0xffffffff);
+    let (b_hi, b_lo) = (b >> 32, b & 0xffffffff);
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+    let cross = (lo_lo >> 32) + (hi_lo & 0xffffffff) + lo_hi;
+    hi_hi + (hi_lo >> 32) + (cross >> 32)
+}
+
+/// Computes SipHash-2-4 of the data with the given key
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [k0 ^ 0x736f6d6570736575,
+                 k1 ^ 0x646f72616e646f6d,
+                 k0 ^ 0x6c7967656e657261,
+                 k1 ^ 0x7465646279746573];
+
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]); v[1] = v[1].rotate_left(13); v[1] ^= v[0]; v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]); v[3] = v[3].rotate_left(16); v[3] ^= v[2];
+        v[0] = v[0].wrapping_add(v[3]); v[3] = v[3].rotate_left(21); v[3] ^= v[0];
+        v[2] = v[2].wrapping_add(v[1]); v[1] = v[1].rotate_left(17); v[1] ^= v[2]; v[2] = v[2].rotate_left(32);
+    }
+
+    let mut chunks = data.chunks(8);
+    let mut last = (data.len() as u64) << 56;
+    for chunk in &mut chunks {
+        if chunk.len() < 8 {
+            for (idx, &byte) in chunk.iter().enumerate() {
+                last |= (byte as u64) << (8 * idx);
+            }
+            break;
+        }
+        let m = LittleEndian::read_u64(chunk);
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    }
+
+    v[3] ^= last;
+    round(&mut v);
+    round(&mut v);
+    v[0] ^= last;
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Writes bits most significant first
+struct BitStreamWriter {
+    buffer: Vec<u8>,
+    /// The bits of the final, partial byte
+    partial: u8,
+    /// The number of bits in `partial`
+    offset: u8,
+}
+
+impl BitStreamWriter {
+    fn new(buffer: Vec<u8>) -> BitStreamWriter {
+        BitStreamWriter { buffer: buffer, partial: 0, offset: 0 }
+    }
+
+    /// Writes the lowest `nbits` bits of `data`
+    fn write(&mut self, data: u64, nbits: u8) {
+        for idx in (0..nbits).rev() {
+            self.partial |= (((data >> idx) & 1) as u8) << (7 - self.offset);
+            self.offset += 1;
+            if self.offset == 8 {
+                self.buffer.push(self.partial);
+                self.partial = 0;
+                self.offset = 0;
+            }
+        }
+    }
+
+    /// Pads the final byte with zeros and returns the buffer
+    fn finish(mut self) -> Vec<u8> {
+        if self.offset > 0 {
+            self.buffer.push(self.partial);
+        }
+        self.buffer
+    }
+}
+
+/// Reads bits most significant first
+struct BitStreamReader<'a> {
+    buffer: &'a [u8],
+    /// The number of bits read
+    position: usize,
+}
+
+impl<'a> BitStreamReader<'a> {
+    fn new(buffer: &'a [u8]) -> BitStreamReader<'a> {
+        BitStreamReader { buffer: buffer, position: 0 }
+    }
+
+    /// Reads `nbits` bits, up to 64, into the lowest bits of the result
+    fn read(&mut self, nbits: u8) -> Result<u64, Error> {
+        let mut data = 0;
+        for _ in 0..nbits {
+            let byte = match self.buffer.get(self.position / 8) {
+                Some(&byte) => byte,
+                None => return Err(Error::MalformedFilter),
+            };
+            data = (data << 1) | ((byte >> (7 - self.position % 8)) & 1) as u64;
+            self.position += 1;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rustc_serialize::json::Json;
+
+    use blockdata::block::Block;
+    use blockdata::constants::genesis_block;
+    use blockdata::script::Script;
+    use blockdata::transaction::OutPoint;
+    use consensus::encode::{deserialize, serialize};
+    use network::constants::Network;
+    use util::hash::{BitcoinHash, Sha256dHash};
+    use util::misc::hex_bytes;
+
+    use super::*;
+
+    #[test]
+    fn siphash() {
+        // The reference SipHash-2-4 vectors, with key 00 01 .. 0f
+        let k0 = 0x0706050403020100;
+        let k1 = 0x0f0e0d0c0b0a0908;
+        let data: Vec<u8> = (0..64).collect();
+        assert_eq!(siphash24(k0, k1, &data[..0]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(k0, k1, &data[..1]), 0x74f839c593dc67fd);
+        assert_eq!(siphash24(k0, k1, &data[..8]), 0x93f5f5799a932462);
+        assert_eq!(siphash24(k0, k1, &data[..15]), 0xa129ca6149be45e5);
+    }
+
+    #[test]
+    fn mul_high_test() {
+        assert_eq!(mul_high(0, 12345), 0);
+        assert_eq!(mul_high(1 << 63, 10), 5);
+        assert_eq!(mul_high(::std::u64::MAX, ::std::u64::MAX), ::std::u64::MAX - 1);
+        assert_eq!(mul_high(0x123456789abcdef0, 0xfedcba9876543210), 0x121fa00ad77d7422);
+    }
+
+    #[test]
+    fn gcs_round_trip() {
+        let filter = GcsFilter::new(0x0102030405060708, 0x1112131415161718, BASIC_FILTER_P, BASIC_FILTER_M);
+        let elements: Vec<Vec<u8>> = (0..1000u32).map(|i| serialize(&i)).collect();
+        let content = filter.build(elements.iter().map(|element| &element[..]));
+
+        let all: Vec<&[u8]> = elements.iter().map(|element| &element[..]).collect();
+        assert_eq!(filter.match_all(&content, &all), Ok(true));
+        for element in &elements {
+            assert_eq!(filter.match_any(&content, &[&element[..]]), Ok(true));
+        }
+
+        let absent: Vec<Vec<u8>> = (1000..2000u32).map(|i| serialize(&i)).collect();
+        let absent: Vec<&[u8]> = absent.iter().map(|element| &element[..]).collect();
+        // With a false positive rate of 1 in 784931, none of these match
+        assert_eq!(filter.match_any(&content, &absent), Ok(false));
+        let mut some = absent.clone();
+        some.push(all[500]);
+        assert_eq!(filter.match_any(&content, &some), Ok(true));
+        assert_eq!(filter.match_all(&content, &some), Ok(false));
+
+        let empty = filter.build(vec![].into_iter());
+        assert_eq!(empty, vec![0]);
+        assert_eq!(filter.match_any(&empty, &all), Ok(false));
+
+        assert_eq!(filter.match_all(&content[..content.len() / 2], &all), Err(Error::MalformedFilter));
+        assert_eq!(filter.match_any(&[], &all), Err(Error::MalformedFilter));
+
+        // Element counts of 2^32 or more are rejected, as is a set whose
+        // range overflows
+        let mut huge = hex_bytes("ff0000000001000000").unwrap();
+        huge.extend_from_slice(&content[1..]);
+        assert_eq!(filter.match_any(&huge, &all), Err(Error::MalformedFilter));
+        assert_eq!(filter.match_all(&huge, &all), Err(Error::MalformedFilter));
+        let wide = GcsFilter::new(0, 0, BASIC_FILTER_P, ::std::u64::MAX);
+        assert_eq!(wide.match_any(&content, &all), Err(Error::MalformedFilter));
+    }
+
+    #[test]
+    fn gcs_query_hashing_to_zero() {
+        // In a set of one element with m = 2, elements hash to 0 or 1. A
+        // query hashing to 0 must not match a set whose element hashes to 1.
+        let filter = GcsFilter::new(0x0102030405060708, 0x1112131415161718, BASIC_FILTER_P, 2);
+        let elements: Vec<Vec<u8>> = (0..100u32).map(|i| serialize(&i)).collect();
+        let one = elements.iter().find(|element| filter.hash_to_range(element, 2) == 1).unwrap();
+        let zero = elements.iter().find(|element| filter.hash_to_range(element, 2) == 0).unwrap();
+
+        let content = filter.build(vec![&one[..]].into_iter());
+        assert_eq!(filter.match_any(&content, &[&zero[..]]), Ok(false));
+        assert_eq!(filter.match_all(&content, &[&zero[..]]), Ok(false));
+        assert_eq!(filter.match_any(&content, &[&zero[..], &one[..]]), Ok(true));
+        assert_eq!(filter.match_all(&content, &[&zero[..], &one[..]]), Ok(false));
+        assert_eq!(filter.match_all(&content, &[&one[..]]), Ok(true));
+    }
+
+    #[test]
+    fn genesis_filter() {
+        // The first of the BIP158 test vectors: the testnet genesis block
+        let genesis = genesis_block(Network::Testnet);
+        let filter = BlockFilter::new_basic(&genesis, |_| None).unwrap();
+        assert_eq!(filter.content, hex_bytes("019dfca8").unwrap());
+        assert_eq!(filter.filter_header(&Default::default()).be_hex_string(),
+                   "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750");
+
+        let script = genesis.txdata[0].output[0].script_pubkey.clone();
+        assert_eq!(filter.match_any(&genesis.bitcoin_hash(), &[&script[..]]), Ok(true));
+        assert_eq!(filter.match_any(&Sha256dHash::default(), &[&script[..]]), Ok(false));
+
+        let encoded = serialize(&filter);
+        assert_eq!(encoded, hex_bytes("04019dfca8").unwrap());
+        assert_eq!(deserialize::<BlockFilter>(&encoded).unwrap(), filter);
+    }
+
+    #[test]
+    fn bip158_vectors() {
+        // Rows of block height, block hash, block, the scripts spent by its
+        // inputs, previous filter header, filter and filter header
+        let rows = Json::from_str(include_str!("../../test_data/testnet-19.json")).unwrap();
+        let mut tested = 0;
+        for row in rows.as_array().unwrap() {
+            let row = row.as_array().unwrap();
+            if row.len() == 1 {
+                continue;  // a comment
+            }
+            let field = |i: usize| row[i].as_string().unwrap();
+
+            let block: Block = deserialize(&hex_bytes(field(2)).unwrap()).unwrap();
+            assert_eq!(block.bitcoin_hash(), Sha256dHash::from_hex(field(1)).unwrap());
+            let mut scripts = row[3].as_array().unwrap().iter()
+                                    .map(|script| Script::from(hex_bytes(script.as_string().unwrap()).unwrap()));
+            let mut spent = HashMap::new();
+            for tx in block.txdata.iter().skip(1) {
+                for input in &tx.input {
+                    spent.insert(input.previous_output, scripts.next().unwrap());
+                }
+            }
+            assert!(scripts.next().is_none());
+
+            let filter = BlockFilter::new_basic(&block, |outpoint| spent.get(outpoint).cloned()).unwrap();
+            let expected = BlockFilter::new(&hex_bytes(field(5)).unwrap());
+            assert_eq!(filter.content, expected.content);
+            assert_eq!(filter.filter_hash(), expected.filter_hash());
+            let previous_header = Sha256dHash::from_hex(field(4)).unwrap();
+            assert_eq!(filter.filter_header(&previous_header), Sha256dHash::from_hex(field(6)).unwrap());
+            tested += 1;
+        }
+        assert!(tested > 0);
+    }
+
+    #[test]
+    fn basic_filter() {
+        let genesis = genesis_block(Network::Testnet);
+        let mut block = genesis.clone();
+        let mut spend = genesis.txdata[0].clone();
+        spend.input[0].previous_output = OutPoint { txid: genesis.txdata[0].txid(), vout: 0 };
+        spend.output[0].script_pubkey = Script::from(vec![0x51]);
+        let mut op_return = spend.output[0].clone();
+        op_return.script_pubkey = Script::from(vec![0x6a, 0x01, 0x02]);
+        spend.output.push(op_return.clone());
+        block.txdata.push(spend.clone());
+
+        let spent = genesis.txdata[0].output[0].script_pubkey.clone();
+        assert_eq!(BlockFilter::new_basic(&block, |_| None), Err(Error::UtxoMissing(spend.input[0].previous_output)));
+        let filter = BlockFilter::new_basic(&block, |_| Some(spent.clone())).unwrap();
+        // The spent script is also an output of the coinbase, so appears once
+        assert_eq!(filter.content[0], 2);
+        let hash = block.bitcoin_hash();
+        assert_eq!(filter.match_all(&hash, &[&spent[..], &[0x51]]), Ok(true));
+        assert_eq!(filter.match_any(&hash, &[&op_return.script_pubkey[..]]), Ok(false));
+
+        // Filter headers chain from the genesis block's
+        let genesis_header = BlockFilter::new_basic(&genesis, |_| None).unwrap().filter_header(&Default::default());
+        let header = filter.filter_header(&genesis_header);
+        let mut data = filter.filter_hash()[..].to_vec();
+        data.extend_from_slice(&genesis_header[..]);
+        assert_eq!(header, Sha256dHash::from_data(&data));
+        assert!(header != filter.filter_header(&Default::default()));
+    }
+
+    #[test]
+    fn basic_filter_elements() {
+        // A block with empty and OP_RETURN outputs, a script repeated across
+        // outputs and spent outputs, and segwit spends with witnesses
+        let genesis = genesis_block(Network::Testnet);
+        let p2wpkh = Script::from(hex_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap());
+        let p2pkh = Script::from(hex_bytes("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").unwrap());
+        let prevout = |vout| OutPoint { txid: Sha256dHash::from_data(&[1]), vout: vout };
+
+        let mut block = genesis.clone();
+        let mut spend = genesis.txdata[0].clone();
+        spend.input[0].previous_output = prevout(0);
+        spend.input[0].script_sig = Script::new();
+        spend.input[0].witness = vec![vec![0x30; 71], vec![0x02; 33]];
+        let mut input = spend.input[0].clone();
+        input.previous_output = prevout(1);
+        spend.input.push(input.clone());
+        input.previous_output = prevout(2);
+        input.witness.clear();
+        spend.input.push(input);
+        let mut output = spend.output[0].clone();
+        spend.output.clear();
+        for script in vec![Script::new(), Script::from(vec![0x6a]), Script::from(vec![0x6a, 0x01, 0x02]), p2wpkh.clone(), p2wpkh.clone()] {
+            output.script_pubkey = script;
+            spend.output.push(output.clone());
+        }
+        block.txdata.push(spend);
+
+        // The first two inputs spend the repeated P2WPKH script, the third
+        // an empty script
+        let filter = BlockFilter::new_basic(&block, |outpoint| Some(match outpoint.vout {
+            0 | 1 => p2wpkh.clone(),
+            _ => Script::new(),
+        })).unwrap();
+        let coinbase_script = &genesis.txdata[0].output[0].script_pubkey;
+        let expected: Vec<&[u8]> = vec![&coinbase_script[..], &p2wpkh[..]];
+        let hash = block.bitcoin_hash();
+        assert_eq!(filter.content, GcsFilter::basic(&hash).build(expected.iter().cloned()));
+        assert_eq!(filter.content[0], 2);
+        assert_eq!(filter.match_all(&hash, &expected), Ok(true));
+        assert_eq!(filter.match_any(&hash, &[&[0x6a][..], &[0x6a, 0x01, 0x02], &p2pkh[..]]), Ok(false));
+
+        // A spent script which is not also an output is included as well
+        let filter = BlockFilter::new_basic(&block, |outpoint| Some(match outpoint.vout {
+            0 => p2pkh.clone(),
+            1 => p2wpkh.clone(),
+            _ => Script::new(),
+        })).unwrap();
+        assert_eq!(filter.content[0], 3);
+        assert_eq!(filter.match_all(&hash, &[&p2pkh[..], &p2wpkh[..], &coinbase_script[..]]), Ok(true));
+    }
+}
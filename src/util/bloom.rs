@@ -0,0 +1,396 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Bloom filters
+//!
+//! This module implements the bloom filters with which light clients ask
+//! their peers to relay only the transactions they are interested in
+//! (BIP37), and the matching rules by which a peer decides whether a
+//! transaction is relevant to a filter.
+//!
+
+use std::cmp;
+use std::f64::consts::LN_2;
+
+use blockdata::transaction::{OutPoint, Transaction};
+use blockdata::script::{Instruction, Script};
+use consensus::encode::{self, serialize, Encodable, Decodable, Encoder, Decoder};
+use util::policy::{self, ScriptType};
+
+/// The maximum size, in bytes, of a bloom filter
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36000;
+/// The maximum number of hash functions of a bloom filter
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// The bits of the flags byte of a `filterload` message which select how
+/// the filter is updated
+const BLOOM_UPDATE_MASK: u8 = 3;
+
+/// The multiplier by which the index of a hash function is scaled to give
+/// its murmur3 seed
+const SEED_MULTIPLIER: u32 = 0xFBA4C795;
+
+/// How a filter is updated when a transaction output matches it
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BloomFlags {
+    /// The filter is never updated
+    None,
+    /// The outpoint of every matching output is added to the filter
+    All,
+    /// The outpoint of a matching output is added to the filter only if
+    /// its script is pay-to-pubkey or bare multisig
+    PubkeyOnly,
+}
+
+impl BloomFlags {
+    /// The value of the flags as they appear in a `filterload` message
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            BloomFlags::None => 0,
+            BloomFlags::All => 1,
+            BloomFlags::PubkeyOnly => 2,
+        }
+    }
+}
+
+impl<S: Encoder> Encodable<S> for BloomFlags {
+    fn consensus_encode(&self, s: &mut S) -> Result<(), encode::Error> {
+        self.to_u8().consensus_encode(s)
+    }
+}
+
+impl<D: Decoder> Decodable<D> for BloomFlags {
+    fn consensus_decode(d: &mut D) -> Result<BloomFlags, encode::Error> {
+        let flags: u8 = Decodable::consensus_decode(d)?;
+        // As in Bitcoin Core, higher bits are ignored and the unused value 3
+        // means the filter is never updated
+        match flags & BLOOM_UPDATE_MASK {
+            1 => Ok(BloomFlags::All),
+            2 => Ok(BloomFlags::PubkeyOnly),
+            _ => Ok(BloomFlags::None),
+        }
+    }
+}
+
+/// A bloom filter, as sent in a `filterload` message
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BloomFilter {
+    /// The bit field of the filter
+    pub data: Vec<u8>,
+    /// The number of hash functions
+    pub n_hash_funcs: u32,
+    /// A random value added to the seeds of the hash functions
+    pub tweak: u32,
+    /// How the filter is updated when a transaction output matches it
+    pub flags: BloomFlags,
+}
+impl_consensus_encoding!(BloomFilter, data, n_hash_funcs, tweak, flags);
+
+impl BloomFilter {
+    /// Creates an empty filter sized to hold the given number of elements
+    /// with at most the given false positive rate, within the protocol's
+    /// limits on the size of filters
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: BloomFlags) -> BloomFilter {
+        let elements = if elements == 0 { 1 } else { elements } as f64;
+        let bits = -1.0 / (LN_2 * LN_2) * elements * fp_rate.ln();
+        let bits = bits.min((MAX_BLOOM_FILTER_SIZE * 8) as f64) as usize;
+        let data = vec![0; bits / 8];
+        let n_hash_funcs = ((data.len() * 8) as f64 / elements * LN_2) as u32;
+        BloomFilter {
+            data: data,
+            n_hash_funcs: cmp::min(n_hash_funcs, MAX_HASH_FUNCS),
+            tweak: tweak,
+            flags: flags,
+        }
+    }
+
+    /// Whether the filter is within the protocol's limits, which peers
+    /// enforce on a `filterload` message
+    pub fn is_within_size_constraints(&self) -> bool {
+        self.data.len() <= MAX_BLOOM_FILTER_SIZE && self.n_hash_funcs <= MAX_HASH_FUNCS
+    }
+
+    /// The index of the bit set by the hash function `n` for some data
+    fn bit_index(&self, n: u32, data: &[u8]) -> usize {
+        let seed = n.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3(seed, data) as usize % (self.data.len() * 8)
+    }
+
+    /// Adds some data to the filter
+    pub fn insert(&mut self, data: &[u8]) {
+        if self.data.is_empty() {
+            return;
+        }
+        for n in 0..self.n_hash_funcs {
+            let idx = self.bit_index(n, data);
+            self.data[idx / 8] |= 1 << (idx % 8);
+        }
+    }
+
+    /// Whether some data may have been added to the filter. An empty filter
+    /// matches everything.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.n_hash_funcs).all(|n| {
+            let idx = self.bit_index(n, data);
+            self.data[idx / 8] & (1 << (idx % 8)) != 0
+        })
+    }
+
+    /// Adds an outpoint, in its serialized form, to the filter
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize(outpoint));
+    }
+
+    /// Whether an outpoint may have been added to the filter
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+
+    /// Whether a transaction matches the filter, as decided by a peer
+    /// relaying transactions to a light client: it does if the filter
+    /// contains its txid, a data push in one of its output scripts, one of
+    /// the outpoints it spends or a data push in one of its input scripts.
+    /// Matching outputs are added to the filter according to its flags, so
+    /// that transactions spending them match as well.
+    pub fn is_relevant_and_update(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.txid();
+        let mut found = self.contains(&txid[..]);
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            if !self.contains_push(&output.script_pubkey) {
+                continue;
+            }
+            found = true;
+            let update = match self.flags {
+                BloomFlags::None => false,
+                BloomFlags::All => true,
+                BloomFlags::PubkeyOnly => match policy::classify(&output.script_pubkey) {
+                    ScriptType::PubKey | ScriptType::Multisig { .. } => true,
+                    _ => false,
+                },
+            };
+            if update {
+                self.insert_outpoint(&OutPoint { txid: txid, vout: vout as u32 });
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.input.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output) || self.contains_push(&input.script_sig)
+        })
+    }
+
+    /// Whether the filter contains any non-empty data push of a script,
+    /// stopping at the first opcode which fails to parse
+    fn contains_push(&self, script: &Script) -> bool {
+        for instruction in script.iter(false) {
+            match instruction {
+                Instruction::PushBytes(data) => {
+                    if !data.is_empty() && self.contains(data) {
+                        return true;
+                    }
+                }
+                Instruction::Op(_) => {}
+                Instruction::Error(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+/// The 32-bit x86 variant of MurmurHash3, as used by bloom filters
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut h1 = seed;
+    let mut chunks = data.chunks(4);
+    let mut tail: &[u8] = &[];
+    for chunk in &mut chunks {
+        if chunk.len() < 4 {
+            tail = chunk;
+            break;
+        }
+        let mut k1 = chunk[0] as u32 | (chunk[1] as u32) << 8 | (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24;
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let mut k1 = 0u32;
+    for (idx, &byte) in tail.iter().enumerate() {
+        k1 ^= (byte as u32) << (8 * idx);
+    }
+    if !tail.is_empty() {
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h1 ^= k1;
+    }
+
+    h1 ^= data.len() as u32;
+    h1 ^= h1 >> 16;
+    h1 = h1.wrapping_mul(0x85ebca6b);
+    h1 ^= h1 >> 13;
+    h1 = h1.wrapping_mul(0xc2b2ae35);
+    h1 ^= h1 >> 16;
+    h1
+}
+
+#[cfg(test)]
+mod tests {
+    use hex::decode as hex_decode;
+
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use util::amount::Amount;
+
+    use super::*;
+
+    fn tx(inputs: Vec<TxIn>, scripts: Vec<Script>) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: inputs,
+            output: scripts.into_iter().map(|script| TxOut { value: Amount::from_sat(1000), script_pubkey: script }).collect(),
+        }
+    }
+
+    fn spend(outpoint: OutPoint, script_sig: Script) -> TxIn {
+        TxIn {
+            previous_output: outpoint,
+            script_sig: script_sig,
+            sequence: 0xffffffff,
+            witness: vec![],
+        }
+    }
+
+    fn p2pk(key: &[u8]) -> Script {
+        Builder::new().push_slice(key).push_opcode(opcodes::All::OP_CHECKSIG).into_script()
+    }
+
+    fn p2pkh(hash: &[u8]) -> Script {
+        Builder::new()
+            .push_opcode(opcodes::All::OP_DUP)
+            .push_opcode(opcodes::All::OP_HASH160)
+            .push_slice(hash)
+            .push_opcode(opcodes::All::OP_EQUALVERIFY)
+            .push_opcode(opcodes::All::OP_CHECKSIG)
+            .into_script()
+    }
+
+    #[test]
+    fn murmur3_test() {
+        let vectors: &[(u32, u32, &str)] = &[
+            (0x00000000, 0x00000000, ""),
+            (0x6a396f08, 0xFBA4C795, ""),
+            (0x81f16f39, 0xffffffff, ""),
+            (0x514e28b7, 0x00000000, "00"),
+            (0xea3f0b17, 0xFBA4C795, "00"),
+            (0xfd6cf10d, 0x00000000, "ff"),
+            (0x16c6b7ab, 0x00000000, "0011"),
+            (0x8eb51c3d, 0x00000000, "001122"),
+            (0xb4471bf8, 0x00000000, "00112233"),
+            (0xe2301fa8, 0x00000000, "0011223344"),
+            (0xfc2e4a15, 0x00000000, "001122334455"),
+            (0xb074502c, 0x00000000, "00112233445566"),
+            (0x8034d2a0, 0x00000000, "0011223344556677"),
+            (0xb4698def, 0x00000000, "001122334455667788"),
+        ];
+        for &(expected, seed, data) in vectors {
+            assert_eq!(murmur3(seed, &hex_decode(data).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn insert_serialize_test() {
+        for &(tweak, expected) in &[(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")] {
+            let mut filter = BloomFilter::new(3, 0.01, tweak, BloomFlags::All);
+            let first = hex_decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+            filter.insert(&first);
+            assert!(filter.contains(&first));
+            assert!(!filter.contains(&hex_decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap()));
+            filter.insert(&hex_decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee").unwrap());
+            filter.insert(&hex_decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5").unwrap());
+
+            let encoded = serialize(&filter);
+            assert_eq!(encoded, hex_decode(expected).unwrap());
+            assert_eq!(deserialize::<BloomFilter>(&encoded).unwrap(), filter);
+        }
+        // Unknown flags are masked off, and 3 means no updates
+        let filter: BloomFilter = deserialize(&hex_decode("03614e9b050000000000000003").unwrap()).unwrap();
+        assert_eq!(filter.flags, BloomFlags::None);
+        let filter: BloomFilter = deserialize(&hex_decode("03614e9b0500000000000000fe").unwrap()).unwrap();
+        assert_eq!(filter.flags, BloomFlags::PubkeyOnly);
+    }
+
+    #[test]
+    fn size_test() {
+        let filter = BloomFilter::new(1_000_000, 0.0001, 0, BloomFlags::None);
+        assert_eq!(filter.data.len(), MAX_BLOOM_FILTER_SIZE);
+        assert!(filter.is_within_size_constraints());
+        let filter = BloomFilter::new(1, 1e-30, 0, BloomFlags::None);
+        assert_eq!(filter.n_hash_funcs, MAX_HASH_FUNCS);
+
+        // An empty filter matches everything
+        let mut filter = BloomFilter::new(1, 0.5, 0, BloomFlags::None);
+        assert!(filter.data.is_empty());
+        filter.insert(&[1]);
+        assert!(filter.contains(&[2]));
+    }
+
+    #[test]
+    fn match_txid_test() {
+        let funding = tx(vec![], vec![p2pkh(&[1; 20])]);
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::None);
+        assert!(!filter.is_relevant_and_update(&funding));
+        filter.insert(&funding.txid()[..]);
+        assert!(filter.is_relevant_and_update(&funding));
+    }
+
+    #[test]
+    fn match_update_test() {
+        let key = [2; 33];
+        let hash = [3; 20];
+        let funding = tx(vec![], vec![p2pk(&key), p2pkh(&hash)]);
+        let outpoint = |vout| OutPoint { txid: funding.txid(), vout: vout };
+        let spending = |vout| tx(vec![spend(outpoint(vout), Script::new())], vec![]);
+
+        for &flags in &[BloomFlags::None, BloomFlags::All, BloomFlags::PubkeyOnly] {
+            let mut filter = BloomFilter::new(10, 0.000001, 0, flags);
+            filter.insert(&key);
+            filter.insert(&hash);
+            assert!(filter.is_relevant_and_update(&funding));
+            // Only the pay-to-pubkey output is added in pubkey-only mode
+            assert_eq!(filter.is_relevant_and_update(&spending(0)), flags != BloomFlags::None);
+            assert_eq!(filter.is_relevant_and_update(&spending(1)), flags == BloomFlags::All);
+        }
+
+        // Outpoints and input pushes match without updating the filter
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BloomFlags::All);
+        filter.insert_outpoint(&outpoint(1));
+        assert!(filter.is_relevant_and_update(&spending(1)));
+        assert!(!filter.is_relevant_and_update(&spending(0)));
+        let signed = tx(vec![spend(outpoint(0), Builder::new().push_slice(&key).into_script())], vec![]);
+        assert!(!filter.is_relevant_and_update(&signed));
+        filter.insert(&key);
+        assert!(filter.is_relevant_and_update(&signed));
+        assert!(!filter.contains_outpoint(&OutPoint { txid: signed.txid(), vout: 0 }));
+    }
+}
@@ -0,0 +1,660 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Coin selection
+//!
+//! Algorithms for choosing which outputs to spend to fund a transaction,
+//! following those used by Bitcoin Core's wallet: Branch-and-Bound, which
+//! searches for a changeless solution, the knapsack solver and single random
+//! draw. Solutions are compared by their waste metric.
+//!
+//! All algorithms work on effective values, i.e. the value of a candidate
+//! less the fee needed to spend it, and ignore candidates whose effective
+//! value is not positive. Randomized algorithms take the random number
+//! generator as an argument, so a seeded generator gives reproducible results.
+//!
+
+use std::{error, fmt};
+
+use rand::Rng;
+
+use blockdata::transaction::{OutPoint, TxOut};
+use util::amount::{Amount, SignedAmount};
+use util::feerate::FeeRate;
+
+/// The maximum number of steps Branch-and-Bound will search for
+const BNB_TOTAL_TRIES: usize = 100_000;
+/// The number of random subsets the knapsack solver tries
+const KNAPSACK_ITERATIONS: usize = 1000;
+/// The weight of an input excluding its scriptSig and witness, i.e. of its
+/// outpoint and sequence number
+const INPUT_BASE_WEIGHT: u64 = 4 * (32 + 4 + 4);
+
+/// An output which may be selected
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Candidate {
+    /// The output
+    pub outpoint: OutPoint,
+    /// The value and scriptPubKey of the output
+    pub txout: TxOut,
+    /// The weight of the scriptSig and witness which will satisfy the output,
+    /// including their length prefixes (see `SpendType::satisfaction_weight`)
+    pub satisfaction_weight: u64,
+}
+
+impl Candidate {
+    /// The weight of an input spending this candidate
+    pub fn input_weight(&self) -> u64 {
+        INPUT_BASE_WEIGHT + self.satisfaction_weight
+    }
+}
+
+/// The parameters of the transaction being funded
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Params {
+    /// The total value paid to recipients
+    pub target: Amount,
+    /// The weight of the transaction with no inputs or change output,
+    /// including the segwit marker and flag if any inputs will be segwit
+    pub base_weight: u64,
+    /// The fee rate of the transaction
+    pub fee_rate: FeeRate,
+    /// The fee rate at which we expect to be able to spend outputs in the
+    /// long run. Spending inputs when `fee_rate` is above this is considered
+    /// wasteful.
+    pub long_term_fee_rate: FeeRate,
+    /// The weight of the change output
+    pub change_weight: u64,
+    /// The satisfaction weight of an input spending the change output
+    pub change_spend_weight: u64,
+    /// The smallest change output worth creating
+    pub min_change: Amount,
+}
+
+impl Params {
+    /// The value the effective values of the selected inputs must add up to
+    fn effective_target(&self) -> i64 {
+        self.target.as_sat() as i64 + fee(self.base_weight, self.fee_rate)
+    }
+
+    /// The cost of creating the change output and of later spending it
+    fn cost_of_change(&self) -> i64 {
+        fee(self.change_weight, self.fee_rate) +
+            fee(INPUT_BASE_WEIGHT + self.change_spend_weight, self.long_term_fee_rate)
+    }
+}
+
+/// The result of a coin selection
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Selection {
+    /// The indices of the selected candidates
+    pub selected: Vec<usize>,
+    /// The value of the change output, if the excess is large enough for one
+    pub change: Option<Amount>,
+    /// The fee paid by the transaction
+    pub fee: Amount,
+    /// The waste metric of the selection: the cost of spending the inputs
+    /// now rather than at the long term fee rate, plus either the cost of
+    /// the change output or the excess given up to fees if there is none.
+    pub waste: SignedAmount,
+}
+
+/// An error selecting coins
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Error {
+    /// The candidates are not worth enough to fund the transaction
+    InsufficientFunds {
+        /// The effective value needed
+        needed: Amount,
+        /// The total effective value of the candidates
+        available: Amount,
+    },
+    /// Branch-and-Bound found no solution without change
+    NoSolution,
+    /// A candidate value is larger than any valid amount
+    Overflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InsufficientFunds { needed, available } =>
+                write!(f, "insufficient funds: needed {}, available {}", needed, available),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::InsufficientFunds { .. } => "insufficient funds",
+            Error::NoSolution => "no changeless solution found",
+            Error::Overflow => "amount overflow",
+        }
+    }
+}
+
+/// The fee, rounded up, for the given weight at the given fee rate
+///
+/// # Panics
+/// Panics if the fee does not fit in a `SignedAmount`, which no sensible fee
+/// rate and transaction weight will cause.
+fn fee(weight: u64, fee_rate: FeeRate) -> i64 {
+    fee_rate.fee_for_weight(weight)
+            .and_then(|fee| fee.to_signed().ok())
+            .expect("fee overflow")
+            .as_sat()
+}
+
+/// A candidate with positive effective value
+struct Utxo {
+    /// Index into the candidate slice
+    index: usize,
+    /// Value less the fee to spend it
+    effective_value: i64,
+    /// Fee to spend it now less the fee to spend it at the long term fee rate
+    waste: i64,
+}
+
+/// Computes the effective values of the candidates, dropping those which
+/// are not worth spending, and checks that they can cover the target
+fn utxo_pool(candidates: &[Candidate], params: &Params) -> Result<Vec<Utxo>, Error> {
+    let mut pool = Vec::with_capacity(candidates.len());
+    let mut available: i64 = 0;
+    for (index, candidate) in candidates.iter().enumerate() {
+        let value = candidate.txout.value.to_signed().map_err(|_| Error::Overflow)?.as_sat();
+        let weight = candidate.input_weight();
+        let effective_value = value - fee(weight, params.fee_rate);
+        if effective_value > 0 {
+            available = available.checked_add(effective_value).ok_or(Error::Overflow)?;
+            pool.push(Utxo {
+                index: index,
+                effective_value: effective_value,
+                waste: fee(weight, params.fee_rate) - fee(weight, params.long_term_fee_rate),
+            });
+        }
+    }
+    let needed = params.effective_target();
+    if available < needed {
+        return Err(Error::InsufficientFunds {
+            needed: Amount::from_sat(needed as u64),
+            available: Amount::from_sat(available as u64),
+        });
+    }
+    Ok(pool)
+}
+
+/// Computes the change, fee and waste of a set of selected candidates
+fn finish_selection(candidates: &[Candidate], mut selected: Vec<usize>, params: &Params,
+                    allow_change: bool) -> Selection {
+    selected.sort();
+    let mut input_value = 0;
+    let mut waste = 0;
+    let mut effective_value = 0;
+    for &idx in &selected {
+        let weight = candidates[idx].input_weight();
+        let value = candidates[idx].txout.value.as_sat() as i64;
+        input_value += value;
+        effective_value += value - fee(weight, params.fee_rate);
+        waste += fee(weight, params.fee_rate) - fee(weight, params.long_term_fee_rate);
+    }
+
+    let excess = effective_value - params.effective_target();
+    let change_value = excess - fee(params.change_weight, params.fee_rate);
+    let change = if allow_change && change_value >= params.min_change.as_sat() as i64 {
+        waste += params.cost_of_change();
+        Some(Amount::from_sat(change_value as u64))
+    } else {
+        waste += excess;
+        None
+    };
+
+    let fee = input_value - params.target.as_sat() as i64 - change.map(|c| c.as_sat() as i64).unwrap_or(0);
+    Selection {
+        selected: selected,
+        change: change,
+        fee: Amount::from_sat(fee as u64),
+        waste: SignedAmount::from_sat(waste),
+    }
+}
+
+/// Computes the waste metric of spending the given candidates, with or
+/// without a change output
+pub fn waste(candidates: &[Candidate], selected: &[usize], params: &Params, allow_change: bool) -> SignedAmount {
+    finish_selection(candidates, selected.to_vec(), params, allow_change).waste
+}
+
+/// Searches for a set of candidates whose effective value exceeds the target
+/// by less than the cost of a change output, so that no change is needed,
+/// returning the one with the least waste. This is Bitcoin Core's
+/// `SelectCoinsBnB`.
+pub fn branch_and_bound(candidates: &[Candidate], params: &Params) -> Result<Selection, Error> {
+    let mut pool = utxo_pool(candidates, params)?;
+    pool.sort_by(|a, b| b.effective_value.cmp(&a.effective_value));
+
+    let target = params.effective_target();
+    let cost_of_change = params.cost_of_change();
+    let mut curr_value = 0;
+    let mut curr_waste = 0;
+    let mut curr_available: i64 = pool.iter().map(|u| u.effective_value).sum();
+    // Whether each of the first `curr_selection.len()` utxos is included
+    let mut curr_selection: Vec<bool> = Vec::with_capacity(pool.len());
+    let mut best_selection: Option<Vec<bool>> = None;
+    let mut best_waste = i64::max_value();
+
+    for _ in 0..BNB_TOTAL_TRIES {
+        let mut backtrack = false;
+        if curr_value + curr_available < target ||
+           curr_value > target + cost_of_change ||
+           (curr_waste > best_waste && params.fee_rate > params.long_term_fee_rate) {
+            // Cannot reach the target, or overshot it, or (when waste can only
+            // grow with more inputs) cannot beat the best solution so far
+            backtrack = true;
+        } else if curr_value >= target {
+            let waste = curr_waste + curr_value - target;
+            if waste <= best_waste {
+                best_selection = Some(curr_selection.clone());
+                best_waste = waste;
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            // Walk back to the last included utxo and try omitting it instead
+            while let Some(&false) = curr_selection.last() {
+                curr_selection.pop();
+                curr_available += pool[curr_selection.len()].effective_value;
+            }
+            let last = match curr_selection.len() {
+                0 => break,  // the whole tree has been searched
+                n => n - 1,
+            };
+            curr_selection[last] = false;
+            curr_value -= pool[last].effective_value;
+            curr_waste -= pool[last].waste;
+        } else {
+            let next = curr_selection.len();
+            let utxo = &pool[next];
+            curr_available -= utxo.effective_value;
+            // Including a utxo equivalent to one we just omitted would only
+            // repeat the search done with that one included
+            let skip = match curr_selection.last() {
+                Some(&false) => {
+                    utxo.effective_value == pool[next - 1].effective_value &&
+                        utxo.waste == pool[next - 1].waste
+                }
+                _ => false,
+            };
+            if skip {
+                curr_selection.push(false);
+            } else {
+                curr_selection.push(true);
+                curr_value += utxo.effective_value;
+                curr_waste += utxo.waste;
+            }
+        }
+    }
+
+    match best_selection {
+        Some(best) => {
+            let selected = best.iter().zip(pool.iter()).filter(|&(&incl, _)| incl).map(|(_, u)| u.index).collect();
+            Ok(finish_selection(candidates, selected, params, false))
+        }
+        None => Err(Error::NoSolution),
+    }
+}
+
+/// Finds a subset of `values` whose sum is as close as possible to, but not
+/// under, `target` by random trials, Bitcoin Core's `ApproximateBestSubset`.
+/// `values` must sum to `total` which must be at least `target`.
+fn approximate_best_subset<R: Rng>(values: &[i64], total: i64, target: i64, rng: &mut R) -> (Vec<bool>, i64) {
+    let mut best = vec![true; values.len()];
+    let mut best_value = total;
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break;
+        }
+        let mut included = vec![false; values.len()];
+        let mut total = 0;
+        let mut reached_target = false;
+        for pass in 0..2 {
+            if reached_target {
+                break;
+            }
+            for i in 0..values.len() {
+                // The first pass picks values at random and the second fills
+                // in the remaining ones in order
+                let pick = if pass == 0 { rng.gen() } else { !included[i] };
+                if pick {
+                    total += values[i];
+                    included[i] = true;
+                    if total >= target {
+                        reached_target = true;
+                        if total < best_value {
+                            best_value = total;
+                            best = included.clone();
+                        }
+                        total -= values[i];
+                        included[i] = false;
+                    }
+                }
+            }
+        }
+    }
+    (best, best_value)
+}
+
+/// Selects candidates with Bitcoin Core's knapsack solver, which looks for
+/// an exact match, then for a random subset leaving at least `min_change`
+/// plus the cost of the change output, then falls back to the smallest
+/// candidate larger than the target.
+pub fn knapsack<R: Rng>(candidates: &[Candidate], params: &Params, rng: &mut R) -> Result<Selection, Error> {
+    let mut pool = utxo_pool(candidates, params)?;
+    rng.shuffle(&mut pool);
+
+    let target = params.effective_target();
+    let min_change = params.min_change.as_sat() as i64 + fee(params.change_weight, params.fee_rate);
+    let mut lowest_larger: Option<&Utxo> = None;
+    let mut smaller = vec![];
+    let mut total_lower = 0;
+    for utxo in &pool {
+        if utxo.effective_value == target {
+            return Ok(finish_selection(candidates, vec![utxo.index], params, true));
+        } else if utxo.effective_value < target + min_change {
+            smaller.push(utxo);
+            total_lower += utxo.effective_value;
+        } else if lowest_larger.map(|l| utxo.effective_value < l.effective_value).unwrap_or(true) {
+            lowest_larger = Some(utxo);
+        }
+    }
+
+    if total_lower == target {
+        let selected = smaller.iter().map(|u| u.index).collect();
+        return Ok(finish_selection(candidates, selected, params, true));
+    }
+    if total_lower < target {
+        // `utxo_pool` checked the candidates can cover the target, so there
+        // must be a larger one
+        let larger = lowest_larger.expect("checked sufficient funds");
+        return Ok(finish_selection(candidates, vec![larger.index], params, true));
+    }
+
+    // Solve subset sum by stochastic approximation
+    smaller.sort_by(|a, b| b.effective_value.cmp(&a.effective_value));
+    let values: Vec<i64> = smaller.iter().map(|u| u.effective_value).collect();
+    let (mut best, mut best_value) = approximate_best_subset(&values, total_lower, target, rng);
+    if best_value != target && total_lower >= target + min_change {
+        let (change_best, change_best_value) = approximate_best_subset(&values, total_lower, target + min_change, rng);
+        best = change_best;
+        best_value = change_best_value;
+    }
+
+    // If we have a bigger coin and either the stochastic approximation
+    // didn't find a good solution or the bigger coin is smaller, use it
+    if let Some(larger) = lowest_larger {
+        if (best_value != target && best_value < target + min_change) || larger.effective_value <= best_value {
+            return Ok(finish_selection(candidates, vec![larger.index], params, true));
+        }
+    }
+    let selected = best.iter().zip(smaller.iter()).filter(|&(&incl, _)| incl).map(|(_, u)| u.index).collect();
+    Ok(finish_selection(candidates, selected, params, true))
+}
+
+/// Selects randomly ordered candidates until they cover the target, the
+/// cost of a change output and `min_change`.
+pub fn single_random_draw<R: Rng>(candidates: &[Candidate], params: &Params, rng: &mut R) -> Result<Selection, Error> {
+    let mut pool = utxo_pool(candidates, params)?;
+    rng.shuffle(&mut pool);
+
+    let target = params.effective_target() + fee(params.change_weight, params.fee_rate) +
+        params.min_change.as_sat() as i64;
+    let mut selected = vec![];
+    let mut value = 0;
+    for utxo in &pool {
+        selected.push(utxo.index);
+        value += utxo.effective_value;
+        if value >= target {
+            return Ok(finish_selection(candidates, selected, params, true));
+        }
+    }
+    // We can cover the target but not leave enough for change, so give the
+    // excess to fees instead
+    Ok(finish_selection(candidates, selected, params, true))
+}
+
+/// Runs Branch-and-Bound, the knapsack solver and single random draw,
+/// returning the solution with the least waste. Ties favour the algorithms
+/// in that order.
+pub fn select_coins<R: Rng>(candidates: &[Candidate], params: &Params, rng: &mut R) -> Result<Selection, Error> {
+    let mut best = match branch_and_bound(candidates, params) {
+        Ok(selection) => Some(selection),
+        Err(Error::NoSolution) => None,
+        Err(e) => return Err(e),
+    };
+    let others = [knapsack(candidates, params, rng)?, single_random_draw(candidates, params, rng)?];
+    for selection in others.iter() {
+        if best.as_ref().map(|b| selection.waste < b.waste).unwrap_or(true) {
+            best = Some(selection.clone());
+        }
+    }
+    Ok(best.expect("knapsack always finds a selection"))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, TxOut};
+    use util::amount::{Amount, SignedAmount};
+    use util::feerate::FeeRate;
+    use util::hash::Sha256dHash;
+    use util::txbuilder::SpendType;
+
+    use super::*;
+
+    fn candidates(values: &[u64]) -> Vec<Candidate> {
+        values.iter().enumerate().map(|(i, &value)| Candidate {
+            outpoint: OutPoint { txid: Sha256dHash::from_data(&[i as u8]), vout: i as u32 },
+            txout: TxOut { value: Amount::from_sat(value), script_pubkey: Script::new() },
+            satisfaction_weight: SpendType::P2wpkh.satisfaction_weight(),
+        }).collect()
+    }
+
+    fn params(target: u64, fee_rate: u64) -> Params {
+        Params {
+            target: Amount::from_sat(target),
+            base_weight: 4 * 10 + 2 + 4 * 31,
+            fee_rate: FeeRate::from_sat_per_kwu(fee_rate),
+            long_term_fee_rate: FeeRate::from_sat_per_kwu(2500),
+            change_weight: 4 * 31,
+            change_spend_weight: SpendType::P2wpkh.satisfaction_weight(),
+            min_change: Amount::from_sat(1000),
+        }
+    }
+
+    fn fee_at(weight: u64, fee_rate: u64) -> i64 {
+        fee(weight, FeeRate::from_sat_per_kwu(fee_rate))
+    }
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([1, 2, 3, 4])
+    }
+
+    /// The value a candidate needs to have the given effective value
+    fn value_for(effective_value: u64, fee_rate: u64) -> u64 {
+        effective_value + fee_at(272, fee_rate) as u64
+    }
+
+    fn selected_values(cands: &[Candidate], sel: &Selection) -> Vec<u64> {
+        let mut ret: Vec<u64> = sel.selected.iter().map(|&i| cands[i].txout.value.as_sat()).collect();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn effective_values() {
+        // At 1 sat/WU a P2WPKH input costs 272 satoshis to spend
+        let cands = candidates(&[272, 100_000]);
+        let p = params(50_000, 1000);
+        let pool = utxo_pool(&cands, &p).unwrap();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool[0].index, 1);
+        assert_eq!(pool[0].effective_value, 100_000 - 272);
+        assert_eq!(pool[0].waste, 272 - 680);
+
+        assert_eq!(utxo_pool(&cands, &params(100_000, 1000)).err(),
+                   Some(Error::InsufficientFunds {
+                       needed: Amount::from_sat(100_000 + 166),
+                       available: Amount::from_sat(100_000 - 272),
+                   }));
+    }
+
+    #[test]
+    fn bnb() {
+        let fee_rate = 1000;
+        let p = params(50_000, fee_rate);
+        let target = p.effective_target() as u64;
+        let cands = candidates(&[
+            value_for(target / 2, fee_rate),
+            value_for(target - 10_000, fee_rate),
+            value_for(target / 2, fee_rate),
+            value_for(10_000, fee_rate),
+            value_for(200_000, fee_rate),
+        ]);
+
+        let sel = branch_and_bound(&cands, &p).unwrap();
+        assert_eq!(sel.change, None);
+        assert_eq!(sel.fee, Amount::from_sat(fee_at(p.base_weight + 2 * 272, fee_rate) as u64));
+        // Both exact matches have the same waste, and ties go to the last
+        // one found
+        assert_eq!(sel.selected, vec![0, 2]);
+        assert_eq!(sel.waste, SignedAmount::from_sat(2 * (272 - 680)));
+        assert_eq!(sel.waste, waste(&cands, &sel.selected, &p, false));
+
+        // Excess below the cost of change is given to fees
+        let p = params(50_000 - 100, fee_rate);
+        let sel = branch_and_bound(&cands, &p).unwrap();
+        assert_eq!(sel.change, None);
+        assert_eq!(sel.waste, SignedAmount::from_sat(2 * (272 - 680) + 100));
+
+        // Nothing within the cost of change of the target
+        let cands = candidates(&[value_for(target / 3, fee_rate), value_for(target, fee_rate) * 2]);
+        assert_eq!(branch_and_bound(&cands, &p), Err(Error::NoSolution));
+    }
+
+    #[test]
+    fn bnb_prefers_fewer_inputs_at_high_fees() {
+        let fee_rate = 10_000;
+        let p = params(100_000, fee_rate);
+        let target = p.effective_target() as u64;
+        let cands = candidates(&[
+            value_for(target / 4, fee_rate),
+            value_for(target / 4, fee_rate),
+            value_for(target / 4, fee_rate),
+            value_for(target / 4, fee_rate),
+            value_for(target / 2, fee_rate),
+            value_for(target / 2, fee_rate),
+        ]);
+        let sel = branch_and_bound(&cands, &p).unwrap();
+        assert_eq!(sel.selected, vec![4, 5]);
+    }
+
+    #[test]
+    fn knapsack_solver() {
+        let fee_rate = 1000;
+        let p = params(50_000, fee_rate);
+        let target = p.effective_target() as u64;
+
+        // Exact match of a single candidate
+        let cands = candidates(&[value_for(target, fee_rate), value_for(3 * target, fee_rate)]);
+        let sel = knapsack(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(sel.selected, vec![0]);
+        assert_eq!(sel.change, None);
+
+        // All smaller candidates sum to the target exactly
+        let cands = candidates(&[value_for(target / 2, fee_rate), value_for(target / 2, fee_rate),
+                                 value_for(3 * target, fee_rate)]);
+        let sel = knapsack(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(sel.selected, vec![0, 1]);
+
+        // Smaller candidates are insufficient, so the smallest larger one is used
+        let cands = candidates(&[10_000, 2_000_000, 1_000_000]);
+        let sel = knapsack(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(sel.selected, vec![2]);
+        let change = sel.change.unwrap();
+        assert_eq!(change + sel.fee + p.target, Amount::from_sat(1_000_000));
+        assert_eq!(sel.fee, Amount::from_sat(fee_at(p.base_weight + p.change_weight + 272, fee_rate) as u64));
+
+        // A subset of small candidates leaving change
+        let cands = candidates(&[20_000, 30_000, 40_000, 5_000, 1_000_000]);
+        let sel = knapsack(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(selected_values(&cands, &sel), vec![5_000, 20_000, 30_000]);
+        assert!(sel.change.unwrap() >= p.min_change);
+        // and seeding makes it reproducible
+        assert_eq!(knapsack(&cands, &p, &mut rng()).unwrap(), sel);
+    }
+
+    #[test]
+    fn srd() {
+        let fee_rate = 1000;
+        let p = params(50_000, fee_rate);
+        let cands = candidates(&[20_000, 30_000, 40_000, 5_000, 1_000_000, 60_000]);
+
+        let sel = single_random_draw(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(single_random_draw(&cands, &p, &mut rng()).unwrap(), sel);
+        let change = sel.change.unwrap();
+        assert!(change >= p.min_change);
+        let input_value: u64 = selected_values(&cands, &sel).iter().sum();
+        assert_eq!(change + sel.fee + p.target, Amount::from_sat(input_value));
+
+        // Not enough for change
+        let target = p.effective_target() as u64;
+        let cands = candidates(&[value_for(target / 2, fee_rate), value_for(target / 2 + 5, fee_rate)]);
+        let sel = single_random_draw(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(sel.change, None);
+        assert_eq!(sel.selected, vec![0, 1]);
+        assert_eq!(sel.fee, Amount::from_sat(fee_at(p.base_weight + 2 * 272, fee_rate) as u64 + 5));
+    }
+
+    #[test]
+    fn select() {
+        let fee_rate = 1000;
+        let p = params(50_000, fee_rate);
+        let target = p.effective_target() as u64;
+
+        // An exact match beats anything with change
+        let cands = candidates(&[value_for(target - 10_000, fee_rate), value_for(10_000, fee_rate), 1_000_000, 2_000_000]);
+        let sel = select_coins(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(sel.selected, vec![0, 1]);
+        assert_eq!(sel.change, None);
+
+        let cands = candidates(&[1_000_000, 2_000_000]);
+        let sel = select_coins(&cands, &p, &mut rng()).unwrap();
+        assert_eq!(sel.selected, vec![0]);
+        assert!(sel.change.is_some());
+
+        assert_eq!(select_coins(&candidates(&[1_000]), &p, &mut rng()).err(),
+                   Some(Error::InsufficientFunds {
+                       needed: Amount::from_sat(target),
+                       available: Amount::from_sat(1000 - 272),
+                   }));
+    }
+}
@@ -0,0 +1,198 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Fee rates
+//!
+//! This module defines the `FeeRate` type, which stores a fee rate in
+//! satoshis per 1000 weight units and converts to and from the per virtual
+//! byte rates used by wallets and nodes.
+//!
+
+use std::fmt;
+
+#[cfg(feature = "serde")] use serde;
+
+use util::amount::Amount;
+
+/// A fee rate, stored as satoshis per 1000 weight units (sat/kwu)
+///
+/// One virtual byte is four weight units, so 1 sat/vB is 250 sat/kwu.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    /// The zero fee rate
+    pub fn zero() -> FeeRate {
+        FeeRate(0)
+    }
+
+    /// The maximum fee rate
+    pub fn max_value() -> FeeRate {
+        FeeRate(u64::max_value())
+    }
+
+    /// Constructs a fee rate from satoshis per 1000 weight units
+    pub fn from_sat_per_kwu(sat_kwu: u64) -> FeeRate {
+        FeeRate(sat_kwu)
+    }
+
+    /// Constructs a fee rate from satoshis per virtual byte, returning `None`
+    /// on overflow
+    pub fn from_sat_per_vb(sat_vb: u64) -> Option<FeeRate> {
+        sat_vb.checked_mul(250).map(FeeRate)
+    }
+
+    /// Constructs a fee rate from satoshis per 1000 virtual bytes, the unit
+    /// used by Bitcoin Core, rounding down. Rates which are not a multiple
+    /// of 4 sat/kvB are truncated, so that 1 to 3 sat/kvB become zero; use
+    /// `from_sat_per_kvb_ceil` for rates which act as minimums.
+    pub fn from_sat_per_kvb(sat_kvb: u64) -> FeeRate {
+        FeeRate(sat_kvb / 4)
+    }
+
+    /// Constructs a fee rate from satoshis per 1000 virtual bytes, rounding
+    /// up, so that the rate is never below the given one
+    pub fn from_sat_per_kvb_ceil(sat_kvb: u64) -> FeeRate {
+        FeeRate(sat_kvb / 4 + if sat_kvb % 4 == 0 { 0 } else { 1 })
+    }
+
+    /// The fee rate of a transaction paying `fee` for `weight`, rounding down.
+    /// Returns `None` if the weight is zero or on overflow.
+    pub fn from_fee_and_weight(fee: Amount, weight: u64) -> Option<FeeRate> {
+        fee.as_sat().checked_mul(1000).and_then(|f| f.checked_div(weight)).map(FeeRate)
+    }
+
+    /// The fee rate in satoshis per 1000 weight units
+    pub fn as_sat_per_kwu(self) -> u64 {
+        self.0
+    }
+
+    /// The fee rate in satoshis per virtual byte, rounding down
+    pub fn as_sat_per_vb_floor(self) -> u64 {
+        self.0 / 250
+    }
+
+    /// The fee rate in satoshis per virtual byte, rounding up
+    pub fn as_sat_per_vb_ceil(self) -> u64 {
+        self.0 / 250 + if self.0 % 250 == 0 { 0 } else { 1 }
+    }
+
+    /// The fee rate in satoshis per 1000 virtual bytes
+    pub fn as_sat_per_kvb(self) -> Option<u64> {
+        self.0.checked_mul(4)
+    }
+
+    /// The fee for a transaction of the given weight at this rate, rounding
+    /// up. Returns `None` on overflow.
+    pub fn fee_for_weight(self, weight: u64) -> Option<Amount> {
+        self.0.checked_mul(weight)
+              .map(|f| f / 1000 + if f % 1000 == 0 { 0 } else { 1 })
+              .map(Amount::from_sat)
+    }
+
+    /// The fee for a transaction of the given virtual size at this rate,
+    /// rounding up. Returns `None` on overflow.
+    pub fn fee_for_vsize(self, vsize: u64) -> Option<Amount> {
+        vsize.checked_mul(4).and_then(|weight| self.fee_for_weight(weight))
+    }
+
+    /// Checked multiplication.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_mul(self, rhs: u64) -> Option<FeeRate> {
+        self.0.checked_mul(rhs).map(FeeRate)
+    }
+
+    /// Checked addition.
+    /// Returns `None` if overflow occurred.
+    pub fn checked_add(self, rhs: FeeRate) -> Option<FeeRate> {
+        self.0.checked_add(rhs.0).map(FeeRate)
+    }
+}
+
+impl fmt::Debug for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FeeRate({} sat/kwu)", self.0)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} sat/kwu", self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FeeRate {
+    /// Fee rates are serialized as an integer number of satoshis per 1000
+    /// weight units
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FeeRate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        u64::deserialize(deserializer).map(FeeRate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use util::amount::Amount;
+
+    use super::FeeRate;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(FeeRate::from_sat_per_vb(1), Some(FeeRate::from_sat_per_kwu(250)));
+        assert_eq!(FeeRate::from_sat_per_vb(u64::max_value()), None);
+        assert_eq!(FeeRate::from_sat_per_kvb(1000), FeeRate::from_sat_per_kwu(250));
+        assert_eq!(FeeRate::from_sat_per_kvb(1001), FeeRate::from_sat_per_kwu(250));
+        assert_eq!(FeeRate::from_sat_per_kvb(3), FeeRate::zero());
+        assert_eq!(FeeRate::from_sat_per_kvb_ceil(1000), FeeRate::from_sat_per_kwu(250));
+        assert_eq!(FeeRate::from_sat_per_kvb_ceil(1001), FeeRate::from_sat_per_kwu(251));
+        assert_eq!(FeeRate::from_sat_per_kvb_ceil(1), FeeRate::from_sat_per_kwu(1));
+        assert_eq!(FeeRate::from_sat_per_kvb_ceil(u64::max_value()).as_sat_per_kwu(), u64::max_value() / 4 + 1);
+
+        let rate = FeeRate::from_sat_per_kwu(2501);
+        assert_eq!(rate.as_sat_per_vb_floor(), 10);
+        assert_eq!(rate.as_sat_per_vb_ceil(), 11);
+        assert_eq!(rate.as_sat_per_kvb(), Some(10004));
+        assert_eq!(FeeRate::from_sat_per_kwu(2500).as_sat_per_vb_ceil(), 10);
+        assert_eq!(FeeRate::max_value().as_sat_per_kvb(), None);
+
+        assert_eq!(format!("{}", rate), "2501 sat/kwu");
+        assert_eq!(format!("{:?}", rate), "FeeRate(2501 sat/kwu)");
+    }
+
+    #[test]
+    fn fees() {
+        let rate = FeeRate::from_sat_per_vb(2).unwrap();
+        assert_eq!(rate.fee_for_weight(400), Some(Amount::from_sat(200)));
+        assert_eq!(rate.fee_for_weight(401), Some(Amount::from_sat(201)));
+        assert_eq!(rate.fee_for_vsize(100), Some(Amount::from_sat(200)));
+        assert_eq!(FeeRate::zero().fee_for_weight(1000), Some(Amount::zero()));
+        assert_eq!(FeeRate::max_value().fee_for_weight(2), None);
+
+        assert_eq!(FeeRate::from_fee_and_weight(Amount::from_sat(201), 401), Some(FeeRate::from_sat_per_kwu(501)));
+        assert_eq!(FeeRate::from_fee_and_weight(Amount::from_sat(1), 0), None);
+    }
+}
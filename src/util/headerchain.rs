@@ -0,0 +1,529 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Header chains
+//!
+//! A tree of block headers rooted at the genesis block, which validates each
+//! header as it is added, tracks the cumulative work of every branch and
+//! follows the branch with the most work, reporting the headers disconnected
+//! and connected whenever the best chain changes.
+//!
+
+use std::collections::HashMap;
+use std::{error, fmt};
+
+use blockdata::block::BlockHeader;
+use blockdata::constants::genesis_block;
+use consensus::params::Params;
+use consensus::pow;
+use util::hash::{BitcoinHash, Sha256dHash};
+use util::locator;
+use util::uint::Uint256;
+
+/// The number of previous blocks whose median timestamp a new block's
+/// timestamp must exceed
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// An error adding a header to a header chain
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The header is already in the chain
+    Duplicate(Sha256dHash),
+    /// The header's parent is not in the chain
+    UnknownParent(Sha256dHash),
+    /// The header's target is not the one required by the retargeting rules
+    BadTarget {
+        /// The target required of the header
+        expected: u32,
+        /// The header's target
+        found: u32,
+    },
+    /// The header's hash is not below its target
+    BadProofOfWork,
+    /// The header's timestamp is not after the median time past of its parent
+    TimeTooOld {
+        /// The header's timestamp
+        time: u32,
+        /// The median time past of the header's parent
+        median_time_past: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Duplicate(ref hash) => write!(f, "duplicate header {}", hash),
+            Error::UnknownParent(ref hash) => write!(f, "unknown parent header {}", hash),
+            Error::BadTarget { expected, found } => write!(f, "bad target: expected {:#010x}, found {:#010x}", expected, found),
+            Error::BadProofOfWork => f.write_str(error::Error::description(self)),
+            Error::TimeTooOld { time, median_time_past } =>
+                write!(f, "timestamp {} not after median time past {}", time, median_time_past),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Duplicate(..) => "duplicate header",
+            Error::UnknownParent(..) => "unknown parent header",
+            Error::BadTarget { .. } => "bad target",
+            Error::BadProofOfWork => "hash is not below target",
+            Error::TimeTooOld { .. } => "timestamp not after median time past",
+        }
+    }
+}
+
+/// A header stored in a header chain
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StoredHeader {
+    /// The header
+    pub header: BlockHeader,
+    /// The height of the header above the genesis block
+    pub height: u32,
+    /// The total work of the header and all its ancestors
+    pub chain_work: Uint256,
+}
+
+/// The change to the best chain caused by adding a header
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ChainUpdate {
+    /// The headers removed from the best chain, from the old tip downwards
+    pub disconnected: Vec<StoredHeader>,
+    /// The headers added to the best chain, from the fork point upwards
+    pub connected: Vec<StoredHeader>,
+}
+
+impl ChainUpdate {
+    /// Whether the best chain is unchanged, because the header was added
+    /// to a branch with less work
+    pub fn is_empty(&self) -> bool {
+        self.disconnected.is_empty() && self.connected.is_empty()
+    }
+
+    /// Whether headers were removed from the best chain
+    pub fn is_reorg(&self) -> bool {
+        !self.disconnected.is_empty()
+    }
+}
+
+/// A tree of validated block headers, following the branch with the most
+/// cumulative work.
+///
+/// Headers are checked for linkage, proof of work, the target required by
+/// the chain's retargeting rules and the median-time-past rule. Checking
+/// that timestamps are not too far in the future is left to the caller,
+/// which knows the current time.
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    /// The consensus parameters of the chain
+    params: Params,
+    /// Every header in the tree, by hash
+    headers: HashMap<Sha256dHash, StoredHeader>,
+    /// The hashes of the best chain, by height
+    best_chain: Vec<Sha256dHash>,
+}
+
+impl HeaderChain {
+    /// Creates a header chain containing only the genesis block of the
+    /// parameters' network
+    pub fn new(params: Params) -> HeaderChain {
+        let genesis = genesis_block(params.network).header;
+        let hash = genesis.bitcoin_hash();
+        let mut headers = HashMap::new();
+        headers.insert(hash, StoredHeader {
+            header: genesis,
+            height: 0,
+            chain_work: genesis.work(),
+        });
+        HeaderChain {
+            params: params,
+            headers: headers,
+            best_chain: vec![hash],
+        }
+    }
+
+    /// The consensus parameters of the chain
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// The tip of the best chain
+    pub fn tip(&self) -> &StoredHeader {
+        &self.headers[self.best_chain.last().unwrap()]
+    }
+
+    /// The height of the tip of the best chain
+    pub fn height(&self) -> u32 {
+        (self.best_chain.len() - 1) as u32
+    }
+
+    /// Looks up a header anywhere in the tree
+    pub fn get(&self, hash: &Sha256dHash) -> Option<&StoredHeader> {
+        self.headers.get(hash)
+    }
+
+    /// Looks up the header at the given height of the best chain
+    pub fn get_by_height(&self, height: u32) -> Option<&StoredHeader> {
+        self.best_chain.get(height as usize).map(|hash| &self.headers[hash])
+    }
+
+    /// Builds the block locator of the best chain, for a `getheaders` or
+    /// `getblocks` message
+    pub fn locator(&self) -> Vec<Sha256dHash> {
+        locator::block_locator(&self.best_chain)
+    }
+
+    /// Finds the height of the first block of a peer's locator which is in
+    /// the best chain, or zero if there is none
+    pub fn find_fork(&self, locator: &[Sha256dHash]) -> u32 {
+        locator::find_fork(locator, |hash| self.best_chain_height(hash))
+    }
+
+    /// Finds the headers to send in reply to a peer's `getheaders` message:
+    /// up to `MAX_HEADERS_RESULTS` headers of the best chain after the fork
+    /// point with the peer's locator, stopping after `stop_hash`
+    pub fn headers_for_locator(&self, locator: &[Sha256dHash], stop_hash: &Sha256dHash) -> Vec<BlockHeader> {
+        let heights = locator::headers_reply_heights(&self.best_chain, locator, stop_hash,
+                                                     |hash| self.best_chain_height(hash));
+        self.best_chain[heights].iter().map(|hash| self.headers[hash].header).collect()
+    }
+
+    /// Finds the hashes of the blocks to announce in reply to a peer's
+    /// `getblocks` message: up to `MAX_BLOCKS_RESULTS` blocks of the best
+    /// chain after the fork point with the peer's locator, stopping before
+    /// `stop_hash`
+    pub fn blocks_for_locator(&self, locator: &[Sha256dHash], stop_hash: &Sha256dHash) -> Vec<Sha256dHash> {
+        let heights = locator::blocks_reply_heights(&self.best_chain, locator, stop_hash,
+                                                    |hash| self.best_chain_height(hash));
+        self.best_chain[heights].to_vec()
+    }
+
+    /// Whether the header with the given hash is in the best chain
+    pub fn is_in_best_chain(&self, hash: &Sha256dHash) -> bool {
+        self.best_chain_height(hash).is_some()
+    }
+
+    /// The height of the header with the given hash, if it is in the best
+    /// chain
+    fn best_chain_height(&self, hash: &Sha256dHash) -> Option<u32> {
+        match self.headers.get(hash) {
+            Some(stored) if self.best_chain.get(stored.height as usize) == Some(hash) => Some(stored.height),
+            _ => None,
+        }
+    }
+
+    /// The median timestamp of the header with the given hash and up to
+    /// ten of its ancestors
+    pub fn median_time_past(&self, hash: &Sha256dHash) -> Option<u32> {
+        let mut times = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut next = self.headers.get(hash);
+        while let Some(stored) = next {
+            times.push(stored.header.time);
+            if times.len() == MEDIAN_TIME_SPAN || stored.height == 0 {
+                break;
+            }
+            next = self.headers.get(&stored.header.prev_blockhash);
+        }
+        if times.is_empty() {
+            return None;
+        }
+        times.sort();
+        Some(times[times.len() / 2])
+    }
+
+    /// Validates a header and adds it to the tree, returning the resulting
+    /// change to the best chain
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<ChainUpdate, Error> {
+        let hash = header.bitcoin_hash();
+        if self.headers.contains_key(&hash) {
+            return Err(Error::Duplicate(hash));
+        }
+        let (height, parent_work) = match self.headers.get(&header.prev_blockhash) {
+            Some(parent) => (parent.height + 1, parent.chain_work),
+            None => return Err(Error::UnknownParent(header.prev_blockhash)),
+        };
+
+        let expected = self.required_bits(&header, height);
+        if header.bits != expected {
+            return Err(Error::BadTarget { expected: expected, found: header.bits });
+        }
+        if hash.into_le() > header.target() {
+            return Err(Error::BadProofOfWork);
+        }
+        let median_time_past = self.median_time_past(&header.prev_blockhash).unwrap();
+        if header.time <= median_time_past {
+            return Err(Error::TimeTooOld { time: header.time, median_time_past: median_time_past });
+        }
+
+        let stored = StoredHeader {
+            header: header,
+            height: height,
+            chain_work: parent_work + header.work(),
+        };
+        let better = stored.chain_work > self.tip().chain_work;
+        self.headers.insert(hash, stored);
+        if better {
+            Ok(self.reorganize(hash))
+        } else {
+            Ok(ChainUpdate::default())
+        }
+    }
+
+    /// Computes the target required of a header at the given height, whose
+    /// parent is in the tree
+    fn required_bits(&self, header: &BlockHeader, height: u32) -> u32 {
+        let parent = &self.headers[&header.prev_blockhash];
+        pow::next_work_required(&self.params, &parent.header, height, header.time,
+                                |header| self.headers.get(&header.prev_blockhash).map(|stored| stored.header))
+            .expect("the ancestors of a header in the tree are in the tree")
+    }
+
+    /// Makes the header with the given hash the tip of the best chain
+    fn reorganize(&mut self, tip: Sha256dHash) -> ChainUpdate {
+        let mut connected = vec![];
+        let mut hash = tip;
+        while !self.is_in_best_chain(&hash) {
+            let stored = self.headers[&hash].clone();
+            hash = stored.header.prev_blockhash;
+            connected.push(stored);
+        }
+        connected.reverse();
+
+        let fork_height = self.headers[&hash].height as usize;
+        let headers = &self.headers;
+        let disconnected = self.best_chain.drain(fork_height + 1..).rev()
+                                          .map(|hash| headers[&hash].clone())
+                                          .collect();
+        self.best_chain.extend(connected.iter().map(|stored| stored.header.bitcoin_hash()));
+        ChainUpdate {
+            disconnected: disconnected,
+            connected: connected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::block::BlockHeader;
+    use consensus::params::Params;
+    use network::constants::Network;
+    use util::hash::BitcoinHash;
+
+    use super::*;
+
+    /// Mines a regtest header on top of `prev`
+    fn mine(prev: &BlockHeader, time: u32, nonce_start: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 1,
+            prev_blockhash: prev.bitcoin_hash(),
+            merkle_root: Default::default(),
+            time: time,
+            bits: prev.bits,
+            nonce: nonce_start,
+        };
+        while header.bitcoin_hash().into_le() > header.target() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    #[test]
+    fn extend_and_reorg() {
+        let mut chain = HeaderChain::new(Params::new(Network::Regtest));
+        let genesis = chain.tip().clone();
+        assert_eq!(chain.height(), 0);
+
+        // Extend the best chain
+        let a1 = mine(&genesis.header, genesis.header.time + 600, 0);
+        let a2 = mine(&a1, a1.time + 600, 0);
+        let update = chain.add_header(a1).unwrap();
+        assert_eq!(update.connected.len(), 1);
+        assert!(!update.is_reorg());
+        assert_eq!(update.connected[0].header, a1);
+        assert_eq!(update.connected[0].height, 1);
+        chain.add_header(a2).unwrap();
+        assert_eq!(chain.height(), 2);
+        assert_eq!(chain.tip().header, a2);
+        assert_eq!(chain.tip().chain_work, genesis.chain_work + a1.work() + a2.work());
+        assert_eq!(chain.add_header(a2), Err(Error::Duplicate(a2.bitcoin_hash())));
+
+        // A competing branch only takes over once it has more work
+        let b1 = mine(&genesis.header, genesis.header.time + 600, 1_000_000);
+        let b2 = mine(&b1, b1.time + 600, 0);
+        let b3 = mine(&b2, b2.time + 600, 0);
+        assert!(chain.add_header(b1).unwrap().is_empty());
+        assert!(chain.add_header(b2).unwrap().is_empty());
+        assert_eq!(chain.tip().header, a2);
+        assert!(chain.is_in_best_chain(&a1.bitcoin_hash()));
+        assert!(!chain.is_in_best_chain(&b1.bitcoin_hash()));
+
+        let update = chain.add_header(b3).unwrap();
+        assert!(update.is_reorg());
+        let disconnected: Vec<BlockHeader> = update.disconnected.iter().map(|s| s.header).collect();
+        let connected: Vec<BlockHeader> = update.connected.iter().map(|s| s.header).collect();
+        assert_eq!(disconnected, vec![a2, a1]);
+        assert_eq!(connected, vec![b1, b2, b3]);
+        assert_eq!(chain.tip().header, b3);
+        assert_eq!(chain.get_by_height(1).unwrap().header, b1);
+        assert!(chain.get_by_height(4).is_none());
+        assert_eq!(chain.get(&a2.bitcoin_hash()).unwrap().height, 2);
+        assert!(!chain.is_in_best_chain(&a1.bitcoin_hash()));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let mut chain = HeaderChain::new(Params::new(Network::Regtest));
+        let genesis = chain.tip().header;
+
+        let orphan = mine(&mine(&genesis, genesis.time + 600, 0), genesis.time + 1200, 0);
+        assert_eq!(chain.add_header(orphan), Err(Error::UnknownParent(orphan.prev_blockhash)));
+
+        let mut bad_bits = mine(&genesis, genesis.time + 600, 0);
+        bad_bits.bits = 0x1d00ffff;
+        assert_eq!(chain.add_header(bad_bits), Err(Error::BadTarget { expected: genesis.bits, found: 0x1d00ffff }));
+
+        let mut bad_pow = mine(&genesis, genesis.time + 600, 0);
+        while bad_pow.bitcoin_hash().into_le() <= bad_pow.target() {
+            bad_pow.nonce += 1;
+        }
+        assert_eq!(chain.add_header(bad_pow), Err(Error::BadProofOfWork));
+
+        let too_old = mine(&genesis, genesis.time, 0);
+        assert_eq!(chain.add_header(too_old), Err(Error::TimeTooOld { time: genesis.time, median_time_past: genesis.time }));
+        assert_eq!(chain.height(), 0);
+    }
+
+    #[test]
+    fn required_bits_matches_pow() {
+        // Headers inserted without validation, on a network with the minimum
+        // difficulty rule, some of them at the minimum difficulty
+        let params = Params::new(Network::Testnet);
+        let mut chain = HeaderChain::new(params.clone());
+        let genesis = chain.tip().header;
+        let pow_limit_bits = genesis.bits;
+        let mut headers = vec![genesis];
+        for height in 1..2100u32 {
+            let prev = headers[headers.len() - 1];
+            let header = BlockHeader {
+                version: 1,
+                prev_blockhash: prev.bitcoin_hash(),
+                merkle_root: Default::default(),
+                time: prev.time + 500 + height % 7 * 100,
+                bits: if height % 5 < 3 { pow_limit_bits } else { 0x1c7fff00 + height % 5 },
+                nonce: 0,
+            };
+            chain.headers.insert(header.bitcoin_hash(), StoredHeader {
+                header: header,
+                height: height,
+                chain_work: Default::default(),
+            });
+            headers.push(header);
+        }
+
+        let by_hash: HashMap<Sha256dHash, BlockHeader> = headers.iter().map(|header| (header.bitcoin_hash(), *header)).collect();
+        for height in (1..2100u32).filter(|h| h % 97 == 0 || (*h >= 2010 && *h <= 2025)) {
+            let prev = &headers[height as usize - 1];
+            for &delay in &[600, 1201] {
+                let next = BlockHeader { prev_blockhash: prev.bitcoin_hash(), time: prev.time + delay, ..*prev };
+                let expected = pow::next_work_required(&params, prev, height, next.time,
+                                                       |header| by_hash.get(&header.prev_blockhash).cloned());
+                assert_eq!(chain.required_bits(&next, height), expected.unwrap());
+            }
+        }
+
+        // Blocks at the minimum difficulty are skipped, back to the start of
+        // the retargeting period
+        let required = |height: u32| {
+            let prev = &headers[height as usize - 1];
+            chain.required_bits(&BlockHeader { prev_blockhash: prev.bitcoin_hash(), time: prev.time + 600, ..*prev }, height)
+        };
+        assert_eq!(required(2021), 0x1c7fff04);
+        assert_eq!(required(2018), pow_limit_bits);
+    }
+
+    #[test]
+    fn median_time_past() {
+        let mut chain = HeaderChain::new(Params::new(Network::Regtest));
+        let mut prev = chain.tip().header;
+        // Timestamps may go backwards, as long as they stay above the
+        // median of the last eleven
+        for i in 0..20 {
+            let time = if i > 10 && i % 2 == 1 { prev.time - 100 } else { prev.time + 600 };
+            let header = mine(&prev, time, 0);
+            chain.add_header(header).unwrap();
+            prev = header;
+        }
+        let tip = chain.tip().header.bitcoin_hash();
+        let mtp = chain.median_time_past(&tip).unwrap();
+        let mut times: Vec<u32> = (10..21).map(|h| chain.get_by_height(h).unwrap().header.time).collect();
+        times.sort();
+        assert_eq!(mtp, times[5]);
+
+        let too_old = mine(&prev, mtp, 0);
+        assert_eq!(chain.add_header(too_old), Err(Error::TimeTooOld { time: mtp, median_time_past: mtp }));
+        chain.add_header(mine(&prev, mtp + 1, 0)).unwrap();
+    }
+
+    #[test]
+    fn locators() {
+        let mut ours = HeaderChain::new(Params::new(Network::Regtest));
+        let mut theirs = ours.clone();
+        let genesis = ours.tip().header;
+        let mut prev = genesis;
+        for i in 0..30 {
+            let header = mine(&prev, prev.time + 600, 0);
+            ours.add_header(header).unwrap();
+            if i < 20 {
+                theirs.add_header(header).unwrap();
+            }
+            prev = header;
+        }
+        let mut prev = theirs.tip().header;
+        for _ in 0..5 {
+            let header = mine(&prev, prev.time + 600, 1_000_000);
+            theirs.add_header(header).unwrap();
+            prev = header;
+        }
+
+        let locator = theirs.locator();
+        assert_eq!(locator.len(), 15);
+        assert_eq!(locator[0], theirs.tip().header.bitcoin_hash());
+        assert_eq!(locator[14], genesis.bitcoin_hash());
+        assert_eq!(ours.find_fork(&locator), 20);
+
+        let headers = ours.headers_for_locator(&locator, &Default::default());
+        assert_eq!(headers.len(), 10);
+        assert_eq!(headers[0], ours.get_by_height(21).unwrap().header);
+        for header in headers {
+            theirs.add_header(header).unwrap();
+        }
+        assert_eq!(theirs.tip(), ours.tip());
+
+        let stop = ours.get_by_height(25).unwrap().header;
+        assert_eq!(ours.headers_for_locator(&locator, &stop.bitcoin_hash()).len(), 5);
+        assert_eq!(ours.headers_for_locator(&[], &stop.bitcoin_hash()), vec![stop]);
+        assert!(ours.headers_for_locator(&ours.locator(), &Default::default()).is_empty());
+
+        let blocks = ours.blocks_for_locator(&locator, &stop.bitcoin_hash());
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0], ours.get_by_height(21).unwrap().header.bitcoin_hash());
+        assert_eq!(ours.blocks_for_locator(&locator, &Default::default()).len(), 10);
+        assert!(ours.blocks_for_locator(&ours.locator(), &Default::default()).is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Block locators
+//!
+//! A block locator is a list of block hashes, from the tip of a chain back
+//! to its genesis block, which is dense near the tip and exponentially
+//! sparser further back. A peer receiving one in a `getheaders` or
+//! `getblocks` message finds the last block it has in common with the
+//! sender and replies with the blocks after it.
+//!
+//! The functions here take a chain as a slice of block hashes indexed by
+//! height, along with a lookup of the height of a block in it.
+//!
+
+use std::ops::Range;
+
+use util::hash::Sha256dHash;
+
+/// The maximum number of headers sent in reply to a `getheaders` message
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+/// The maximum number of blocks announced in reply to a `getblocks` message
+pub const MAX_BLOCKS_RESULTS: usize = 500;
+
+/// Computes the heights of the blocks in the locator of a chain whose tip
+/// is at the given height: the ten highest blocks, then blocks with the
+/// step between them doubling, and finally the genesis block
+pub fn locator_heights(tip_height: u32) -> Vec<u32> {
+    let mut heights = vec![];
+    let mut height = tip_height;
+    let mut step = 1;
+    loop {
+        heights.push(height);
+        if height == 0 {
+            break;
+        }
+        height = height.saturating_sub(step);
+        if heights.len() > 10 {
+            step = step.saturating_mul(2);
+        }
+    }
+    heights
+}
+
+/// Builds the locator of a chain, given the hashes of its blocks by height
+pub fn block_locator(chain: &[Sha256dHash]) -> Vec<Sha256dHash> {
+    if chain.is_empty() {
+        return vec![];
+    }
+    locator_heights(chain.len() as u32 - 1).iter().map(|&height| chain[height as usize]).collect()
+}
+
+/// Finds the height of the first block of a peer's locator which is in a
+/// chain, or zero, the genesis block, if there is none. This is the fork
+/// point as found by Bitcoin Core.
+///
+/// `height_of` looks up the height of a block in the chain, returning `None`
+/// for blocks not in it, so that a caller with an index of its chain does
+/// not need to search it.
+pub fn find_fork<F>(locator: &[Sha256dHash], height_of: F) -> u32
+    where F: Fn(&Sha256dHash) -> Option<u32>
+{
+    locator.iter().filter_map(|hash| height_of(hash)).next().unwrap_or(0)
+}
+
+/// Finds the heights of the blocks of a chain whose headers to send in reply
+/// to a peer's `getheaders` message: those after the fork point, up to and
+/// including the block with `stop_hash` and at most `MAX_HEADERS_RESULTS`
+/// of them. As in Bitcoin Core, an empty locator requests just the header
+/// of the block with `stop_hash`.
+///
+/// `height_of` looks up the height of a block in `chain`, as for `find_fork`.
+pub fn headers_reply_heights<F>(chain: &[Sha256dHash], locator: &[Sha256dHash], stop_hash: &Sha256dHash,
+                                height_of: F) -> Range<usize>
+    where F: Fn(&Sha256dHash) -> Option<u32>
+{
+    if locator.is_empty() {
+        return match height_of(stop_hash) {
+            Some(height) => height as usize..height as usize + 1,
+            None => 0..0,
+        };
+    }
+    let start = find_fork(locator, height_of) as usize + 1;
+    let mut end = start;
+    while end < chain.len() && end - start < MAX_HEADERS_RESULTS {
+        end += 1;
+        if chain[end - 1] == *stop_hash {
+            break;
+        }
+    }
+    start..end
+}
+
+/// Finds the heights of the blocks of a chain to announce in reply to a
+/// peer's `getblocks` message: those after the fork point, up to but
+/// excluding the block with `stop_hash` and at most `MAX_BLOCKS_RESULTS` of
+/// them. An empty locator is treated like any locator with nothing in
+/// common with the chain.
+///
+/// `height_of` looks up the height of a block in `chain`, as for `find_fork`.
+pub fn blocks_reply_heights<F>(chain: &[Sha256dHash], locator: &[Sha256dHash], stop_hash: &Sha256dHash,
+                               height_of: F) -> Range<usize>
+    where F: Fn(&Sha256dHash) -> Option<u32>
+{
+    let start = find_fork(locator, height_of) as usize + 1;
+    let mut end = start;
+    while end < chain.len() && end - start < MAX_BLOCKS_RESULTS && chain[end] != *stop_hash {
+        end += 1;
+    }
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use consensus::encode::serialize;
+    use util::hash::Sha256dHash;
+
+    use super::*;
+
+    fn chain(len: u32, seed: u32) -> Vec<Sha256dHash> {
+        (0..len).map(|height| Sha256dHash::from_data(&serialize(&(height, if height == 0 { 0 } else { seed })))).collect()
+    }
+
+    fn index(chain: &[Sha256dHash]) -> HashMap<Sha256dHash, u32> {
+        chain.iter().enumerate().map(|(height, hash)| (*hash, height as u32)).collect()
+    }
+
+    #[test]
+    fn heights() {
+        assert_eq!(locator_heights(0), vec![0]);
+        assert_eq!(locator_heights(5), vec![5, 4, 3, 2, 1, 0]);
+        assert_eq!(locator_heights(10), vec![10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(locator_heights(100), vec![100, 99, 98, 97, 96, 95, 94, 93, 92, 91, 90, 89, 87, 83, 75, 59, 27, 0]);
+        let heights = locator_heights(1_000_000);
+        assert_eq!(heights.len(), 31);
+        assert_eq!(heights[heights.len() - 1], 0);
+    }
+
+    #[test]
+    fn locator() {
+        let ours = chain(1000, 1);
+        let index = index(&ours);
+        let height_of = |hash: &Sha256dHash| index.get(hash).cloned();
+        assert!(block_locator(&[]).is_empty());
+        let locator = block_locator(&ours);
+        assert_eq!(locator[0], ours[999]);
+        assert_eq!(locator[locator.len() - 1], ours[0]);
+
+        // A peer on a fork from height 900 of our chain
+        let mut theirs = ours[..901].to_vec();
+        theirs.extend_from_slice(&chain(1200, 2)[901..]);
+        let locator = block_locator(&theirs);
+        // The fork point is the last block of ours in their locator
+        let fork = find_fork(&locator, &height_of);
+        assert!(fork > 0 && fork <= 900);
+        assert_eq!(ours[fork as usize], theirs[fork as usize]);
+        assert_eq!(find_fork(&block_locator(&ours), &height_of), 999);
+        assert_eq!(find_fork(&block_locator(&chain(10, 3)[1..]), &height_of), 0);
+
+        // The first entry of the locator in the chain is the fork point, even
+        // if a later one is higher
+        assert_eq!(find_fork(&[theirs[1000], ours[500], ours[900]], &height_of), 500);
+        assert_eq!(find_fork(&[], &height_of), 0);
+    }
+
+    #[test]
+    fn headers_reply() {
+        let ours = chain(5000, 1);
+        let index = index(&ours);
+        let height_of = |hash: &Sha256dHash| index.get(hash).cloned();
+        let zero = Default::default();
+        assert_eq!(headers_reply_heights(&ours, &block_locator(&ours[..11]), &zero, &height_of), 11..2011);
+        assert_eq!(headers_reply_heights(&ours, &block_locator(&ours[..4001]), &zero, &height_of), 4001..5000);
+        assert_eq!(headers_reply_heights(&ours, &block_locator(&ours), &zero, &height_of), 5000..5000);
+        // The stop block is included
+        assert_eq!(headers_reply_heights(&ours, &block_locator(&ours[..11]), &ours[20], &height_of), 11..21);
+        // A peer with nothing in common starts after the genesis block
+        assert_eq!(headers_reply_heights(&ours, &[zero], &zero, &height_of), 1..2001);
+        // An empty locator requests a single block
+        assert_eq!(headers_reply_heights(&ours, &[], &ours[42], &height_of), 42..43);
+        assert_eq!(headers_reply_heights(&ours, &[], &zero, &height_of), 0..0);
+    }
+
+    #[test]
+    fn blocks_reply() {
+        let ours = chain(5000, 1);
+        let index = index(&ours);
+        let height_of = |hash: &Sha256dHash| index.get(hash).cloned();
+        let zero = Default::default();
+        assert_eq!(blocks_reply_heights(&ours, &block_locator(&ours[..11]), &zero, &height_of), 11..511);
+        assert_eq!(blocks_reply_heights(&ours, &block_locator(&ours[..4801]), &zero, &height_of), 4801..5000);
+        assert_eq!(blocks_reply_heights(&ours, &block_locator(&ours), &zero, &height_of), 5000..5000);
+        // The stop block is excluded
+        assert_eq!(blocks_reply_heights(&ours, &block_locator(&ours[..11]), &ours[20], &height_of), 11..20);
+        assert_eq!(blocks_reply_heights(&ours, &block_locator(&ours[..11]), &ours[11], &height_of), 11..11);
+        // An empty locator is not special
+        assert_eq!(blocks_reply_heights(&ours, &[], &ours[42], &height_of), 1..42);
+        assert_eq!(blocks_reply_heights(&ours, &[], &zero, &height_of), 1..501);
+    }
+}
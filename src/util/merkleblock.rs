@@ -0,0 +1,476 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Merkle blocks
+//!
+//! Partial merkle trees, which prove that some transactions are included in
+//! a block without giving the others, and the `merkleblock` message which
+//! pairs one with the block header (BIP37). Bitcoin Core's `gettxoutproof`
+//! returns a serialized `MerkleBlock`.
+//!
+
+use std::collections::HashSet;
+use std::{error, fmt};
+
+use blockdata::block::{Block, BlockHeader};
+use blockdata::constants::MAX_BLOCK_WEIGHT;
+use consensus::encode::{self, Encodable, Decodable, Encoder, Decoder};
+use util::hash::{Sha256dEncoder, Sha256dHash};
+
+/// The weight of the smallest possible transaction, which bounds the number
+/// of transactions in a block: 60 bytes times `WITNESS_SCALE_FACTOR`
+const MIN_TRANSACTION_WEIGHT: u64 = 240;
+
+/// An error extracting the matched transactions from a partial merkle tree
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MerkleBlockError {
+    /// The computed merkle root does not match the block header's
+    MerkleRootMismatch,
+    /// The tree claims to cover no transactions
+    NoTransactions,
+    /// The tree claims to cover more transactions than fit in a block
+    TooManyTransactions,
+    /// The tree has more hashes than transactions
+    TooManyHashes,
+    /// The tree has fewer flag bits than hashes
+    NotEnoughBits,
+    /// The flag bits ran out while traversing the tree
+    BitsArrayOverflow,
+    /// The hashes ran out while traversing the tree
+    HashesArrayOverflow,
+    /// Some flag bytes were not used in traversing the tree
+    NotAllBitsConsumed,
+    /// Some hashes were not used in traversing the tree
+    NotAllHashesConsumed,
+    /// Two sibling nodes have the same hash, which allows a tree with
+    /// duplicated transactions to be passed off as another (CVE-2012-2459)
+    IdenticalHashesFound,
+}
+
+impl fmt::Display for MerkleBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(error::Error::description(self))
+    }
+}
+
+impl error::Error for MerkleBlockError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            MerkleBlockError::MerkleRootMismatch => "merkle root does not match header",
+            MerkleBlockError::NoTransactions => "partial merkle tree covers no transactions",
+            MerkleBlockError::TooManyTransactions => "too many transactions",
+            MerkleBlockError::TooManyHashes => "more hashes than transactions",
+            MerkleBlockError::NotEnoughBits => "fewer flag bits than hashes",
+            MerkleBlockError::BitsArrayOverflow => "ran out of flag bits",
+            MerkleBlockError::HashesArrayOverflow => "ran out of hashes",
+            MerkleBlockError::NotAllBitsConsumed => "not all flag bits consumed",
+            MerkleBlockError::NotAllHashesConsumed => "not all hashes consumed",
+            MerkleBlockError::IdenticalHashesFound => "identical sibling hashes found",
+        }
+    }
+}
+
+/// A merkle tree pruned to the branches leading to some matched transactions
+///
+/// It is serialized as the total number of transactions in the tree, the
+/// hashes of the pruned subtrees and matched transactions, and one flag bit
+/// for each node visited in a depth-first traversal, set if the node is a
+/// matched transaction or has one beneath it. Nodes whose bit is unset, and
+/// matched transactions, have their hash included; the descendants of other
+/// nodes are visited in turn.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PartialMerkleTree {
+    /// The total number of transactions in the block
+    num_transactions: u32,
+    /// The node flags, in depth-first order
+    bits: Vec<bool>,
+    /// The included hashes, in depth-first order
+    hashes: Vec<Sha256dHash>,
+}
+
+/// Hashes a pair of merkle tree nodes
+fn parent_hash(left: &Sha256dHash, right: &Sha256dHash) -> Sha256dHash {
+    let mut encoder = Sha256dEncoder::new();
+    left.consensus_encode(&mut encoder).unwrap();
+    right.consensus_encode(&mut encoder).unwrap();
+    encoder.into_hash()
+}
+
+impl PartialMerkleTree {
+    /// Builds the partial merkle tree of a block with the given txids, in
+    /// block order, proving the inclusion of those whose entry in `matches`
+    /// is set.
+    ///
+    /// # Panics
+    /// Panics if `txids` is empty or if `matches` is not the same length
+    pub fn from_txids(txids: &[Sha256dHash], matches: &[bool]) -> PartialMerkleTree {
+        assert!(!txids.is_empty(), "no transactions");
+        assert_eq!(txids.len(), matches.len(), "txids and matches differ in length");
+
+        let mut tree = PartialMerkleTree {
+            num_transactions: txids.len() as u32,
+            bits: Vec::with_capacity(txids.len()),
+            hashes: vec![],
+        };
+        let mut height = 0;
+        while tree.tree_width(height) > 1 {
+            height += 1;
+        }
+        tree.traverse_and_build(height, 0, txids, matches);
+        tree
+    }
+
+    /// The total number of transactions in the block
+    pub fn num_transactions(&self) -> u32 {
+        self.num_transactions
+    }
+
+    /// The node flags, in depth-first order
+    pub fn bits(&self) -> &[bool] {
+        &self.bits
+    }
+
+    /// The included hashes, in depth-first order
+    pub fn hashes(&self) -> &[Sha256dHash] {
+        &self.hashes
+    }
+
+    /// Extracts the matched txids, appending them to `matches` and their
+    /// positions in the block to `indexes`, and returns the merkle root the
+    /// tree commits to
+    pub fn extract_matches(&self, matches: &mut Vec<Sha256dHash>, indexes: &mut Vec<u32>)
+                           -> Result<Sha256dHash, MerkleBlockError> {
+        if self.num_transactions == 0 {
+            return Err(MerkleBlockError::NoTransactions);
+        }
+        if self.num_transactions as u64 > MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT {
+            return Err(MerkleBlockError::TooManyTransactions);
+        }
+        // There can never be more hashes than transactions
+        if self.hashes.len() as u64 > self.num_transactions as u64 {
+            return Err(MerkleBlockError::TooManyHashes);
+        }
+        // There must be at least one bit per hash
+        if self.bits.len() < self.hashes.len() {
+            return Err(MerkleBlockError::NotEnoughBits);
+        }
+
+        let mut height = 0;
+        while self.tree_width(height) > 1 {
+            height += 1;
+        }
+        let mut bits_used = 0;
+        let mut hashes_used = 0;
+        let root = self.traverse_and_extract(height, 0, &mut bits_used, &mut hashes_used, matches, indexes)?;
+        // All flag bytes must have been used, although the last may be padded
+        if (bits_used + 7) / 8 != (self.bits.len() + 7) / 8 {
+            return Err(MerkleBlockError::NotAllBitsConsumed);
+        }
+        if hashes_used != self.hashes.len() {
+            return Err(MerkleBlockError::NotAllHashesConsumed);
+        }
+        Ok(root)
+    }
+
+    /// The number of nodes at the given height, where the leaves are at
+    /// height zero
+    fn tree_width(&self, height: u32) -> u32 {
+        ((self.num_transactions as u64 + (1 << height) - 1) >> height) as u32
+    }
+
+    /// Computes the hash of the node at the given height and position
+    fn calc_hash(&self, height: u32, pos: u32, txids: &[Sha256dHash]) -> Sha256dHash {
+        if height == 0 {
+            return txids[pos as usize];
+        }
+        let left = self.calc_hash(height - 1, pos * 2, txids);
+        let right = if pos * 2 + 1 < self.tree_width(height - 1) {
+            self.calc_hash(height - 1, pos * 2 + 1, txids)
+        } else {
+            left
+        };
+        parent_hash(&left, &right)
+    }
+
+    fn traverse_and_build(&mut self, height: u32, pos: u32, txids: &[Sha256dHash], matches: &[bool]) {
+        // Whether this node is, or is an ancestor of, a matched transaction
+        let start = (pos as usize) << height;
+        let end = ::std::cmp::min(((pos as usize) + 1) << height, txids.len());
+        let parent_of_match = matches[start..end].iter().any(|&m| m);
+        self.bits.push(parent_of_match);
+
+        if height == 0 || !parent_of_match {
+            let hash = self.calc_hash(height, pos, txids);
+            self.hashes.push(hash);
+        } else {
+            self.traverse_and_build(height - 1, pos * 2, txids, matches);
+            if pos * 2 + 1 < self.tree_width(height - 1) {
+                self.traverse_and_build(height - 1, pos * 2 + 1, txids, matches);
+            }
+        }
+    }
+
+    fn traverse_and_extract(&self, height: u32, pos: u32, bits_used: &mut usize, hashes_used: &mut usize,
+                            matches: &mut Vec<Sha256dHash>, indexes: &mut Vec<u32>)
+                            -> Result<Sha256dHash, MerkleBlockError> {
+        if *bits_used >= self.bits.len() {
+            return Err(MerkleBlockError::BitsArrayOverflow);
+        }
+        let parent_of_match = self.bits[*bits_used];
+        *bits_used += 1;
+
+        if height == 0 || !parent_of_match {
+            if *hashes_used >= self.hashes.len() {
+                return Err(MerkleBlockError::HashesArrayOverflow);
+            }
+            let hash = self.hashes[*hashes_used];
+            *hashes_used += 1;
+            if height == 0 && parent_of_match {
+                matches.push(hash);
+                indexes.push(pos);
+            }
+            Ok(hash)
+        } else {
+            let left = self.traverse_and_extract(height - 1, pos * 2, bits_used, hashes_used, matches, indexes)?;
+            let right = if pos * 2 + 1 < self.tree_width(height - 1) {
+                let right = self.traverse_and_extract(height - 1, pos * 2 + 1, bits_used, hashes_used, matches, indexes)?;
+                if right == left {
+                    return Err(MerkleBlockError::IdenticalHashesFound);
+                }
+                right
+            } else {
+                left
+            };
+            Ok(parent_hash(&left, &right))
+        }
+    }
+}
+
+impl<S: Encoder> Encodable<S> for PartialMerkleTree {
+    fn consensus_encode(&self, s: &mut S) -> Result<(), encode::Error> {
+        self.num_transactions.consensus_encode(s)?;
+        self.hashes.consensus_encode(s)?;
+        let mut bytes = vec![0u8; (self.bits.len() + 7) / 8];
+        for (idx, &bit) in self.bits.iter().enumerate() {
+            bytes[idx / 8] |= (bit as u8) << (idx % 8);
+        }
+        bytes.consensus_encode(s)
+    }
+}
+
+impl<D: Decoder> Decodable<D> for PartialMerkleTree {
+    fn consensus_decode(d: &mut D) -> Result<PartialMerkleTree, encode::Error> {
+        let num_transactions: u32 = Decodable::consensus_decode(d)?;
+        let hashes: Vec<Sha256dHash> = Decodable::consensus_decode(d)?;
+        let bytes: Vec<u8> = Decodable::consensus_decode(d)?;
+        let mut bits = Vec::with_capacity(bytes.len() * 8);
+        for byte in bytes {
+            for idx in 0..8 {
+                bits.push(byte & (1 << idx) != 0);
+            }
+        }
+        Ok(PartialMerkleTree {
+            num_transactions: num_transactions,
+            bits: bits,
+            hashes: hashes,
+        })
+    }
+}
+
+/// A block header with a partial merkle tree proving that some of the
+/// block's transactions are included in it (BIP37)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MerkleBlock {
+    /// The block header
+    pub header: BlockHeader,
+    /// The partial merkle tree of the block's transactions
+    pub txn: PartialMerkleTree,
+}
+
+impl MerkleBlock {
+    /// Builds the merkle block of `block` proving the inclusion of those of
+    /// its transactions whose txids are in `match_txids`
+    ///
+    /// # Panics
+    /// Panics if the block has no transactions
+    pub fn from_block(block: &Block, match_txids: &HashSet<Sha256dHash>) -> MerkleBlock {
+        let txids: Vec<Sha256dHash> = block.txdata.iter().map(|tx| tx.txid()).collect();
+        MerkleBlock::from_header_txids(&block.header, &txids, match_txids)
+    }
+
+    /// Builds the merkle block of a block with the given header and txids,
+    /// proving the inclusion of those in `match_txids`
+    ///
+    /// # Panics
+    /// Panics if `block_txids` is empty
+    pub fn from_header_txids(header: &BlockHeader, block_txids: &[Sha256dHash],
+                             match_txids: &HashSet<Sha256dHash>) -> MerkleBlock {
+        let matches: Vec<bool> = block_txids.iter().map(|txid| match_txids.contains(txid)).collect();
+        MerkleBlock {
+            header: *header,
+            txn: PartialMerkleTree::from_txids(block_txids, &matches),
+        }
+    }
+
+    /// Extracts the matched txids and their positions in the block, checking
+    /// that the partial merkle tree commits to the header's merkle root
+    pub fn extract_matches(&self, matches: &mut Vec<Sha256dHash>, indexes: &mut Vec<u32>)
+                           -> Result<(), MerkleBlockError> {
+        let merkle_root = self.txn.extract_matches(matches, indexes)?;
+        if merkle_root == self.header.merkle_root {
+            Ok(())
+        } else {
+            Err(MerkleBlockError::MerkleRootMismatch)
+        }
+    }
+}
+
+impl_consensus_encoding!(MerkleBlock, header, txn);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    use blockdata::block::Block;
+    use consensus::encode::{deserialize, serialize};
+    use util::hash::{Sha256dHash, bitcoin_merkle_root};
+    use util::misc::hex_bytes;
+
+    use super::*;
+
+    #[test]
+    fn pmt_round_trip() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for &num_tx in [1, 4, 7, 17, 56, 100, 127, 256, 312, 513, 1000, 4095].iter() {
+            let txids: Vec<Sha256dHash> = (0..num_tx).map(|i: u32| Sha256dHash::from_data(&serialize(&i))).collect();
+            let merkle_root = bitcoin_merkle_root(txids.clone());
+
+            // Match with decreasing probability
+            for &one_in in [1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096].iter() {
+                let matches: Vec<bool> = (0..num_tx).map(|_| rng.gen_range(0, one_in) == 0).collect();
+                let expected: Vec<Sha256dHash> = txids.iter().zip(matches.iter())
+                                                      .filter(|&(_, &m)| m).map(|(txid, _)| *txid).collect();
+
+                let tree = PartialMerkleTree::from_txids(&txids, &matches);
+                let serialized = serialize(&tree);
+                // The tree is at most as large as the full list of txids
+                // plus the flag bits
+                assert!(serialized.len() <= 10 + (258 * num_tx as usize + 7) / 8);
+
+                let tree: PartialMerkleTree = deserialize(&serialized).unwrap();
+                let mut found = vec![];
+                let mut indexes = vec![];
+                assert_eq!(tree.extract_matches(&mut found, &mut indexes), Ok(merkle_root));
+                assert_eq!(found, expected);
+                for (txid, &idx) in found.iter().zip(indexes.iter()) {
+                    assert_eq!(*txid, txids[idx as usize]);
+                }
+
+                // Changing a hash changes the root
+                for _ in 0..4 {
+                    let idx = rng.gen_range(0, tree.hashes.len());
+                    let mut bad = tree.clone();
+                    bad.hashes[idx] = Sha256dHash::from_data(&bad.hashes[idx][..]);
+                    let root = bad.extract_matches(&mut vec![], &mut vec![]);
+                    assert!(root != Ok(merkle_root));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pmt_malleability() {
+        // A tree whose last two transactions are duplicates of each other
+        // has the same root as one without the duplicate
+        let txids: Vec<Sha256dHash> = [1u32, 2, 3, 4, 5, 6, 7, 8, 9, 10, 9, 10].iter()
+                                                        .map(|i| Sha256dHash::from_data(&serialize(i))).collect();
+        let matches = [false, false, false, false, false, false, false, false, false, true, true, false];
+        let tree = PartialMerkleTree::from_txids(&txids, &matches);
+        assert_eq!(tree.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::IdenticalHashesFound));
+    }
+
+    #[test]
+    fn pmt_bad_encoding() {
+        let txids: Vec<Sha256dHash> = (0..5u32).map(|i| Sha256dHash::from_data(&serialize(&i))).collect();
+        let tree = PartialMerkleTree::from_txids(&txids, &[false, true, false, false, false]);
+
+        let mut bad = tree.clone();
+        bad.num_transactions = 0;
+        assert_eq!(bad.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::NoTransactions));
+        bad.num_transactions = 1_000_000;
+        assert_eq!(bad.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::TooManyTransactions));
+
+        let mut bad = tree.clone();
+        bad.hashes.pop();
+        assert_eq!(bad.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::HashesArrayOverflow));
+        let mut bad = tree.clone();
+        let hash = bad.hashes[0];
+        bad.hashes.push(hash);
+        assert_eq!(bad.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::NotAllHashesConsumed));
+
+        let mut bad = tree.clone();
+        bad.bits.truncate(bad.hashes.len());
+        assert!(bad.extract_matches(&mut vec![], &mut vec![]).is_err());
+        let mut bad = tree.clone();
+        bad.bits.extend_from_slice(&[false; 8]);
+        assert_eq!(bad.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::NotAllBitsConsumed));
+    }
+
+    #[test]
+    fn merkleblock() {
+        // Output of Bitcoin Core's `gettxoutproof` for a transaction in
+        // testnet block 000000000000b731f2eef9e8c63173adfb07e41bd53eb0ef0a6b720d6cb6dea4
+        let proof = hex_bytes("01000000ba8b9cda965dd8e536670f9ddec10e53aab14b20bacad27b9137190000000000190760b278fe7b8565fda3b968b918d5fd997f993b23674c0af3b6fde300b38f33a5914ce6ed5b1b01e32f570200000002252bf9d75c4f481ebb6278d708257d1f12beb6dd30301d26c623f789b2ba6fc0e2d32adb5f8ca820731dff234a84e78ec30bce4ec69dbd562d0b2b8266bf4e5a0105").unwrap();
+        let merkle_block: MerkleBlock = deserialize(&proof).unwrap();
+        assert_eq!(serialize(&merkle_block), proof);
+
+        let mut matches = vec![];
+        let mut indexes = vec![];
+        assert_eq!(merkle_block.extract_matches(&mut matches, &mut indexes), Ok(()));
+        assert_eq!(matches, vec![Sha256dHash::from_hex("5a4ebf66822b0b2d56bd9dc64ece0bc38ee7844a23ff1d7320a88c5fdb2ad3e2").unwrap()]);
+        assert_eq!(indexes, vec![1]);
+
+        let mut bad = merkle_block.clone();
+        bad.header.merkle_root = Default::default();
+        assert_eq!(bad.extract_matches(&mut vec![], &mut vec![]), Err(MerkleBlockError::MerkleRootMismatch));
+    }
+
+    #[test]
+    fn merkleblock_from_block() {
+        let block: Block = deserialize(&hex_bytes("010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e400000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f417a914cd74d6e49ffff001d323b3a7b0201000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0804ffff001d026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a4246c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c924664889942260000000049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d159bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b46621f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501fffffffffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c60030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1feff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00000000").unwrap()).unwrap();
+        let txid = block.txdata[1].txid();
+        let mut match_txids = HashSet::new();
+        match_txids.insert(txid);
+
+        let merkle_block = MerkleBlock::from_block(&block, &match_txids);
+        assert_eq!(merkle_block.header, block.header);
+        assert_eq!(merkle_block.txn.num_transactions(), 2);
+        let mut matches = vec![];
+        let mut indexes = vec![];
+        assert_eq!(merkle_block.extract_matches(&mut matches, &mut indexes), Ok(()));
+        assert_eq!(matches, vec![txid]);
+        assert_eq!(indexes, vec![1]);
+
+        let empty = MerkleBlock::from_block(&block, &HashSet::new());
+        assert_eq!(empty.txn.hashes(), &[block.header.merkle_root]);
+        assert_eq!(empty.extract_matches(&mut matches, &mut indexes), Ok(()));
+        assert_eq!(matches.len(), 1);
+    }
+}
@@ -18,15 +18,29 @@
 
 pub mod privkey;
 pub mod address;
+pub mod amount;
 pub mod base58;
 pub mod bip32;
 pub mod bip143;
+pub mod bip158;
+pub mod bloom;
+pub mod coinselect;
 pub mod contracthash;
 pub mod decimal;
+pub mod feerate;
 pub mod hash;
+pub mod headerchain;
 pub mod iter;
+pub mod locator;
+pub mod merkleblock;
 pub mod misc;
+pub mod policy;
+pub mod psbt;
+pub mod rbf;
+pub mod signer;
+pub mod txbuilder;
 pub mod uint;
+pub mod utxo;
 
 #[cfg(feature = "fuzztarget")]
 pub mod sha2;
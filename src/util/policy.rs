@@ -0,0 +1,443 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Standardness policy
+//!
+//! Checks mirroring Bitcoin Core's `IsStandardTx`, which nodes apply before
+//! relaying a transaction or accepting it to their mempool. Transactions
+//! failing them are valid, but will generally only be mined if given to a
+//! miner directly.
+//!
+
+use std::{error, fmt};
+
+use blockdata::opcodes;
+use blockdata::script::{Instruction, Script};
+use blockdata::transaction::{Transaction, TxOut};
+use util::amount::Amount;
+use util::feerate::FeeRate;
+
+/// The highest transaction version relayed
+pub const MAX_STANDARD_TX_VERSION: u32 = 2;
+/// The maximum weight of a relayed transaction
+pub const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+/// The maximum size of a relayed scriptSig, large enough for a 15-of-15
+/// P2SH multisig spend with compressed keys
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+/// The maximum size of a relayed OP_RETURN output script, including the
+/// OP_RETURN and push opcodes
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+/// The maximum number of keys in a relayed bare multisig output
+pub const MAX_STANDARD_BARE_MULTISIG_KEYS: usize = 3;
+/// Fee rate, in satoshis per 1000 virtual bytes, used to determine dust
+pub const DUST_RELAY_TX_FEE: u64 = 3000;
+
+/// The standard scriptPubKey templates
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum ScriptType {
+    /// Pay-to-pubkey
+    PubKey,
+    /// Pay-to-pubkey-hash
+    PubKeyHash,
+    /// Pay-to-script-hash
+    ScriptHash,
+    /// Bare multisig
+    Multisig {
+        /// The number of signatures required
+        required: usize,
+        /// The number of keys
+        keys: usize,
+    },
+    /// An OP_RETURN followed only by pushes, carrying data
+    NullData,
+    /// Segwit v0 pay-to-witness-pubkey-hash
+    WitnessV0KeyHash,
+    /// Segwit v0 pay-to-witness-script-hash
+    WitnessV0ScriptHash,
+    /// A witness program of version 1 or above
+    WitnessUnknown,
+    /// Any other script
+    NonStandard,
+}
+
+/// Whether `key` has the size and prefix of a serialized public key
+fn is_pubkey_format(key: &[u8]) -> bool {
+    match key.len() {
+        33 => key[0] == 0x02 || key[0] == 0x03,
+        65 => key[0] == 0x04 || key[0] == 0x06 || key[0] == 0x07,
+        _ => false,
+    }
+}
+
+/// The value of an OP_1 to OP_16 instruction
+fn small_int(ins: &Instruction) -> Option<usize> {
+    match *ins {
+        Instruction::Op(op) => match op.classify() {
+            opcodes::Class::PushNum(n) if n >= 1 && n <= 16 => Some(n as usize),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Parses a bare multisig script, returning the number of signatures
+/// required and of keys
+fn multisig(script: &Script) -> Option<(usize, usize)> {
+    let ins: Vec<Instruction> = script.iter(false).collect();
+    if ins.len() < 4 || ins[ins.len() - 1] != Instruction::Op(opcodes::All::OP_CHECKMULTISIG) {
+        return None;
+    }
+    let (required, keys) = match (small_int(&ins[0]), small_int(&ins[ins.len() - 2])) {
+        (Some(required), Some(keys)) => (required, keys),
+        _ => return None,
+    };
+    let all_keys = ins[1..ins.len() - 2].iter().all(|ins| match *ins {
+        Instruction::PushBytes(key) => is_pubkey_format(key),
+        _ => false,
+    });
+    if all_keys && keys == ins.len() - 3 && required <= keys {
+        Some((required, keys))
+    } else {
+        None
+    }
+}
+
+/// Matches a scriptPubKey against the standard templates
+pub fn classify(script: &Script) -> ScriptType {
+    let bytes = script.as_bytes();
+    if script.is_p2sh() {
+        ScriptType::ScriptHash
+    } else if script.is_v0_p2wpkh() {
+        ScriptType::WitnessV0KeyHash
+    } else if script.is_v0_p2wsh() {
+        ScriptType::WitnessV0ScriptHash
+    } else if script.is_witness_program() {
+        if bytes[0] == opcodes::All::OP_PUSHBYTES_0 as u8 {
+            // Version 0 programs must be one of the lengths above
+            ScriptType::NonStandard
+        } else {
+            ScriptType::WitnessUnknown
+        }
+    } else if script.is_op_return() {
+        if Script::from(bytes[1..].to_vec()).is_push_only() {
+            ScriptType::NullData
+        } else {
+            ScriptType::NonStandard
+        }
+    } else if script.is_p2pkh() {
+        ScriptType::PubKeyHash
+    } else if bytes.len() >= 2 && bytes[0] as usize + 2 == bytes.len() &&
+              bytes[bytes.len() - 1] == opcodes::All::OP_CHECKSIG as u8 &&
+              is_pubkey_format(&bytes[1..bytes.len() - 1]) {
+        ScriptType::PubKey
+    } else if let Some((required, keys)) = multisig(script) {
+        ScriptType::Multisig { required: required, keys: keys }
+    } else {
+        ScriptType::NonStandard
+    }
+}
+
+/// The value below which an output with the given scriptPubKey is dust,
+/// i.e. costs more to spend at `dust_relay_fee` than it is worth. Unspendable
+/// outputs are never dust.
+pub fn dust_threshold(script_pubkey: &Script, dust_relay_fee: FeeRate) -> Amount {
+    if script_pubkey.is_provably_unspendable() {
+        return Amount::zero();
+    }
+    let output_size = 8 + 1 + script_pubkey.len() as u64;
+    // outpoint, scriptSig length, nSequence plus the size of a typical
+    // P2PKH or (discounted) P2WPKH satisfaction
+    let input_size = if script_pubkey.is_witness_program() {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    let fee = dust_relay_fee.as_sat_per_kvb()
+                            .and_then(|rate| rate.checked_mul(output_size + input_size))
+                            .map(|fee| fee / 1000)
+                            .unwrap_or(u64::max_value());
+    Amount::from_sat(fee)
+}
+
+/// Whether an output is worth less than it would cost to spend
+pub fn is_dust(output: &TxOut, dust_relay_fee: FeeRate) -> bool {
+    output.value < dust_threshold(&output.script_pubkey, dust_relay_fee)
+}
+
+/// Policy parameters which nodes may configure
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Params {
+    /// The fee rate used to determine dust outputs
+    pub dust_relay_fee: FeeRate,
+    /// The maximum size of an OP_RETURN output script
+    pub max_op_return_size: usize,
+    /// Whether bare multisig outputs are relayed
+    pub permit_bare_multisig: bool,
+}
+
+impl Default for Params {
+    /// Bitcoin Core's defaults
+    fn default() -> Params {
+        Params {
+            dust_relay_fee: FeeRate::from_sat_per_kvb_ceil(DUST_RELAY_TX_FEE),
+            max_op_return_size: MAX_OP_RETURN_RELAY,
+            permit_bare_multisig: true,
+        }
+    }
+}
+
+/// The rule a non-standard transaction breaks
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Error {
+    /// The transaction version is zero or above `MAX_STANDARD_TX_VERSION`
+    Version(u32),
+    /// The transaction weight is above `MAX_STANDARD_TX_WEIGHT`
+    Weight(u64),
+    /// The scriptSig of the input with the given index is too large
+    ScriptSigSize(usize),
+    /// The scriptSig of the input with the given index contains non-push
+    /// opcodes
+    ScriptSigNotPushOnly(usize),
+    /// The output with the given index does not match a standard template
+    NonStandardOutput(usize),
+    /// The OP_RETURN output with the given index is too large
+    OpReturnSize(usize),
+    /// The output with the given index is bare multisig with too many keys,
+    /// or bare multisig is not permitted
+    BareMultisig(usize),
+    /// The output with the given index is dust
+    Dust(usize),
+    /// The transaction has more than one OP_RETURN output
+    MultipleOpReturn,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Version(v) => write!(f, "non-standard version {}", v),
+            Error::Weight(w) => write!(f, "weight {} exceeds the standard maximum", w),
+            Error::ScriptSigSize(idx) => write!(f, "scriptSig of input {} is too large", idx),
+            Error::ScriptSigNotPushOnly(idx) => write!(f, "scriptSig of input {} is not push-only", idx),
+            Error::NonStandardOutput(idx) => write!(f, "output {} is non-standard", idx),
+            Error::OpReturnSize(idx) => write!(f, "OP_RETURN output {} is too large", idx),
+            Error::BareMultisig(idx) => write!(f, "bare multisig output {} is not permitted", idx),
+            Error::Dust(idx) => write!(f, "output {} is dust", idx),
+            Error::MultipleOpReturn => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Version(_) => "non-standard version",
+            Error::Weight(_) => "transaction too large",
+            Error::ScriptSigSize(_) => "scriptSig too large",
+            Error::ScriptSigNotPushOnly(_) => "scriptSig not push-only",
+            Error::NonStandardOutput(_) => "non-standard output",
+            Error::OpReturnSize(_) => "OP_RETURN output too large",
+            Error::BareMultisig(_) => "bare multisig output not permitted",
+            Error::Dust(_) => "dust output",
+            Error::MultipleOpReturn => "more than one OP_RETURN output",
+        }
+    }
+}
+
+/// Checks that a transaction is standard, i.e. will be relayed by nodes
+/// using the given policy parameters. This does not check the inputs'
+/// spent outputs or their witnesses, nor the transaction's validity.
+pub fn check_standard_tx(tx: &Transaction, params: &Params) -> Result<(), Error> {
+    if tx.version < 1 || tx.version > MAX_STANDARD_TX_VERSION {
+        return Err(Error::Version(tx.version));
+    }
+
+    let weight = tx.get_weight();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        return Err(Error::Weight(weight));
+    }
+
+    for (idx, input) in tx.input.iter().enumerate() {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(Error::ScriptSigSize(idx));
+        }
+        if !input.script_sig.is_push_only() {
+            return Err(Error::ScriptSigNotPushOnly(idx));
+        }
+    }
+
+    let mut op_returns = 0;
+    for (idx, output) in tx.output.iter().enumerate() {
+        match classify(&output.script_pubkey) {
+            ScriptType::NonStandard => return Err(Error::NonStandardOutput(idx)),
+            ScriptType::NullData => {
+                if output.script_pubkey.len() > params.max_op_return_size {
+                    return Err(Error::OpReturnSize(idx));
+                }
+                op_returns += 1;
+            }
+            ScriptType::Multisig { keys, .. } => {
+                if !params.permit_bare_multisig || keys > MAX_STANDARD_BARE_MULTISIG_KEYS {
+                    return Err(Error::BareMultisig(idx));
+                }
+            }
+            _ => {}
+        }
+        if is_dust(output, params.dust_relay_fee) {
+            return Err(Error::Dust(idx));
+        }
+    }
+
+    if op_returns > 1 {
+        return Err(Error::MultipleOpReturn);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::opcodes;
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use util::amount::Amount;
+    use util::feerate::FeeRate;
+    use util::misc::hex_bytes;
+
+    use super::*;
+
+    fn script(hex: &str) -> Script {
+        Script::from(hex_bytes(hex).unwrap())
+    }
+
+    fn multisig_script(required: i64, keys: usize) -> Script {
+        let key = hex_bytes("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798").unwrap();
+        let mut builder = Builder::new().push_int(required);
+        for _ in 0..keys {
+            builder = builder.push_slice(&key);
+        }
+        builder.push_int(keys as i64).push_opcode(opcodes::All::OP_CHECKMULTISIG).into_script()
+    }
+
+    #[test]
+    fn classification() {
+        assert_eq!(classify(&script("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac")), ScriptType::PubKeyHash);
+        assert_eq!(classify(&script("a914da1745e9b549bd0bfa1a569971c77eba30cd5a4b87")), ScriptType::ScriptHash);
+        assert_eq!(classify(&script("0014751e76e8199196d454941c45d1b3a323f1433bd6")), ScriptType::WitnessV0KeyHash);
+        assert_eq!(classify(&script("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262")),
+                   ScriptType::WitnessV0ScriptHash);
+        assert_eq!(classify(&script("5128751e76e8199196d454941c45d1b3a323f1433bd6751e76e8199196d454941c45d1b3a323f1433bd6")),
+                   ScriptType::WitnessUnknown);
+        assert_eq!(classify(&script("0015751e76e8199196d454941c45d1b3a323f1433bd600")), ScriptType::NonStandard);
+        assert_eq!(classify(&script("210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac")),
+                   ScriptType::PubKey);
+        assert_eq!(classify(&script("210579be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798ac")),
+                   ScriptType::NonStandard);
+        assert_eq!(classify(&script("6a0401020304")), ScriptType::NullData);
+        assert_eq!(classify(&script("6a")), ScriptType::NullData);
+        assert_eq!(classify(&script("6a0401020304ac")), ScriptType::NonStandard);
+        assert_eq!(classify(&multisig_script(1, 3)), ScriptType::Multisig { required: 1, keys: 3 });
+        assert_eq!(classify(&multisig_script(2, 1)), ScriptType::NonStandard);
+        assert_eq!(classify(&Script::new()), ScriptType::NonStandard);
+        assert_eq!(classify(&script("51")), ScriptType::NonStandard);
+    }
+
+    #[test]
+    fn dust() {
+        let rate = FeeRate::from_sat_per_kvb(DUST_RELAY_TX_FEE);
+        let p2pkh = script("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac");
+        assert_eq!(dust_threshold(&p2pkh, rate), Amount::from_sat(546));
+        assert_eq!(dust_threshold(&script("0014751e76e8199196d454941c45d1b3a323f1433bd6"), rate), Amount::from_sat(294));
+        assert_eq!(dust_threshold(&script("6a0401020304"), rate), Amount::zero());
+        assert_eq!(dust_threshold(&p2pkh, FeeRate::zero()), Amount::zero());
+        assert_eq!(dust_threshold(&p2pkh, FeeRate::max_value()), Amount::from_sat(u64::max_value()));
+
+        assert!(is_dust(&TxOut { value: Amount::from_sat(545), script_pubkey: p2pkh.clone() }, rate));
+        assert!(!is_dust(&TxOut { value: Amount::from_sat(546), script_pubkey: p2pkh }, rate));
+    }
+
+    #[test]
+    fn standard_tx() {
+        let params = Params::default();
+        let pay = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: script("76a914162c5ea71c0b23f5b9022ef047c4a86470a5b07088ac"),
+        };
+        let data = TxOut { value: Amount::zero(), script_pubkey: script("6a0401020304") };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: script("0401020304"),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![pay.clone(), data.clone()],
+        };
+        assert_eq!(check_standard_tx(&tx, &params), Ok(()));
+
+        let mut bad = tx.clone();
+        bad.version = 3;
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::Version(3)));
+        bad.version = 0;
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::Version(0)));
+
+        let mut bad = tx.clone();
+        bad.output.push(TxOut { value: Amount::from_sat(10_000), script_pubkey: Script::from(vec![0x51; 100_000]) });
+        match check_standard_tx(&bad, &params) {
+            Err(Error::Weight(w)) => assert!(w > MAX_STANDARD_TX_WEIGHT),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut bad = tx.clone();
+        bad.input[0].script_sig = Builder::new().push_slice(&[0; 1647]).into_script();
+        assert_eq!(check_standard_tx(&bad, &params), Ok(()));
+        bad.input[0].script_sig = Builder::new().push_slice(&[0; 1648]).into_script();
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::ScriptSigSize(0)));
+        bad.input[0].script_sig = script("51ac");
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::ScriptSigNotPushOnly(0)));
+
+        let mut bad = tx.clone();
+        bad.output[0].script_pubkey = script("51");
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::NonStandardOutput(0)));
+
+        let mut bad = tx.clone();
+        bad.output[1].script_pubkey = Builder::new().push_opcode(opcodes::All::OP_RETURN)
+                                                    .push_slice(&[0; 80]).into_script();
+        assert_eq!(check_standard_tx(&bad, &params), Ok(()));
+        bad.output[1].script_pubkey = Builder::new().push_opcode(opcodes::All::OP_RETURN)
+                                                    .push_slice(&[0; 81]).into_script();
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::OpReturnSize(1)));
+        let mut bad = tx.clone();
+        bad.output.push(data);
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::MultipleOpReturn));
+
+        let mut bad = tx.clone();
+        bad.output[0].script_pubkey = multisig_script(2, 3);
+        assert_eq!(check_standard_tx(&bad, &params), Ok(()));
+        let no_multisig = Params { permit_bare_multisig: false, ..Params::default() };
+        assert_eq!(check_standard_tx(&bad, &no_multisig), Err(Error::BareMultisig(0)));
+        bad.output[0].script_pubkey = multisig_script(2, 4);
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::BareMultisig(0)));
+
+        let mut bad = tx.clone();
+        bad.output[0].value = Amount::from_sat(545);
+        assert_eq!(check_standard_tx(&bad, &params), Err(Error::Dust(0)));
+        let no_dust = Params { dust_relay_fee: FeeRate::zero(), ..Params::default() };
+        assert_eq!(check_standard_tx(&bad, &no_dust), Ok(()));
+    }
+}
@@ -0,0 +1,121 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+use std::error;
+use std::fmt;
+
+#[cfg(feature = "base64")] use base64;
+
+use blockdata::transaction::Transaction;
+use util::psbt::raw;
+
+/// Ways that a Partially Signed Transaction might fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Magic bytes for a PSBT must be the ASCII for "psbt" serialized in most
+    /// significant byte order.
+    InvalidMagic,
+    /// The separator for a PSBT must be `0xff`.
+    InvalidSeparator,
+    /// Known keys must be according to spec.
+    InvalidKey(raw::Key),
+    /// Keys within key-value map should never be duplicated.
+    DuplicateKey(raw::Key),
+    /// The scriptSigs for the unsigned transaction must be empty.
+    UnsignedTxHasScriptSigs,
+    /// The scriptWitnesses for the unsigned transaction must be empty.
+    UnsignedTxHasScriptWitnesses,
+    /// A PSBT must have an unsigned transaction.
+    MustHaveUnsignedTx,
+    /// Signals that there are no more key-value pairs in a key-value map.
+    NoMorePairs,
+    /// Attempted to merge two PSBTs which do not share the same unsigned
+    /// transaction.
+    UnexpectedUnsignedTx {
+        /// Expected
+        expected: Transaction,
+        /// Actual
+        actual: Transaction,
+    },
+    /// Unable to parse as a standard SigHash type.
+    NonStandardSigHashType(u32),
+    /// The PSBT was not valid base64.
+    #[cfg(feature = "base64")]
+    Base64(base64::DecodeError),
+    /// The input at the given index has neither a witness nor a non-witness
+    /// UTXO, so the output it spends is unknown.
+    MissingUtxo(usize),
+    /// The input at the given index could not be finalized, either because
+    /// its script is not a template the finalizer understands or because it
+    /// does not carry enough partial signatures.
+    CannotFinalize(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidKey(ref rkey) => write!(f, "{}: {}", error::Error::description(self), rkey),
+            Error::DuplicateKey(ref rkey) => write!(f, "{}: {}", error::Error::description(self), rkey),
+            Error::UnexpectedUnsignedTx { expected: ref e, actual: ref a } => write!(f, "{}: expected {}, actual {}", error::Error::description(self), e.txid(), a.txid()),
+            Error::NonStandardSigHashType(ref sht) => write!(f, "{}: {}", error::Error::description(self), sht),
+            #[cfg(feature = "base64")]
+            Error::Base64(ref e) => fmt::Display::fmt(e, f),
+            Error::MissingUtxo(ref idx) => write!(f, "{}: input {}", error::Error::description(self), idx),
+            Error::CannotFinalize(ref idx) => write!(f, "{}: input {}", error::Error::description(self), idx),
+            Error::InvalidMagic
+            | Error::InvalidSeparator
+            | Error::UnsignedTxHasScriptSigs
+            | Error::UnsignedTxHasScriptWitnesses
+            | Error::MustHaveUnsignedTx
+            | Error::NoMorePairs => f.write_str(error::Error::description(self))
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            #[cfg(feature = "base64")]
+            Error::Base64(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::InvalidMagic => "invalid magic",
+            Error::InvalidSeparator => "invalid separator",
+            Error::InvalidKey(..) => "invalid key",
+            Error::DuplicateKey(..) => "duplicate key",
+            Error::UnsignedTxHasScriptSigs => "the unsigned transaction has script sigs",
+            Error::UnsignedTxHasScriptWitnesses => "the unsigned transaction has script witnesses",
+            Error::MustHaveUnsignedTx => "partially signed transactions must have an unsigned transaction",
+            Error::NoMorePairs => "no more key-value pairs for this psbt map",
+            Error::UnexpectedUnsignedTx { .. } => "different unsigned transaction",
+            Error::NonStandardSigHashType(..) => "non-standard sighash type",
+            #[cfg(feature = "base64")]
+            Error::Base64(..) => "invalid base64",
+            Error::MissingUtxo(..) => "input is missing its spent output",
+            Error::CannotFinalize(..) => "input cannot be finalized",
+        }
+    }
+}
+
+#[doc(hidden)]
+#[cfg(feature = "base64")]
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Error {
+        Error::Base64(e)
+    }
+}
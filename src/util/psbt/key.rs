@@ -0,0 +1,56 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+use secp256k1::{self, Secp256k1};
+
+/// A public key as it appears in PSBT key-value pairs. Whether the key is
+/// serialized compressed changes the scripts and hashes it appears in, so
+/// is kept along with the key.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct PublicKey {
+    /// Whether the key is serialized in compressed form
+    pub compressed: bool,
+    /// The actual ECDSA key
+    pub key: secp256k1::key::PublicKey,
+}
+
+impl PublicKey {
+    /// Parses a compressed or uncompressed public key
+    pub fn from_slice(data: &[u8]) -> Result<PublicKey, secp256k1::Error> {
+        let key = secp256k1::key::PublicKey::from_slice(&Secp256k1::without_caps(), data)?;
+        Ok(PublicKey {
+            compressed: data.len() == secp256k1::constants::PUBLIC_KEY_SIZE,
+            key: key,
+        })
+    }
+
+    /// Serializes the key in its compressed or uncompressed form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.compressed {
+            self.key.serialize().to_vec()
+        } else {
+            self.key.serialize_uncompressed().to_vec()
+        }
+    }
+}
+
+impl From<secp256k1::key::PublicKey> for PublicKey {
+    /// Wraps a key to be serialized in compressed form
+    fn from(key: secp256k1::key::PublicKey) -> PublicKey {
+        PublicKey {
+            compressed: true,
+            key: key,
+        }
+    }
+}
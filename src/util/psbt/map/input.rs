@@ -0,0 +1,215 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+use std::collections::BTreeMap;
+
+
+use blockdata::script::Script;
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use consensus::encode;
+use util::bip32::{ChildNumber, Fingerprint};
+use util::psbt;
+use util::psbt::map::Map;
+use util::psbt::raw;
+use util::psbt::{Error, PublicKey};
+
+/// A key-value map for an input of the corresponding index in the unsigned
+/// transaction.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct Input {
+    /// The non-witness transaction this input spends from. Should only be
+    /// [std::option::Option::Some] for inputs which spend non-segwit outputs.
+    pub non_witness_utxo: Option<Transaction>,
+    /// The transaction output this input spends from. Should only be
+    /// [std::option::Option::Some] for inputs which spend segwit outputs,
+    /// including P2SH embedded ones.
+    pub witness_utxo: Option<TxOut>,
+    /// A map from public keys to their corresponding signature as would be
+    /// pushed to the stack from a scriptSig or witness.
+    pub partial_sigs: BTreeMap<PublicKey, Vec<u8>>,
+    /// The sighash type to be used for this input. Signatures for this input
+    /// must use the sighash type.
+    pub sighash_type: Option<SigHashType>,
+    /// The redeem script for this input.
+    pub redeem_script: Option<Script>,
+    /// The witness script for this input.
+    pub witness_script: Option<Script>,
+    /// A map from public keys needed to sign this input to their corresponding
+    /// master key fingerprints and derivation paths.
+    pub hd_keypaths: BTreeMap<PublicKey, (Fingerprint, Vec<ChildNumber>)>,
+    /// The finalized, fully-constructed scriptSig with signatures and any other
+    /// scripts necessary for this input to pass validation.
+    pub final_script_sig: Option<Script>,
+    /// The finalized, fully-constructed scriptWitness with signatures and any
+    /// other scripts necessary for this input to pass validation.
+    pub final_script_witness: Option<Vec<Vec<u8>>>,
+    /// Unknown key-value pairs for this input.
+    pub unknown: BTreeMap<raw::Key, Vec<u8>>,
+}
+
+impl Input {
+    /// Whether this input carries a final scriptSig or scriptWitness, i.e.
+    /// whether it has gone through the finalizer.
+    pub fn is_finalized(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+
+    /// Returns the output spent by this input, taken from `witness_utxo` if
+    /// present or else looked up in `non_witness_utxo` at the given `vout`.
+    pub fn spent_output(&self, vout: u32) -> Option<&TxOut> {
+        match (&self.witness_utxo, &self.non_witness_utxo) {
+            (&Some(ref txout), _) => Some(txout),
+            (&None, &Some(ref tx)) => tx.output.get(vout as usize),
+            (&None, &None) => None,
+        }
+    }
+}
+
+impl Map for Input {
+    fn insert_pair(&mut self, pair: raw::Pair) -> Result<(), encode::Error> {
+        let raw::Pair {
+            key: raw_key,
+            value: raw_value,
+        } = pair;
+
+        match raw_key.type_value {
+            0u8 => {
+                impl_psbt_insert_pair! {
+                    self.non_witness_utxo <= <raw_key: _>|<raw_value: Transaction>
+                }
+            }
+            1u8 => {
+                impl_psbt_insert_pair! {
+                    self.witness_utxo <= <raw_key: _>|<raw_value: TxOut>
+                }
+            }
+            3u8 => {
+                impl_psbt_insert_pair! {
+                    self.sighash_type <= <raw_key: _>|<raw_value: SigHashType>
+                }
+            }
+            4u8 => {
+                impl_psbt_insert_pair! {
+                    self.redeem_script <= <raw_key: _>|<raw_value: Script>
+                }
+            }
+            5u8 => {
+                impl_psbt_insert_pair! {
+                    self.witness_script <= <raw_key: _>|<raw_value: Script>
+                }
+            }
+            7u8 => {
+                impl_psbt_insert_pair! {
+                    self.final_script_sig <= <raw_key: _>|<raw_value: Script>
+                }
+            }
+            8u8 => {
+                impl_psbt_insert_pair! {
+                    self.final_script_witness <= <raw_key: _>|<raw_value: Vec<Vec<u8>>>
+                }
+            }
+            2u8 => {
+                impl_psbt_insert_pair! {
+                    self.partial_sigs <= <raw_key: PublicKey>|<raw_value: Vec<u8>>
+                }
+            }
+            6u8 => {
+                impl_psbt_insert_pair! {
+                    self.hd_keypaths <= <raw_key: PublicKey>|<raw_value: (Fingerprint, Vec<ChildNumber>)>
+                }
+            }
+            _ => {
+                if self.unknown.contains_key(&raw_key) {
+                    return Err(Error::DuplicateKey(raw_key).into());
+                } else {
+                    self.unknown.insert(raw_key, raw_value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_pairs(&self) -> Result<Vec<raw::Pair>, encode::Error> {
+        let mut rv: Vec<raw::Pair> = Default::default();
+
+        impl_psbt_get_pair! {
+            rv.push(self.non_witness_utxo as <0u8, _>|<Transaction>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.witness_utxo as <1u8, _>|<TxOut>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.partial_sigs as <2u8, PublicKey>|<Vec<u8>>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.sighash_type as <3u8, _>|<SigHashType>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.redeem_script as <4u8, _>|<Script>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.witness_script as <5u8, _>|<Script>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.hd_keypaths as <6u8, PublicKey>|<(Fingerprint, Vec<ChildNumber>)>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.final_script_sig as <7u8, _>|<Script>)
+        }
+
+        impl_psbt_get_pair! {
+            rv.push(self.final_script_witness as <8u8, _>|<Vec<Vec<u8>>>)
+        }
+
+        for (key, value) in self.unknown.iter() {
+            rv.push(raw::Pair {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+
+        Ok(rv)
+    }
+
+    fn merge(&mut self, other: Self) -> Result<(), psbt::Error> {
+        merge!(non_witness_utxo, self, other);
+
+        if let (&None, Some(witness_utxo)) = (&self.witness_utxo, other.witness_utxo) {
+            self.witness_utxo = Some(witness_utxo);
+            self.non_witness_utxo = None; // Clear out any non-witness UTXO when we set a witness one
+        }
+
+        self.partial_sigs.extend(other.partial_sigs);
+        self.hd_keypaths.extend(other.hd_keypaths);
+        self.unknown.extend(other.unknown);
+
+        merge!(sighash_type, self, other);
+        merge!(redeem_script, self, other);
+        merge!(witness_script, self, other);
+        merge!(final_script_sig, self, other);
+        merge!(final_script_witness, self, other);
+
+        Ok(())
+    }
+}
+
+impl_psbtmap_consensus_enc_dec_oding!(Input);
@@ -0,0 +1,639 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Partially Signed Transactions
+//!
+//! Implementation of BIP174 Partially Signed Bitcoin Transaction Format as
+//! defined at https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
+//! except we define PSBTs containing non-standard SigHash types as invalid.
+//!
+//! The Creator role is `PartiallySignedTransaction::from_unsigned_tx`, the
+//! Combiner role is `PartiallySignedTransaction::merge`, the Input Finalizer
+//! role is `PartiallySignedTransaction::finalize` and the Transaction
+//! Extractor role is `PartiallySignedTransaction::extract_tx`. PSBTs are
+//! consensus-encoded in their binary format and, with the `base64` feature,
+//! displayed/parsed as base64.
+
+#[cfg(feature = "base64")] use std::fmt;
+#[cfg(feature = "base64")] use std::str::FromStr;
+
+#[cfg(feature = "base64")] use base64;
+
+use blockdata::opcodes;
+use blockdata::script::{Builder, Instruction, Script};
+use blockdata::transaction::Transaction;
+use consensus::encode::{self, Encodable, Decodable, Encoder, Decoder};
+use util::hash::Hash160;
+
+mod error;
+pub use self::error::Error;
+
+mod key;
+pub use self::key::PublicKey;
+
+pub mod raw;
+
+#[macro_use]
+mod macros;
+
+pub mod serialize;
+
+mod map;
+pub use self::map::{Map, Global, Input, Output};
+
+/// A Partially Signed Transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartiallySignedTransaction {
+    /// The key-value pairs for all global data.
+    pub global: Global,
+    /// The corresponding key-value map for each input in the unsigned
+    /// transaction.
+    pub inputs: Vec<Input>,
+    /// The corresponding key-value map for each output in the unsigned
+    /// transaction.
+    pub outputs: Vec<Output>,
+}
+
+impl PartiallySignedTransaction {
+    /// Create a PartiallySignedTransaction from an unsigned transaction, error
+    /// if not unsigned
+    pub fn from_unsigned_tx(tx: Transaction) -> Result<Self, self::Error> {
+        Ok(PartiallySignedTransaction {
+            inputs: vec![Default::default(); tx.input.len()],
+            outputs: vec![Default::default(); tx.output.len()],
+            global: Global::from_unsigned_tx(tx)?,
+        })
+    }
+
+    /// Extract the Transaction from a PartiallySignedTransaction by filling in
+    /// the available signature information in place.
+    pub fn extract_tx(self) -> Transaction {
+        let mut tx: Transaction = self.global.unsigned_tx;
+
+        for (vin, psbtin) in tx.input.iter_mut().zip(self.inputs.into_iter()) {
+            vin.script_sig = psbtin.final_script_sig.unwrap_or_else(Script::new);
+            vin.witness = psbtin.final_script_witness.unwrap_or_else(Vec::new);
+        }
+
+        tx
+    }
+
+    /// Attempt to merge with another `PartiallySignedTransaction`.
+    pub fn merge(&mut self, other: Self) -> Result<(), self::Error> {
+        self.global.merge(other.global)?;
+
+        for (self_input, other_input) in self.inputs.iter_mut().zip(other.inputs.into_iter()) {
+            self_input.merge(other_input)?;
+        }
+
+        for (self_output, other_output) in self.outputs.iter_mut().zip(other.outputs.into_iter()) {
+            self_output.merge(other_output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finalize every input which has not been finalized yet, see
+    /// `finalize_input`. Stops at the first input which cannot be finalized.
+    pub fn finalize(&mut self) -> Result<(), self::Error> {
+        for index in 0..self.inputs.len() {
+            self.finalize_input(index)?;
+        }
+        Ok(())
+    }
+
+    /// Build the final scriptSig and scriptWitness of the input at `index`
+    /// from its partial signatures, then clear all data which is no longer
+    /// needed, as described for the Input Finalizer role in BIP174.
+    ///
+    /// Spends of P2PK, P2PKH, P2WPKH, bare multisig and multisig wrapped in
+    /// P2SH, P2WSH or P2SH-P2WSH are supported, as is P2SH-P2WPKH. Inputs which
+    /// are already final are left alone.
+    ///
+    /// # Panics
+    /// Panics if `index` is greater than or equal to `self.inputs.len()`
+    pub fn finalize_input(&mut self, index: usize) -> Result<(), self::Error> {
+        let vout = self.global.unsigned_tx.input[index].previous_output.vout;
+        let input = &mut self.inputs[index];
+
+        if input.is_finalized() {
+            return Ok(());
+        }
+
+        let script_pubkey = match input.spent_output(vout) {
+            Some(txout) => txout.script_pubkey.clone(),
+            None => return Err(Error::MissingUtxo(index)),
+        };
+
+        let (script_sig, witness) = match satisfy(input, &script_pubkey) {
+            Some(satisfaction) => satisfaction,
+            None => return Err(Error::CannotFinalize(index)),
+        };
+
+        input.final_script_sig = if script_sig.is_empty() { None } else { Some(script_sig) };
+        input.final_script_witness = if witness.is_empty() { None } else { Some(witness) };
+        input.partial_sigs.clear();
+        input.sighash_type = None;
+        input.redeem_script = None;
+        input.witness_script = None;
+        input.hd_keypaths.clear();
+
+        Ok(())
+    }
+}
+
+/// Find the partial signature whose public key hashes to `hash`, returning it
+/// along with the serialization of the key.
+fn pubkeyhash_sig(input: &Input, hash: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    for (pk, sig) in &input.partial_sigs {
+        let bytes = pk.to_bytes();
+        if &Hash160::from_data(&bytes)[..] == hash {
+            return Some((sig.clone(), bytes));
+        }
+    }
+    None
+}
+
+/// Stack elements satisfying `script`, which must be a P2PK, P2PKH or
+/// `m`-of-`n` CHECKMULTISIG template
+fn satisfy_template(input: &Input, script: &Script) -> Option<Vec<Vec<u8>>> {
+    if script.is_p2pkh() {
+        let (sig, pk) = pubkeyhash_sig(input, &script[3..23])?;
+        return Some(vec![sig, pk]);
+    }
+
+    let instructions: Vec<Instruction> = script.iter(true).collect();
+    match instructions.last() {
+        Some(&Instruction::Op(opcodes::All::OP_CHECKSIG)) if instructions.len() == 2 => {
+            if let Instruction::PushBytes(data) = instructions[0] {
+                let pk = PublicKey::from_slice(data).ok()?;
+                let sig = input.partial_sigs.get(&pk)?;
+                return Some(vec![sig.clone()]);
+            }
+            None
+        }
+        Some(&Instruction::Op(opcodes::All::OP_CHECKMULTISIG)) if instructions.len() >= 4 => {
+            let n_keys = instructions.len() - 3;
+            let required = match instructions[0] {
+                Instruction::Op(op) => match op.classify() {
+                    opcodes::Class::PushNum(m) if m > 0 => m as usize,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            match instructions[n_keys + 1] {
+                Instruction::Op(op) if op.classify() == opcodes::Class::PushNum(n_keys as i32) => {},
+                _ => return None,
+            }
+
+            // CHECKMULTISIG pops one element more than it uses
+            let mut stack = vec![vec![]];
+            for instruction in &instructions[1..n_keys + 1] {
+                if stack.len() == required + 1 {
+                    break;
+                }
+                match *instruction {
+                    Instruction::PushBytes(data) => {
+                        let pk = PublicKey::from_slice(data).ok()?;
+                        if let Some(sig) = input.partial_sigs.get(&pk) {
+                            stack.push(sig.clone());
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            if stack.len() == required + 1 { Some(stack) } else { None }
+        }
+        _ => None,
+    }
+}
+
+/// Compute the final scriptSig and witness for an input spending `script_pubkey`
+fn satisfy(input: &Input, script_pubkey: &Script) -> Option<(Script, Vec<Vec<u8>>)> {
+    fn push_all(stack: Vec<Vec<u8>>) -> Builder {
+        stack.iter().fold(Builder::new(), |builder, elem| builder.push_slice(elem))
+    }
+
+    fn satisfy_witness(input: &Input, witness_program: &Script) -> Option<Vec<Vec<u8>>> {
+        if witness_program.is_v0_p2wpkh() {
+            let (sig, pk) = pubkeyhash_sig(input, &witness_program[2..22])?;
+            Some(vec![sig, pk])
+        } else if witness_program.is_v0_p2wsh() {
+            let witness_script = input.witness_script.as_ref()?;
+            if witness_script.to_v0_p2wsh() != *witness_program {
+                return None;
+            }
+            let mut stack = satisfy_template(input, witness_script)?;
+            stack.push(witness_script.to_bytes());
+            Some(stack)
+        } else {
+            None
+        }
+    }
+
+    if script_pubkey.is_p2sh() {
+        let redeem_script = input.redeem_script.as_ref()?;
+        if redeem_script.to_p2sh() != *script_pubkey {
+            return None;
+        }
+        if redeem_script.is_v0_p2wpkh() || redeem_script.is_v0_p2wsh() {
+            let witness = satisfy_witness(input, redeem_script)?;
+            let script_sig = Builder::new().push_slice(&redeem_script[..]).into_script();
+            Some((script_sig, witness))
+        } else {
+            let stack = satisfy_template(input, redeem_script)?;
+            let script_sig = push_all(stack).push_slice(&redeem_script[..]).into_script();
+            Some((script_sig, vec![]))
+        }
+    } else if script_pubkey.is_v0_p2wpkh() || script_pubkey.is_v0_p2wsh() {
+        Some((Script::new(), satisfy_witness(input, script_pubkey)?))
+    } else {
+        let stack = satisfy_template(input, script_pubkey)?;
+        Some((push_all(stack).into_script(), vec![]))
+    }
+}
+
+impl<S: Encoder> Encodable<S> for PartiallySignedTransaction {
+    fn consensus_encode(&self, s: &mut S) -> Result<(), encode::Error> {
+        b"psbt".consensus_encode(s)?;
+
+        0xff_u8.consensus_encode(s)?;
+
+        self.global.consensus_encode(s)?;
+
+        for i in &self.inputs {
+            i.consensus_encode(s)?;
+        }
+
+        for i in &self.outputs {
+            i.consensus_encode(s)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: Decoder> Decodable<D> for PartiallySignedTransaction {
+    fn consensus_decode(d: &mut D) -> Result<Self, encode::Error> {
+        let magic: [u8; 4] = Decodable::consensus_decode(d)?;
+
+        if *b"psbt" != magic {
+            return Err(Error::InvalidMagic.into());
+        }
+
+        if 0xff_u8 != u8::consensus_decode(d)? {
+            return Err(Error::InvalidSeparator.into());
+        }
+
+        let global: Global = Decodable::consensus_decode(d)?;
+
+        let inputs: Vec<Input> = {
+            let inputs_len: usize = (&global.unsigned_tx.input).len();
+
+            let mut inputs: Vec<Input> = Vec::with_capacity(inputs_len);
+
+            for _ in 0..inputs_len {
+                inputs.push(Decodable::consensus_decode(d)?);
+            }
+
+            inputs
+        };
+
+        let outputs: Vec<Output> = {
+            let outputs_len: usize = (&global.unsigned_tx.output).len();
+
+            let mut outputs: Vec<Output> = Vec::with_capacity(outputs_len);
+
+            for _ in 0..outputs_len {
+                outputs.push(Decodable::consensus_decode(d)?);
+            }
+
+            outputs
+        };
+
+        Ok(PartiallySignedTransaction {
+            global: global,
+            inputs: inputs,
+            outputs: outputs,
+        })
+    }
+}
+
+#[cfg(feature = "base64")]
+impl fmt::Display for PartiallySignedTransaction {
+    /// Output the PSBT as base64, the format used by Bitcoin Core's RPC
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&base64::encode(&encode::serialize(self)))
+    }
+}
+
+#[cfg(feature = "base64")]
+impl FromStr for PartiallySignedTransaction {
+    type Err = encode::Error;
+
+    fn from_str(s: &str) -> Result<Self, encode::Error> {
+        let data = base64::decode(s).map_err(Error::Base64)?;
+        encode::deserialize(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "base64")] use std::str::FromStr;
+
+    use secp256k1::Secp256k1;
+    use secp256k1::key::{self, SecretKey};
+
+    use blockdata::script::{Builder, Script};
+    use blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut};
+    use blockdata::opcodes;
+    use consensus::encode::{self, deserialize, serialize, serialize_hex};
+    use util::amount::Amount;
+    use util::bip32::{ChildNumber, Fingerprint};
+    use util::hash::Hash160;
+
+    use super::{Error, Input, Output, PartiallySignedTransaction, PublicKey};
+    use super::raw;
+
+    fn keys(n: u8) -> Vec<PublicKey> {
+        let secp = Secp256k1::new();
+        (1..n + 1).map(|i| {
+            let sk = SecretKey::from_slice(&secp, &[i; 32]).unwrap();
+            PublicKey::from(key::PublicKey::from_secret_key(&secp, &sk))
+        }).collect()
+    }
+
+    fn unsigned_tx() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 1257139,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: hex_hash!("f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126"),
+                    vout: 0,
+                },
+                script_sig: Script::new(),
+                sequence: 4294967294,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(99999699),
+                    script_pubkey: hex_script!("76a914d0c59903c5bac2868760e90fd521a4665aa7652088ac"),
+                },
+                TxOut {
+                    value: Amount::from_sat(100000000),
+                    script_pubkey: hex_script!("a9143545e6e33b832c47050f24d3eeb93c9c03948bc787"),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn trivial_psbt() {
+        let psbt = PartiallySignedTransaction::from_unsigned_tx(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        }).unwrap();
+        assert_eq!(serialize_hex(&psbt), "70736274ff01000a0200000000000000000000");
+
+        let decoded: PartiallySignedTransaction = hex_psbt!("70736274ff01000a0200000000000000000000").unwrap();
+        assert_eq!(decoded, psbt);
+    }
+
+    #[test]
+    fn serialize_then_deserialize() {
+        let pks = keys(2);
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(200000000),
+            script_pubkey: hex_script!("0014d85c2b71d0060b09c9886aeb815e50991dda124d"),
+        });
+        psbt.inputs[0].partial_sigs.insert(pks[0], vec![0x30, 0x01, 0x01]);
+        psbt.inputs[0].sighash_type = Some(SigHashType::All);
+        psbt.inputs[0].hd_keypaths.insert(pks[0], (
+            Fingerprint::from(&[0xd9, 0x0c, 0x6a, 0x4f][..]),
+            vec![ChildNumber::from_hardened_idx(0), ChildNumber::from_normal_idx(7)],
+        ));
+        psbt.inputs[0].unknown.insert(raw::Key { type_value: 0x0f, key: vec![1, 2, 3] }, vec![4, 5, 6]);
+        psbt.outputs[1].redeem_script = Some(hex_script!("0014be18d152a9b012039daf3da7de4f53349eecb985"));
+        psbt.outputs[1].hd_keypaths.insert(pks[1], (Fingerprint::default(), vec![]));
+
+        let encoded = serialize(&psbt);
+        let decoded: PartiallySignedTransaction = deserialize(&encoded).unwrap();
+        assert_eq!(decoded, psbt);
+        assert_eq!(serialize(&decoded), encoded);
+    }
+
+    #[test]
+    #[cfg(feature = "base64")]
+    fn base64_round_trip() {
+        let psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        let base64 = psbt.to_string();
+        assert!(base64.starts_with("cHNidP8"));
+        assert_eq!(PartiallySignedTransaction::from_str(&base64).unwrap(), psbt);
+
+        match PartiallySignedTransaction::from_str("cHNidP8!") {
+            Err(encode::Error::Psbt(Error::Base64(..))) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn invalid_psbts() {
+        // A network transaction rather than a PSBT
+        match hex_psbt!("0200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf6000000006a473044022070b2245123e6bf474d60c5b50c043d4c691a5d2435f09a34a7662a9dc251790a022001329ca9dacf280bdf30740ec0390422422c81cb45839457aeb76fc12edd95b3012102657d118d3357b8e0f4c2cd46db7b39f6d9c38d9a70abcb9b2de5dc8dbfe4ce31feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300") as Result<PartiallySignedTransaction, _> {
+            Err(encode::Error::Psbt(Error::InvalidMagic)) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // Missing the unsigned transaction
+        match hex_psbt!("70736274ff00") as Result<PartiallySignedTransaction, _> {
+            Err(encode::Error::Psbt(Error::MustHaveUnsignedTx)) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // Unsigned transaction with a scriptSig
+        let mut tx = unsigned_tx();
+        tx.input[0].script_sig = Builder::new().push_int(1).into_script();
+        assert_eq!(PartiallySignedTransaction::from_unsigned_tx(tx).err(), Some(Error::UnsignedTxHasScriptSigs));
+
+        // Duplicate global key
+        match hex_psbt!("70736274ff01000a0200000000000000000001000a0200000000000000000000") as Result<PartiallySignedTransaction, _> {
+            Err(encode::Error::Psbt(Error::DuplicateKey(..))) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn nonstandard_sighash() {
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        psbt.inputs[0].sighash_type = Some(SigHashType::All);
+        let mut encoded = serialize(&psbt);
+        // The sighash type is the last value of the input map, followed by
+        // its separator and those of the two empty output maps
+        let pos = encoded.len() - 3 - 5;
+        assert_eq!(&encoded[pos..pos + 5], &[0x04, 0x01, 0x00, 0x00, 0x00]);
+        encoded[pos + 1] = 0x04;
+        match deserialize::<PartiallySignedTransaction>(&encoded) {
+            Err(encode::Error::Psbt(Error::NonStandardSigHashType(4))) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let pks = keys(2);
+        let mut psbt1 = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        let mut psbt2 = psbt1.clone();
+
+        psbt1.inputs[0].partial_sigs.insert(pks[0], vec![1]);
+        psbt2.inputs[0].partial_sigs.insert(pks[1], vec![2]);
+        psbt2.inputs[0].witness_script = Some(hex_script!("51"));
+        psbt2.outputs[0].unknown.insert(raw::Key { type_value: 0x0f, key: vec![] }, vec![]);
+
+        psbt1.merge(psbt2).unwrap();
+        assert_eq!(psbt1.inputs[0].partial_sigs.len(), 2);
+        assert_eq!(psbt1.inputs[0].witness_script, Some(hex_script!("51")));
+        assert_eq!(psbt1.outputs[0].unknown.len(), 1);
+
+        let mut other_tx = unsigned_tx();
+        other_tx.lock_time = 0;
+        let psbt3 = PartiallySignedTransaction::from_unsigned_tx(other_tx).unwrap();
+        match psbt1.merge(psbt3) {
+            Err(Error::UnexpectedUnsignedTx { .. }) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn finalize_p2pkh_and_p2sh_p2wpkh() {
+        let pks = keys(1);
+        let pkh = Hash160::from_data(&pks[0].to_bytes()[..]);
+        let p2wpkh = Builder::new().push_int(0).push_slice(&pkh[..]).into_script();
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: Amount::from_sat(1), script_pubkey: p2wpkh.to_p2sh() });
+        psbt.inputs[0].partial_sigs.insert(pks[0], vec![0x30, 0x01]);
+        assert_eq!(psbt.clone().finalize(), Err(Error::CannotFinalize(0)));
+
+        psbt.inputs[0].redeem_script = Some(p2wpkh.clone());
+        psbt.finalize().unwrap();
+        assert!(psbt.inputs[0].is_finalized());
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        assert_eq!(psbt.inputs[0].redeem_script, None);
+
+        let tx = psbt.extract_tx();
+        assert_eq!(tx.input[0].script_sig, Builder::new().push_slice(&p2wpkh[..]).into_script());
+        assert_eq!(tx.input[0].witness, vec![vec![0x30, 0x01], pks[0].to_bytes()]);
+
+        // Legacy p2pkh spend, with the UTXO given as a full transaction
+        let mut prev_tx = unsigned_tx();
+        prev_tx.output[0].script_pubkey = Builder::new()
+            .push_opcode(opcodes::All::OP_DUP)
+            .push_opcode(opcodes::All::OP_HASH160)
+            .push_slice(&pkh[..])
+            .push_opcode(opcodes::All::OP_EQUALVERIFY)
+            .push_opcode(opcodes::All::OP_CHECKSIG)
+            .into_script();
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        assert_eq!(psbt.clone().finalize(), Err(Error::MissingUtxo(0)));
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        psbt.inputs[0].partial_sigs.insert(pks[0], vec![0x30, 0x02]);
+        psbt.finalize().unwrap();
+
+        let tx = psbt.extract_tx();
+        assert_eq!(tx.input[0].script_sig, Builder::new().push_slice(&[0x30, 0x02]).push_slice(&pks[0].to_bytes()[..]).into_script());
+        assert!(tx.input[0].witness.is_empty());
+    }
+
+    #[test]
+    fn uncompressed_keys() {
+        let mut pk = keys(1)[0];
+        pk.compressed = false;
+        assert_eq!(pk.to_bytes().len(), 65);
+        assert_eq!(PublicKey::from_slice(&pk.to_bytes()), Ok(pk));
+
+        // Uncompressed keys keep their form through serialization
+        let mut prev_tx = unsigned_tx();
+        prev_tx.output[0].script_pubkey = Builder::new()
+            .push_opcode(opcodes::All::OP_DUP)
+            .push_opcode(opcodes::All::OP_HASH160)
+            .push_slice(&Hash160::from_data(&pk.to_bytes())[..])
+            .push_opcode(opcodes::All::OP_EQUALVERIFY)
+            .push_opcode(opcodes::All::OP_CHECKSIG)
+            .into_script();
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        psbt.inputs[0].partial_sigs.insert(pk, vec![0x30, 0x03]);
+        psbt.inputs[0].hd_keypaths.insert(pk, (Fingerprint::default(), vec![]));
+        psbt.outputs[0].hd_keypaths.insert(pk, (Fingerprint::default(), vec![]));
+        let mut decoded: PartiallySignedTransaction = deserialize(&serialize(&psbt)).unwrap();
+        assert_eq!(decoded, psbt);
+        assert!(!decoded.inputs[0].partial_sigs.keys().next().unwrap().compressed);
+        assert!(!decoded.outputs[0].hd_keypaths.keys().next().unwrap().compressed);
+
+        decoded.finalize().unwrap();
+        let tx = decoded.extract_tx();
+        assert_eq!(tx.input[0].script_sig, Builder::new().push_slice(&[0x30, 0x03]).push_slice(&pk.to_bytes()).into_script());
+    }
+
+    #[test]
+    fn finalize_multisig() {
+        let pks = keys(3);
+        let multisig = Builder::new()
+            .push_int(2)
+            .push_slice(&pks[0].to_bytes()[..])
+            .push_slice(&pks[1].to_bytes()[..])
+            .push_slice(&pks[2].to_bytes()[..])
+            .push_int(3)
+            .push_opcode(opcodes::All::OP_CHECKMULTISIG)
+            .into_script();
+
+        // p2wsh
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: Amount::from_sat(1), script_pubkey: multisig.to_v0_p2wsh() });
+        psbt.inputs[0].witness_script = Some(multisig.clone());
+        psbt.inputs[0].partial_sigs.insert(pks[2], vec![3]);
+        assert_eq!(psbt.finalize_input(0), Err(Error::CannotFinalize(0)));
+        psbt.inputs[0].partial_sigs.insert(pks[0], vec![1]);
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(psbt.inputs[0].final_script_sig, None);
+        assert_eq!(psbt.inputs[0].final_script_witness, Some(vec![vec![], vec![1], vec![3], multisig.to_bytes()]));
+
+        // p2sh
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: Amount::from_sat(1), script_pubkey: multisig.to_p2sh() });
+        psbt.inputs[0].redeem_script = Some(multisig.clone());
+        psbt.inputs[0].partial_sigs.insert(pks[1], vec![2]);
+        psbt.inputs[0].partial_sigs.insert(pks[2], vec![3]);
+        psbt.finalize_input(0).unwrap();
+        assert_eq!(
+            psbt.inputs[0].final_script_sig,
+            Some(Builder::new().push_slice(&[]).push_slice(&[2]).push_slice(&[3]).push_slice(&multisig[..]).into_script())
+        );
+        assert_eq!(psbt.inputs[0].final_script_witness, None);
+
+        let mut finalized = Input::default();
+        finalized.final_script_sig = Some(hex_script!("51"));
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx()).unwrap();
+        psbt.inputs[0] = finalized.clone();
+        psbt.finalize().unwrap();
+        assert_eq!(psbt.inputs[0], finalized);
+        assert_eq!(psbt.outputs[0], Output::default());
+    }
+}
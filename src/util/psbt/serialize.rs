@@ -0,0 +1,132 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Serialization
+//!
+//! Defines traits used for (de)serializing PSBT values into/from raw
+//! bytes in PSBT key-value pairs.
+
+use std::io::Cursor;
+
+use blockdata::script::Script;
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use consensus::encode::{self, serialize, Decodable};
+use util::bip32::{ChildNumber, Fingerprint};
+use util::psbt::{self, PublicKey};
+
+/// A trait for serializing a value as raw data for insertion into PSBT
+/// key-value pairs.
+pub trait Serialize {
+    /// Serialize a value as raw data.
+    fn serialize(&self) -> Vec<u8>;
+}
+
+/// A trait for deserializing a value from raw data in PSBT key-value pairs.
+pub trait Deserialize: Sized {
+    /// Deserialize a value from raw data.
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error>;
+}
+
+impl_psbt_de_serialize!(Transaction);
+impl_psbt_de_serialize!(TxOut);
+impl_psbt_de_serialize!(Vec<Vec<u8>>); // scriptWitness
+
+impl Serialize for Script {
+    fn serialize(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+}
+
+impl Deserialize for Script {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        Ok(Script::from(bytes.to_vec()))
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+}
+
+impl Deserialize for PublicKey {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        PublicKey::from_slice(bytes)
+            .map_err(|_| encode::Error::ParseFailed("invalid public key"))
+    }
+}
+
+impl Serialize for (Fingerprint, Vec<ChildNumber>) {
+    fn serialize(&self) -> Vec<u8> {
+        let mut rv: Vec<u8> = Vec::with_capacity(4 + 4 * (self.1).len());
+
+        rv.extend_from_slice(&self.0[..]);
+
+        for cnum in &self.1 {
+            rv.append(&mut serialize(&u32::from(*cnum)))
+        }
+
+        rv
+    }
+}
+
+impl Deserialize for (Fingerprint, Vec<ChildNumber>) {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        if bytes.len() < 4 {
+            return Err(encode::Error::Io(::std::io::Error::from(::std::io::ErrorKind::UnexpectedEof)))
+        }
+
+        let fprint: Fingerprint = Fingerprint::from(&bytes[0..4]);
+        let mut dpath: Vec<ChildNumber> = Default::default();
+
+        let d = &mut Cursor::new(&bytes[4..]);
+        while d.position() < (bytes.len() - 4) as u64 {
+            let index: u32 = Decodable::consensus_decode(d)?;
+            dpath.push(ChildNumber::from(index));
+        }
+
+        Ok((fprint, dpath))
+    }
+}
+
+impl Serialize for Vec<u8> {
+    fn serialize(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl Deserialize for Vec<u8> {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Serialize for SigHashType {
+    fn serialize(&self) -> Vec<u8> {
+        serialize(&self.as_u32())
+    }
+}
+
+impl Deserialize for SigHashType {
+    fn deserialize(bytes: &[u8]) -> Result<Self, encode::Error> {
+        let raw: u32 = encode::deserialize(bytes)?;
+        let rv: SigHashType = SigHashType::from_u32(raw);
+
+        if rv.as_u32() == raw {
+            Ok(rv)
+        } else {
+            Err(psbt::Error::NonStandardSigHashType(raw).into())
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Replace-by-fee
+//!
+//! Detection of replaceable unconfirmed transactions and checks that a
+//! replacement transaction satisfies the fee rules of BIP125, as applied
+//! by Bitcoin Core's mempool.
+//!
+
+use std::{error, fmt};
+use std::collections::{HashMap, HashSet};
+
+use blockdata::transaction::Transaction;
+use util::amount::Amount;
+use util::feerate::FeeRate;
+use util::hash::Sha256dHash;
+
+/// The largest sequence number which signals replaceability
+pub const MAX_BIP125_RBF_SEQUENCE: u32 = 0xFFFFFFFD;
+
+/// Whether any of the transaction's inputs explicitly signals that it may be
+/// replaced
+pub fn signals_rbf(tx: &Transaction) -> bool {
+    tx.input.iter().any(|input| input.get_sequence().signals_rbf())
+}
+
+/// Whether an unconfirmed transaction may be replaced, either because it
+/// signals replaceability itself or because it inherits it from one of its
+/// unconfirmed ancestors. `unconfirmed` must contain every unconfirmed
+/// transaction, indexed by txid; parents missing from it are taken to be
+/// confirmed.
+pub fn is_replaceable(tx: &Transaction, unconfirmed: &HashMap<Sha256dHash, Transaction>) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![tx];
+    while let Some(tx) = stack.pop() {
+        if signals_rbf(tx) {
+            return true;
+        }
+        for input in &tx.input {
+            let txid = input.previous_output.txid;
+            if seen.insert(txid) {
+                if let Some(parent) = unconfirmed.get(&txid) {
+                    stack.push(parent);
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Mempool policy parameters for replacements
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Policy {
+    /// The fee rate at which the replacement must pay for its own relay, on
+    /// top of the fees of the transactions it evicts
+    pub incremental_relay_fee: FeeRate,
+    /// The maximum number of transactions a replacement may evict
+    pub max_replacements: usize,
+}
+
+impl Default for Policy {
+    /// Bitcoin Core's defaults, of 1000 sat/kvB and 100 transactions
+    fn default() -> Policy {
+        Policy {
+            incremental_relay_fee: FeeRate::from_sat_per_kvb_ceil(1000),
+            max_replacements: 100,
+        }
+    }
+}
+
+/// A mempool transaction which a replacement would evict
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Evicted {
+    /// The txid of the evicted transaction
+    pub txid: Sha256dHash,
+    /// The fee it pays
+    pub fee: Amount,
+    /// Its weight
+    pub weight: u64,
+    /// Whether it spends an output also spent by the replacement, rather
+    /// than being evicted as a descendant of such a transaction
+    pub conflict: bool,
+    /// Whether it may be replaced, see `is_replaceable`. Only checked for
+    /// direct conflicts.
+    pub replaceable: bool,
+}
+
+/// A reason for a replacement to be rejected
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// A directly conflicting transaction does not signal replaceability
+    NotReplaceable(Sha256dHash),
+    /// The replacement would evict more transactions than allowed
+    TooManyReplacements(usize),
+    /// The replacement's fee rate does not exceed that of a directly
+    /// conflicting transaction
+    InsufficientFeeRate {
+        /// The conflicting transaction
+        txid: Sha256dHash,
+        /// The replacement's fee rate
+        fee_rate: FeeRate,
+        /// The conflicting transaction's fee rate
+        conflict_fee_rate: FeeRate,
+    },
+    /// The replacement pays less than the total fee of the evicted transactions
+    InsufficientFee {
+        /// The replacement's fee
+        fee: Amount,
+        /// The evicted transactions' total fee
+        evicted_fee: Amount,
+    },
+    /// The replacement's additional fee does not pay for its relay at the
+    /// incremental relay fee rate
+    InsufficientIncrementalFee {
+        /// The replacement's fee less that of the evicted transactions
+        additional_fee: Amount,
+        /// The fee required to relay the replacement
+        required: Amount,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotReplaceable(ref txid) => write!(f, "conflicting transaction {} is not replaceable", txid),
+            Error::TooManyReplacements(n) => write!(f, "replacement would evict {} transactions", n),
+            Error::InsufficientFeeRate { ref txid, fee_rate, conflict_fee_rate } =>
+                write!(f, "fee rate {} does not exceed {} of conflicting transaction {}", fee_rate, conflict_fee_rate, txid),
+            Error::InsufficientFee { fee, evicted_fee } =>
+                write!(f, "fee {} is less than the {} paid by evicted transactions", fee, evicted_fee),
+            Error::InsufficientIncrementalFee { additional_fee, required } =>
+                write!(f, "additional fee {} is less than the {} required to relay the replacement", additional_fee, required),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::NotReplaceable(_) => "conflicting transaction is not replaceable",
+            Error::TooManyReplacements(_) => "too many transactions evicted",
+            Error::InsufficientFeeRate { .. } => "fee rate does not exceed that of a conflicting transaction",
+            Error::InsufficientFee { .. } => "fee is less than that of evicted transactions",
+            Error::InsufficientIncrementalFee { .. } => "additional fee does not cover relay",
+        }
+    }
+}
+
+/// Checks whether `replacement`, paying `fee`, may replace the `evicted`
+/// transactions: its direct conflicts and all their descendants. The rules
+/// checked are that every direct conflict is replaceable, that no more than
+/// `policy.max_replacements` transactions are evicted, that the replacement
+/// has a higher fee rate than each direct conflict, and that it pays at
+/// least the evicted transactions' total fee plus its own relay fee at the
+/// incremental relay fee rate.
+pub fn check_replacement(replacement: &Transaction, fee: Amount, evicted: &[Evicted],
+                         policy: &Policy) -> Result<(), Error> {
+    for tx in evicted {
+        if tx.conflict && !tx.replaceable {
+            return Err(Error::NotReplaceable(tx.txid));
+        }
+    }
+
+    if evicted.len() > policy.max_replacements {
+        return Err(Error::TooManyReplacements(evicted.len()));
+    }
+
+    let weight = replacement.get_weight();
+    let fee_rate = FeeRate::from_fee_and_weight(fee, weight).unwrap_or(FeeRate::max_value());
+    for tx in evicted.iter().filter(|tx| tx.conflict) {
+        let conflict_fee_rate = FeeRate::from_fee_and_weight(tx.fee, tx.weight).unwrap_or(FeeRate::max_value());
+        if fee_rate <= conflict_fee_rate {
+            return Err(Error::InsufficientFeeRate {
+                txid: tx.txid,
+                fee_rate: fee_rate,
+                conflict_fee_rate: conflict_fee_rate,
+            });
+        }
+    }
+
+    let evicted_fee = evicted.iter().fold(Some(Amount::zero()), |sum, tx| sum.and_then(|s| s.checked_add(tx.fee)));
+    let evicted_fee = evicted_fee.unwrap_or(Amount::max_value());
+    let additional_fee = match fee.checked_sub(evicted_fee) {
+        Some(additional_fee) => additional_fee,
+        None => return Err(Error::InsufficientFee { fee: fee, evicted_fee: evicted_fee }),
+    };
+
+    let required = policy.incremental_relay_fee.fee_for_vsize(replacement.get_vsize()).unwrap_or(Amount::max_value());
+    if additional_fee < required {
+        return Err(Error::InsufficientIncrementalFee { additional_fee: additional_fee, required: required });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use blockdata::script::Script;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use util::amount::Amount;
+    use util::feerate::FeeRate;
+    use util::hash::Sha256dHash;
+
+    use super::*;
+
+    fn tx(prevouts: &[(Sha256dHash, u32)], sequence: u32, value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: prevouts.iter().map(|&(txid, vout)| TxIn {
+                previous_output: OutPoint { txid: txid, vout: vout },
+                script_sig: Script::new(),
+                sequence: sequence,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut { value: Amount::from_sat(value), script_pubkey: Script::from(vec![0x51]) }],
+        }
+    }
+
+    #[test]
+    fn signaling() {
+        let confirmed = Sha256dHash::from_data(b"confirmed");
+        let parent = tx(&[(confirmed, 0)], MAX_BIP125_RBF_SEQUENCE, 1000);
+        let child = tx(&[(parent.txid(), 0)], 0xffffffff, 900);
+        let grandchild = tx(&[(child.txid(), 0), (confirmed, 1)], 0xfffffffe, 800);
+        assert!(signals_rbf(&parent));
+        assert!(!signals_rbf(&child));
+        assert!(!signals_rbf(&grandchild));
+
+        let mut unconfirmed = HashMap::new();
+        assert!(is_replaceable(&parent, &unconfirmed));
+        // Once the parent is confirmed its descendants are no longer replaceable
+        assert!(!is_replaceable(&child, &unconfirmed));
+        assert!(!is_replaceable(&grandchild, &unconfirmed));
+
+        unconfirmed.insert(parent.txid(), parent.clone());
+        unconfirmed.insert(child.txid(), child.clone());
+        assert!(is_replaceable(&child, &unconfirmed));
+        assert!(is_replaceable(&grandchild, &unconfirmed));
+    }
+
+    #[test]
+    fn replacement() {
+        let confirmed = Sha256dHash::from_data(b"confirmed");
+        let original = tx(&[(confirmed, 0)], MAX_BIP125_RBF_SEQUENCE, 10_000);
+        let child = tx(&[(original.txid(), 0)], 0xffffffff, 9_000);
+        let replacement = tx(&[(confirmed, 0)], 0xffffffff, 8_000);
+        let vsize = replacement.get_vsize();
+
+        let evicted = vec![
+            Evicted { txid: original.txid(), fee: Amount::from_sat(1_000), weight: original.get_weight(),
+                      conflict: true, replaceable: true },
+            Evicted { txid: child.txid(), fee: Amount::from_sat(1_000), weight: child.get_weight(),
+                      conflict: false, replaceable: true },
+        ];
+        let policy = Policy::default();
+        assert_eq!(policy.incremental_relay_fee, FeeRate::from_sat_per_vb(1).unwrap());
+
+        // Must pay for both evicted transactions and its own relay
+        let fee = Amount::from_sat(2_000 + vsize);
+        assert_eq!(check_replacement(&replacement, fee, &evicted, &policy), Ok(()));
+        assert_eq!(check_replacement(&replacement, fee - Amount::one_sat(), &evicted, &policy),
+                   Err(Error::InsufficientIncrementalFee {
+                       additional_fee: Amount::from_sat(vsize - 1),
+                       required: Amount::from_sat(vsize),
+                   }));
+        assert_eq!(check_replacement(&replacement, Amount::from_sat(1_999), &evicted, &policy),
+                   Err(Error::InsufficientFee { fee: Amount::from_sat(1_999), evicted_fee: Amount::from_sat(2_000) }));
+
+        // A fee rate no higher than the direct conflict's is rejected even
+        // if the absolute fee is sufficient
+        let mut expensive = evicted.clone();
+        expensive[0].fee = Amount::from_sat(5_000);
+        expensive[0].weight = original.get_weight() / 4;
+        match check_replacement(&replacement, Amount::from_sat(5_000 + 1_000 + vsize), &expensive, &policy) {
+            Err(Error::InsufficientFeeRate { txid, .. }) => assert_eq!(txid, original.txid()),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut final_original = evicted.clone();
+        final_original[0].replaceable = false;
+        assert_eq!(check_replacement(&replacement, fee, &final_original, &policy),
+                   Err(Error::NotReplaceable(original.txid())));
+        // Descendants need not signal themselves
+        let mut final_child = evicted.clone();
+        final_child[1].replaceable = false;
+        assert_eq!(check_replacement(&replacement, fee, &final_child, &policy), Ok(()));
+
+        let strict = Policy { max_replacements: 1, ..Policy::default() };
+        assert_eq!(check_replacement(&replacement, fee, &evicted, &strict), Err(Error::TooManyReplacements(2)));
+    }
+}
@@ -0,0 +1,311 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Input signing
+//!
+//! Signs transaction inputs spending standard single-key outputs (P2PKH,
+//! P2PK, P2WPKH and P2SH-wrapped P2WPKH) and fills in their scriptSig and
+//! witness.
+//!
+
+use std::{error, fmt};
+
+use secp256k1::{self, Message, Secp256k1};
+
+use blockdata::opcodes;
+use blockdata::script::{Builder, Script};
+use blockdata::transaction::{SigHashType, Transaction, TxOut};
+use util::address::Address;
+use util::bip143::SigHashCache;
+use util::hash::Sha256dHash;
+use util::privkey::Privkey;
+
+/// An error signing an input
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Error {
+    /// The input index is out of range for the transaction
+    InputIndexOutOfRange(usize),
+    /// The spent output is not of a supported type
+    UnsupportedScript,
+    /// The spent output is not locked to the given key. For P2SH outputs
+    /// this also means the output is not P2SH-wrapped P2WPKH.
+    KeyMismatch,
+    /// Segwit outputs can only be spent with compressed keys
+    UncompressedKey,
+    /// A `SIGHASH_SINGLE` signature was requested for an input with no
+    /// output at the same index. Legacy signatures would then commit to the
+    /// constant hash 1 and be valid for any transaction.
+    SighashSingleWithoutOutput(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InputIndexOutOfRange(idx) => write!(f, "input index {} out of range", idx),
+            Error::SighashSingleWithoutOutput(idx) => write!(f, "SIGHASH_SINGLE for input {} without corresponding output", idx),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::InputIndexOutOfRange(_) => "input index out of range",
+            Error::UnsupportedScript => "unsupported script type",
+            Error::KeyMismatch => "spent output does not match key",
+            Error::UncompressedKey => "uncompressed key used for segwit output",
+            Error::SighashSingleWithoutOutput(_) => "SIGHASH_SINGLE without corresponding output",
+        }
+    }
+}
+
+/// Signs `hash` with `key`, producing a low-S DER signature with the
+/// sighash type appended
+fn sign_hash<C: secp256k1::Signing>(secp: &Secp256k1<C>, hash: Sha256dHash, key: &Privkey,
+                                    sighash_type: SigHashType) -> Vec<u8> {
+    let msg = Message::from_slice(&hash[..]).expect("32-byte hash");
+    let mut sig = secp.sign(&msg, &key.key);
+    sig.normalize_s(secp);
+    let mut ret = sig.serialize_der(secp);
+    ret.push(sighash_type.as_u32() as u8);
+    ret
+}
+
+/// Whether `script` has the form of a pay-to-pubkey output, with either a
+/// compressed or an uncompressed key
+fn is_any_p2pk(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    let checksig = opcodes::All::OP_CHECKSIG as u8;
+    (bytes.len() == 35 && bytes[0] == opcodes::All::OP_PUSHBYTES_33 as u8 && bytes[34] == checksig) ||
+        (bytes.len() == 67 && bytes[0] == opcodes::All::OP_PUSHBYTES_65 as u8 && bytes[66] == checksig)
+}
+
+/// Signs input `input_index` of `tx`, which spends `spent`, with `key`,
+/// committing to `sighash_type`. The spent output must be P2PKH, P2PK, P2WPKH
+/// or P2SH-wrapped P2WPKH locked to `key`, and the input's scriptSig and
+/// witness are replaced with ones satisfying it.
+///
+/// Signatures commit to the transaction's outputs and other inputs as
+/// selected by the sighash type, so those must be final before signing.
+/// `SIGHASH_SINGLE` signatures require an output at the input's index.
+pub fn sign_input<C: secp256k1::Signing>(secp: &Secp256k1<C>, tx: &mut Transaction, input_index: usize,
+                                         spent: &TxOut, key: &Privkey, sighash_type: SigHashType)
+                                         -> Result<(), Error> {
+    if input_index >= tx.input.len() {
+        return Err(Error::InputIndexOutOfRange(input_index));
+    }
+    match sighash_type {
+        SigHashType::Single | SigHashType::SinglePlusAnyoneCanPay if input_index >= tx.output.len() => {
+            return Err(Error::SighashSingleWithoutOutput(input_index));
+        }
+        _ => {}
+    }
+
+    let pk = key.public_key(secp);
+    let pk_bytes = if key.compressed {
+        pk.serialize().to_vec()
+    } else {
+        pk.serialize_uncompressed().to_vec()
+    };
+    let script_pubkey = &spent.script_pubkey;
+    let sighash_u32 = sighash_type.as_u32();
+
+    let (script_sig, witness) = if script_pubkey.is_p2pkh() {
+        if *script_pubkey != key.to_legacy_address(secp).script_pubkey() {
+            return Err(Error::KeyMismatch);
+        }
+        let hash = tx.signature_hash(input_index, script_pubkey, sighash_u32);
+        let sig = sign_hash(secp, hash, key, sighash_type);
+        (Builder::new().push_slice(&sig).push_slice(&pk_bytes).into_script(), vec![])
+    } else if script_pubkey.is_v0_p2wpkh() || script_pubkey.is_p2sh() {
+        if !key.compressed {
+            return Err(Error::UncompressedKey);
+        }
+        let witness_program = Address::p2wpkh(&pk, key.network).script_pubkey();
+        let script_sig = if script_pubkey.is_p2sh() {
+            if *script_pubkey != witness_program.to_p2sh() {
+                return Err(Error::KeyMismatch);
+            }
+            Builder::new().push_slice(witness_program.as_bytes()).into_script()
+        } else {
+            if *script_pubkey != witness_program {
+                return Err(Error::KeyMismatch);
+            }
+            Script::new()
+        };
+        let script_code = Address::p2pkh(&pk, key.network).script_pubkey();
+        let hash = SigHashCache::new(tx).signature_hash(input_index, &script_code, spent.value, sighash_u32);
+        let sig = sign_hash(secp, hash, key, sighash_type);
+        (script_sig, vec![sig, pk_bytes])
+    } else if is_any_p2pk(script_pubkey) {
+        let expected = Builder::new().push_slice(&pk_bytes)
+                                     .push_opcode(opcodes::All::OP_CHECKSIG)
+                                     .into_script();
+        if *script_pubkey != expected {
+            return Err(Error::KeyMismatch);
+        }
+        let hash = tx.signature_hash(input_index, script_pubkey, sighash_u32);
+        let sig = sign_hash(secp, hash, key, sighash_type);
+        (Builder::new().push_slice(&sig).into_script(), vec![])
+    } else {
+        return Err(Error::UnsupportedScript);
+    };
+
+    tx.input[input_index].script_sig = script_sig;
+    tx.input[input_index].witness = witness;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::Secp256k1;
+    use secp256k1::key::SecretKey;
+
+    use blockdata::opcodes;
+    use blockdata::script::{self, Builder, Script};
+    use blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut};
+    use network::constants::Network;
+    use util::address::Address;
+    use util::amount::Amount;
+    use util::hash::Sha256dHash;
+    use util::privkey::Privkey;
+
+    use super::*;
+
+    const FLAGS: u32 = script::VERIFY_CONSENSUS | script::VERIFY_STRICTENC | script::VERIFY_LOW_S |
+        script::VERIFY_WITNESS_PUBKEYTYPE | script::VERIFY_CLEANSTACK | script::VERIFY_NULLFAIL;
+
+    fn key(byte: u8, compressed: bool) -> Privkey {
+        let secp = Secp256k1::without_caps();
+        let sk = SecretKey::from_slice(&secp, &[byte; 32]).unwrap();
+        Privkey::from_secret_key(sk, compressed, Network::Bitcoin)
+    }
+
+    fn spending_tx(n_inputs: usize) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..n_inputs).map(|i| TxIn {
+                previous_output: OutPoint { txid: Sha256dHash::from_data(&[i as u8]), vout: i as u32 },
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }).collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: hex_script!("76a914000000000000000000000000000000000000000088ac"),
+            }],
+        }
+    }
+
+    #[test]
+    fn sign_all_types() {
+        let secp = Secp256k1::new();
+        let compressed = key(1, true);
+        let uncompressed = key(2, false);
+        let pk = compressed.public_key(&secp);
+        let upk = uncompressed.public_key(&secp);
+
+        let p2pk = |bytes: &[u8]| Builder::new().push_slice(bytes)
+                                               .push_opcode(opcodes::All::OP_CHECKSIG)
+                                               .into_script();
+        let spent = vec![
+            (compressed.to_legacy_address(&secp).script_pubkey(), &compressed),
+            (uncompressed.to_legacy_address(&secp).script_pubkey(), &uncompressed),
+            (p2pk(&pk.serialize()), &compressed),
+            (p2pk(&upk.serialize_uncompressed()), &uncompressed),
+            (Address::p2wpkh(&pk, Network::Bitcoin).script_pubkey(), &compressed),
+            (Address::p2shwpkh(&pk, Network::Bitcoin).script_pubkey(), &compressed),
+        ];
+        let spent: Vec<(TxOut, &Privkey)> = spent.into_iter().enumerate().map(|(i, (script_pubkey, key))| {
+            (TxOut { value: Amount::from_sat(20_000 + i as u64), script_pubkey: script_pubkey }, key)
+        }).collect();
+
+        for &sighash_type in [SigHashType::All, SigHashType::None, SigHashType::Single,
+                              SigHashType::AllPlusAnyoneCanPay, SigHashType::NonePlusAnyoneCanPay,
+                              SigHashType::SinglePlusAnyoneCanPay].iter() {
+            let single = sighash_type == SigHashType::Single || sighash_type == SigHashType::SinglePlusAnyoneCanPay;
+            let mut tx = spending_tx(spent.len());
+            if single {
+                // Only the first input has an output at its index, so the
+                // others are signed with `All` to get a verifiable transaction
+                for (idx, &(ref txout, key)) in spent.iter().enumerate().skip(1) {
+                    let unsigned = tx.clone();
+                    assert_eq!(sign_input(&secp, &mut tx, idx, txout, key, sighash_type),
+                               Err(Error::SighashSingleWithoutOutput(idx)));
+                    assert_eq!(tx, unsigned);
+                }
+            }
+            for (idx, &(ref txout, key)) in spent.iter().enumerate() {
+                let input_type = if single && idx > 0 { SigHashType::All } else { sighash_type };
+                sign_input(&secp, &mut tx, idx, txout, key, input_type).unwrap();
+            }
+            for (idx, &(ref txout, _)) in spent.iter().enumerate() {
+                assert_eq!(txout.script_pubkey.verify_with_flags(idx, txout.value, &tx, FLAGS), Ok(()));
+                let sig = if tx.input[idx].witness.is_empty() {
+                    match tx.input[idx].script_sig.iter(true).next() {
+                        Some(script::Instruction::PushBytes(sig)) => sig.to_vec(),
+                        _ => panic!("no signature in scriptSig"),
+                    }
+                } else {
+                    tx.input[idx].witness[0].clone()
+                };
+                let input_type = if single && idx > 0 { SigHashType::All } else { sighash_type };
+                assert_eq!(*sig.last().unwrap() as u32, input_type.as_u32());
+            }
+            assert!(tx.input[4].script_sig.is_empty());
+            assert_eq!(tx.input[5].script_sig.len(), 23);
+
+            // A signature for the wrong amount fails
+            let (ref txout, _) = spent[4];
+            assert_eq!(txout.script_pubkey.verify_with_flags(4, txout.value + Amount::one_sat(), &tx, script::VERIFY_CONSENSUS),
+                       Err(script::Error::EvalFalse));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let secp = Secp256k1::new();
+        let compressed = key(1, true);
+        let uncompressed = key(2, false);
+        let pk = compressed.public_key(&secp);
+        let mut tx = spending_tx(1);
+        let txout = |script_pubkey: Script| TxOut { value: Amount::from_sat(1000), script_pubkey: script_pubkey };
+        let all = SigHashType::All;
+
+        let p2wpkh = txout(Address::p2wpkh(&pk, Network::Bitcoin).script_pubkey());
+        assert_eq!(sign_input(&secp, &mut tx, 1, &p2wpkh, &compressed, all), Err(Error::InputIndexOutOfRange(1)));
+        assert_eq!(sign_input(&secp, &mut tx, 0, &p2wpkh, &key(3, true), all), Err(Error::KeyMismatch));
+        assert_eq!(sign_input(&secp, &mut tx, 0, &p2wpkh, &uncompressed, all), Err(Error::UncompressedKey));
+
+        // Compressed key used for an uncompressed P2PKH output
+        let p2upkh = txout(Address::p2upkh(&pk, Network::Bitcoin).script_pubkey());
+        assert_eq!(sign_input(&secp, &mut tx, 0, &p2upkh, &compressed, all), Err(Error::KeyMismatch));
+
+        // P2SH which is not P2SH-P2WPKH
+        let p2sh = txout(Address::p2pkh(&pk, Network::Bitcoin).script_pubkey().to_p2sh());
+        assert_eq!(sign_input(&secp, &mut tx, 0, &p2sh, &compressed, all), Err(Error::KeyMismatch));
+
+        let p2wsh = txout(Address::p2pkh(&pk, Network::Bitcoin).script_pubkey().to_v0_p2wsh());
+        assert_eq!(sign_input(&secp, &mut tx, 0, &p2wsh, &compressed, all), Err(Error::UnsupportedScript));
+
+        // Nothing was changed by the failures
+        assert_eq!(tx, spending_tx(1));
+    }
+}
@@ -0,0 +1,527 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Transaction builder
+//!
+//! Constructs unsigned transactions from a set of spendable outputs and a
+//! list of recipients, paying a fee computed from the predicted weight of
+//! the signed transaction and returning any excess to a change address.
+//!
+
+use std::{error, fmt};
+
+use blockdata::constants::MAX_SEQUENCE;
+use blockdata::script::Script;
+use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use consensus::encode::VarInt;
+use util::address::Address;
+use util::amount::Amount;
+use util::feerate::FeeRate;
+use util::policy;
+
+/// Size of a DER signature plus sighash byte, assuming a low-S signature
+/// with a high R value, which is the largest that secp256k1 will produce
+const MAX_SIG_LEN: usize = 72;
+/// Size of a compressed public key
+const COMPRESSED_PUBKEY_LEN: usize = 33;
+/// Size of an uncompressed public key
+const UNCOMPRESSED_PUBKEY_LEN: usize = 65;
+
+/// How a spendable output will be satisfied, which determines the size of
+/// its scriptSig and witness once signed
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum SpendType {
+    /// Pay-to-pubkey-hash, with a compressed or uncompressed key
+    P2pkh {
+        /// Whether the key hashed by the output is compressed
+        compressed: bool,
+    },
+    /// Pay-to-pubkey
+    P2pk,
+    /// Native segwit v0 pay-to-witness-pubkey-hash
+    P2wpkh,
+    /// Pay-to-witness-pubkey-hash nested in P2SH
+    P2shP2wpkh,
+}
+
+impl SpendType {
+    /// The weight of the scriptSig and witness satisfying an output of this
+    /// type, including their length prefixes. An input's total weight is
+    /// this plus 160 for the outpoint and sequence number.
+    pub fn satisfaction_weight(&self) -> u64 {
+        let (script_sig, witness) = self.dummy_satisfaction();
+        let mut weight = 4 * (VarInt(script_sig.len() as u64).encoded_length() + script_sig.len() as u64);
+        if !witness.is_empty() {
+            weight += VarInt(witness.len() as u64).encoded_length();
+            for elem in &witness {
+                weight += VarInt(elem.len() as u64).encoded_length() + elem.len() as u64;
+            }
+        }
+        weight
+    }
+
+    /// A scriptSig and witness of the same size as this spend type's
+    /// satisfaction, for weight prediction
+    fn dummy_satisfaction(&self) -> (Script, Vec<Vec<u8>>) {
+        let push = |len: usize| {
+            let mut ret = vec![len as u8];
+            ret.extend(vec![0; len]);
+            ret
+        };
+        match *self {
+            SpendType::P2pkh { compressed } => {
+                let pk_len = if compressed { COMPRESSED_PUBKEY_LEN } else { UNCOMPRESSED_PUBKEY_LEN };
+                let mut script_sig = push(MAX_SIG_LEN);
+                script_sig.extend(push(pk_len));
+                (Script::from(script_sig), vec![])
+            }
+            SpendType::P2pk => (Script::from(push(MAX_SIG_LEN)), vec![]),
+            SpendType::P2wpkh => {
+                (Script::new(), vec![vec![0; MAX_SIG_LEN], vec![0; COMPRESSED_PUBKEY_LEN]])
+            }
+            SpendType::P2shP2wpkh => {
+                // A push of the 22-byte v0 witness program
+                (Script::from(push(22)), vec![vec![0; MAX_SIG_LEN], vec![0; COMPRESSED_PUBKEY_LEN]])
+            }
+        }
+    }
+}
+
+/// An output which the builder may spend
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct SpendableOutput {
+    /// The output being spent
+    pub outpoint: OutPoint,
+    /// The value and scriptPubKey of the output, as needed for signing
+    pub txout: TxOut,
+    /// How the output will be satisfied
+    pub spend_type: SpendType,
+}
+
+/// An unsigned transaction produced by `TxBuilder`, along with the data
+/// needed to sign it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UnsignedTransaction {
+    /// The transaction, with empty scriptSigs and witnesses
+    pub transaction: Transaction,
+    /// The outputs spent by the transaction, in input order
+    pub inputs: Vec<SpendableOutput>,
+    /// The fee paid by the transaction
+    pub fee: Amount,
+    /// The index of the change output, if one was added
+    pub change_index: Option<usize>,
+    /// The predicted weight of the transaction once fully signed
+    pub weight: u64,
+}
+
+/// An error building a transaction
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// No spendable outputs were given
+    NoInputs,
+    /// No recipients were given
+    NoRecipients,
+    /// The recipient output with the given index is below the dust threshold
+    DustOutput(usize),
+    /// The inputs do not cover the recipients and fee
+    InsufficientFunds {
+        /// The amount needed for recipients and fee
+        needed: Amount,
+        /// The total value of the inputs
+        available: Amount,
+    },
+    /// The excess over the recipients and fee is enough for a change
+    /// output, but no change address was given
+    NoChangeAddress(Amount),
+    /// Summing input or output values overflowed
+    Overflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DustOutput(idx) => write!(f, "output {} is below the dust threshold", idx),
+            Error::InsufficientFunds { needed, available } =>
+                write!(f, "insufficient funds: needed {}, available {}", needed, available),
+            Error::NoChangeAddress(excess) => write!(f, "no change address given for change of {}", excess),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::NoInputs => "no inputs to spend",
+            Error::NoRecipients => "no recipients",
+            Error::DustOutput(_) => "output is below the dust threshold",
+            Error::InsufficientFunds { .. } => "insufficient funds",
+            Error::NoChangeAddress(_) => "no change address given",
+            Error::Overflow => "amount overflow",
+        }
+    }
+}
+
+/// The value below which an output with the given scriptPubKey is dust at
+/// Bitcoin Core's default dust relay fee
+fn dust_threshold(script_pubkey: &Script) -> Amount {
+    policy::dust_threshold(script_pubkey, FeeRate::from_sat_per_kvb_ceil(policy::DUST_RELAY_TX_FEE))
+}
+
+/// A builder for unsigned transactions
+///
+/// Inputs are spent in the order given and recipients are paid in the order
+/// given; a change output, if needed, is appended after the recipients.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TxBuilder {
+    inputs: Vec<SpendableOutput>,
+    recipients: Vec<TxOut>,
+    change_script: Option<Script>,
+    fee_rate: FeeRate,
+    version: u32,
+    lock_time: u32,
+    sequence: u32,
+}
+
+impl TxBuilder {
+    /// Creates a new builder for a version 2 transaction with no lock time
+    /// and a zero fee rate
+    pub fn new() -> TxBuilder {
+        TxBuilder {
+            inputs: vec![],
+            recipients: vec![],
+            change_script: None,
+            fee_rate: FeeRate::zero(),
+            version: 2,
+            lock_time: 0,
+            sequence: MAX_SEQUENCE,
+        }
+    }
+
+    /// Adds an output to be spent
+    pub fn add_input(mut self, input: SpendableOutput) -> TxBuilder {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Adds an output paying `amount` to `address`
+    pub fn add_recipient(mut self, address: &Address, amount: Amount) -> TxBuilder {
+        self.recipients.push(TxOut {
+            value: amount,
+            script_pubkey: address.script_pubkey(),
+        });
+        self
+    }
+
+    /// Sets the address any change is sent to
+    pub fn change_address(mut self, address: &Address) -> TxBuilder {
+        self.change_script = Some(address.script_pubkey());
+        self
+    }
+
+    /// Sets the fee rate
+    pub fn fee_rate(mut self, fee_rate: FeeRate) -> TxBuilder {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Sets the transaction version
+    pub fn version(mut self, version: u32) -> TxBuilder {
+        self.version = version;
+        self
+    }
+
+    /// Sets the transaction lock time
+    pub fn lock_time(mut self, lock_time: u32) -> TxBuilder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// Sets the sequence number used for every input
+    pub fn sequence(mut self, sequence: u32) -> TxBuilder {
+        self.sequence = sequence;
+        self
+    }
+
+    /// The fee for a transaction of the given weight at the builder's fee rate
+    fn fee_for_weight(&self, weight: u64) -> Result<Amount, Error> {
+        self.fee_rate.fee_for_weight(weight).ok_or(Error::Overflow)
+    }
+
+    /// Builds the unsigned transaction
+    pub fn finish(self) -> Result<UnsignedTransaction, Error> {
+        if self.inputs.is_empty() {
+            return Err(Error::NoInputs);
+        }
+        if self.recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+        for (idx, output) in self.recipients.iter().enumerate() {
+            if output.value < dust_threshold(&output.script_pubkey) {
+                return Err(Error::DustOutput(idx));
+            }
+        }
+
+        let mut available = Amount::zero();
+        for input in &self.inputs {
+            available = available.checked_add(input.txout.value).ok_or(Error::Overflow)?;
+        }
+        let mut spent = Amount::zero();
+        for output in &self.recipients {
+            spent = spent.checked_add(output.value).ok_or(Error::Overflow)?;
+        }
+
+        // A stand-in for the signed transaction, whose weight we use to
+        // compute the fee
+        let mut dummy = Transaction {
+            version: self.version,
+            lock_time: self.lock_time,
+            input: self.inputs.iter().map(|input| {
+                let (script_sig, witness) = input.spend_type.dummy_satisfaction();
+                TxIn {
+                    previous_output: input.outpoint,
+                    script_sig: script_sig,
+                    sequence: self.sequence,
+                    witness: witness,
+                }
+            }).collect(),
+            output: self.recipients.clone(),
+        };
+
+        let weight = dummy.get_weight();
+        let fee = self.fee_for_weight(weight)?;
+        let needed = spent.checked_add(fee).ok_or(Error::Overflow)?;
+        if available < needed {
+            return Err(Error::InsufficientFunds { needed: needed, available: available });
+        }
+
+        // See whether the excess is worth a change output, once the change
+        // output has paid for its own weight
+        let change_script = match self.change_script {
+            Some(ref script) => script.clone(),
+            // A P2WPKH output, the smallest standard one, for the purpose of
+            // deciding whether a change address should have been given
+            None => {
+                let mut script = vec![0x00, 0x14];
+                script.extend(vec![0; 20]);
+                Script::from(script)
+            }
+        };
+        dummy.output.push(TxOut { value: Amount::zero(), script_pubkey: change_script });
+        let change_weight = dummy.get_weight();
+        let change_fee = self.fee_for_weight(change_weight)?;
+        let change = (available - spent).checked_sub(change_fee);
+
+        let (weight, fee, change_index) = match change {
+            Some(change) if change >= dust_threshold(&dummy.output.last().unwrap().script_pubkey) => {
+                if self.change_script.is_none() {
+                    return Err(Error::NoChangeAddress(change));
+                }
+                dummy.output.last_mut().unwrap().value = change;
+                (change_weight, change_fee, Some(dummy.output.len() - 1))
+            }
+            _ => {
+                dummy.output.pop();
+                (weight, available - spent, None)
+            }
+        };
+
+        for input in &mut dummy.input {
+            input.script_sig = Script::new();
+            input.witness = vec![];
+        }
+
+        Ok(UnsignedTransaction {
+            transaction: dummy,
+            inputs: self.inputs,
+            fee: fee,
+            change_index: change_index,
+            weight: weight,
+        })
+    }
+}
+
+impl Default for TxBuilder {
+    fn default() -> TxBuilder {
+        TxBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{Message, Secp256k1};
+    use secp256k1::key::{PublicKey, SecretKey};
+
+    use blockdata::script::Builder;
+    use blockdata::transaction::{OutPoint, SigHashType, TxOut};
+    use network::constants::Network;
+    use util::address::Address;
+    use util::amount::Amount;
+    use util::feerate::FeeRate;
+    use util::bip143::SigHashCache;
+    use util::hash::Sha256dHash;
+
+    use super::*;
+
+    fn keypair(byte: u8) -> (SecretKey, PublicKey) {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&secp, &[byte; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&secp, &sk);
+        (sk, pk)
+    }
+
+    fn spendable(vout: u32, value: u64, address: &Address, spend_type: SpendType) -> SpendableOutput {
+        SpendableOutput {
+            outpoint: OutPoint { txid: Sha256dHash::from_data(&[vout as u8]), vout: vout },
+            txout: TxOut { value: Amount::from_sat(value), script_pubkey: address.script_pubkey() },
+            spend_type: spend_type,
+        }
+    }
+
+    #[test]
+    fn satisfaction_weight() {
+        assert_eq!(SpendType::P2pkh { compressed: true }.satisfaction_weight(), 4 * 108);
+        assert_eq!(SpendType::P2pkh { compressed: false }.satisfaction_weight(), 4 * 140);
+        assert_eq!(SpendType::P2pk.satisfaction_weight(), 4 * 74);
+        assert_eq!(SpendType::P2wpkh.satisfaction_weight(), 4 + 108);
+        assert_eq!(SpendType::P2shP2wpkh.satisfaction_weight(), 4 * 24 + 108);
+    }
+
+    #[test]
+    fn dust() {
+        let (_, pk) = keypair(1);
+        assert_eq!(dust_threshold(&Address::p2pkh(&pk, Network::Bitcoin).script_pubkey()), Amount::from_sat(546));
+        assert_eq!(dust_threshold(&Address::p2wpkh(&pk, Network::Bitcoin).script_pubkey()), Amount::from_sat(294));
+        assert_eq!(dust_threshold(&Builder::new().push_opcode(::blockdata::opcodes::All::OP_RETURN).into_script()),
+                   Amount::zero());
+    }
+
+    #[test]
+    fn errors() {
+        let (_, pk) = keypair(1);
+        let addr = Address::p2wpkh(&pk, Network::Bitcoin);
+        let input = spendable(0, 100_000, &addr, SpendType::P2wpkh);
+
+        assert_eq!(TxBuilder::new().add_recipient(&addr, Amount::from_sat(1000)).finish(), Err(Error::NoInputs));
+        assert_eq!(TxBuilder::new().add_input(input.clone()).finish(), Err(Error::NoRecipients));
+        assert_eq!(TxBuilder::new().add_input(input.clone())
+                                   .add_recipient(&addr, Amount::from_sat(1000))
+                                   .add_recipient(&addr, Amount::from_sat(293))
+                                   .finish(),
+                   Err(Error::DustOutput(1)));
+        match TxBuilder::new().add_input(input.clone())
+                              .add_recipient(&addr, Amount::from_sat(100_000))
+                              .fee_rate(FeeRate::from_sat_per_kwu(250))
+                              .finish() {
+            Err(Error::InsufficientFunds { needed, available }) => {
+                assert!(needed > Amount::from_sat(100_000));
+                assert_eq!(available, Amount::from_sat(100_000));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match TxBuilder::new().add_input(input.clone())
+                              .add_recipient(&addr, Amount::from_sat(50_000))
+                              .fee_rate(FeeRate::from_sat_per_kwu(250))
+                              .finish() {
+            Err(Error::NoChangeAddress(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn change_and_fee() {
+        let (_, pk) = keypair(1);
+        let addr = Address::p2wpkh(&pk, Network::Bitcoin);
+        let change = Address::p2pkh(&pk, Network::Bitcoin);
+
+        // Change above dust is returned, and the fee pays for the change output
+        let unsigned = TxBuilder::new().add_input(spendable(0, 100_000, &addr, SpendType::P2wpkh))
+                                       .add_input(spendable(1, 20_000, &change, SpendType::P2pkh { compressed: true }))
+                                       .add_recipient(&addr, Amount::from_sat(50_000))
+                                       .change_address(&change)
+                                       .fee_rate(FeeRate::from_sat_per_kwu(250))
+                                       .finish()
+                                       .unwrap();
+        let tx = &unsigned.transaction;
+        assert_eq!(unsigned.change_index, Some(1));
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].script_pubkey, change.script_pubkey());
+        assert_eq!(unsigned.fee, Amount::from_sat((unsigned.weight * 250 + 999) / 1000));
+        assert_eq!(tx.output[0].value + tx.output[1].value + unsigned.fee, Amount::from_sat(120_000));
+        assert_eq!(tx.input[1].previous_output, unsigned.inputs[1].outpoint);
+        assert!(tx.input.iter().all(|i| i.script_sig.is_empty() && i.witness.is_empty()));
+
+        // Change which would be dust once paying for itself goes to the fee
+        let fee_without_change = TxBuilder::new().add_input(spendable(0, 100_000, &addr, SpendType::P2wpkh))
+                                                 .add_recipient(&addr, Amount::from_sat(90_000))
+                                                 .change_address(&change)
+                                                 .fee_rate(FeeRate::from_sat_per_kwu(1000))
+                                                 .finish()
+                                                 .unwrap()
+                                                 .fee;
+        let unsigned = TxBuilder::new().add_input(spendable(0, 100_000, &addr, SpendType::P2wpkh))
+                                       .add_recipient(&addr, Amount::from_sat(100_000) - fee_without_change - Amount::from_sat(500))
+                                       .fee_rate(FeeRate::from_sat_per_kwu(1000))
+                                       .change_address(&change)
+                                       .finish()
+                                       .unwrap();
+        assert_eq!(unsigned.change_index, None);
+        assert_eq!(unsigned.transaction.output.len(), 1);
+        assert_eq!(unsigned.fee, fee_without_change + Amount::from_sat(500));
+    }
+
+    #[test]
+    fn weight_prediction() {
+        let secp = Secp256k1::new();
+        let (sk, pk) = keypair(2);
+        let p2wpkh = Address::p2wpkh(&pk, Network::Bitcoin);
+        let p2pkh = Address::p2pkh(&pk, Network::Bitcoin);
+        let p2pk = Address::p2pk(&pk, Network::Bitcoin);
+
+        let unsigned = TxBuilder::new().add_input(spendable(0, 100_000, &p2wpkh, SpendType::P2wpkh))
+                                       .add_input(spendable(1, 100_000, &p2pkh, SpendType::P2pkh { compressed: true }))
+                                       .add_input(spendable(2, 100_000, &p2pk, SpendType::P2pk))
+                                       .add_recipient(&p2pkh, Amount::from_sat(150_000))
+                                       .change_address(&p2wpkh)
+                                       .fee_rate(FeeRate::from_sat_per_kwu(1000))
+                                       .finish()
+                                       .unwrap();
+        let mut tx = unsigned.transaction.clone();
+        let sign = |hash: Sha256dHash| {
+            let msg = Message::from_slice(&hash[..]).unwrap();
+            let mut sig = secp.sign(&msg, &sk).serialize_der(&secp);
+            sig.push(SigHashType::All.as_u32() as u8);
+            sig
+        };
+
+        let script_code = p2pkh.script_pubkey();
+        let sig = sign(SigHashCache::new(&unsigned.transaction).signature_hash(0, &script_code, Amount::from_sat(100_000),
+                                                                                SigHashType::All.as_u32()));
+        tx.input[0].witness = vec![sig, pk.serialize().to_vec()];
+        let sig = sign(unsigned.transaction.signature_hash(1, &p2pkh.script_pubkey(), SigHashType::All.as_u32()));
+        tx.input[1].script_sig = Builder::new().push_slice(&sig).push_slice(&pk.serialize()).into_script();
+        let sig = sign(unsigned.transaction.signature_hash(2, &p2pk.script_pubkey(), SigHashType::All.as_u32()));
+        tx.input[2].script_sig = Builder::new().push_slice(&sig).into_script();
+
+        // Signatures are usually exactly as long as predicted, and at most a
+        // byte shorter
+        let weight = tx.get_weight();
+        assert!(weight <= unsigned.weight);
+        assert!(weight + 4 + 4 + 1 >= unsigned.weight);
+    }
+}
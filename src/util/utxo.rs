@@ -0,0 +1,358 @@
+// Rust Bitcoin Library
+// Written by
+//   The Rust Bitcoin developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! UTXO sets
+//!
+//! A trait for stores of unspent transaction outputs, an in-memory
+//! implementation, and functions which connect blocks to a store, returning
+//! the undo data needed to disconnect them again during a reorganization.
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map;
+use std::{error, fmt};
+
+use blockdata::block::Block;
+use blockdata::transaction::{OutPoint, TxOut};
+
+/// An unspent transaction output, with the context needed to validate
+/// spending it
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Coin {
+    /// The output
+    pub output: TxOut,
+    /// The height of the block containing the output's transaction
+    pub height: u32,
+    /// Whether the output's transaction is a coinbase, which cannot be
+    /// spent until it matures
+    pub is_coinbase: bool,
+}
+
+impl_consensus_encoding!(Coin, output, height, is_coinbase);
+
+/// A set of changes to a UTXO set, to be applied together
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct UtxoBatch {
+    /// The outputs to remove, which are removed before any are added
+    pub spend: Vec<OutPoint>,
+    /// The outputs to add
+    pub insert: Vec<(OutPoint, Coin)>,
+}
+
+/// A store of unspent transaction outputs
+pub trait UtxoSet {
+    /// Looks up an unspent output
+    fn get(&self, outpoint: &OutPoint) -> Option<Coin>;
+
+    /// Adds an unspent output, replacing any with the same outpoint
+    fn insert(&mut self, outpoint: OutPoint, coin: Coin);
+
+    /// Removes an unspent output, returning it if it was present
+    fn spend(&mut self, outpoint: &OutPoint) -> Option<Coin>;
+
+    /// Applies a batch of changes. Stores which can write atomically should
+    /// override this so that a batch is never partially applied.
+    fn commit(&mut self, batch: UtxoBatch) {
+        for outpoint in &batch.spend {
+            self.spend(outpoint);
+        }
+        for (outpoint, coin) in batch.insert {
+            self.insert(outpoint, coin);
+        }
+    }
+}
+
+/// A UTXO set held in memory
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct MemoryUtxoSet {
+    coins: HashMap<OutPoint, Coin>,
+}
+
+impl MemoryUtxoSet {
+    /// Creates an empty UTXO set
+    pub fn new() -> MemoryUtxoSet {
+        MemoryUtxoSet { coins: HashMap::new() }
+    }
+
+    /// The number of unspent outputs
+    pub fn len(&self) -> usize {
+        self.coins.len()
+    }
+
+    /// Whether there are no unspent outputs
+    pub fn is_empty(&self) -> bool {
+        self.coins.is_empty()
+    }
+
+    /// Iterates over the unspent outputs, in no particular order
+    pub fn iter(&self) -> hash_map::Iter<OutPoint, Coin> {
+        self.coins.iter()
+    }
+}
+
+impl UtxoSet for MemoryUtxoSet {
+    fn get(&self, outpoint: &OutPoint) -> Option<Coin> {
+        self.coins.get(outpoint).cloned()
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, coin: Coin) {
+        self.coins.insert(outpoint, coin);
+    }
+
+    fn spend(&mut self, outpoint: &OutPoint) -> Option<Coin> {
+        self.coins.remove(outpoint)
+    }
+}
+
+/// The outputs spent by a block, in the order its inputs spend them, which
+/// must be restored to disconnect it. Outputs both created and spent within
+/// the block are not included.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BlockUndo {
+    /// The spent outputs
+    pub spent: Vec<(OutPoint, Coin)>,
+}
+
+impl_consensus_encoding!(BlockUndo, spent);
+
+/// An error connecting or disconnecting a block
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// An input spends an output which is not in the UTXO set
+    MissingInput(OutPoint),
+    /// An output created by the block being disconnected is not in the
+    /// UTXO set
+    MissingOutput(OutPoint),
+    /// The undo data does not match the block being disconnected
+    UndoMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MissingInput(ref outpoint) => write!(f, "missing input {}:{}", outpoint.txid, outpoint.vout),
+            Error::MissingOutput(ref outpoint) => write!(f, "missing output {}:{}", outpoint.txid, outpoint.vout),
+            Error::UndoMismatch => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::MissingInput(..) => "input not in UTXO set",
+            Error::MissingOutput(..) => "output not in UTXO set",
+            Error::UndoMismatch => "undo data does not match block",
+        }
+    }
+}
+
+/// The spendable outputs created by a block, and the outpoints spent by its
+/// non-coinbase inputs which are not among them
+fn block_changes(block: &Block, height: u32) -> (HashMap<OutPoint, Coin>, Vec<OutPoint>) {
+    let mut created = HashMap::new();
+    let mut spent = vec![];
+    for tx in &block.txdata {
+        let is_coinbase = tx.is_coin_base();
+        if !is_coinbase {
+            for input in &tx.input {
+                if created.remove(&input.previous_output).is_none() {
+                    spent.push(input.previous_output);
+                }
+            }
+        }
+        let txid = tx.txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            // Provably unspendable outputs are never added to the set
+            if output.script_pubkey.is_provably_unspendable() {
+                continue;
+            }
+            let coin = Coin {
+                output: output.clone(),
+                height: height,
+                is_coinbase: is_coinbase,
+            };
+            created.insert(OutPoint { txid: txid, vout: vout as u32 }, coin);
+        }
+    }
+    (created, spent)
+}
+
+/// Connects a block at the given height to the UTXO set, spending the
+/// outputs its inputs refer to and adding its outputs, and returns the
+/// undo data needed to disconnect it. The set is unchanged on error.
+///
+/// Only the availability of the spent outputs is checked: scripts, amounts
+/// and coinbase maturity are left to the caller, which can look up the
+/// spent outputs in the returned undo data.
+pub fn connect_block<U: UtxoSet>(utxos: &mut U, block: &Block, height: u32) -> Result<BlockUndo, Error> {
+    let (created, spent) = block_changes(block, height);
+    let mut undo = BlockUndo { spent: Vec::with_capacity(spent.len()) };
+    let mut seen = HashSet::with_capacity(spent.len());
+    for outpoint in spent {
+        // An output spent twice in the block is missing the second time
+        if !seen.insert(outpoint) {
+            return Err(Error::MissingInput(outpoint));
+        }
+        match utxos.get(&outpoint) {
+            Some(coin) => undo.spent.push((outpoint, coin)),
+            None => return Err(Error::MissingInput(outpoint)),
+        }
+    }
+
+    utxos.commit(UtxoBatch {
+        spend: undo.spent.iter().map(|&(outpoint, _)| outpoint).collect(),
+        insert: created.into_iter().collect(),
+    });
+    Ok(undo)
+}
+
+/// Disconnects a block, the last connected to the UTXO set, removing its
+/// outputs and restoring the outputs it spent from its undo data. The set
+/// is unchanged on error.
+pub fn disconnect_block<U: UtxoSet>(utxos: &mut U, block: &Block, undo: &BlockUndo) -> Result<(), Error> {
+    let (created, spent) = block_changes(block, 0);
+    if spent.len() != undo.spent.len() ||
+       spent.iter().zip(undo.spent.iter()).any(|(outpoint, &(undo_outpoint, _))| *outpoint != undo_outpoint) {
+        return Err(Error::UndoMismatch);
+    }
+    for outpoint in created.keys() {
+        if utxos.get(outpoint).is_none() {
+            return Err(Error::MissingOutput(*outpoint));
+        }
+    }
+
+    utxos.commit(UtxoBatch {
+        spend: created.into_iter().map(|(outpoint, _)| outpoint).collect(),
+        insert: undo.spent.clone(),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use blockdata::block::Block;
+    use blockdata::constants::genesis_block;
+    use blockdata::script::{Builder, Script};
+    use blockdata::opcodes;
+    use blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+    use consensus::encode::{deserialize, serialize};
+    use network::constants::Network;
+    use util::amount::Amount;
+
+    use super::*;
+
+    fn spend(outpoints: &[OutPoint], values: &[u64]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: outpoints.iter().map(|&outpoint| TxIn {
+                previous_output: outpoint,
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }).collect(),
+            output: values.iter().map(|&value| TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: Builder::new().push_opcode(opcodes::OP_TRUE).into_script(),
+            }).collect(),
+        }
+    }
+
+    fn outpoint(tx: &Transaction, vout: u32) -> OutPoint {
+        OutPoint { txid: tx.txid(), vout: vout }
+    }
+
+    #[test]
+    fn connect_disconnect() {
+        let mut utxos = MemoryUtxoSet::new();
+        let genesis = genesis_block(Network::Regtest);
+        let coinbase = genesis.txdata[0].clone();
+        let undo = connect_block(&mut utxos, &genesis, 0).unwrap();
+        assert_eq!(undo, BlockUndo::default());
+        assert_eq!(utxos.len(), 1);
+        let coin = utxos.get(&outpoint(&coinbase, 0)).unwrap();
+        assert!(coin.is_coinbase);
+        assert_eq!(coin.height, 0);
+        let before = utxos.clone();
+
+        // A block spending the genesis coinbase, with a chain of spends
+        // within the block and an unspendable output
+        let mut coinbase1 = coinbase.clone();
+        coinbase1.lock_time = 1;
+        let tx1 = spend(&[outpoint(&coinbase, 0)], &[1000, 2000]);
+        let tx2 = spend(&[outpoint(&tx1, 1)], &[1500]);
+        let mut tx3 = spend(&[outpoint(&tx2, 0)], &[1000, 0]);
+        tx3.output[1].script_pubkey = Builder::new().push_opcode(opcodes::All::OP_RETURN).into_script();
+        let mut block = Block { header: genesis.header, txdata: vec![coinbase1.clone(), tx1.clone(), tx2.clone(), tx3.clone()] };
+
+        let undo = connect_block(&mut utxos, &block, 1).unwrap();
+        assert_eq!(undo.spent, vec![(outpoint(&coinbase, 0), coin)]);
+        let mut expected = vec![outpoint(&coinbase1, 0), outpoint(&tx1, 0), outpoint(&tx3, 0)];
+        let mut unspent: Vec<OutPoint> = utxos.iter().map(|(outpoint, _)| *outpoint).collect();
+        expected.sort();
+        unspent.sort();
+        assert_eq!(unspent, expected);
+        assert_eq!(utxos.get(&outpoint(&tx3, 0)).unwrap().height, 1);
+        assert!(!utxos.get(&outpoint(&tx3, 0)).unwrap().is_coinbase);
+        assert!(utxos.get(&outpoint(&coinbase1, 0)).unwrap().is_coinbase);
+
+        // Undo data survives a round trip through its encoding
+        let undo: BlockUndo = deserialize(&serialize(&undo)).unwrap();
+        disconnect_block(&mut utxos, &block, &undo).unwrap();
+        assert_eq!(utxos, before);
+
+        // Disconnecting with the wrong undo data, or twice, fails cleanly
+        assert_eq!(disconnect_block(&mut utxos, &block, &BlockUndo::default()), Err(Error::UndoMismatch));
+        match disconnect_block(&mut utxos, &block, &undo) {
+            Err(Error::MissingOutput(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(utxos, before);
+
+        // Spending a missing output, or the same output twice, fails and
+        // leaves the set unchanged
+        block.txdata.push(spend(&[outpoint(&tx1, 1)], &[1]));
+        assert_eq!(connect_block(&mut utxos, &block, 1), Err(Error::MissingInput(outpoint(&tx1, 1))));
+        block.txdata.pop();
+        block.txdata.push(spend(&[outpoint(&coinbase, 0)], &[1]));
+        assert_eq!(connect_block(&mut utxos, &block, 1), Err(Error::MissingInput(outpoint(&coinbase, 0))));
+        assert_eq!(utxos, before);
+    }
+
+    #[test]
+    fn batch_commit() {
+        let mut utxos = MemoryUtxoSet::new();
+        let tx = spend(&[OutPoint::null()], &[1, 2]);
+        let coin = |value| Coin {
+            output: TxOut { value: Amount::from_sat(value), script_pubkey: Script::new() },
+            height: 7,
+            is_coinbase: false,
+        };
+        utxos.insert(outpoint(&tx, 0), coin(1));
+        utxos.commit(UtxoBatch {
+            spend: vec![outpoint(&tx, 0)],
+            insert: vec![(outpoint(&tx, 0), coin(3)), (outpoint(&tx, 1), coin(2))],
+        });
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(utxos.get(&outpoint(&tx, 0)), Some(coin(3)));
+        assert_eq!(utxos.spend(&outpoint(&tx, 1)), Some(coin(2)));
+        assert_eq!(utxos.spend(&outpoint(&tx, 1)), None);
+        assert!(!utxos.is_empty());
+    }
+}